@@ -0,0 +1,104 @@
+//! Throughput of bulk IN reads, one transfer at a time and queued.
+//!
+//! Needs a device that sends data on an IN endpoint as fast as it is read,
+//! e.g. the source endpoint of Linux's gadget zero (`modprobe g_zero`):
+//!
+//! ```sh
+//! WEBUSB_BENCH_DEVICE=0525:a4a0 WEBUSB_BENCH_ENDPOINT=1 cargo bench
+//! ```
+//!
+//! `WEBUSB_BENCH_INTERFACE` selects the interface to claim (0 by default)
+//! and `WEBUSB_BENCH_BYTES` the amount of data per run (64 MiB).
+
+use std::env;
+use std::io;
+use std::io::Read;
+use std::time::Instant;
+
+use webusb::pipe::EndpointReader;
+use webusb::pipe::QueuedReader;
+use webusb::Context;
+use webusb::UsbDevice;
+
+const TRANSFER_SIZES: [usize; 3] = [16 * 1024, 64 * 1024, 256 * 1024];
+const QUEUE_LENGTHS: [usize; 4] = [1, 2, 4, 8];
+
+fn var(name: &str) -> Option<String> {
+  env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_id(id: &str) -> Option<(u16, u16)> {
+  let (vendor_id, product_id) = id.split_once(':')?;
+  Some((
+    u16::from_str_radix(vendor_id, 16).ok()?,
+    u16::from_str_radix(product_id, 16).ok()?,
+  ))
+}
+
+// Reads `bytes` bytes from `reader`, returning MB/s.
+fn measure(mut reader: impl Read, bytes: usize) -> io::Result<f64> {
+  let mut buffer = vec![0; 1024 * 1024];
+  let mut remaining = bytes;
+  let start = Instant::now();
+  while remaining > 0 {
+    let length = remaining.min(buffer.len());
+    match reader.read(&mut buffer[..length])? {
+      // A zero length packet.
+      0 => continue,
+      read => remaining -= read,
+    }
+  }
+  Ok(bytes as f64 / start.elapsed().as_secs_f64() / 1e6)
+}
+
+fn run(device: &mut UsbDevice, endpoint: u8, bytes: usize) -> io::Result<()> {
+  println!("{:>12} {:>8} {:>10}", "transfer", "queue", "MB/s");
+  for &size in TRANSFER_SIZES.iter() {
+    let reader =
+      EndpointReader::new(device, endpoint)?.with_transfer_size(size);
+    let speed = measure(reader, bytes)?;
+    println!("{:>12} {:>8} {:>10.1}", size, "-", speed);
+
+    for &length in QUEUE_LENGTHS.iter() {
+      let reader = QueuedReader::new(device, endpoint)?
+        .with_transfer_size(size)
+        .with_queue_length(length);
+      let speed = measure(reader, bytes)?;
+      println!("{:>12} {:>8} {:>10.1}", size, length, speed);
+    }
+  }
+  Ok(())
+}
+
+fn main() -> io::Result<()> {
+  let (vendor_id, product_id) =
+    match var("WEBUSB_BENCH_DEVICE").as_deref().and_then(parse_id) {
+      Some(id) => id,
+      None => {
+        println!("throughput: skipped, WEBUSB_BENCH_DEVICE=vid:pid not set");
+        return Ok(());
+      }
+    };
+  let interface = var("WEBUSB_BENCH_INTERFACE")
+    .and_then(|value| value.parse().ok())
+    .unwrap_or(0);
+  let endpoint = var("WEBUSB_BENCH_ENDPOINT")
+    .and_then(|value| value.parse().ok())
+    .unwrap_or(1);
+  let bytes = var("WEBUSB_BENCH_BYTES")
+    .and_then(|value| value.parse().ok())
+    .unwrap_or(64 * 1024 * 1024);
+
+  let context = Context::init()?;
+  let mut device = context
+    .devices()?
+    .into_iter()
+    .find(|d| d.vendor_id == vendor_id && d.product_id == product_id)
+    .expect("Device not found.");
+  device.open()?;
+  device.claim_interface(interface)?;
+
+  let result = run(&mut device, endpoint, bytes);
+  device.close()?;
+  result
+}
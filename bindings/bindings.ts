@@ -49,66 +49,84 @@ const opts = {
   policy: undefined,
 }
 const _lib = await prepare(opts, {
+  cancel: { parameters: ["u32"], result: "void", nonblocking: false },
   claim_interface: {
-    parameters: ["pointer", "usize", "u8"],
+    parameters: ["u32", "u8"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
   clear_halt: {
-    parameters: ["pointer", "usize", "pointer", "usize", "u8"],
-    result: "pointer",
-    nonblocking: false,
-  },
-  close: {
-    parameters: ["pointer", "usize"],
+    parameters: ["u32", "pointer", "usize", "u8"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
+  close: { parameters: ["u32"], result: "pointer", nonblocking: true },
+  close_reader: { parameters: ["u32"], result: "void", nonblocking: false },
   control_transfer_in: {
-    parameters: ["pointer", "usize", "pointer", "usize", "usize"],
+    parameters: ["u32", "pointer", "usize", "pointer", "usize", "u32"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
   control_transfer_out: {
-    parameters: ["pointer", "usize", "pointer", "usize", "pointer", "usize"],
-    result: "usize",
-    nonblocking: false,
+    parameters: ["u32", "pointer", "usize", "pointer", "usize", "u32"],
+    result: "pointer",
+    nonblocking: true,
   },
+  forget: { parameters: ["u32"], result: "void", nonblocking: false },
   get_devices: { parameters: [], result: "pointer", nonblocking: true },
-  open: {
-    parameters: ["pointer", "usize"],
+  hotplug: { parameters: [], result: "pointer", nonblocking: true },
+  isochronous_transfer_in: {
+    parameters: ["u32", "u8", "pointer", "usize", "pointer", "usize"],
+    result: "pointer",
+    nonblocking: true,
+  },
+  isochronous_transfer_out: {
+    parameters: ["u32", "u8", "pointer", "usize", "pointer", "usize"],
+    result: "pointer",
+    nonblocking: true,
+  },
+  open: { parameters: ["u32"], result: "pointer", nonblocking: true },
+  open_reader: {
+    parameters: ["u32", "u8", "usize", "usize"],
     result: "pointer",
     nonblocking: false,
   },
+  read_chunk: {
+    parameters: ["u32", "pointer", "usize"],
+    result: "pointer",
+    nonblocking: true,
+  },
+  release: { parameters: ["u32"], result: "void", nonblocking: false },
   release_interface: {
-    parameters: ["pointer", "usize", "u8"],
+    parameters: ["u32", "u8"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
-  reset: {
+  request_device: {
     parameters: ["pointer", "usize"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
+  reset: { parameters: ["u32"], result: "pointer", nonblocking: true },
   select_alternate_interface: {
-    parameters: ["pointer", "usize", "u8", "u8"],
+    parameters: ["u32", "u8", "u8"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
   select_configuration: {
-    parameters: ["pointer", "usize", "u8"],
+    parameters: ["u32", "u8"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
   transfer_in: {
-    parameters: ["pointer", "usize", "u8", "usize"],
+    parameters: ["u32", "u8", "pointer", "usize", "u32"],
     result: "pointer",
-    nonblocking: false,
+    nonblocking: true,
   },
   transfer_out: {
-    parameters: ["pointer", "usize", "u8", "pointer", "usize"],
-    result: "void",
-    nonblocking: false,
+    parameters: ["u32", "u8", "pointer", "usize", "u32"],
+    result: "pointer",
+    nonblocking: true,
   },
 })
 export type UsbInterface = {
@@ -212,16 +230,69 @@ export type UsbDevice = {
    * If true, the underlying device handle is owned by this object.
    */
   opened: boolean
+  /**
+   * Number of the bus the device is connected to.
+   */
+  busNumber: number
+  /**
+   * Address of the device on its bus. Reassigned by the host controller
+   * every time the device is (re-)enumerated.
+   */
+  deviceAddress: number
+  /**
+   * Port numbers from the root hub down to the device. Stable across
+   * re-enumeration as long as the physical topology does not change.
+   */
+  portNumbers: Array<number>
+  /**
+   * Negotiated connection speed of the device.
+   */
+  speed: UsbSpeed
+  /**
+   * The hub this device is plugged into.
+   * None, if the device is a root hub.
+   */
+  parentHub: UsbParentHub | undefined | null
   /**
    * WEBUSB_URL value of the WebUSB Platform Capability Descriptor.
    */
   url: string | undefined | null
+}
+/**
+ * Negotiated connection speed of a device.
+ */
+export type UsbSpeed =
+  | "unknown"
+  | "low"
+  | "full"
+  | "high"
+  | "super"
+  | "superPlus"
+/**
+ * Identifies the hub a device is plugged into.
+ */
+export type UsbParentHub = {
   /**
-   * Resource ID associated with this Device instance.
+   * Number of the bus the hub is connected to.
    */
-  rid: number
-  device: Device<Context>
-  deviceHandle: DeviceHandle<Context> | undefined | null
+  busNumber: number
+  /**
+   * Address of the hub on its bus.
+   */
+  deviceAddress: number
+  /**
+   * Port numbers from the root hub down to the hub.
+   * Empty, if the hub is a root hub.
+   */
+  portNumbers: Array<number>
+  /**
+   * idVendor field of the hub's device descriptor.
+   */
+  vendorId: number
+  /**
+   * idProduct field of the hub's device descriptor.
+   */
+  productId: number
 }
 export type UsbControlTransferParameters = {
   requestType: UsbRequestType
@@ -241,22 +312,155 @@ export type UsbRecipient =
 export type Direction =
   | "in"
   | "out"
-export type Device = {
+/**
+ * Attributes of a device as seen from JS.
+ */
+export type DeviceInfo = {
+  rid: number
   device: UsbDevice
 }
+/**
+ * Attributes of a device that change with its state.
+ */
+export type DeviceState = {
+  opened: boolean
+  configuration: UsbConfiguration | undefined | null
+}
 export type UsbConfiguration = {
   configurationName: string | undefined | null
   configurationValue: number
   interfaces: Array<UsbInterface>
+  selfPowered: boolean
+  remoteWakeup: boolean
+  maxPower: number
+  rawDescriptor: Array<number> | undefined | null
 }
 export type Devices = {
-  devices: Array<UsbDevice>
+  devices: Array<DeviceInfo>
+}
+/**
+ * Error of a call, as seen from JS. `name` is the name of the
+ * DOMException to reject with.
+ */
+export type FfiError = {
+  name: string
+  message: string
+}
+export type DevicesResult = {
+  ok: Devices | undefined | null
+  error: FfiError | undefined | null
+}
+export type StateResult = {
+  ok: DeviceState | undefined | null
+  error: FfiError | undefined | null
+}
+export type DeviceResult = {
+  ok: DeviceInfo | undefined | null
+  error: FfiError | undefined | null
+}
+/**
+ * Outcome of a transfer, the `USBTransferStatus` and the number of bytes
+ * transferred.
+ */
+export type Transfer = {
+  status: string
+  length: number
+}
+export type TransferResult = {
+  ok: Transfer | undefined | null
+  error: FfiError | undefined | null
+}
+export type ReaderResult = {
+  ok: number | undefined | null
+  error: FfiError | undefined | null
+}
+/**
+ * Result of `read_chunk`, the length of the chunk.
+ */
+export type ChunkResult = {
+  ok: number | undefined | null
+  error: FfiError | undefined | null
+}
+/**
+ * Devices connected and resource IDs of devices disconnected since the
+ * last call to `hotplug`.
+ */
+export type HotplugEvents = {
+  connected: Array<DeviceInfo>
+  disconnected: Array<number>
+}
+export type HotplugResult = {
+  ok: HotplugEvents | undefined | null
+  error: FfiError | undefined | null
+}
+export type EmptyResult = {
+  error: FfiError | undefined | null
+}
+/**
+ * Matches devices the way `navigator.usb.requestDevice` does. A device
+ * matches if all of the present fields match.
+ * https://wicg.github.io/webusb/#dictdef-usbdevicefilter
+ */
+export type UsbDeviceFilter = {
+  vendorId: number | undefined | null
+  productId: number | undefined | null
+  /**
+   * Matches the device class or the class of any of its interfaces.
+   */
+  classCode: number | undefined | null
+  subclassCode: number | undefined | null
+  protocolCode: number | undefined | null
+  serialNumber: string | undefined | null
+}
+export type FfiUsbDeviceRequestOptions = {
+  filters: Array<UsbDeviceFilter>
 }
 export type UsbEndpoint = {
   endpointNumber: number
   direction: Direction
   type: UsbEndpointType
   packetSize: number
+  transactionsPerMicroframe: number
+  interval: number
+  syncType: UsbSyncType
+  usageType: UsbUsageType
+  superspeedCompanion: UsbSuperSpeedEndpointCompanion | undefined | null
+}
+export type UsbSyncType =
+  | "none"
+  | "asynchronous"
+  | "adaptive"
+  | "synchronous"
+export type UsbUsageType =
+  | "data"
+  | "feedback"
+  | "implicitFeedbackData"
+  | "reserved"
+/**
+ * SuperSpeed Endpoint Companion descriptor.
+ * https://www.usb.org/document-library/usb-32-revision-11-june-2022 (9.6.7)
+ */
+export type UsbSuperSpeedEndpointCompanion = {
+  /**
+   * Maximum number of packets the endpoint can send or receive as part of
+   * a burst (1-16). Derived from bMaxBurst.
+   */
+  maxBurst: number
+  /**
+   * Maximum number of streams supported by a bulk endpoint.
+   * 0, if the endpoint does not support streams.
+   */
+  maxStreams: number
+  /**
+   * Maximum number of bursts within a service interval for isochronous
+   * endpoints (1-3). Always 1 for other endpoints.
+   */
+  mult: number
+  /**
+   * Total number of bytes the endpoint will transfer every service
+   * interval. Only meaningful for periodic endpoints.
+   */
+  bytesPerInterval: number
 }
 export type UsbAlternateInterface = {
   alternateSetting: number
@@ -270,130 +474,230 @@ export type UsbRequestType =
   | "standard"
   | "class"
   | "vendor"
+export type FfiPacketLengths = {
+  inner: Array<number>
+}
+/**
+ * Status and length of every packet of an isochronous transfer.
+ */
+export type IsochronousTransfer = {
+  packets: Array<Transfer>
+}
+export type IsochronousTransferResult = {
+  ok: IsochronousTransfer | undefined | null
+  error: FfiError | undefined | null
+}
 export type FfiUsbControlTransferParameters = {
   inner: UsbControlTransferParameters
 }
-export function claim_interface(a0: Device, a1: number) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.claim_interface(a0_buf, a0_buf.byteLength, a1)
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
+export function cancel(a0: number) {
+  let rawResult = _lib.symbols.cancel(a0)
+  const result = rawResult
+  return result
 }
-export function clear_halt(a0: Device, a1: FfiDirection, a2: number) {
-  const a0_buf = encode(JSON.stringify(a0))
+export function claim_interface(a0: number, a1: number) {
+  let rawResult = _lib.symbols.claim_interface(a0, a1)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<StateResult>
+}
+export function clear_halt(a0: number, a1: FfiDirection, a2: number) {
   const a1_buf = encode(JSON.stringify(a1))
-  let rawResult = _lib.symbols.clear_halt(
-    a0_buf,
-    a0_buf.byteLength,
-    a1_buf,
-    a1_buf.byteLength,
-    a2,
-  )
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
+  let rawResult = _lib.symbols.clear_halt(a0, a1_buf, a1_buf.byteLength, a2)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<EmptyResult>
 }
-export function close(a0: Device) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.close(a0_buf, a0_buf.byteLength)
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
+export function close(a0: number) {
+  let rawResult = _lib.symbols.close(a0)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<StateResult>
+}
+export function close_reader(a0: number) {
+  let rawResult = _lib.symbols.close_reader(a0)
+  const result = rawResult
+  return result
 }
 export function control_transfer_in(
-  a0: Device,
+  a0: number,
   a1: FfiUsbControlTransferParameters,
-  a2: bigint,
+  a2: Uint8Array,
+  a3: number,
 ) {
-  const a0_buf = encode(JSON.stringify(a0))
   const a1_buf = encode(JSON.stringify(a1))
+  const a2_buf = encode(a2)
   let rawResult = _lib.symbols.control_transfer_in(
-    a0_buf,
-    a0_buf.byteLength,
+    a0,
     a1_buf,
     a1_buf.byteLength,
-    a2,
+    a2_buf,
+    a2_buf.byteLength,
+    a3,
   )
-  const result = readPointer(rawResult)
-  return result
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<TransferResult>
 }
 export function control_transfer_out(
-  a0: Device,
+  a0: number,
   a1: FfiUsbControlTransferParameters,
   a2: Uint8Array,
+  a3: number,
 ) {
-  const a0_buf = encode(JSON.stringify(a0))
   const a1_buf = encode(JSON.stringify(a1))
   const a2_buf = encode(a2)
   let rawResult = _lib.symbols.control_transfer_out(
-    a0_buf,
-    a0_buf.byteLength,
+    a0,
     a1_buf,
     a1_buf.byteLength,
     a2_buf,
     a2_buf.byteLength,
+    a3,
   )
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<TransferResult>
+}
+export function forget(a0: number) {
+  let rawResult = _lib.symbols.forget(a0)
   const result = rawResult
   return result
 }
 export function get_devices() {
   let rawResult = _lib.symbols.get_devices()
   const result = rawResult.then(readPointer)
-  return result.then(r => JSON.parse(decode(r))) as Promise<Devices>
+  return result.then(r => JSON.parse(decode(r))) as Promise<DevicesResult>
 }
-export function open(a0: Device) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.open(a0_buf, a0_buf.byteLength)
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
-}
-export function release_interface(a0: Device, a1: number) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.release_interface(a0_buf, a0_buf.byteLength, a1)
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
-}
-export function reset(a0: Device) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.reset(a0_buf, a0_buf.byteLength)
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
+export function hotplug() {
+  let rawResult = _lib.symbols.hotplug()
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<HotplugResult>
 }
-export function select_alternate_interface(a0: Device, a1: number, a2: number) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.select_alternate_interface(
-    a0_buf,
-    a0_buf.byteLength,
+export function isochronous_transfer_in(
+  a0: number,
+  a1: number,
+  a2: FfiPacketLengths,
+  a3: Uint8Array,
+) {
+  const a2_buf = encode(JSON.stringify(a2))
+  const a3_buf = encode(a3)
+  let rawResult = _lib.symbols.isochronous_transfer_in(
+    a0,
     a1,
-    a2,
+    a2_buf,
+    a2_buf.byteLength,
+    a3_buf,
+    a3_buf.byteLength,
   )
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<
+    IsochronousTransferResult
+  >
 }
-export function select_configuration(a0: Device, a1: number) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.select_configuration(
-    a0_buf,
-    a0_buf.byteLength,
+export function isochronous_transfer_out(
+  a0: number,
+  a1: number,
+  a2: Uint8Array,
+  a3: FfiPacketLengths,
+) {
+  const a2_buf = encode(a2)
+  const a3_buf = encode(JSON.stringify(a3))
+  let rawResult = _lib.symbols.isochronous_transfer_out(
+    a0,
     a1,
+    a2_buf,
+    a2_buf.byteLength,
+    a3_buf,
+    a3_buf.byteLength,
   )
-  const result = readPointer(rawResult)
-  return JSON.parse(decode(result)) as Device
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<
+    IsochronousTransferResult
+  >
 }
-export function transfer_in(a0: Device, a1: number, a2: bigint) {
-  const a0_buf = encode(JSON.stringify(a0))
-  let rawResult = _lib.symbols.transfer_in(a0_buf, a0_buf.byteLength, a1, a2)
+export function open(a0: number) {
+  let rawResult = _lib.symbols.open(a0)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<StateResult>
+}
+export function open_reader(
+  a0: number,
+  a1: number,
+  a2: bigint,
+  a3: bigint,
+) {
+  let rawResult = _lib.symbols.open_reader(a0, a1, a2, a3)
   const result = readPointer(rawResult)
+  return JSON.parse(decode(result)) as ReaderResult
+}
+export function read_chunk(a0: number, a1: Uint8Array) {
+  const a1_buf = encode(a1)
+  let rawResult = _lib.symbols.read_chunk(a0, a1_buf, a1_buf.byteLength)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<ChunkResult>
+}
+export function release(a0: number) {
+  let rawResult = _lib.symbols.release(a0)
+  const result = rawResult
   return result
 }
-export function transfer_out(a0: Device, a1: number, a2: Uint8Array) {
+export function release_interface(a0: number, a1: number) {
+  let rawResult = _lib.symbols.release_interface(a0, a1)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<StateResult>
+}
+export function request_device(a0: FfiUsbDeviceRequestOptions) {
   const a0_buf = encode(JSON.stringify(a0))
+  let rawResult = _lib.symbols.request_device(a0_buf, a0_buf.byteLength)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<DeviceResult>
+}
+export function reset(a0: number) {
+  let rawResult = _lib.symbols.reset(a0)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<StateResult>
+}
+export function select_alternate_interface(
+  a0: number,
+  a1: number,
+  a2: number,
+) {
+  let rawResult = _lib.symbols.select_alternate_interface(a0, a1, a2)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<StateResult>
+}
+export function select_configuration(a0: number, a1: number) {
+  let rawResult = _lib.symbols.select_configuration(a0, a1)
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<StateResult>
+}
+export function transfer_in(
+  a0: number,
+  a1: number,
+  a2: Uint8Array,
+  a3: number,
+) {
+  const a2_buf = encode(a2)
+  let rawResult = _lib.symbols.transfer_in(
+    a0,
+    a1,
+    a2_buf,
+    a2_buf.byteLength,
+    a3,
+  )
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<TransferResult>
+}
+export function transfer_out(
+  a0: number,
+  a1: number,
+  a2: Uint8Array,
+  a3: number,
+) {
   const a2_buf = encode(a2)
   let rawResult = _lib.symbols.transfer_out(
-    a0_buf,
-    a0_buf.byteLength,
+    a0,
     a1,
     a2_buf,
     a2_buf.byteLength,
+    a3,
   )
-  const result = rawResult
-  return result
+  const result = rawResult.then(readPointer)
+  return result.then(r => JSON.parse(decode(r))) as Promise<TransferResult>
 }
@@ -0,0 +1,286 @@
+/* WebUSB API implementation in Rust. Generated by cbindgen from src/capi.rs, do not edit. */
+
+#ifndef WEBUSB_H
+#define WEBUSB_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// String descriptors of a device.
+typedef enum WebusbDeviceString {
+  WEBUSB_DEVICE_STRING_MANUFACTURER_NAME,
+  WEBUSB_DEVICE_STRING_PRODUCT_NAME,
+  WEBUSB_DEVICE_STRING_SERIAL_NUMBER,
+} WebusbDeviceString;
+
+typedef enum WebusbDirection {
+  WEBUSB_DIRECTION_IN,
+  WEBUSB_DIRECTION_OUT,
+} WebusbDirection;
+
+typedef enum WebusbRecipient {
+  WEBUSB_RECIPIENT_DEVICE,
+  WEBUSB_RECIPIENT_INTERFACE,
+  WEBUSB_RECIPIENT_ENDPOINT,
+  WEBUSB_RECIPIENT_OTHER,
+} WebusbRecipient;
+
+typedef enum WebusbRequestType {
+  WEBUSB_REQUEST_TYPE_STANDARD,
+  WEBUSB_REQUEST_TYPE_CLASS,
+  WEBUSB_REQUEST_TYPE_VENDOR,
+} WebusbRequestType;
+
+// Result of the functions of the C API.
+typedef enum WebusbStatus {
+  WEBUSB_STATUS_SUCCESS = 0,
+  // A null pointer or an out of range value was passed.
+  WEBUSB_STATUS_INVALID_ARGUMENT = -1,
+  // The device, interface, endpoint or configuration does not exist.
+  WEBUSB_STATUS_NOT_FOUND = -2,
+  // The device is not open, or the interface is not claimed.
+  WEBUSB_STATUS_INVALID_STATE = -3,
+  // The endpoint does not support the transfer.
+  WEBUSB_STATUS_INVALID_ACCESS = -4,
+  // The endpoint or control request stalled.
+  WEBUSB_STATUS_STALL = -5,
+  // The device sent more data than requested.
+  WEBUSB_STATUS_BABBLE = -6,
+  // The transfer did not complete within the transfer timeout.
+  WEBUSB_STATUS_TIMEOUT = -7,
+  // Permission to access the device was denied.
+  WEBUSB_STATUS_ACCESS = -8,
+  // The device is gone.
+  WEBUSB_STATUS_NO_DEVICE = -9,
+  // A buffer passed is too small for the result.
+  WEBUSB_STATUS_BUFFER_TOO_SMALL = -10,
+  // Any other I/O or USB error.
+  WEBUSB_STATUS_OTHER = -99,
+} WebusbStatus;
+
+// Enumerates devices. Opaque.
+typedef struct WebusbContext WebusbContext;
+
+// A device returned by `webusb_context_devices`. Opaque.
+typedef struct WebusbDevice WebusbDevice;
+
+// Descriptor fields and state of a device.
+typedef struct WebusbDeviceInfo {
+  uint8_t usb_version_major;
+  uint8_t usb_version_minor;
+  uint8_t usb_version_subminor;
+  uint8_t device_class;
+  uint8_t device_subclass;
+  uint8_t device_protocol;
+  uint16_t vendor_id;
+  uint16_t product_id;
+  uint8_t device_version_major;
+  uint8_t device_version_minor;
+  uint8_t device_version_subminor;
+  uint8_t bus_number;
+  uint8_t device_address;
+  bool opened;
+  // bConfigurationValue of the selected configuration. 0, if the device
+  // is not configured.
+  uint8_t configuration_value;
+} WebusbDeviceInfo;
+
+// https://wicg.github.io/webusb/#dictdef-usbcontroltransferparameters
+typedef struct WebusbControlSetup {
+  enum WebusbRequestType request_type;
+  enum WebusbRecipient recipient;
+  uint8_t request;
+  uint16_t value;
+  uint16_t index;
+} WebusbControlSetup;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Name of `status`, e.g. "NotFound". The string is static.
+const char *webusb_status_name(enum WebusbStatus status);
+
+// Creates a context and stores it in `*ctx`. Free it with
+// `webusb_context_free`.
+//
+// # Safety
+//
+// `ctx` must be valid for writes.
+enum WebusbStatus webusb_context_new(struct WebusbContext **ctx);
+
+// # Safety
+//
+// `ctx` must be null or returned by `webusb_context_new`. Devices listed
+// by the context stay valid.
+void webusb_context_free(struct WebusbContext *ctx);
+
+// Lists the devices in a new array of `*len` devices stored in
+// `*devices`. Free it with `webusb_device_list_free`.
+//
+// # Safety
+//
+// `ctx` must be returned by `webusb_context_new`, `devices` and `len`
+// must be valid for writes.
+enum WebusbStatus webusb_context_devices(const struct WebusbContext *ctx,
+                                         struct WebusbDevice ***devices,
+                                         size_t *len);
+
+// Frees the array and the devices left in it. Keep a device by setting
+// its entry to null before freeing the list, and free it with
+// `webusb_device_free` later.
+//
+// # Safety
+//
+// `devices` and `len` must be returned by `webusb_context_devices`.
+// `devices` may be null.
+void webusb_device_list_free(struct WebusbDevice **devices, size_t len);
+
+// Closes the device if it is open.
+//
+// # Safety
+//
+// `device` must be null or a device listed by `webusb_context_devices`
+// that is no longer in use by other threads.
+void webusb_device_free(struct WebusbDevice *device);
+
+// # Safety
+//
+// `device` must be a valid device, `info` must be valid for writes.
+enum WebusbStatus webusb_device_info(const struct WebusbDevice *device,
+                                     struct WebusbDeviceInfo *info);
+
+// Copies a string descriptor of the device into `buffer` as a
+// NUL-terminated UTF-8 string. `*length` receives the length of the
+// string without the NUL, also if `capacity` is too small.
+//
+// Returns `NotFound` if the device has no such string.
+//
+// # Safety
+//
+// `device` must be a valid device, `buffer` must be valid for `capacity`
+// bytes of writes and `length` must be null or valid for writes.
+enum WebusbStatus webusb_device_string(const struct WebusbDevice *device,
+                                       enum WebusbDeviceString which,
+                                       char *buffer,
+                                       size_t capacity,
+                                       size_t *length);
+
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_open(const struct WebusbDevice *device);
+
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_close(const struct WebusbDevice *device);
+
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_reset(const struct WebusbDevice *device);
+
+// `configuration_value` is the bConfigurationValue of the configuration.
+//
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_select_configuration(const struct WebusbDevice *device,
+                                                     uint8_t configuration_value);
+
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_claim_interface(const struct WebusbDevice *device,
+                                                uint8_t interface_number);
+
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_release_interface(const struct WebusbDevice *device,
+                                                  uint8_t interface_number);
+
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_select_alternate_interface(const struct WebusbDevice *device,
+                                                           uint8_t interface_number,
+                                                           uint8_t alternate_setting);
+
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_clear_halt(const struct WebusbDevice *device,
+                                           enum WebusbDirection direction,
+                                           uint8_t endpoint_number);
+
+// Bulk and interrupt transfers that do not complete within `timeout_ms`
+// milliseconds fail with `Timeout`. 0, the default, waits forever.
+//
+// # Safety
+//
+// `device` must be a valid device.
+enum WebusbStatus webusb_device_set_transfer_timeout(const struct WebusbDevice *device,
+                                                     uint32_t timeout_ms);
+
+// Reads up to `length` bytes from IN endpoint `endpoint_number` into
+// `buffer`. `*transferred` receives the number of bytes read.
+//
+// # Safety
+//
+// `device` must be a valid device, `buffer` must be valid for `length`
+// bytes of writes and `transferred` must be null or valid for writes.
+enum WebusbStatus webusb_transfer_in(const struct WebusbDevice *device,
+                                     uint8_t endpoint_number,
+                                     uint8_t *buffer,
+                                     size_t length,
+                                     size_t *transferred);
+
+// Writes `length` bytes of `data` to OUT endpoint `endpoint_number`.
+// `*transferred` receives the number of bytes written.
+//
+// # Safety
+//
+// `device` must be a valid device, `data` must be valid for `length`
+// bytes of reads and `transferred` must be null or valid for writes.
+enum WebusbStatus webusb_transfer_out(const struct WebusbDevice *device,
+                                      uint8_t endpoint_number,
+                                      const uint8_t *data,
+                                      size_t length,
+                                      size_t *transferred);
+
+// Control transfer reading up to `length` bytes into `buffer`.
+// `*transferred` receives the number of bytes read.
+//
+// # Safety
+//
+// `device` and `setup` must be valid, `buffer` must be valid for `length`
+// bytes of writes and `transferred` must be null or valid for writes.
+enum WebusbStatus webusb_control_transfer_in(const struct WebusbDevice *device,
+                                             const struct WebusbControlSetup *setup,
+                                             uint8_t *buffer,
+                                             size_t length,
+                                             size_t *transferred);
+
+// Control transfer writing `length` bytes of `data`. `*transferred`
+// receives the number of bytes written.
+//
+// # Safety
+//
+// `device` and `setup` must be valid, `data` must be valid for `length`
+// bytes of reads and `transferred` must be null or valid for writes.
+enum WebusbStatus webusb_control_transfer_out(const struct WebusbDevice *device,
+                                              const struct WebusbControlSetup *setup,
+                                              const uint8_t *data,
+                                              size_t length,
+                                              size_t *transferred);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WEBUSB_H */
@@ -1,38 +1,341 @@
 import {
+  cancel,
   claim_interface,
   clear_halt,
   close,
+  close_reader,
   control_transfer_in,
   control_transfer_out,
+  DeviceInfo,
+  DeviceState,
   Direction,
+  FfiError,
+  forget,
   get_devices,
+  hotplug,
+  isochronous_transfer_in,
+  isochronous_transfer_out,
   open,
+  open_reader,
+  read_chunk,
+  release,
   release_interface,
+  request_device,
   reset,
   select_alternate_interface,
   select_configuration,
+  Transfer,
   transfer_in,
   transfer_out,
+  UsbAlternateInterface as RawAlternateInterface,
+  UsbConfiguration as RawConfiguration,
   UsbControlTransferParameters,
+  UsbDevice as RawDevice,
+  UsbDeviceFilter,
+  UsbEndpointType,
+  UsbInterface as RawInterface,
 } from "./bindings/bindings.ts";
 export * from "./bindings/bindings.ts";
 
+// How often the device list is checked for hotplug events, in ms.
+const HOTPLUG_INTERVAL = 500;
+
+// Returns `ok`, or throws `error` the way WebUSB would reject.
+function unwrap<T>(
+  { ok, error }: { ok?: T | null; error?: FfiError | null },
+): T {
+  if (error) {
+    if (error.name == "TypeError") {
+      throw new TypeError(error.message);
+    }
+    throw new DOMException(error.message, error.name);
+  }
+  return ok as T;
+}
+
+// WebUSB takes any BufferSource, the bindings take Uint8Arrays.
+function bytes(data: BufferSource): Uint8Array {
+  if (data instanceof Uint8Array) return data;
+  if (ArrayBuffer.isView(data)) {
+    return new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+  }
+  return new Uint8Array(data);
+}
+
+// Not part of WebUSB. Lets a pending transfer be aborted.
+export type USBTransferOptions = {
+  signal?: AbortSignal;
+};
+
+let nextToken = 0;
+
+// Runs `transfer` with a new cancellation token, cancelling it when
+// `signal` aborts. Cancelled transfers reject with an AbortError.
+async function cancellable<T>(
+  signal: AbortSignal | undefined,
+  transfer: (token: number) => Promise<T>,
+): Promise<T> {
+  signal?.throwIfAborted();
+  const token = nextToken;
+  nextToken = (nextToken + 1) >>> 0;
+  const abort = () => cancel(token);
+  signal?.addEventListener("abort", abort);
+  try {
+    return await transfer(token);
+  } finally {
+    signal?.removeEventListener("abort", abort);
+  }
+}
+
+// Not part of WebUSB.
+export type USBReadableStreamOptions = {
+  // Size of each transfer. A multiple of the packet size of the endpoint.
+  transferSize?: number;
+  // Number of transfers read ahead of the consumer of the stream.
+  queueLength?: number;
+};
+
+// Stops the reader of a garbage collected stream.
+const readers = new FinalizationRegistry((reader: number) =>
+  close_reader(reader)
+);
+
+export type USBTransferStatus = "ok" | "stall" | "babble";
+
+export class USBInTransferResult {
+  constructor(
+    readonly status: USBTransferStatus,
+    readonly data?: DataView,
+  ) {}
+}
+
+export class USBOutTransferResult {
+  constructor(
+    readonly status: USBTransferStatus,
+    readonly bytesWritten = 0,
+  ) {}
+}
+
+export class USBIsochronousInTransferPacket {
+  constructor(
+    readonly status: USBTransferStatus,
+    readonly data?: DataView,
+  ) {}
+}
+
+export class USBIsochronousInTransferResult {
+  constructor(
+    readonly packets: USBIsochronousInTransferPacket[],
+    readonly data?: DataView,
+  ) {}
+}
+
+export class USBIsochronousOutTransferPacket {
+  constructor(
+    readonly status: USBTransferStatus,
+    readonly bytesWritten = 0,
+  ) {}
+}
+
+export class USBIsochronousOutTransferResult {
+  constructor(readonly packets: USBIsochronousOutTransferPacket[]) {}
+}
+
+function inResult({ status, length }: Transfer, buffer: Uint8Array) {
+  return new USBInTransferResult(
+    status as USBTransferStatus,
+    new DataView(buffer.buffer, 0, length),
+  );
+}
+
+function outResult({ status, length }: Transfer) {
+  return new USBOutTransferResult(status as USBTransferStatus, length);
+}
+
+// State shared between a `UsbDevice` and the descriptor objects built
+// from it.
+type Inner = {
+  // Resource ID of the device in `ffi::RESOURCES`.
+  rid: number;
+  device: RawDevice;
+  state: DeviceState;
+};
+
+const inners = new WeakMap<UsbDevice, Inner>();
+const configurations = new WeakMap<
+  USBConfiguration,
+  { device: UsbDevice; raw: RawConfiguration }
+>();
+const interfaces = new WeakMap<USBInterface, RawInterface>();
+const alternates = new WeakMap<USBAlternateInterface, RawAlternateInterface>();
+
+// https://wicg.github.io/webusb/#usbendpoint
+export class USBEndpoint {
+  readonly endpointNumber: number;
+  readonly direction: Direction;
+  readonly type: UsbEndpointType;
+  readonly packetSize: number;
+
+  constructor(
+    alternate: USBAlternateInterface,
+    endpointNumber: number,
+    direction: Direction,
+  ) {
+    const endpoint = alternates.get(alternate)?.endpoints.find((endpoint) =>
+      endpoint.endpointNumber == endpointNumber &&
+      endpoint.direction == direction
+    );
+    if (endpoint === undefined) {
+      throw new RangeError("No such endpoint");
+    }
+    this.endpointNumber = endpointNumber;
+    this.direction = direction;
+    this.type = endpoint.type;
+    this.packetSize = endpoint.packetSize;
+  }
+}
+
+// https://wicg.github.io/webusb/#usbalternateinterface
+export class USBAlternateInterface {
+  readonly alternateSetting: number;
+  readonly interfaceClass: number;
+  readonly interfaceSubclass: number;
+  readonly interfaceProtocol: number;
+  readonly interfaceName?: string;
+  readonly endpoints: USBEndpoint[];
+
+  constructor(deviceInterface: USBInterface, alternateSetting: number) {
+    const alternate = interfaces.get(deviceInterface)?.alternates.find(
+      (alternate) => alternate.alternateSetting == alternateSetting,
+    );
+    if (alternate === undefined) {
+      throw new RangeError("No such alternate setting");
+    }
+    alternates.set(this, alternate);
+    this.alternateSetting = alternateSetting;
+    this.interfaceClass = alternate.interfaceClass;
+    this.interfaceSubclass = alternate.interfaceSubclass;
+    this.interfaceProtocol = alternate.interfaceProtocol;
+    this.interfaceName = alternate.interfaceName ?? undefined;
+    this.endpoints = alternate.endpoints.map((endpoint) =>
+      new USBEndpoint(this, endpoint.endpointNumber, endpoint.direction)
+    );
+  }
+}
+
+// https://wicg.github.io/webusb/#usbinterface
+export class USBInterface {
+  readonly interfaceNumber: number;
+  readonly alternates: USBAlternateInterface[];
+  #configuration: USBConfiguration;
+
+  constructor(configuration: USBConfiguration, interfaceNumber: number) {
+    const raw = configurations.get(configuration)?.raw.interfaces.find(
+      (deviceInterface) => deviceInterface.interfaceNumber == interfaceNumber,
+    );
+    if (raw === undefined) {
+      throw new RangeError("No such interface");
+    }
+    interfaces.set(this, raw);
+    this.#configuration = configuration;
+    this.interfaceNumber = interfaceNumber;
+    this.alternates = raw.alternates.map((alternate) =>
+      new USBAlternateInterface(this, alternate.alternateSetting)
+    );
+  }
+
+  // The interface in the current state of the device, if its
+  // configuration is selected.
+  get #current(): RawInterface | undefined {
+    const { device, raw } = configurations.get(this.#configuration)!;
+    const { configuration } = inners.get(device)!.state;
+    if (
+      !configuration ||
+      configuration.configurationValue != raw.configurationValue
+    ) {
+      return undefined;
+    }
+    return configuration.interfaces.find((deviceInterface) =>
+      deviceInterface.interfaceNumber == this.interfaceNumber
+    );
+  }
+
+  get alternate(): USBAlternateInterface {
+    const setting = this.#current?.alternate.alternateSetting ?? 0;
+    return this.alternates.find((alternate) =>
+      alternate.alternateSetting == setting
+    ) ?? this.alternates[0];
+  }
+
+  get claimed(): boolean {
+    return this.#current?.claimed ?? false;
+  }
+}
+
+// https://wicg.github.io/webusb/#usbconfiguration
+export class USBConfiguration {
+  readonly configurationValue: number;
+  readonly configurationName?: string;
+  readonly interfaces: USBInterface[];
+
+  constructor(device: UsbDevice, configurationValue: number) {
+    const raw = inners.get(device)?.device.configurations.find(
+      (configuration) => configuration.configurationValue == configurationValue,
+    );
+    if (raw === undefined) {
+      throw new RangeError("No such configuration");
+    }
+    configurations.set(this, { device, raw });
+    this.configurationValue = configurationValue;
+    this.configurationName = raw.configurationName ?? undefined;
+    this.interfaces = raw.interfaces.map((deviceInterface) =>
+      new USBInterface(this, deviceInterface.interfaceNumber)
+    );
+  }
+}
+
+// Releases the resource ID of a garbage collected `UsbDevice`.
+const registry = new FinalizationRegistry((rid: number) => release(rid));
+
 export class UsbDevice {
-  #inner;
+  #inner: Inner;
+  #configurations?: USBConfiguration[];
 
-  constructor(raw) {
-    this.#inner = { device: raw };
+  constructor({ rid, device }: DeviceInfo) {
+    registry.register(this, rid, this);
+    this.#inner = {
+      rid,
+      device,
+      state: {
+        opened: device.opened,
+        configuration: device.configuration,
+      },
+    };
+    inners.set(this, this.#inner);
+  }
+
+  get #rid() {
+    return this.#inner.rid;
   }
 
   get raw() {
     return this.#inner.device;
   }
 
-  get configurations() {
-    return this.#inner.device.configurations;
+  get configurations(): USBConfiguration[] {
+    if (this.#configurations === undefined) {
+      this.#configurations = this.#inner.device.configurations.map((
+        configuration,
+      ) => new USBConfiguration(this, configuration.configurationValue));
+    }
+    return this.#configurations;
   }
-  get configuration() {
-    return this.#inner.device.configuration;
+
+  get configuration(): USBConfiguration | null {
+    const value = this.#inner.state.configuration?.configurationValue;
+    return this.configurations.find((configuration) =>
+      configuration.configurationValue == value
+    ) ?? null;
   }
 
   get deviceClass() {
@@ -60,7 +363,7 @@ export class UsbDevice {
   }
 
   get manufacturerName() {
-    return this.#inner.device.manufacturerName;
+    return this.#inner.device.manufacturerName ?? undefined;
   }
 
   get productId() {
@@ -68,11 +371,11 @@ export class UsbDevice {
   }
 
   get productName() {
-    return this.#inner.device.productName;
+    return this.#inner.device.productName ?? undefined;
   }
 
   get serialNumber() {
-    return this.#inner.device.serialNumber;
+    return this.#inner.device.serialNumber ?? undefined;
   }
 
   get usbVersionMajor() {
@@ -92,74 +395,153 @@ export class UsbDevice {
   }
 
   get opened() {
-    return this.#inner.opened;
+    return this.#inner.state.opened;
   }
 
   get url() {
-    return this.#inner.url;
+    return this.#inner.device.url;
   }
 
   async open() {
-    this.#inner = await open(this.#inner);
+    this.#inner.state = unwrap(await open(this.#rid));
   }
 
   async reset() {
-    this.#inner = await reset(this.#inner);
+    this.#inner.state = unwrap(await reset(this.#rid));
   }
 
   async close() {
-    this.#inner = await close(this.#inner);
+    this.#inner.state = unwrap(await close(this.#rid));
   }
 
-  async transferIn(endpointNumber: number, length: number) {
-    const pointer = await transfer_in(
-      this.#inner,
-      endpointNumber,
-      length,
+  async forget() {
+    registry.unregister(this);
+    forget(this.#rid);
+    this.#inner.state = { ...this.#inner.state, opened: false };
+  }
+
+  async transferIn(
+    endpointNumber: number,
+    length: number,
+    { signal }: USBTransferOptions = {},
+  ) {
+    const buffer = new Uint8Array(length);
+    const result = await cancellable(
+      signal,
+      (token) => transfer_in(this.#rid, endpointNumber, buffer, token),
     );
-    const view = new Deno.UnsafePointerView(pointer);
-    const u8 = new Uint8Array(length);
-    view.copyInto(u8);
-    return u8;
+    return inResult(unwrap(result), buffer);
   }
 
-  async transferOut(endpointNumber: number, data: Uint8Array) {
-    await transfer_out(this.#inner, endpointNumber, data);
+  async transferOut(
+    endpointNumber: number,
+    data: BufferSource,
+    { signal }: USBTransferOptions = {},
+  ) {
+    const result = await cancellable(
+      signal,
+      (token) => transfer_out(this.#rid, endpointNumber, bytes(data), token),
+    );
+    return outResult(unwrap(result));
   }
 
+  // Control transfers can only be cancelled before they start.
   async controlTransferIn(
     setup: UsbControlTransferParameters,
     length: number,
-  ): Promise<Uint8Array> {
-    const ptr = await control_transfer_in(
-      this.#inner,
-      { inner: setup },
+    { signal }: USBTransferOptions = {},
+  ): Promise<USBInTransferResult> {
+    const buffer = new Uint8Array(length);
+    const result = await cancellable(
+      signal,
+      (token) =>
+        control_transfer_in(this.#rid, { inner: setup }, buffer, token),
     );
-    const view = new Deno.UnsafePointerView(ptr);
-    const u8 = new Uint8Array(length);
-    view.copyInto(u8);
-    return u8;
+    return inResult(unwrap(result), buffer);
   }
 
-  controlTransferOut(
+  async controlTransferOut(
     setup: UsbControlTransferParameters,
-    data?: Uint8Array,
-  ): Promise<number> {
-    return control_transfer_out(
-      this.#inner,
-      { inner: setup },
-      data || new Uint8Array(),
+    data?: BufferSource,
+    { signal }: USBTransferOptions = {},
+  ): Promise<USBOutTransferResult> {
+    const result = await cancellable(
+      signal,
+      (token) =>
+        control_transfer_out(
+          this.#rid,
+          { inner: setup },
+          data ? bytes(data) : new Uint8Array(),
+          token,
+        ),
     );
+    return outResult(unwrap(result));
   }
 
   async clearHalt(
     direction: Direction,
     endpointNumber: number,
   ) {
-    await clear_halt(
-      this.#inner,
-      { inner: direction },
-      endpointNumber,
+    unwrap(
+      await clear_halt(
+        this.#rid,
+        { inner: direction },
+        endpointNumber,
+      ),
+    );
+  }
+
+  // Packets are received at the offset of their requested length in
+  // `data`, like in browsers.
+  async isochronousTransferIn(
+    endpointNumber: number,
+    packetLengths: number[],
+  ): Promise<USBIsochronousInTransferResult> {
+    const buffer = new Uint8Array(
+      packetLengths.reduce((sum, length) => sum + length, 0),
+    );
+    const { packets } = unwrap(
+      await isochronous_transfer_in(
+        this.#rid,
+        endpointNumber,
+        { inner: packetLengths },
+        buffer,
+      ),
+    );
+    let offset = 0;
+    return new USBIsochronousInTransferResult(
+      packets.map(({ status, length }, index) => {
+        const data = new DataView(buffer.buffer, offset, length);
+        offset += packetLengths[index];
+        return new USBIsochronousInTransferPacket(
+          status as USBTransferStatus,
+          data,
+        );
+      }),
+      new DataView(buffer.buffer),
+    );
+  }
+
+  async isochronousTransferOut(
+    endpointNumber: number,
+    data: BufferSource,
+    packetLengths: number[],
+  ): Promise<USBIsochronousOutTransferResult> {
+    const { packets } = unwrap(
+      await isochronous_transfer_out(
+        this.#rid,
+        endpointNumber,
+        bytes(data),
+        { inner: packetLengths },
+      ),
+    );
+    return new USBIsochronousOutTransferResult(
+      packets.map(({ status, length }) =>
+        new USBIsochronousOutTransferPacket(
+          status as USBTransferStatus,
+          length,
+        )
+      ),
     );
   }
 
@@ -167,44 +549,191 @@ export class UsbDevice {
     interfaceNumber: number,
     alternateSetting: number,
   ) {
-    this.#inner = await select_alternate_interface(
-      this.#inner,
-      interfaceNumber,
-      alternateSetting,
+    this.#inner.state = unwrap(
+      await select_alternate_interface(
+        this.#rid,
+        interfaceNumber,
+        alternateSetting,
+      ),
     );
   }
 
   async selectConfiguration(configurationValue: number) {
-    this.#inner = await select_configuration(this.#inner, configurationValue);
+    this.#inner.state = unwrap(
+      await select_configuration(this.#rid, configurationValue),
+    );
   }
 
   async releaseInterface(
     interfaceNumber: number,
   ) {
-    this.#inner = await release_interface(
-      this.#inner,
-      interfaceNumber,
+    this.#inner.state = unwrap(
+      await release_interface(
+        this.#rid,
+        interfaceNumber,
+      ),
     );
   }
 
   async claimInterface(
     interfaceNumber: number,
   ) {
-    this.#inner = await claim_interface(
-      this.#inner,
-      interfaceNumber,
+    this.#inner.state = unwrap(
+      await claim_interface(
+        this.#rid,
+        interfaceNumber,
+      ),
     );
   }
+
+  // Not part of WebUSB. Streams the data received on IN endpoint
+  // `endpointNumber`. Reading stops when the stream is cancelled or an
+  // error occurs.
+  readableStream(
+    endpointNumber: number,
+    { transferSize = 16384, queueLength = 4 }: USBReadableStreamOptions = {},
+  ): ReadableStream<Uint8Array> {
+    const reader = unwrap(
+      open_reader(
+        this.#rid,
+        endpointNumber,
+        BigInt(transferSize),
+        BigInt(queueLength),
+      ),
+    );
+    const stream = new ReadableStream<Uint8Array>({
+      async pull(controller) {
+        try {
+          // Skips zero length packets.
+          let length = 0;
+          while (length == 0) {
+            const buffer = new Uint8Array(transferSize);
+            length = unwrap(await read_chunk(reader, buffer));
+            if (length > 0) {
+              controller.enqueue(buffer.subarray(0, length));
+            }
+          }
+        } catch (err) {
+          close_reader(reader);
+          throw err;
+        }
+      },
+      cancel() {
+        readers.unregister(stream);
+        close_reader(reader);
+      },
+    }, { highWaterMark: 0 });
+    readers.register(stream, reader, stream);
+    return stream;
+  }
+
+  // Not part of WebUSB. Writes each chunk to OUT endpoint
+  // `endpointNumber`. Aborting the stream cancels the pending transfer.
+  writableStream(endpointNumber: number): WritableStream<BufferSource> {
+    return new WritableStream<BufferSource>({
+      write: async (chunk, controller) => {
+        const { status } = await this.transferOut(endpointNumber, chunk, {
+          signal: controller.signal,
+        });
+        if (status != "ok") {
+          throw new DOMException(`Transfer ${status}`, "NetworkError");
+        }
+      },
+    });
+  }
 }
 
-class Usb {
+export { UsbDevice as USBDevice };
+
+export class USBConnectionEvent extends Event {
+  readonly device: UsbDevice;
+
+  constructor(type: string, { device }: { device: UsbDevice }) {
+    super(type);
+    this.device = device;
+  }
+}
+
+export type USBDeviceRequestOptions = {
+  filters: Partial<UsbDeviceFilter>[];
+};
+
+type Handler = ((this: USB, event: USBConnectionEvent) => unknown) | null;
+
+// https://wicg.github.io/webusb/#usb
+export class USB extends EventTarget {
+  // One UsbDevice per resource ID, as in browsers.
+  #devices = new Map<number, WeakRef<UsbDevice>>();
+  onconnect: Handler = null;
+  ondisconnect: Handler = null;
+
+  constructor() {
+    super();
+    this.#watch();
+  }
+
+  #device(info: DeviceInfo): UsbDevice {
+    const device = this.#devices.get(info.rid)?.deref();
+    if (device !== undefined) {
+      // The existing UsbDevice already holds a reference.
+      release(info.rid);
+      return device;
+    }
+    const created = new UsbDevice(info);
+    this.#devices.set(info.rid, new WeakRef(created));
+    return created;
+  }
+
+  #dispatch(type: "connect" | "disconnect", device: UsbDevice) {
+    const event = new USBConnectionEvent(type, { device });
+    this.dispatchEvent(event);
+    this[`on${type}`]?.call(this, event);
+  }
+
+  // Polls for hotplug events without keeping the process alive.
+  async #watch() {
+    try {
+      const { connected, disconnected } = unwrap(await hotplug());
+      for (const info of connected) {
+        this.#dispatch("connect", this.#device(info));
+      }
+      for (const rid of disconnected) {
+        const device = this.#devices.get(rid)?.deref();
+        this.#devices.delete(rid);
+        if (device === undefined) continue;
+        const inner = inners.get(device)!;
+        inner.state = { ...inner.state, opened: false };
+        this.#dispatch("disconnect", device);
+      }
+    } catch {
+      // Retried on the next poll.
+    }
+    Deno.unrefTimer(setTimeout(() => this.#watch(), HOTPLUG_INTERVAL));
+  }
+
   async getDevices() {
-    const { devices } = await get_devices();
-    return devices.map((dev) => new UsbDevice(dev));
+    const { devices } = unwrap(await get_devices());
+    return devices.map((info) => this.#device(info));
+  }
+
+  // There is no chooser, resolves to the first matching device.
+  async requestDevice(options: USBDeviceRequestOptions) {
+    if (options === undefined || !Array.isArray(options.filters)) {
+      throw new TypeError("requestDevice requires filters");
+    }
+    const filters = options.filters.map((filter) => ({
+      vendorId: filter.vendorId,
+      productId: filter.productId,
+      classCode: filter.classCode,
+      subclassCode: filter.subclassCode,
+      protocolCode: filter.protocolCode,
+      serialNumber: filter.serialNumber,
+    }));
+    return this.#device(unwrap(await request_device({ filters })));
   }
 }
 
-const usb = new Usb();
+const usb = new USB();
 navigator.usb = usb;
 
 export default usb;
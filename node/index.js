@@ -0,0 +1,171 @@
+// `navigator.usb` for Node.js on top of the N-API bindings in src/node.rs.
+//
+//   const { usb } = require("webusb");
+//   const device = await usb.requestDevice({ filters: [{ vendorId: 0x2341 }] });
+//   await device.open();
+//
+// `install()` also sets `navigator.usb`, for code written for browsers.
+// Build the bindings with `npm run build`.
+
+"use strict";
+
+const native = require("./webusb.node");
+
+// Rejections of the bindings carry the name of the DOMException in their
+// message, e.g. "InvalidStateError: InvalidState".
+function domException(err) {
+  const match = /^(\w+): ([\s\S]*)$/.exec(err.message);
+  if (match === null) return err;
+  const [, name, message] = match;
+  return name === "TypeError"
+    ? new TypeError(message)
+    : new DOMException(message, name);
+}
+
+// WebUSB takes any BufferSource, the bindings take Buffers.
+function buffer(data) {
+  if (data instanceof ArrayBuffer) return Buffer.from(data);
+  if (ArrayBuffer.isView(data)) {
+    return Buffer.from(data.buffer, data.byteOffset, data.byteLength);
+  }
+  return data;
+}
+
+for (
+  const name of [
+    "open",
+    "close",
+    "reset",
+    "selectConfiguration",
+    "claimInterface",
+    "releaseInterface",
+    "selectAlternateInterface",
+    "clearHalt",
+    "controlTransferIn",
+    "controlTransferOut",
+    "transferIn",
+    "transferOut",
+  ]
+) {
+  const method = native.USBDevice.prototype[name];
+  native.USBDevice.prototype[name] = async function (...args) {
+    let result;
+    try {
+      result = await method.apply(this, args.map(buffer));
+    } catch (err) {
+      throw domException(err);
+    }
+    if (result !== undefined && result !== null && "data" in result) {
+      const { data } = result;
+      result.data = new DataView(data.buffer, data.byteOffset, data.length);
+    }
+    return result;
+  };
+}
+
+class USBConnectionEvent extends Event {
+  constructor(type, { device }) {
+    super(type);
+    this.device = device;
+  }
+}
+
+function key(device) {
+  return `${device.busNumber}-${device.deviceAddress}`;
+}
+
+class USB extends EventTarget {
+  #native = new native.USB();
+  // One USBDevice per device, as in browsers.
+  #devices = new Map();
+
+  constructor() {
+    super();
+    this.onconnect = null;
+    this.ondisconnect = null;
+    this.#native.watch((event) => this.#hotplug(event));
+  }
+
+  #hotplug({ type, busNumber, deviceAddress, device }) {
+    const id = key({ busNumber, deviceAddress });
+    if (type === "connect") {
+      this.#devices.set(id, device);
+    } else {
+      device = this.#devices.get(id);
+      this.#devices.delete(id);
+      if (device === undefined) return;
+    }
+
+    const event = new USBConnectionEvent(type, { device });
+    this.dispatchEvent(event);
+    const handler = this[`on${type}`];
+    if (typeof handler === "function") handler.call(this, event);
+  }
+
+  #device(device) {
+    const id = key(device);
+    if (!this.#devices.has(id)) this.#devices.set(id, device);
+    return this.#devices.get(id);
+  }
+
+  async getDevices() {
+    let devices;
+    try {
+      devices = await this.#native.getDevices();
+    } catch (err) {
+      throw domException(err);
+    }
+    const present = new Set(devices.map(key));
+    for (const id of this.#devices.keys()) {
+      if (!present.has(id)) this.#devices.delete(id);
+    }
+    return devices.map((device) => this.#device(device));
+  }
+
+  // There is no chooser, resolves to the first matching device.
+  async requestDevice(options) {
+    if (options === undefined || !Array.isArray(options.filters)) {
+      throw new TypeError("requestDevice requires filters");
+    }
+    try {
+      return this.#device(await this.#native.requestDevice(options));
+    } catch (err) {
+      throw domException(err);
+    }
+  }
+}
+
+let usb;
+
+function getUsb() {
+  if (usb === undefined) usb = new USB();
+  return usb;
+}
+
+// Sets `navigator.usb` unless it is already there.
+function install() {
+  if (globalThis.navigator === undefined) {
+    Object.defineProperty(globalThis, "navigator", {
+      value: {},
+      configurable: true,
+      writable: true,
+    });
+  }
+  if (globalThis.navigator.usb === undefined) {
+    Object.defineProperty(globalThis.navigator, "usb", {
+      get: getUsb,
+      configurable: true,
+    });
+  }
+  return globalThis.navigator.usb;
+}
+
+module.exports = {
+  get usb() {
+    return getUsb();
+  },
+  install,
+  USB,
+  USBDevice: native.USBDevice,
+  USBConnectionEvent,
+};
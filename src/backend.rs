@@ -0,0 +1,949 @@
+//! Device backends.
+//!
+//! A `UsbDevice` performs the WebUSB algorithm steps itself (state checks,
+//! endpoint lookup, ...) and hands the actual I/O to a `DeviceBackend`.
+
+#[cfg(feature = "libusb")]
+use core::convert::TryFrom;
+#[cfg(feature = "libusb")]
+use std::os::raw::c_int;
+#[cfg(feature = "libusb")]
+use std::os::raw::c_uint;
+#[cfg(feature = "libusb")]
+use std::os::raw::c_void;
+#[cfg(feature = "libusb")]
+use std::sync::atomic::AtomicI32;
+#[cfg(feature = "libusb")]
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+#[cfg(feature = "libusb")]
+use std::sync::Weak;
+use std::time::Duration;
+#[cfg(feature = "libusb")]
+use std::time::Instant;
+
+#[cfg(feature = "libusb")]
+use libusb1_sys::constants::*;
+#[cfg(feature = "libusb")]
+use libusb1_sys::libusb_alloc_transfer;
+#[cfg(feature = "libusb")]
+use libusb1_sys::libusb_cancel_transfer;
+#[cfg(feature = "libusb")]
+use libusb1_sys::libusb_free_transfer;
+#[cfg(feature = "libusb")]
+use libusb1_sys::libusb_handle_events_completed;
+#[cfg(feature = "libusb")]
+use libusb1_sys::libusb_handle_events_timeout_completed;
+#[cfg(feature = "libusb")]
+use libusb1_sys::libusb_submit_transfer;
+#[cfg(feature = "libusb")]
+use libusb1_sys::libusb_transfer;
+#[cfg(feature = "libusb")]
+use rusb::UsbContext;
+
+use crate::Error;
+use crate::Result;
+use crate::UsbEndpointType;
+
+/// Low level device I/O. Implemented once per way of reaching a device
+/// (libusb, USB/IP, ...).
+///
+/// `request_type` is the raw bmRequestType of the setup packet, and
+/// `endpoint` is the raw bEndpointAddress (including the direction bit).
+pub trait DeviceBackend: Send {
+  fn open(&mut self) -> Result<()>;
+
+  /// Releases claimed interfaces and closes the device.
+  fn close(&mut self) -> Result<()>;
+
+  fn set_configuration(&mut self, configuration_value: u8) -> Result<()>;
+
+  fn claim_interface(&mut self, interface_number: u8) -> Result<()>;
+
+  fn release_interface(&mut self, interface_number: u8) -> Result<()>;
+
+  fn set_alternate_setting(
+    &mut self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Result<()>;
+
+  fn control_in(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buffer: &mut [u8],
+  ) -> Result<usize>;
+
+  fn control_out(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+  ) -> Result<usize>;
+
+  fn clear_halt(&mut self, endpoint: u8) -> Result<()>;
+
+  /// Bulk or interrupt IN transfer.
+  fn transfer_in(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    buffer: &mut [u8],
+  ) -> Result<usize>;
+
+  /// Bulk or interrupt OUT transfer.
+  fn transfer_out(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    data: &[u8],
+  ) -> Result<usize>;
+
+  fn reset(&mut self) -> Result<()>;
+
+  /// Timeout of `transfer_in` and `transfer_out`. Transfers that time out
+  /// fail with `Error::Io(ErrorKind::TimedOut)`. None waits forever.
+  fn set_transfer_timeout(&mut self, timeout: Option<Duration>);
+
+  /// Isochronous transfer of one packet per entry of `packet_lengths`.
+  /// Packets are received into, or sent from, consecutive parts of
+  /// `buffer` of their length. Returns the number of bytes transferred by
+  /// every packet, or the error it failed with. Times out like
+  /// `transfer_in` and `transfer_out`.
+  ///
+  /// The default fails with `Error::Io(ErrorKind::Unsupported)`.
+  fn isochronous_transfer(
+    &mut self,
+    _endpoint: u8,
+    _buffer: &mut [u8],
+    _packet_lengths: &[u32],
+  ) -> Result<Vec<Result<usize>>> {
+    Err(Error::Io(std::io::ErrorKind::Unsupported))
+  }
+
+  /// Starts a bulk or interrupt transfer on `endpoint`: receives up to
+  /// `buffer.len()` bytes on IN endpoints, sends `buffer` on OUT
+  /// endpoints. The transfer times out like `transfer_in` and
+  /// `transfer_out`. Closing the backend cancels it.
+  ///
+  /// The default carries the transfer out before returning.
+  fn submit(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    mut buffer: Vec<u8>,
+  ) -> Result<Arc<dyn Transfer>> {
+    let result = match endpoint & 0x80 {
+      0 => self.transfer_out(r#type, endpoint, &buffer),
+      _ => self.transfer_in(r#type, endpoint, &mut buffer),
+    };
+    let result = result.map(|length| {
+      buffer.truncate(length);
+      buffer
+    });
+    Ok(Arc::new(Completed(Mutex::new(Some(result)))))
+  }
+
+  /// Keeps `count` bulk or interrupt IN transfers of `length` bytes in
+  /// flight on `endpoint`. The queue must be dropped before the device is
+  /// closed.
+  ///
+  /// The default makes one transfer at a time, once its data is asked for.
+  fn queue_in(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    length: usize,
+    _count: usize,
+  ) -> Result<Box<dyn TransferQueue>> {
+    Ok(Box::new(Synchronous {
+      r#type,
+      endpoint,
+      length,
+    }))
+  }
+}
+
+pub type BoxedBackend = Box<dyn DeviceBackend>;
+
+/// IN transfers kept in flight by `DeviceBackend::queue_in`.
+pub trait TransferQueue: Send {
+  /// Waits for the oldest transfer and swaps its data into `buffer`. The
+  /// transfer is submitted again, reusing the old contents of `buffer`.
+  fn next(
+    &mut self,
+    backend: &mut dyn DeviceBackend,
+    buffer: &mut Vec<u8>,
+  ) -> Result<()>;
+}
+
+// `TransferQueue` of backends without asynchronous I/O.
+struct Synchronous {
+  r#type: UsbEndpointType,
+  endpoint: u8,
+  length: usize,
+}
+
+impl TransferQueue for Synchronous {
+  fn next(
+    &mut self,
+    backend: &mut dyn DeviceBackend,
+    buffer: &mut Vec<u8>,
+  ) -> Result<()> {
+    buffer.resize(self.length, 0);
+    let length = backend.transfer_in(self.r#type, self.endpoint, buffer)?;
+    buffer.truncate(length);
+    Ok(())
+  }
+}
+
+/// A transfer started by `DeviceBackend::submit`. It runs without
+/// borrowing the backend, so other calls can be made while it is pending.
+pub trait Transfer: Send + Sync {
+  /// Waits up to `timeout`, or forever if None, for the transfer to
+  /// complete. Returns whether it did.
+  fn wait(&self, timeout: Option<Duration>) -> Result<bool>;
+
+  /// Stops the transfer if it is still pending. It then completes with
+  /// `Error::Io(ErrorKind::Interrupted)`.
+  fn cancel(&self);
+
+  /// Result of the completed transfer: the data received, or the part of
+  /// the buffer that was sent. Fails with `Error::InvalidState` if the
+  /// transfer is still pending or its result was taken already.
+  fn take(&self) -> Result<Vec<u8>>;
+}
+
+// `Transfer` that completed during `DeviceBackend::submit`.
+struct Completed(Mutex<Option<Result<Vec<u8>>>>);
+
+impl Transfer for Completed {
+  fn wait(&self, _: Option<Duration>) -> Result<bool> {
+    Ok(true)
+  }
+
+  fn cancel(&self) {}
+
+  fn take(&self) -> Result<Vec<u8>> {
+    self.0.lock().unwrap().take().ok_or(Error::InvalidState)?
+  }
+}
+
+/// Backend of a `UsbDevice` that is not attached to anything, e.g. one
+/// created through deserialization. Every operation fails.
+pub(crate) struct Detached;
+
+impl Detached {
+  #[allow(dead_code)]
+  pub(crate) fn boxed() -> BoxedBackend {
+    Box::new(Detached)
+  }
+}
+
+impl DeviceBackend for Detached {
+  fn open(&mut self) -> Result<()> {
+    Err(Error::NotFound)
+  }
+
+  fn close(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  fn set_configuration(&mut self, _: u8) -> Result<()> {
+    Err(Error::NotFound)
+  }
+
+  fn claim_interface(&mut self, _: u8) -> Result<()> {
+    Err(Error::NotFound)
+  }
+
+  fn release_interface(&mut self, _: u8) -> Result<()> {
+    Err(Error::NotFound)
+  }
+
+  fn set_alternate_setting(&mut self, _: u8, _: u8) -> Result<()> {
+    Err(Error::NotFound)
+  }
+
+  fn control_in(
+    &mut self,
+    _: u8,
+    _: u8,
+    _: u16,
+    _: u16,
+    _: &mut [u8],
+  ) -> Result<usize> {
+    Err(Error::NotFound)
+  }
+
+  fn control_out(
+    &mut self,
+    _: u8,
+    _: u8,
+    _: u16,
+    _: u16,
+    _: &[u8],
+  ) -> Result<usize> {
+    Err(Error::NotFound)
+  }
+
+  fn clear_halt(&mut self, _: u8) -> Result<()> {
+    Err(Error::NotFound)
+  }
+
+  fn transfer_in(
+    &mut self,
+    _: UsbEndpointType,
+    _: u8,
+    _: &mut [u8],
+  ) -> Result<usize> {
+    Err(Error::NotFound)
+  }
+
+  fn transfer_out(
+    &mut self,
+    _: UsbEndpointType,
+    _: u8,
+    _: &[u8],
+  ) -> Result<usize> {
+    Err(Error::NotFound)
+  }
+
+  fn reset(&mut self) -> Result<()> {
+    Err(Error::NotFound)
+  }
+
+  fn set_transfer_timeout(&mut self, _: Option<Duration>) {}
+}
+
+#[cfg(feature = "libusb")]
+pub(crate) struct Libusb {
+  device: rusb::Device<rusb::Context>,
+  handle: Option<rusb::DeviceHandle<rusb::Context>>,
+  transfer_timeout: Duration,
+  // Transfers started by `submit`, cancelled before the handle is closed.
+  submitted: Vec<Weak<LibusbTransfer>>,
+}
+
+#[cfg(feature = "libusb")]
+impl Libusb {
+  pub(crate) fn new(device: rusb::Device<rusb::Context>) -> Self {
+    Self {
+      device,
+      handle: None,
+      transfer_timeout: TIMEOUT,
+      submitted: vec![],
+    }
+  }
+
+  fn handle(&mut self) -> Result<&mut rusb::DeviceHandle<rusb::Context>> {
+    self.handle.as_mut().ok_or(Error::InvalidState)
+  }
+
+  // libusb must not close a handle with transfers in flight.
+  fn cancel_submitted(&mut self) {
+    let submitted = self
+      .submitted
+      .drain(..)
+      .filter_map(|transfer| transfer.upgrade())
+      .collect::<Vec<_>>();
+    for transfer in &submitted {
+      transfer.cancel();
+    }
+    for transfer in &submitted {
+      let _ = transfer.wait(None);
+    }
+  }
+}
+
+#[cfg(feature = "libusb")]
+impl Drop for Libusb {
+  fn drop(&mut self) {
+    self.cancel_submitted();
+  }
+}
+
+// Zero means no timeout.
+#[cfg(feature = "libusb")]
+const TIMEOUT: Duration = Duration::from_secs(0);
+
+#[cfg(feature = "libusb")]
+fn transfer_error(err: rusb::Error) -> Error {
+  match err {
+    rusb::Error::Timeout => Error::Io(std::io::ErrorKind::TimedOut),
+    rusb::Error::Pipe => Error::Stall,
+    err => Error::Usb(err),
+  }
+}
+
+#[cfg(feature = "libusb")]
+impl DeviceBackend for Libusb {
+  fn open(&mut self) -> Result<()> {
+    self.handle = Some(self.device.open()?);
+    Ok(())
+  }
+
+  fn close(&mut self) -> Result<()> {
+    self.cancel_submitted();
+    // Dropping the handle releases claimed interfaces.
+    self.handle = None;
+    Ok(())
+  }
+
+  fn set_configuration(&mut self, configuration_value: u8) -> Result<()> {
+    // Calls `libusb_set_configuration`
+    Ok(
+      self
+        .handle()?
+        .set_active_configuration(configuration_value)?,
+    )
+  }
+
+  fn claim_interface(&mut self, interface_number: u8) -> Result<()> {
+    Ok(self.handle()?.claim_interface(interface_number)?)
+  }
+
+  fn release_interface(&mut self, interface_number: u8) -> Result<()> {
+    Ok(self.handle()?.release_interface(interface_number)?)
+  }
+
+  fn set_alternate_setting(
+    &mut self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Result<()> {
+    Ok(
+      self
+        .handle()?
+        .set_alternate_setting(interface_number, alternate_setting)?,
+    )
+  }
+
+  fn control_in(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    self
+      .handle()?
+      .read_control(request_type, request, value, index, buffer, TIMEOUT)
+      .map_err(transfer_error)
+  }
+
+  fn control_out(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+  ) -> Result<usize> {
+    self
+      .handle()?
+      .write_control(request_type, request, value, index, data, TIMEOUT)
+      .map_err(transfer_error)
+  }
+
+  fn clear_halt(&mut self, endpoint: u8) -> Result<()> {
+    Ok(self.handle()?.clear_halt(endpoint)?)
+  }
+
+  fn transfer_in(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    let timeout = self.transfer_timeout;
+    let handle = self.handle()?;
+    match r#type {
+      UsbEndpointType::Bulk => handle.read_bulk(endpoint, buffer, timeout),
+      UsbEndpointType::Interrupt => {
+        handle.read_interrupt(endpoint, buffer, timeout)
+      }
+      _ => return Err(Error::InvalidAccess),
+    }
+    .map_err(transfer_error)
+  }
+
+  fn transfer_out(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    data: &[u8],
+  ) -> Result<usize> {
+    let timeout = self.transfer_timeout;
+    let handle = self.handle()?;
+    match r#type {
+      UsbEndpointType::Bulk => handle.write_bulk(endpoint, data, timeout),
+      UsbEndpointType::Interrupt => {
+        handle.write_interrupt(endpoint, data, timeout)
+      }
+      _ => return Err(Error::InvalidAccess),
+    }
+    .map_err(transfer_error)
+  }
+
+  fn reset(&mut self) -> Result<()> {
+    Ok(self.handle()?.reset()?)
+  }
+
+  fn set_transfer_timeout(&mut self, timeout: Option<Duration>) {
+    self.transfer_timeout = timeout.unwrap_or(TIMEOUT);
+  }
+
+  fn submit(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    mut buffer: Vec<u8>,
+  ) -> Result<Arc<dyn Transfer>> {
+    let transfer_type = match r#type {
+      UsbEndpointType::Bulk => LIBUSB_TRANSFER_TYPE_BULK,
+      UsbEndpointType::Interrupt => LIBUSB_TRANSFER_TYPE_INTERRUPT,
+      _ => return Err(Error::InvalidAccess),
+    };
+    let timeout = c_uint::try_from(self.transfer_timeout.as_millis())
+      .unwrap_or(c_uint::MAX);
+    let length = c_int::try_from(buffer.len())
+      .map_err(|_| Error::Usb(rusb::Error::InvalidParam))?;
+    let handle = self.handle()?;
+
+    let raw = unsafe { libusb_alloc_transfer(0) };
+    if raw.is_null() {
+      return Err(Error::Usb(rusb::Error::NoMem));
+    }
+    let transfer = Arc::new(LibusbTransfer {
+      context: handle.context().clone(),
+      transfer: raw,
+      buffer: buffer.as_mut_ptr(),
+      data: Mutex::new(Some(buffer)),
+      completed: AtomicI32::new(0),
+    });
+    let code = unsafe {
+      (*raw).dev_handle = handle.as_raw();
+      (*raw).endpoint = endpoint;
+      (*raw).transfer_type = transfer_type;
+      (*raw).timeout = timeout;
+      (*raw).buffer = transfer.buffer;
+      (*raw).length = length;
+      (*raw).callback = transfer_callback;
+      (*raw).user_data = transfer.completed.as_ptr() as *mut c_void;
+      libusb_submit_transfer(raw)
+    };
+    if code < 0 {
+      // Never submitted, so `drop` does not wait for it.
+      transfer.completed.store(1, Ordering::SeqCst);
+      return Err(libusb_error(code));
+    }
+
+    self.submitted.retain(|weak| weak.strong_count() > 0);
+    self.submitted.push(Arc::downgrade(&transfer));
+    Ok(transfer)
+  }
+
+  fn isochronous_transfer(
+    &mut self,
+    endpoint: u8,
+    buffer: &mut [u8],
+    packet_lengths: &[u32],
+  ) -> Result<Vec<Result<usize>>> {
+    let timeout = c_uint::try_from(self.transfer_timeout.as_millis())
+      .unwrap_or(c_uint::MAX);
+    let num_packets = c_int::try_from(packet_lengths.len())
+      .map_err(|_| Error::Usb(rusb::Error::InvalidParam))?;
+    let length = c_int::try_from(buffer.len())
+      .map_err(|_| Error::Usb(rusb::Error::InvalidParam))?;
+    let handle = self.handle()?;
+    let context = handle.context().clone();
+
+    let transfer = unsafe { libusb_alloc_transfer(num_packets) };
+    if transfer.is_null() {
+      return Err(Error::Usb(rusb::Error::NoMem));
+    }
+    // Owned here rather than borrowed from `buffer`, so it can be leaked
+    // to libusb if the transfer cannot be waited for.
+    let mut data = buffer.to_vec();
+    let completed = Box::new(AtomicI32::new(0));
+    let code = unsafe {
+      (*transfer).dev_handle = handle.as_raw();
+      (*transfer).endpoint = endpoint;
+      (*transfer).transfer_type = LIBUSB_TRANSFER_TYPE_ISOCHRONOUS;
+      (*transfer).timeout = timeout;
+      (*transfer).buffer = data.as_mut_ptr();
+      (*transfer).length = length;
+      (*transfer).num_iso_packets = num_packets;
+      (*transfer).callback = transfer_callback;
+      (*transfer).user_data = completed.as_ptr() as *mut c_void;
+      let packets = (*transfer).iso_packet_desc.as_mut_ptr();
+      for (index, length) in packet_lengths.iter().enumerate() {
+        (*packets.add(index)).length = *length as c_uint;
+      }
+      libusb_submit_transfer(transfer)
+    };
+    if code < 0 {
+      unsafe { libusb_free_transfer(transfer) };
+      return Err(libusb_error(code));
+    }
+
+    while completed.load(Ordering::SeqCst) == 0 {
+      let code = unsafe {
+        libusb_handle_events_completed(context.as_raw(), completed.as_ptr())
+      };
+      if code < 0 && code != LIBUSB_ERROR_INTERRUPTED {
+        // Still owned by libusb.
+        unsafe { libusb_cancel_transfer(transfer) };
+        std::mem::forget(data);
+        Box::leak(completed);
+        return Err(libusb_error(code));
+      }
+    }
+
+    let status = unsafe { (*transfer).status };
+    let packets = (0..packet_lengths.len())
+      .map(|index| {
+        let packet =
+          unsafe { &*(*transfer).iso_packet_desc.as_ptr().add(index) };
+        let length = packet.actual_length as usize;
+        transfer_status(packet.status, length).map(|()| length)
+      })
+      .collect();
+    unsafe { libusb_free_transfer(transfer) };
+    buffer.copy_from_slice(&data);
+    // Packets only carry their own status once the transfer as a whole
+    // completed.
+    transfer_status(status, 0).map(|()| packets)
+  }
+
+  fn queue_in(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    length: usize,
+    count: usize,
+  ) -> Result<Box<dyn TransferQueue>> {
+    let transfer_type = match r#type {
+      UsbEndpointType::Bulk => LIBUSB_TRANSFER_TYPE_BULK,
+      UsbEndpointType::Interrupt => LIBUSB_TRANSFER_TYPE_INTERRUPT,
+      _ => return Err(Error::InvalidAccess),
+    };
+    let timeout = c_uint::try_from(self.transfer_timeout.as_millis())
+      .unwrap_or(c_uint::MAX);
+    if c_int::try_from(length).is_err() {
+      return Err(Error::Usb(rusb::Error::InvalidParam));
+    }
+    let handle = self.handle()?;
+
+    let mut queue = LibusbQueue {
+      context: handle.context().clone(),
+      slots: Vec::with_capacity(count.max(1)),
+      head: 0,
+      length,
+      stopped: false,
+    };
+    for _ in 0..count.max(1) {
+      let transfer = unsafe { libusb_alloc_transfer(0) };
+      if transfer.is_null() {
+        return Err(Error::Usb(rusb::Error::NoMem));
+      }
+      let slot = Slot {
+        transfer,
+        buffer: vec![],
+        completed: Box::new(AtomicI32::new(0)),
+        submitted: false,
+      };
+      unsafe {
+        (*transfer).dev_handle = handle.as_raw();
+        (*transfer).endpoint = endpoint;
+        (*transfer).transfer_type = transfer_type;
+        (*transfer).timeout = timeout;
+        (*transfer).callback = transfer_callback;
+        (*transfer).user_data = slot.completed.as_ptr() as *mut c_void;
+      }
+      queue.slots.push(slot);
+    }
+    for index in 0..queue.slots.len() {
+      queue.submit(index)?;
+    }
+    Ok(Box::new(queue))
+  }
+}
+
+#[cfg(feature = "libusb")]
+struct Slot {
+  transfer: *mut libusb_transfer,
+  buffer: Vec<u8>,
+  // Set by `transfer_callback`, read through `libusb_handle_events_completed`.
+  completed: Box<AtomicI32>,
+  submitted: bool,
+}
+
+/// `TransferQueue` of asynchronous libusb transfers. The slots are
+/// submitted in turn, so libusb completes them in order.
+#[cfg(feature = "libusb")]
+struct LibusbQueue {
+  context: rusb::Context,
+  slots: Vec<Slot>,
+  // Slot of the oldest transfer.
+  head: usize,
+  length: usize,
+  // A transfer failed to submit. The others are let to complete before
+  // submitting again, to keep the data in order.
+  stopped: bool,
+}
+
+// The transfers are only touched through `&mut LibusbQueue`, or by libusb
+// until they complete.
+#[cfg(feature = "libusb")]
+unsafe impl Send for LibusbQueue {}
+
+#[cfg(feature = "libusb")]
+extern "system" fn transfer_callback(transfer: *mut libusb_transfer) {
+  unsafe {
+    let completed = (*transfer).user_data as *const AtomicI32;
+    (*completed).store(1, Ordering::SeqCst);
+  }
+}
+
+#[cfg(feature = "libusb")]
+fn libusb_error(code: c_int) -> Error {
+  if code == LIBUSB_ERROR_PIPE {
+    return Error::Stall;
+  }
+  Error::Usb(match code {
+    LIBUSB_ERROR_IO => rusb::Error::Io,
+    LIBUSB_ERROR_INVALID_PARAM => rusb::Error::InvalidParam,
+    LIBUSB_ERROR_ACCESS => rusb::Error::Access,
+    LIBUSB_ERROR_NO_DEVICE => rusb::Error::NoDevice,
+    LIBUSB_ERROR_NOT_FOUND => rusb::Error::NotFound,
+    LIBUSB_ERROR_BUSY => rusb::Error::Busy,
+    LIBUSB_ERROR_OVERFLOW => rusb::Error::Overflow,
+    LIBUSB_ERROR_NO_MEM => rusb::Error::NoMem,
+    LIBUSB_ERROR_NOT_SUPPORTED => rusb::Error::NotSupported,
+    _ => rusb::Error::Other,
+  })
+}
+
+// Same errors as the synchronous transfers of rusb.
+#[cfg(feature = "libusb")]
+fn transfer_status(status: c_int, length: usize) -> Result<()> {
+  match status {
+    LIBUSB_TRANSFER_COMPLETED => Ok(()),
+    // Like `read_bulk`, keeps the data received before the timeout.
+    LIBUSB_TRANSFER_TIMED_OUT if length > 0 => Ok(()),
+    LIBUSB_TRANSFER_TIMED_OUT => Err(Error::Io(std::io::ErrorKind::TimedOut)),
+    LIBUSB_TRANSFER_STALL => Err(Error::Stall),
+    LIBUSB_TRANSFER_NO_DEVICE => Err(Error::Usb(rusb::Error::NoDevice)),
+    LIBUSB_TRANSFER_OVERFLOW => Err(Error::Usb(rusb::Error::Overflow)),
+    LIBUSB_TRANSFER_CANCELLED => {
+      Err(Error::Io(std::io::ErrorKind::Interrupted))
+    }
+    _ => Err(Error::Usb(rusb::Error::Io)),
+  }
+}
+
+/// `Transfer` of an asynchronous libusb transfer.
+#[cfg(feature = "libusb")]
+struct LibusbTransfer {
+  context: rusb::Context,
+  transfer: *mut libusb_transfer,
+  // Start of the buffer owned by `data`, filled by libusb.
+  buffer: *mut u8,
+  // The buffer, taken once the transfer completed.
+  data: Mutex<Option<Vec<u8>>>,
+  // Set by `transfer_callback`.
+  completed: AtomicI32,
+}
+
+// libusb only touches the transfer until it completes, and `data` is not
+// taken before that.
+#[cfg(feature = "libusb")]
+unsafe impl Send for LibusbTransfer {}
+#[cfg(feature = "libusb")]
+unsafe impl Sync for LibusbTransfer {}
+
+#[cfg(feature = "libusb")]
+impl Transfer for LibusbTransfer {
+  fn wait(&self, timeout: Option<Duration>) -> Result<bool> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    while self.completed.load(Ordering::SeqCst) == 0 {
+      let code = match deadline {
+        Some(deadline) => {
+          let remaining = deadline.saturating_duration_since(Instant::now());
+          if remaining == Duration::from_secs(0) {
+            return Ok(false);
+          }
+          let tv = libc::timeval {
+            tv_sec: remaining.as_secs() as _,
+            tv_usec: remaining.subsec_micros() as _,
+          };
+          unsafe {
+            libusb_handle_events_timeout_completed(
+              self.context.as_raw(),
+              &tv,
+              self.completed.as_ptr(),
+            )
+          }
+        }
+        None => unsafe {
+          libusb_handle_events_completed(
+            self.context.as_raw(),
+            self.completed.as_ptr(),
+          )
+        },
+      };
+      if code < 0 && code != LIBUSB_ERROR_INTERRUPTED {
+        return Err(libusb_error(code));
+      }
+    }
+    Ok(true)
+  }
+
+  fn cancel(&self) {
+    if self.completed.load(Ordering::SeqCst) == 0 {
+      // Fails if the transfer completed in the meantime.
+      unsafe { libusb_cancel_transfer(self.transfer) };
+    }
+  }
+
+  fn take(&self) -> Result<Vec<u8>> {
+    if self.completed.load(Ordering::SeqCst) == 0 {
+      return Err(Error::InvalidState);
+    }
+    let data = self.data.lock().unwrap().take();
+    let mut data = data.ok_or(Error::InvalidState)?;
+    let (status, length) = unsafe {
+      (
+        (*self.transfer).status,
+        (*self.transfer).actual_length as usize,
+      )
+    };
+    data.truncate(length);
+    transfer_status(status, length).map(|()| data)
+  }
+}
+
+#[cfg(feature = "libusb")]
+impl Drop for LibusbTransfer {
+  fn drop(&mut self) {
+    self.cancel();
+    if self.wait(None).is_err() {
+      // Still owned by libusb.
+      if let Some(data) = self.data.lock().unwrap().take() {
+        std::mem::forget(data);
+      }
+      return;
+    }
+    unsafe { libusb_free_transfer(self.transfer) };
+  }
+}
+
+#[cfg(feature = "libusb")]
+impl LibusbQueue {
+  fn submit(&mut self, index: usize) -> Result<()> {
+    let slot = &mut self.slots[index];
+    slot.buffer.resize(self.length, 0);
+    slot.completed.store(0, Ordering::SeqCst);
+    let code = unsafe {
+      (*slot.transfer).buffer = slot.buffer.as_mut_ptr();
+      (*slot.transfer).length = self.length as c_int;
+      libusb_submit_transfer(slot.transfer)
+    };
+    if code < 0 {
+      return Err(libusb_error(code));
+    }
+    slot.submitted = true;
+    Ok(())
+  }
+
+  fn wait(&mut self, index: usize) -> Result<()> {
+    let completed = &self.slots[index].completed;
+    while completed.load(Ordering::SeqCst) == 0 {
+      let code = unsafe {
+        libusb_handle_events_completed(
+          self.context.as_raw(),
+          completed.as_ptr(),
+        )
+      };
+      if code < 0 && code != LIBUSB_ERROR_INTERRUPTED {
+        return Err(libusb_error(code));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature = "libusb")]
+impl TransferQueue for LibusbQueue {
+  fn next(
+    &mut self,
+    _: &mut dyn DeviceBackend,
+    buffer: &mut Vec<u8>,
+  ) -> Result<()> {
+    let head = self.head;
+    if !self.slots[head].submitted {
+      // Every transfer completed after a failed submission. Start over.
+      self.stopped = false;
+      for offset in 0..self.slots.len() {
+        let index = (head + offset) % self.slots.len();
+        if let Err(err) = self.submit(index) {
+          self.stopped = true;
+          if offset == 0 {
+            return Err(err);
+          }
+          break;
+        }
+      }
+    }
+    self.wait(head)?;
+    self.head = (head + 1) % self.slots.len();
+
+    let slot = &mut self.slots[head];
+    slot.submitted = false;
+    let (status, length) = unsafe {
+      (
+        (*slot.transfer).status,
+        (*slot.transfer).actual_length as usize,
+      )
+    };
+    std::mem::swap(buffer, &mut slot.buffer);
+    buffer.truncate(length);
+    if !self.stopped && self.submit(head).is_err() {
+      self.stopped = true;
+    }
+    transfer_status(status, length)
+  }
+}
+
+#[cfg(feature = "libusb")]
+impl Drop for LibusbQueue {
+  fn drop(&mut self) {
+    for slot in &self.slots {
+      if slot.submitted {
+        unsafe { libusb_cancel_transfer(slot.transfer) };
+      }
+    }
+    for index in 0..self.slots.len() {
+      if self.slots[index].submitted && self.wait(index).is_err() {
+        // Still owned by libusb.
+        let slot = &mut self.slots[index];
+        std::mem::forget(std::mem::take(&mut slot.buffer));
+        Box::leak(std::mem::replace(
+          &mut slot.completed,
+          Box::new(AtomicI32::new(0)),
+        ));
+        continue;
+      }
+      unsafe { libusb_free_transfer(self.slots[index].transfer) };
+    }
+  }
+}
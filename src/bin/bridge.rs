@@ -0,0 +1,37 @@
+//! Serves local devices to web pages over WebSocket.
+//!
+//! ```text
+//! webusb-bridge [--listen <address>] [--allow-origin <origin>...] [<busid>...]
+//! ```
+//!
+//! Pages reach the devices through `navigator.usb` as polyfilled by
+//! `src/bridge/shim.js`. Only pages from the allowed origins may connect.
+
+use std::net::TcpListener;
+
+use webusb::bridge::BridgeServer;
+use webusb::bridge::BRIDGE_PORT;
+
+mod common;
+
+const USAGE: &str = "usage: webusb-bridge [--listen <address>] \
+                     [--allow-origin <origin>...] [<busid>...]";
+
+fn main() -> webusb::Result<()> {
+  let args = common::Args::parse(USAGE, &["--listen", "--allow-origin"]);
+  let listen = args
+    .values("--listen")
+    .last()
+    .unwrap_or_else(|| format!("127.0.0.1:{}", BRIDGE_PORT));
+  let origins = args.values("--allow-origin").collect::<Vec<_>>();
+  let devices = args.devices("Serving")?;
+  if origins.is_empty() {
+    println!("No --allow-origin given, web pages cannot connect");
+  }
+
+  let listener = TcpListener::bind(&listen)?;
+  println!("Listening on ws://{}", listen);
+  BridgeServer::new(devices)
+    .with_allowed_origins(origins)
+    .serve(listener)
+}
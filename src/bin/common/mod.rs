@@ -0,0 +1,69 @@
+//! Command line handling shared by the device servers.
+
+use webusb::Context;
+use webusb::UsbDevice;
+
+/// Command line of a server: `--<flag> <value>` options and the bus IDs of
+/// the devices to serve, in any order.
+pub struct Args {
+  options: Vec<(String, String)>,
+  busids: Vec<String>,
+}
+
+impl Args {
+  /// Parses the command line of a server taking the given `flags`. Prints
+  /// `usage` and exits on `--help` or a flag without value.
+  pub fn parse(usage: &str, flags: &[&str]) -> Self {
+    let mut options = vec![];
+    let mut busids = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "-h" | "--help" => {
+          println!("{}", usage);
+          std::process::exit(0);
+        }
+        flag if flags.contains(&flag) => match args.next() {
+          Some(value) => options.push((arg, value)),
+          None => {
+            eprintln!("{}", usage);
+            std::process::exit(2);
+          }
+        },
+        _ => busids.push(arg),
+      }
+    }
+
+    Args { options, busids }
+  }
+
+  /// Values given for `flag`, in order.
+  pub fn values<'a>(
+    &'a self,
+    flag: &'a str,
+  ) -> impl Iterator<Item = String> + 'a {
+    self
+      .options
+      .iter()
+      .filter(move |(name, _)| name == flag)
+      .map(|(_, value)| value.clone())
+  }
+
+  /// Lists the devices selected by the bus IDs, or every device without
+  /// any. Each one is printed as `<verb> <busid> <vid>:<pid> <product>`.
+  pub fn devices(&self, verb: &str) -> webusb::Result<Vec<UsbDevice>> {
+    let devices = Context::init()?.devices_by_port_path(&self.busids)?;
+    for device in &devices {
+      println!(
+        "{} {} {:04x}:{:04x} {}",
+        verb,
+        device.port_path(),
+        device.vendor_id,
+        device.product_id,
+        device.product_name.as_deref().unwrap_or("")
+      );
+    }
+    Ok(devices)
+  }
+}
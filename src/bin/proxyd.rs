@@ -0,0 +1,52 @@
+//! Shares local devices between processes.
+//!
+//! ```text
+//! webusb-proxyd [--listen <address> | --unix <path>] [<busid>...]
+//! ```
+//!
+//! Without bus IDs every device is shared. Clients reach the devices through
+//! `webusb::proxy::client::ProxyHost`.
+
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use webusb::proxy::server::ProxyServer;
+use webusb::proxy::PROXY_PORT;
+
+mod common;
+
+const USAGE: &str =
+  "usage: webusb-proxyd [--listen <address> | --unix <path>] [<busid>...]";
+
+fn main() -> webusb::Result<()> {
+  let args = common::Args::parse(USAGE, &["--listen", "--unix"]);
+  let listen = args
+    .values("--listen")
+    .last()
+    .unwrap_or_else(|| format!("127.0.0.1:{}", PROXY_PORT));
+  let unix = args.values("--unix").last();
+  let devices = args.devices("Sharing")?;
+
+  let server = ProxyServer::new(devices);
+  match unix {
+    #[cfg(unix)]
+    Some(path) => {
+      // Left behind by an earlier run.
+      let _ = std::fs::remove_file(&path);
+      let listener = UnixListener::bind(&path)?;
+      println!("Listening on {}", path);
+      server.serve_unix(listener)
+    }
+    #[cfg(not(unix))]
+    Some(_) => {
+      eprintln!("Unix sockets are not supported on this platform");
+      std::process::exit(2);
+    }
+    None => {
+      let listener = TcpListener::bind(&listen)?;
+      println!("Listening on {}", listen);
+      server.serve(listener)
+    }
+  }
+}
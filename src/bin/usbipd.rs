@@ -0,0 +1,31 @@
+//! Exports local devices over USB/IP.
+//!
+//! ```text
+//! webusb-usbipd [--listen <address>] [<busid>...]
+//! ```
+//!
+//! Without bus IDs every device is exported. Only local clients can connect
+//! unless another address is given, e.g. `--listen 0.0.0.0:3240`. Attach the
+//! devices from a remote Linux host with `usbip attach -r <host> -b <busid>`.
+
+use std::net::TcpListener;
+
+use webusb::usbip::server::UsbIpServer;
+use webusb::usbip::USBIP_PORT;
+
+mod common;
+
+const USAGE: &str = "usage: webusb-usbipd [--listen <address>] [<busid>...]";
+
+fn main() -> webusb::Result<()> {
+  let args = common::Args::parse(USAGE, &["--listen"]);
+  let listen = args
+    .values("--listen")
+    .last()
+    .unwrap_or_else(|| format!("127.0.0.1:{}", USBIP_PORT));
+  let devices = args.devices("Exporting")?;
+
+  let listener = TcpListener::bind(&listen)?;
+  println!("Listening on {}", listen);
+  UsbIpServer::new(devices).serve(listener)
+}
@@ -0,0 +1,872 @@
+//! WebSocket bridge. Serves `UsbDevice`s to web pages with a protocol that
+//! mirrors the WebUSB IDL, so that `navigator.usb` can be polyfilled in
+//! browsers without WebUSB. See `shim.js` next to this file.
+//!
+//! ```no_run
+//! # fn main() -> webusb::Result<()> {
+//! use std::net::TcpListener;
+//! use webusb::bridge::BridgeServer;
+//! use webusb::Context;
+//!
+//! let ctx = Context::init()?;
+//! let server = BridgeServer::new(ctx.devices()?)
+//!   .with_allowed_origins(vec!["https://example.com".to_string()]);
+//! server.serve(TcpListener::bind("127.0.0.1:3245")?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Protocol
+//!
+//! Requests are JSON text messages naming a method of `USB` or `USBDevice`
+//! along with its arguments. Devices are identified by their index in
+//! `getDevices`:
+//!
+//! ```text
+//! {"id": 1, "method": "claimInterface", "device": 0, "interfaceNumber": 0}
+//! ```
+//!
+//! Replies carry the `id` of their request and either a `result` or the
+//! name of the DOMException the WebUSB method would reject with:
+//!
+//! ```text
+//! {"id": 1, "result": {...}}
+//! {"id": 1, "error": {"name": "InvalidStateError", "message": "..."}}
+//! ```
+//!
+//! Methods and their arguments:
+//!
+//! - `getDevices`
+//! - `requestDevice`: `filters`
+//! - `open`, `close`, `reset`: `device`
+//! - `selectConfiguration`: `device`, `configurationValue`
+//! - `claimInterface`, `releaseInterface`: `device`, `interfaceNumber`
+//! - `selectAlternateInterface`: `device`, `interfaceNumber`,
+//!   `alternateSetting`
+//! - `controlTransferIn`: `device`, `setup`, `length`
+//! - `controlTransferOut`: `device`, `setup`
+//! - `clearHalt`: `device`, `direction`, `endpointNumber`
+//! - `transferIn`: `device`, `endpointNumber`, `length`
+//! - `transferOut`: `device`, `endpointNumber`
+//!
+//! `getDevices` replies with the list of devices, `requestDevice` with the
+//! first device matching one of the filters as there is no chooser. The
+//! other methods that change the state of a device reply with the updated
+//! device. Transfers reply with a `status` of `ok`, `stall` or `babble`,
+//! OUT transfers also with `bytesWritten`.
+//!
+//! Transfer data travels in binary messages: a big-endian u32 length, a
+//! JSON request or reply of that many bytes and the data.
+//! `controlTransferOut` and `transferOut` requests with data and
+//! `controlTransferIn` and `transferIn` replies are binary.
+//!
+//! A device can be opened by one connection at a time and is closed when
+//! the connection goes away. Requests are carried out in the order they
+//! were received, except that `transferIn` and `transferOut` only start
+//! their transfer and reply once it completes, without holding up the
+//! requests after them. Up to 64 transfers per connection can be in flight,
+//! more fail with a `NetworkError`. `transferIn` lengths are limited to
+//! 16 MiB.
+
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use tungstenite::handshake::server::ErrorResponse;
+use tungstenite::handshake::server::Request as HandshakeRequest;
+use tungstenite::handshake::server::Response as HandshakeResponse;
+use tungstenite::http::StatusCode;
+use tungstenite::Message;
+
+use crate::backend::Transfer;
+use crate::transfer_status;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbDeviceFilter;
+
+/// Default port of `webusb-bridge`.
+pub const BRIDGE_PORT: u16 = 3245;
+
+// Transfers of a connection waited for at the same time, each on its own
+// thread.
+const MAX_TRANSFERS: usize = 64;
+
+// Longest `transferIn`, as the buffer is allocated up front.
+const MAX_TRANSFER_LENGTH: usize = 16 * 1024 * 1024;
+
+// How often replies are sent while waiting for the next request.
+const REPLY_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Serves devices to web pages over WebSocket.
+#[derive(Clone)]
+pub struct BridgeServer {
+  devices: Arc<Vec<Mutex<Slot>>>,
+  allowed_origins: Arc<Vec<String>>,
+  next_client: Arc<AtomicU32>,
+}
+
+struct Slot {
+  device: UsbDevice,
+  // Connection that opened the device.
+  owner: Option<u32>,
+}
+
+struct Request {
+  id: Value,
+  call: Call,
+  data: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(
+  tag = "method",
+  rename_all = "camelCase",
+  rename_all_fields = "camelCase"
+)]
+enum Call {
+  GetDevices,
+  RequestDevice {
+    #[serde(default)]
+    filters: Vec<UsbDeviceFilter>,
+  },
+  Open {
+    device: usize,
+  },
+  Close {
+    device: usize,
+  },
+  SelectConfiguration {
+    device: usize,
+    configuration_value: u8,
+  },
+  ClaimInterface {
+    device: usize,
+    interface_number: u8,
+  },
+  ReleaseInterface {
+    device: usize,
+    interface_number: u8,
+  },
+  SelectAlternateInterface {
+    device: usize,
+    interface_number: u8,
+    alternate_setting: u8,
+  },
+  ControlTransferIn {
+    device: usize,
+    setup: UsbControlTransferParameters,
+    length: u16,
+  },
+  ControlTransferOut {
+    device: usize,
+    setup: UsbControlTransferParameters,
+  },
+  ClearHalt {
+    device: usize,
+    direction: Direction,
+    endpoint_number: u8,
+  },
+  TransferIn {
+    device: usize,
+    endpoint_number: u8,
+    length: usize,
+  },
+  TransferOut {
+    device: usize,
+    endpoint_number: u8,
+  },
+  Reset {
+    device: usize,
+  },
+}
+
+impl BridgeServer {
+  /// Devices are identified by their index in `devices`.
+  pub fn new(devices: Vec<UsbDevice>) -> Self {
+    let devices = devices
+      .into_iter()
+      .map(|device| {
+        Mutex::new(Slot {
+          device,
+          owner: None,
+        })
+      })
+      .collect();
+    BridgeServer {
+      devices: Arc::new(devices),
+      allowed_origins: Arc::new(vec![]),
+      next_client: Arc::new(AtomicU32::new(0)),
+    }
+  }
+
+  /// Origins of the pages allowed to connect, e.g. `https://example.com`.
+  /// `*` allows every origin.
+  ///
+  /// Browsers send the origin of the page with every WebSocket handshake.
+  /// Connections without one come from other programs and are always
+  /// accepted.
+  pub fn with_allowed_origins(mut self, origins: Vec<String>) -> Self {
+    self.allowed_origins = Arc::new(origins);
+    self
+  }
+
+  /// Accepts connections on `listener` until it fails.
+  pub fn serve(&self, listener: TcpListener) -> Result<()> {
+    for stream in listener.incoming() {
+      let stream = stream?;
+      stream.set_nodelay(true)?;
+      let server = self.clone();
+      std::thread::spawn(move || server.handle_connection(stream));
+    }
+    Ok(())
+  }
+
+  fn origin_allowed(&self, origin: Option<&str>) -> bool {
+    match origin {
+      Some(origin) => self
+        .allowed_origins
+        .iter()
+        .any(|allowed| allowed == "*" || allowed == origin),
+      None => true,
+    }
+  }
+
+  fn handle_connection(&self, stream: TcpStream) {
+    // The error response is dictated by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let check_origin = |request: &HandshakeRequest,
+                        response: HandshakeResponse| {
+      let origin = request
+        .headers()
+        .get("Origin")
+        .map(|origin| origin.to_str().unwrap_or_default());
+      if self.origin_allowed(origin) {
+        return Ok(response);
+      }
+      let mut response =
+        ErrorResponse::new(Some("Origin not allowed".to_string()));
+      *response.status_mut() = StatusCode::FORBIDDEN;
+      Err(response)
+    };
+    let mut socket = match tungstenite::accept_hdr(stream, check_origin) {
+      Ok(socket) => socket,
+      Err(_) => return,
+    };
+    // Replies are sent in between reads.
+    if socket
+      .get_ref()
+      .set_read_timeout(Some(REPLY_INTERVAL))
+      .is_err()
+    {
+      return;
+    }
+
+    let client = self.next_client.fetch_add(1, Ordering::Relaxed);
+    let (reply_sender, replies) = mpsc::channel();
+    let (request_sender, requests) = mpsc::channel::<Request>();
+    let worker = {
+      let server = self.clone();
+      let replies = reply_sender.clone();
+      let in_flight = Arc::new(AtomicUsize::new(0));
+      std::thread::spawn(move || {
+        for request in requests {
+          match request.call {
+            Call::TransferIn { .. } | Call::TransferOut { .. } => {
+              server.start_transfer(client, request, &replies, &in_flight)
+            }
+            _ => {
+              let _ = replies.send(server.handle_request(client, request));
+            }
+          }
+        }
+      })
+    };
+
+    'connection: loop {
+      let request = match socket.read() {
+        Ok(Message::Text(text)) => {
+          Some(Request::parse(text.as_bytes(), vec![]))
+        }
+        Ok(Message::Binary(bytes)) => Some(Request::parse_binary(&bytes)),
+        Ok(_) => None,
+        Err(tungstenite::Error::Io(err))
+          if matches!(
+            err.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+          ) =>
+        {
+          None
+        }
+        Err(_) => break,
+      };
+
+      match request {
+        Some(Ok(request)) => {
+          let _ = request_sender.send(request);
+        }
+        Some(Err(reply)) => {
+          let _ = reply_sender.send(reply);
+        }
+        None => {}
+      }
+
+      for reply in replies.try_iter() {
+        if socket.send(reply).is_err() {
+          break 'connection;
+        }
+      }
+    }
+
+    // Let the worker finish so that it does not open devices behind our
+    // back, then give up whatever the client left behind. Transfers still
+    // in flight fail once the device is closed.
+    drop(request_sender);
+    let _ = worker.join();
+    for slot in self.devices.iter() {
+      let mut slot = slot.lock().unwrap();
+      if slot.owner == Some(client) {
+        slot.owner = None;
+        let _ = slot.device.close();
+      }
+    }
+  }
+
+  fn handle_request(&self, client: u32, request: Request) -> Message {
+    let Request { id, call, data } = request;
+    result_reply(&id, self.call(client, call, data))
+  }
+
+  // Submits the transfer of `request` in order with the other requests, then
+  // waits for it on its own thread.
+  fn start_transfer(
+    &self,
+    client: u32,
+    request: Request,
+    replies: &mpsc::Sender<Message>,
+    in_flight: &Arc<AtomicUsize>,
+  ) {
+    let Request { id, call, data } = request;
+    if in_flight.load(Ordering::SeqCst) >= MAX_TRANSFERS {
+      let message = "Too many transfers in flight";
+      let _ = replies.send(error_reply(&id, "NetworkError", message));
+      return;
+    }
+    let (direction, transfer) = match self.submit(client, call, data) {
+      Ok(submitted) => submitted,
+      Err(err) => {
+        let _ = replies.send(result_reply(&id, Err(err)));
+        return;
+      }
+    };
+
+    in_flight.fetch_add(1, Ordering::SeqCst);
+    let in_flight = in_flight.clone();
+    let replies = replies.clone();
+    std::thread::spawn(move || {
+      let result = transfer.wait(None).and_then(|_| transfer.take());
+      in_flight.fetch_sub(1, Ordering::SeqCst);
+      let result = match direction {
+        Direction::In => transfer_status(result)
+          .map(|(status, data)| (json!({ "status": status }), Some(data))),
+        Direction::Out => transfer_status(result.map(|data| data.len())).map(
+          |(status, length)| {
+            (json!({ "status": status, "bytesWritten": length }), None)
+          },
+        ),
+      };
+      let _ = replies.send(result_reply(&id, result));
+    });
+  }
+
+  fn submit(
+    &self,
+    client: u32,
+    call: Call,
+    data: Vec<u8>,
+  ) -> Result<(Direction, Arc<dyn Transfer>)> {
+    let (id, direction, endpoint_number, buffer) = match call {
+      Call::TransferIn {
+        device,
+        endpoint_number,
+        length,
+      } => {
+        if length > MAX_TRANSFER_LENGTH {
+          return Err(Error::Io(ErrorKind::InvalidInput));
+        }
+        (device, Direction::In, endpoint_number, vec![0; length])
+      }
+      Call::TransferOut {
+        device,
+        endpoint_number,
+      } => (device, Direction::Out, endpoint_number, data),
+      _ => unreachable!(),
+    };
+
+    let mut slot = self.devices.get(id).ok_or(Error::NotFound)?.lock().unwrap();
+    if slot.owner != Some(client) {
+      return Err(Error::InvalidState);
+    }
+    // WebUSB transfers do not time out.
+    slot.device.set_transfer_timeout(None);
+    let transfer =
+      slot
+        .device
+        .submit_transfer(direction, endpoint_number, buffer)?;
+    Ok((direction, transfer))
+  }
+
+  // Result of `call` and the data of IN transfers.
+  fn call(
+    &self,
+    client: u32,
+    call: Call,
+    data: Vec<u8>,
+  ) -> Result<(Value, Option<Vec<u8>>)> {
+    let id = match call {
+      Call::GetDevices => {
+        let devices = self
+          .devices
+          .iter()
+          .enumerate()
+          .map(|(id, slot)| device_json(id, &slot.lock().unwrap().device))
+          .collect();
+        return Ok((Value::Array(devices), None));
+      }
+      Call::RequestDevice { filters } => {
+        let device = self
+          .devices
+          .iter()
+          .enumerate()
+          .find_map(|(id, slot)| {
+            let slot = slot.lock().unwrap();
+            let matches = filters.is_empty()
+              || filters.iter().any(|filter| filter.matches(&slot.device));
+            matches.then(|| device_json(id, &slot.device))
+          })
+          .ok_or(Error::NotFound)?;
+        return Ok((device, None));
+      }
+      Call::Open { device }
+      | Call::Close { device }
+      | Call::SelectConfiguration { device, .. }
+      | Call::ClaimInterface { device, .. }
+      | Call::ReleaseInterface { device, .. }
+      | Call::SelectAlternateInterface { device, .. }
+      | Call::ControlTransferIn { device, .. }
+      | Call::ControlTransferOut { device, .. }
+      | Call::ClearHalt { device, .. }
+      | Call::TransferIn { device, .. }
+      | Call::TransferOut { device, .. }
+      | Call::Reset { device } => device,
+    };
+    let mut slot = self.devices.get(id).ok_or(Error::NotFound)?.lock().unwrap();
+    match (&call, slot.owner) {
+      (Call::Open { .. }, None) => {
+        slot.owner = Some(client);
+        if let Err(err) = slot.device.open() {
+          slot.owner = None;
+          return Err(err);
+        }
+        return Ok((device_json(id, &slot.device), None));
+      }
+      (_, Some(owner)) if owner == client => {}
+      // Opened by another connection, or not at all.
+      _ => return Err(Error::InvalidState),
+    }
+
+    let device = &mut slot.device;
+    match call {
+      Call::Open { .. } => device.open()?,
+      Call::Close { .. } => {
+        device.close()?;
+        slot.owner = None;
+      }
+      Call::SelectConfiguration {
+        configuration_value,
+        ..
+      } => device.select_configuration(configuration_value)?,
+      Call::ClaimInterface {
+        interface_number, ..
+      } => device.claim_interface(interface_number)?,
+      Call::ReleaseInterface {
+        interface_number, ..
+      } => device.release_interface(interface_number)?,
+      Call::SelectAlternateInterface {
+        interface_number,
+        alternate_setting,
+        ..
+      } => device
+        .select_alternate_interface(interface_number, alternate_setting)?,
+      Call::ControlTransferIn { setup, length, .. } => {
+        let (status, data) =
+          transfer_status(device.control_transfer_in(setup, length as usize))?;
+        return Ok((json!({ "status": status }), Some(data)));
+      }
+      Call::ControlTransferOut { setup, .. } => {
+        let (status, length) =
+          transfer_status(device.control_transfer_out(setup, &data))?;
+        let result = json!({ "status": status, "bytesWritten": length });
+        return Ok((result, None));
+      }
+      Call::ClearHalt {
+        direction,
+        endpoint_number,
+        ..
+      } => device.clear_halt(direction, endpoint_number)?,
+      Call::Reset { .. } => device.reset()?,
+      Call::GetDevices
+      | Call::RequestDevice { .. }
+      | Call::TransferIn { .. }
+      | Call::TransferOut { .. } => unreachable!(),
+    }
+    Ok((device_json(id, &slot.device), None))
+  }
+}
+
+impl Request {
+  // Failures are turned into the reply right away.
+  fn parse(header: &[u8], data: Vec<u8>) -> std::result::Result<Self, Message> {
+    let header: Value = serde_json::from_slice(header).map_err(|err| {
+      error_reply(&Value::Null, "TypeError", &err.to_string())
+    })?;
+    let id = header.get("id").cloned().unwrap_or(Value::Null);
+    match Call::deserialize(&header) {
+      Ok(call) => Ok(Request { id, call, data }),
+      Err(err) => Err(error_reply(&id, "TypeError", &err.to_string())),
+    }
+  }
+
+  fn parse_binary(message: &[u8]) -> std::result::Result<Self, Message> {
+    let truncated =
+      || error_reply(&Value::Null, "TypeError", "Truncated binary message");
+    if message.len() < 4 {
+      return Err(truncated());
+    }
+    let length =
+      u32::from_be_bytes([message[0], message[1], message[2], message[3]]);
+    let end = 4 + length as usize;
+    let header = message.get(4..end).ok_or_else(truncated)?;
+    Request::parse(header, message[end..].to_vec())
+  }
+}
+
+fn device_json(id: usize, device: &UsbDevice) -> Value {
+  let mut value = serde_json::to_value(device).unwrap();
+  value["id"] = json!(id);
+  value
+}
+
+fn result_reply(
+  id: &Value,
+  result: Result<(Value, Option<Vec<u8>>)>,
+) -> Message {
+  match result {
+    Ok((result, None)) => reply(id, result),
+    Ok((result, Some(data))) => binary_reply(id, result, &data),
+    Err(err) => {
+      error_reply(id, err.dom_exception_name(), &format!("{:?}", err))
+    }
+  }
+}
+
+fn reply(id: &Value, result: Value) -> Message {
+  Message::Text(json!({ "id": id, "result": result }).to_string())
+}
+
+fn binary_reply(id: &Value, result: Value, data: &[u8]) -> Message {
+  let header = json!({ "id": id, "result": result }).to_string();
+  let mut message = (header.len() as u32).to_be_bytes().to_vec();
+  message.extend_from_slice(header.as_bytes());
+  message.extend_from_slice(data);
+  Message::Binary(message)
+}
+
+fn error_reply(id: &Value, name: &str, message: &str) -> Message {
+  let error = json!({ "name": name, "message": message });
+  Message::Text(json!({ "id": id, "error": error }).to_string())
+}
+
+// The loopback device is read through `enumeration`.
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod tests {
+  use std::net::TcpListener;
+  use std::net::TcpStream;
+
+  use serde_json::json;
+  use serde_json::Value;
+  use tungstenite::client::IntoClientRequest;
+  use tungstenite::Message;
+  use tungstenite::WebSocket;
+
+  use crate::bridge::BridgeServer;
+  use crate::loopback::Loopback;
+
+  type Socket = WebSocket<TcpStream>;
+
+  fn serve_loopback() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let server = BridgeServer::new(vec![Loopback::device()])
+      .with_allowed_origins(vec!["https://example.com".to_string()]);
+    std::thread::spawn(move || server.serve(listener));
+    address
+  }
+
+  // The error is tungstenite's.
+  #[allow(clippy::result_large_err)]
+  fn connect(
+    address: &str,
+    origin: Option<&str>,
+  ) -> tungstenite::Result<Socket> {
+    let mut request =
+      format!("ws://{}", address).into_client_request().unwrap();
+    if let Some(origin) = origin {
+      request
+        .headers_mut()
+        .insert("Origin", origin.parse().unwrap());
+    }
+    let stream = TcpStream::connect(address).unwrap();
+    tungstenite::client(request, stream)
+      .map(|(socket, _)| socket)
+      .map_err(|err| match err {
+        tungstenite::HandshakeError::Failure(err) => err,
+        tungstenite::HandshakeError::Interrupted(_) => unreachable!(),
+      })
+  }
+
+  // Reply to the request and the data of binary replies.
+  fn read_reply(socket: &mut Socket) -> (Value, Vec<u8>) {
+    loop {
+      match socket.read().unwrap() {
+        Message::Text(text) => {
+          return (serde_json::from_str(&text).unwrap(), vec![])
+        }
+        Message::Binary(bytes) => {
+          let length =
+            u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+              as usize;
+          let header = serde_json::from_slice(&bytes[4..4 + length]).unwrap();
+          return (header, bytes[4 + length..].to_vec());
+        }
+        _ => {}
+      }
+    }
+  }
+
+  fn call(socket: &mut Socket, request: Value) -> (Value, Vec<u8>) {
+    socket.send(Message::Text(request.to_string())).unwrap();
+    read_reply(socket)
+  }
+
+  fn transfer_out(socket: &mut Socket, id: u32, data: &[u8]) {
+    let header = json!({
+      "id": id,
+      "method": "transferOut",
+      "device": 0,
+      "endpointNumber": 2,
+    })
+    .to_string();
+    let mut message = (header.len() as u32).to_be_bytes().to_vec();
+    message.extend_from_slice(header.as_bytes());
+    message.extend_from_slice(data);
+    socket.send(Message::Binary(message)).unwrap();
+  }
+
+  fn open(socket: &mut Socket) {
+    for (id, request) in [
+      json!({ "method": "open" }),
+      json!({ "method": "selectConfiguration", "configurationValue": 1 }),
+      json!({ "method": "claimInterface", "interfaceNumber": 0 }),
+    ]
+    .iter()
+    .enumerate()
+    {
+      let mut request = request.clone();
+      request["id"] = json!(id);
+      request["device"] = json!(0);
+      let (reply, _) = call(socket, request);
+      assert_eq!(reply["id"], json!(id));
+      assert!(reply["result"]["opened"].as_bool().unwrap());
+    }
+  }
+
+  #[test]
+  fn test_origins() {
+    let address = serve_loopback();
+    assert!(connect(&address, None).is_ok());
+    assert!(connect(&address, Some("https://example.com")).is_ok());
+    match connect(&address, Some("https://evil.example")) {
+      Err(tungstenite::Error::Http(response)) => {
+        assert_eq!(response.status(), 403)
+      }
+      _ => panic!("connection from a foreign origin was accepted"),
+    }
+  }
+
+  #[test]
+  fn test_devices() {
+    let address = serve_loopback();
+    let mut socket = connect(&address, None).unwrap();
+
+    let (reply, _) =
+      call(&mut socket, json!({ "id": 1, "method": "getDevices" }));
+    let devices = reply["result"].as_array().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0]["id"], json!(0));
+    assert_eq!(devices[0]["vendorId"], json!(0x1234));
+    assert_eq!(devices[0]["productName"], json!("Loopback"));
+    let endpoint = &devices[0]["configuration"]["interfaces"][0]["alternate"]
+      ["endpoints"][0];
+    assert_eq!(endpoint["direction"], json!("in"));
+    assert_eq!(endpoint["type"], json!("bulk"));
+
+    let (reply, _) = call(
+      &mut socket,
+      json!({
+        "id": 2,
+        "method": "requestDevice",
+        "filters": [{ "classCode": 0xFF }],
+      }),
+    );
+    assert_eq!(reply["result"]["productId"], json!(0x5678));
+
+    let (reply, _) = call(
+      &mut socket,
+      json!({
+        "id": 3,
+        "method": "requestDevice",
+        "filters": [{ "vendorId": 0x1234, "productId": 0x0001 }],
+      }),
+    );
+    assert_eq!(reply["error"]["name"], json!("NotFoundError"));
+
+    let (reply, _) = call(&mut socket, json!({ "id": 4, "method": "eject" }));
+    assert_eq!(reply["id"], json!(4));
+    assert_eq!(reply["error"]["name"], json!("TypeError"));
+  }
+
+  #[test]
+  fn test_transfers() {
+    let address = serve_loopback();
+    let mut socket = connect(&address, None).unwrap();
+    open(&mut socket);
+
+    // Sent before any data is there, answered after the OUT transfer.
+    let (reply, _) = call(
+      &mut socket,
+      json!({
+        "id": 10,
+        "method": "transferIn",
+        "device": 0,
+        "endpointNumber": 3,
+        "length": 64,
+      }),
+    );
+    assert_eq!(reply["result"]["status"], json!("stall"));
+
+    socket
+      .send(Message::Text(
+        json!({
+          "id": 11,
+          "method": "transferIn",
+          "device": 0,
+          "endpointNumber": 1,
+          "length": 64,
+        })
+        .to_string(),
+      ))
+      .unwrap();
+    transfer_out(&mut socket, 12, b"hello");
+
+    // Both complete together, in either order.
+    let mut replies = [read_reply(&mut socket), read_reply(&mut socket)];
+    replies.sort_by_key(|(reply, _)| reply["id"].as_u64());
+    let (reply, data) = &replies[0];
+    assert_eq!(reply["id"], json!(11));
+    assert_eq!(reply["result"]["status"], json!("ok"));
+    assert_eq!(data, b"hello");
+    let (reply, _) = &replies[1];
+    assert_eq!(reply["result"]["status"], json!("ok"));
+    assert_eq!(reply["result"]["bytesWritten"], json!(5));
+
+    let (reply, _) = call(
+      &mut socket,
+      json!({
+        "id": 14,
+        "method": "transferIn",
+        "device": 0,
+        "endpointNumber": 1,
+        "length": 1 << 30,
+      }),
+    );
+    assert_eq!(reply["error"]["name"], json!("TypeError"));
+
+    let (reply, data) = call(
+      &mut socket,
+      json!({
+        "id": 13,
+        "method": "controlTransferIn",
+        "device": 0,
+        "setup": {
+          "requestType": "standard",
+          "recipient": "device",
+          "request": 0x08,
+          "value": 0,
+          "index": 0,
+        },
+        "length": 1,
+      }),
+    );
+    assert_eq!(reply["result"]["status"], json!("ok"));
+    assert_eq!(data, vec![1]);
+  }
+
+  #[test]
+  fn test_ownership() {
+    let address = serve_loopback();
+    let mut first = connect(&address, None).unwrap();
+    let mut second = connect(&address, None).unwrap();
+    open(&mut first);
+
+    let (reply, _) = call(
+      &mut second,
+      json!({ "id": 1, "method": "open", "device": 0 }),
+    );
+    assert_eq!(reply["error"]["name"], json!("InvalidStateError"));
+    let (reply, _) = call(
+      &mut second,
+      json!({ "id": 2, "method": "reset", "device": 0 }),
+    );
+    assert_eq!(reply["error"]["name"], json!("InvalidStateError"));
+
+    // The device is closed when its owner goes away.
+    drop(first);
+    let mut opened = false;
+    for _ in 0..50 {
+      let (reply, _) = call(
+        &mut second,
+        json!({ "id": 3, "method": "open", "device": 0 }),
+      );
+      if reply.get("result").is_some() {
+        opened = true;
+        break;
+      }
+      std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(opened);
+  }
+}
@@ -0,0 +1,225 @@
+// Polyfills `navigator.usb` on top of `webusb-bridge` for browsers without
+// WebUSB. See src/bridge/mod.rs for the protocol.
+//
+//   <script src="shim.js"></script>
+//   <script>
+//     installWebUsbBridge("ws://127.0.0.1:3245");
+//     const device = await navigator.usb.requestDevice({ filters: [] });
+//   </script>
+//
+// `navigator.usb` is left alone where the browser implements it.
+
+(function (global) {
+  const encoder = new TextEncoder();
+  const decoder = new TextDecoder();
+
+  function bytes(data) {
+    if (ArrayBuffer.isView(data)) {
+      return new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+    }
+    return new Uint8Array(data);
+  }
+
+  function error({ name, message }) {
+    return name === "TypeError"
+      ? new TypeError(message)
+      : new DOMException(message, name);
+  }
+
+  class Connection {
+    constructor(url) {
+      this.nextId = 0;
+      this.pending = new Map();
+      this.socket = new WebSocket(url);
+      this.socket.binaryType = "arraybuffer";
+      this.ready = new Promise((resolve, reject) => {
+        this.socket.onopen = resolve;
+        this.socket.onerror = () =>
+          reject(new DOMException("Bridge unreachable", "NetworkError"));
+      });
+      this.socket.onmessage = (event) => this.receive(event.data);
+      this.socket.onclose = () => {
+        for (const { reject } of this.pending.values()) {
+          reject(new DOMException("Bridge disconnected", "NetworkError"));
+        }
+        this.pending.clear();
+      };
+    }
+
+    // Resolves to the result of the request and the data of binary replies.
+    async call(method, args = {}, data = null) {
+      await this.ready;
+      const id = this.nextId++;
+      const header = JSON.stringify({ id, method, ...args });
+      if (data === null) {
+        this.socket.send(header);
+      } else {
+        const json = encoder.encode(header);
+        const payload = bytes(data);
+        const message = new Uint8Array(4 + json.length + payload.length);
+        new DataView(message.buffer).setUint32(0, json.length);
+        message.set(json, 4);
+        message.set(payload, 4 + json.length);
+        this.socket.send(message);
+      }
+      return new Promise((resolve, reject) => {
+        this.pending.set(id, { resolve, reject });
+      });
+    }
+
+    receive(message) {
+      let reply;
+      let data = null;
+      if (typeof message === "string") {
+        reply = JSON.parse(message);
+      } else {
+        const length = new DataView(message).getUint32(0);
+        reply = JSON.parse(decoder.decode(new Uint8Array(message, 4, length)));
+        data = new DataView(message, 4 + length);
+      }
+
+      const request = this.pending.get(reply.id);
+      if (request === undefined) return;
+      this.pending.delete(reply.id);
+      if (reply.error) {
+        request.reject(error(reply.error));
+      } else {
+        request.resolve({ result: reply.result, data });
+      }
+    }
+  }
+
+  class USBDevice {
+    constructor(connection, state) {
+      this.connection = connection;
+      this.update(state);
+    }
+
+    update(state) {
+      Object.assign(this, state);
+    }
+
+    call(method, args = {}, data = null) {
+      return this.connection.call(method, { device: this.id, ...args }, data);
+    }
+
+    async change(method, args = {}) {
+      const { result } = await this.call(method, args);
+      this.update(result);
+    }
+
+    open() {
+      return this.change("open");
+    }
+
+    close() {
+      return this.change("close");
+    }
+
+    reset() {
+      return this.change("reset");
+    }
+
+    selectConfiguration(configurationValue) {
+      return this.change("selectConfiguration", { configurationValue });
+    }
+
+    claimInterface(interfaceNumber) {
+      return this.change("claimInterface", { interfaceNumber });
+    }
+
+    releaseInterface(interfaceNumber) {
+      return this.change("releaseInterface", { interfaceNumber });
+    }
+
+    selectAlternateInterface(interfaceNumber, alternateSetting) {
+      return this.change("selectAlternateInterface", {
+        interfaceNumber,
+        alternateSetting,
+      });
+    }
+
+    clearHalt(direction, endpointNumber) {
+      return this.change("clearHalt", { direction, endpointNumber });
+    }
+
+    async controlTransferIn(setup, length) {
+      const { result, data } = await this.call("controlTransferIn", {
+        setup,
+        length,
+      });
+      return { status: result.status, data };
+    }
+
+    async controlTransferOut(setup, data) {
+      const { result } = await this.call(
+        "controlTransferOut",
+        { setup },
+        data === undefined ? null : data,
+      );
+      return result;
+    }
+
+    async transferIn(endpointNumber, length) {
+      const { result, data } = await this.call("transferIn", {
+        endpointNumber,
+        length,
+      });
+      return { status: result.status, data };
+    }
+
+    async transferOut(endpointNumber, data) {
+      const { result } = await this.call(
+        "transferOut",
+        { endpointNumber },
+        data,
+      );
+      return result;
+    }
+  }
+
+  class USB extends EventTarget {
+    constructor(url) {
+      super();
+      this.connection = new Connection(url);
+      // One USBDevice per device, as in browsers.
+      this.devices = new Map();
+    }
+
+    device(state) {
+      let device = this.devices.get(state.id);
+      if (device === undefined) {
+        device = new USBDevice(this.connection, state);
+        this.devices.set(state.id, device);
+      } else {
+        device.update(state);
+      }
+      return device;
+    }
+
+    async getDevices() {
+      const { result } = await this.connection.call("getDevices");
+      return result.map((state) => this.device(state));
+    }
+
+    async requestDevice({ filters } = {}) {
+      if (!Array.isArray(filters)) {
+        throw new TypeError("filters is required");
+      }
+      const { result } = await this.connection.call("requestDevice", {
+        filters,
+      });
+      return this.device(result);
+    }
+  }
+
+  global.installWebUsbBridge = function (url = "ws://127.0.0.1:3245") {
+    if (global.navigator.usb === undefined) {
+      Object.defineProperty(global.navigator, "usb", {
+        value: new USB(url),
+        configurable: true,
+      });
+    }
+    return global.navigator.usb;
+  };
+})(globalThis);
@@ -0,0 +1,816 @@
+//! C API of the cdylib, built with the `capi` feature. `include/webusb.h`
+//! is generated from this module with `cbindgen --output include/webusb.h`.
+//!
+//! ```c
+//! WebusbContext *ctx;
+//! WebusbDevice **devices;
+//! size_t len;
+//! webusb_context_new(&ctx);
+//! webusb_context_devices(ctx, &devices, &len);
+//!
+//! WebusbDevice *device = devices[0];
+//! devices[0] = NULL;
+//! webusb_device_list_free(devices, len);
+//!
+//! uint8_t data[64];
+//! size_t transferred;
+//! webusb_device_open(device);
+//! webusb_device_select_configuration(device, 1);
+//! webusb_device_claim_interface(device, 0);
+//! webusb_transfer_in(device, 1, data, sizeof(data), &transferred);
+//!
+//! webusb_device_free(device);
+//! webusb_context_free(ctx);
+//! ```
+//!
+//! Every function that can fail returns a `WebusbStatus`. Handles are
+//! opaque and owned by the caller, who frees them with the matching
+//! `_free` function. Buffers are always owned by the caller.
+//!
+//! Devices may be used from several threads. Calls on the same device are
+//! carried out one at a time, so a pending `webusb_transfer_in` holds up
+//! the other calls on its device until it completes or times out (see
+//! `webusb_device_set_transfer_timeout`).
+
+use std::os::raw::c_char;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::Context;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbRecipient;
+use crate::UsbRequestType;
+
+/// Result of the functions of the C API.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WebusbStatus {
+  Success = 0,
+  /// A null pointer or an out of range value was passed.
+  InvalidArgument = -1,
+  /// The device, interface, endpoint or configuration does not exist.
+  NotFound = -2,
+  /// The device is not open, or the interface is not claimed.
+  InvalidState = -3,
+  /// The endpoint does not support the transfer.
+  InvalidAccess = -4,
+  /// The endpoint or control request stalled.
+  Stall = -5,
+  /// The device sent more data than requested.
+  Babble = -6,
+  /// The transfer did not complete within the transfer timeout.
+  Timeout = -7,
+  /// Permission to access the device was denied.
+  Access = -8,
+  /// The device is gone.
+  NoDevice = -9,
+  /// A buffer passed is too small for the result.
+  BufferTooSmall = -10,
+  /// Any other I/O or USB error.
+  Other = -99,
+}
+
+impl From<Error> for WebusbStatus {
+  fn from(err: Error) -> Self {
+    use std::io::ErrorKind;
+
+    match err {
+      Error::NotFound => WebusbStatus::NotFound,
+      Error::InvalidState => WebusbStatus::InvalidState,
+      Error::InvalidAccess => WebusbStatus::InvalidAccess,
+      Error::Stall => WebusbStatus::Stall,
+      Error::Io(ErrorKind::TimedOut) => WebusbStatus::Timeout,
+      Error::Io(ErrorKind::PermissionDenied) => WebusbStatus::Access,
+      Error::Io(ErrorKind::InvalidInput) => WebusbStatus::InvalidArgument,
+      Error::Io(_) => WebusbStatus::Other,
+      Error::Usb(rusb::Error::InvalidParam) => WebusbStatus::InvalidArgument,
+      Error::Usb(rusb::Error::NotFound) => WebusbStatus::NotFound,
+      Error::Usb(rusb::Error::Overflow) => WebusbStatus::Babble,
+      Error::Usb(rusb::Error::Timeout) => WebusbStatus::Timeout,
+      Error::Usb(rusb::Error::Access) => WebusbStatus::Access,
+      Error::Usb(rusb::Error::NoDevice) => WebusbStatus::NoDevice,
+      Error::Usb(_) => WebusbStatus::Other,
+    }
+  }
+}
+
+fn status(result: Result<()>) -> WebusbStatus {
+  match result {
+    Ok(()) => WebusbStatus::Success,
+    Err(err) => err.into(),
+  }
+}
+
+/// Enumerates devices. Opaque.
+pub struct WebusbContext(Context);
+
+/// A device returned by `webusb_context_devices`. Opaque.
+pub struct WebusbDevice(Mutex<UsbDevice>);
+
+impl WebusbDevice {
+  fn run<T>(&self, f: impl FnOnce(&mut UsbDevice) -> Result<T>) -> Result<T> {
+    f(&mut self.0.lock().unwrap())
+  }
+}
+
+/// Descriptor fields and state of a device.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct WebusbDeviceInfo {
+  pub usb_version_major: u8,
+  pub usb_version_minor: u8,
+  pub usb_version_subminor: u8,
+  pub device_class: u8,
+  pub device_subclass: u8,
+  pub device_protocol: u8,
+  pub vendor_id: u16,
+  pub product_id: u16,
+  pub device_version_major: u8,
+  pub device_version_minor: u8,
+  pub device_version_subminor: u8,
+  pub bus_number: u8,
+  pub device_address: u8,
+  pub opened: bool,
+  /// bConfigurationValue of the selected configuration. 0, if the device
+  /// is not configured.
+  pub configuration_value: u8,
+}
+
+/// String descriptors of a device.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WebusbDeviceString {
+  ManufacturerName,
+  ProductName,
+  SerialNumber,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WebusbDirection {
+  In,
+  Out,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WebusbRequestType {
+  Standard,
+  Class,
+  Vendor,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WebusbRecipient {
+  Device,
+  Interface,
+  Endpoint,
+  Other,
+}
+
+/// https://wicg.github.io/webusb/#dictdef-usbcontroltransferparameters
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct WebusbControlSetup {
+  pub request_type: WebusbRequestType,
+  pub recipient: WebusbRecipient,
+  pub request: u8,
+  pub value: u16,
+  pub index: u16,
+}
+
+impl From<&WebusbControlSetup> for UsbControlTransferParameters {
+  fn from(setup: &WebusbControlSetup) -> Self {
+    UsbControlTransferParameters {
+      request_type: match setup.request_type {
+        WebusbRequestType::Standard => UsbRequestType::Standard,
+        WebusbRequestType::Class => UsbRequestType::Class,
+        WebusbRequestType::Vendor => UsbRequestType::Vendor,
+      },
+      recipient: match setup.recipient {
+        WebusbRecipient::Device => UsbRecipient::Device,
+        WebusbRecipient::Interface => UsbRecipient::Interface,
+        WebusbRecipient::Endpoint => UsbRecipient::Endpoint,
+        WebusbRecipient::Other => UsbRecipient::Other,
+      },
+      request: setup.request,
+      value: setup.value,
+      index: setup.index,
+    }
+  }
+}
+
+// Copies `data` to the caller's buffer of `capacity` bytes.
+unsafe fn copy_out(
+  data: &[u8],
+  buffer: *mut u8,
+  capacity: usize,
+  transferred: *mut usize,
+) {
+  let length = data.len().min(capacity);
+  if length > 0 {
+    std::ptr::copy_nonoverlapping(data.as_ptr(), buffer, length);
+  }
+  if !transferred.is_null() {
+    *transferred = length;
+  }
+}
+
+unsafe fn slice<'a>(data: *const u8, length: usize) -> &'a [u8] {
+  if length == 0 {
+    &[]
+  } else {
+    std::slice::from_raw_parts(data, length)
+  }
+}
+
+/// Name of `status`, e.g. "NotFound". The string is static.
+#[no_mangle]
+pub extern "C" fn webusb_status_name(status: WebusbStatus) -> *const c_char {
+  let name: &'static [u8] = match status {
+    WebusbStatus::Success => b"Success\0",
+    WebusbStatus::InvalidArgument => b"InvalidArgument\0",
+    WebusbStatus::NotFound => b"NotFound\0",
+    WebusbStatus::InvalidState => b"InvalidState\0",
+    WebusbStatus::InvalidAccess => b"InvalidAccess\0",
+    WebusbStatus::Stall => b"Stall\0",
+    WebusbStatus::Babble => b"Babble\0",
+    WebusbStatus::Timeout => b"Timeout\0",
+    WebusbStatus::Access => b"Access\0",
+    WebusbStatus::NoDevice => b"NoDevice\0",
+    WebusbStatus::BufferTooSmall => b"BufferTooSmall\0",
+    WebusbStatus::Other => b"Other\0",
+  };
+  name.as_ptr() as *const c_char
+}
+
+/// Creates a context and stores it in `*ctx`. Free it with
+/// `webusb_context_free`.
+///
+/// # Safety
+///
+/// `ctx` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_context_new(
+  ctx: *mut *mut WebusbContext,
+) -> WebusbStatus {
+  if ctx.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  match Context::init() {
+    Ok(context) => {
+      *ctx = Box::into_raw(Box::new(WebusbContext(context)));
+      WebusbStatus::Success
+    }
+    Err(err) => err.into(),
+  }
+}
+
+/// # Safety
+///
+/// `ctx` must be null or returned by `webusb_context_new`. Devices listed
+/// by the context stay valid.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_context_free(ctx: *mut WebusbContext) {
+  if !ctx.is_null() {
+    drop(Box::from_raw(ctx));
+  }
+}
+
+/// Lists the devices in a new array of `*len` devices stored in
+/// `*devices`. Free it with `webusb_device_list_free`.
+///
+/// # Safety
+///
+/// `ctx` must be returned by `webusb_context_new`, `devices` and `len`
+/// must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_context_devices(
+  ctx: *const WebusbContext,
+  devices: *mut *mut *mut WebusbDevice,
+  len: *mut usize,
+) -> WebusbStatus {
+  if ctx.is_null() || devices.is_null() || len.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  let list = match (*ctx).0.devices() {
+    Ok(list) => list,
+    Err(err) => return err.into(),
+  };
+
+  let list = list
+    .into_iter()
+    .map(|device| Box::into_raw(Box::new(WebusbDevice(Mutex::new(device)))))
+    .collect::<Box<[_]>>();
+  *len = list.len();
+  *devices = Box::into_raw(list) as *mut *mut WebusbDevice;
+  WebusbStatus::Success
+}
+
+/// Frees the array and the devices left in it. Keep a device by setting
+/// its entry to null before freeing the list, and free it with
+/// `webusb_device_free` later.
+///
+/// # Safety
+///
+/// `devices` and `len` must be returned by `webusb_context_devices`.
+/// `devices` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_list_free(
+  devices: *mut *mut WebusbDevice,
+  len: usize,
+) {
+  if devices.is_null() {
+    return;
+  }
+  let list = Box::from_raw(std::ptr::slice_from_raw_parts_mut(devices, len));
+  for &device in list.iter() {
+    webusb_device_free(device);
+  }
+}
+
+/// Closes the device if it is open.
+///
+/// # Safety
+///
+/// `device` must be null or a device listed by `webusb_context_devices`
+/// that is no longer in use by other threads.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_free(device: *mut WebusbDevice) {
+  if !device.is_null() {
+    drop(Box::from_raw(device));
+  }
+}
+
+/// # Safety
+///
+/// `device` must be a valid device, `info` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_info(
+  device: *const WebusbDevice,
+  info: *mut WebusbDeviceInfo,
+) -> WebusbStatus {
+  if device.is_null() || info.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  let device = (*device).0.lock().unwrap();
+  *info = WebusbDeviceInfo {
+    usb_version_major: device.usb_version_major,
+    usb_version_minor: device.usb_version_minor,
+    usb_version_subminor: device.usb_version_subminor,
+    device_class: device.device_class,
+    device_subclass: device.device_subclass,
+    device_protocol: device.device_protocol,
+    vendor_id: device.vendor_id,
+    product_id: device.product_id,
+    device_version_major: device.device_version_major,
+    device_version_minor: device.device_version_minor,
+    device_version_subminor: device.device_version_subminor,
+    bus_number: device.bus_number,
+    device_address: device.device_address,
+    opened: device.opened,
+    configuration_value: device
+      .configuration
+      .as_ref()
+      .map_or(0, |configuration| configuration.configuration_value()),
+  };
+  WebusbStatus::Success
+}
+
+/// Copies a string descriptor of the device into `buffer` as a
+/// NUL-terminated UTF-8 string. `*length` receives the length of the
+/// string without the NUL, also if `capacity` is too small.
+///
+/// Returns `NotFound` if the device has no such string.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `buffer` must be valid for `capacity`
+/// bytes of writes and `length` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_string(
+  device: *const WebusbDevice,
+  which: WebusbDeviceString,
+  buffer: *mut c_char,
+  capacity: usize,
+  length: *mut usize,
+) -> WebusbStatus {
+  if device.is_null() || (buffer.is_null() && capacity > 0) {
+    return WebusbStatus::InvalidArgument;
+  }
+  let device = (*device).0.lock().unwrap();
+  let string = match which {
+    WebusbDeviceString::ManufacturerName => &device.manufacturer_name,
+    WebusbDeviceString::ProductName => &device.product_name,
+    WebusbDeviceString::SerialNumber => &device.serial_number,
+  };
+  let string = match string {
+    Some(string) => string.as_bytes(),
+    None => return WebusbStatus::NotFound,
+  };
+
+  if !length.is_null() {
+    *length = string.len();
+  }
+  if capacity <= string.len() {
+    return WebusbStatus::BufferTooSmall;
+  }
+  std::ptr::copy_nonoverlapping(
+    string.as_ptr(),
+    buffer as *mut u8,
+    string.len(),
+  );
+  *buffer.add(string.len()) = 0;
+  WebusbStatus::Success
+}
+
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_open(
+  device: *const WebusbDevice,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  status((*device).run(|device| device.open()))
+}
+
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_close(
+  device: *const WebusbDevice,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  status((*device).run(|device| device.close()))
+}
+
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_reset(
+  device: *const WebusbDevice,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  status((*device).run(|device| device.reset()))
+}
+
+/// `configuration_value` is the bConfigurationValue of the configuration.
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_select_configuration(
+  device: *const WebusbDevice,
+  configuration_value: u8,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  status(
+    (*device).run(|device| device.select_configuration(configuration_value)),
+  )
+}
+
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_claim_interface(
+  device: *const WebusbDevice,
+  interface_number: u8,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  status((*device).run(|device| device.claim_interface(interface_number)))
+}
+
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_release_interface(
+  device: *const WebusbDevice,
+  interface_number: u8,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  status((*device).run(|device| device.release_interface(interface_number)))
+}
+
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_select_alternate_interface(
+  device: *const WebusbDevice,
+  interface_number: u8,
+  alternate_setting: u8,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  status((*device).run(|device| {
+    device.select_alternate_interface(interface_number, alternate_setting)
+  }))
+}
+
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_clear_halt(
+  device: *const WebusbDevice,
+  direction: WebusbDirection,
+  endpoint_number: u8,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  let direction = match direction {
+    WebusbDirection::In => Direction::In,
+    WebusbDirection::Out => Direction::Out,
+  };
+  status((*device).run(|device| device.clear_halt(direction, endpoint_number)))
+}
+
+/// Bulk and interrupt transfers that do not complete within `timeout_ms`
+/// milliseconds fail with `Timeout`. 0, the default, waits forever.
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_device_set_transfer_timeout(
+  device: *const WebusbDevice,
+  timeout_ms: u32,
+) -> WebusbStatus {
+  if device.is_null() {
+    return WebusbStatus::InvalidArgument;
+  }
+  let timeout = match timeout_ms {
+    0 => None,
+    ms => Some(Duration::from_millis(ms as u64)),
+  };
+  status((*device).run(|device| {
+    device.set_transfer_timeout(timeout);
+    Ok(())
+  }))
+}
+
+/// Reads up to `length` bytes from IN endpoint `endpoint_number` into
+/// `buffer`. `*transferred` receives the number of bytes read.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `buffer` must be valid for `length`
+/// bytes of writes and `transferred` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_transfer_in(
+  device: *const WebusbDevice,
+  endpoint_number: u8,
+  buffer: *mut u8,
+  length: usize,
+  transferred: *mut usize,
+) -> WebusbStatus {
+  if device.is_null() || (buffer.is_null() && length > 0) {
+    return WebusbStatus::InvalidArgument;
+  }
+  match (*device).run(|device| device.transfer_in(endpoint_number, length)) {
+    Ok(data) => {
+      copy_out(&data, buffer, length, transferred);
+      WebusbStatus::Success
+    }
+    Err(err) => err.into(),
+  }
+}
+
+/// Writes `length` bytes of `data` to OUT endpoint `endpoint_number`.
+/// `*transferred` receives the number of bytes written.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `data` must be valid for `length`
+/// bytes of reads and `transferred` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_transfer_out(
+  device: *const WebusbDevice,
+  endpoint_number: u8,
+  data: *const u8,
+  length: usize,
+  transferred: *mut usize,
+) -> WebusbStatus {
+  if device.is_null() || (data.is_null() && length > 0) {
+    return WebusbStatus::InvalidArgument;
+  }
+  let data = slice(data, length);
+  match (*device).run(|device| device.transfer_out(endpoint_number, data)) {
+    Ok(written) => {
+      if !transferred.is_null() {
+        *transferred = written;
+      }
+      WebusbStatus::Success
+    }
+    Err(err) => err.into(),
+  }
+}
+
+/// Control transfer reading up to `length` bytes into `buffer`.
+/// `*transferred` receives the number of bytes read.
+///
+/// # Safety
+///
+/// `device` and `setup` must be valid, `buffer` must be valid for `length`
+/// bytes of writes and `transferred` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_control_transfer_in(
+  device: *const WebusbDevice,
+  setup: *const WebusbControlSetup,
+  buffer: *mut u8,
+  length: usize,
+  transferred: *mut usize,
+) -> WebusbStatus {
+  if device.is_null() || setup.is_null() || (buffer.is_null() && length > 0) {
+    return WebusbStatus::InvalidArgument;
+  }
+  let setup = UsbControlTransferParameters::from(&*setup);
+  match (*device).run(|device| device.control_transfer_in(setup, length)) {
+    Ok(data) => {
+      copy_out(&data, buffer, length, transferred);
+      WebusbStatus::Success
+    }
+    Err(err) => err.into(),
+  }
+}
+
+/// Control transfer writing `length` bytes of `data`. `*transferred`
+/// receives the number of bytes written.
+///
+/// # Safety
+///
+/// `device` and `setup` must be valid, `data` must be valid for `length`
+/// bytes of reads and `transferred` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn webusb_control_transfer_out(
+  device: *const WebusbDevice,
+  setup: *const WebusbControlSetup,
+  data: *const u8,
+  length: usize,
+  transferred: *mut usize,
+) -> WebusbStatus {
+  if device.is_null() || setup.is_null() || (data.is_null() && length > 0) {
+    return WebusbStatus::InvalidArgument;
+  }
+  let setup = UsbControlTransferParameters::from(&*setup);
+  let data = slice(data, length);
+  match (*device).run(|device| device.control_transfer_out(setup, data)) {
+    Ok(written) => {
+      if !transferred.is_null() {
+        *transferred = written;
+      }
+      WebusbStatus::Success
+    }
+    Err(err) => err.into(),
+  }
+}
+
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod tests {
+  use std::ptr::null_mut;
+  use std::sync::Mutex;
+
+  use super::*;
+  use crate::loopback::Loopback;
+
+  fn loopback() -> *mut WebusbDevice {
+    Box::into_raw(Box::new(WebusbDevice(Mutex::new(Loopback::device()))))
+  }
+
+  #[test]
+  fn test_device_info() {
+    let device = loopback();
+    let mut info = WebusbDeviceInfo::default();
+    unsafe {
+      assert_eq!(webusb_device_info(device, &mut info), WebusbStatus::Success);
+      assert_eq!((info.vendor_id, info.product_id), (0x1234, 0x5678));
+      assert!(!info.opened);
+
+      let mut buffer = [0 as c_char; 64];
+      let mut length = 0;
+      let status = webusb_device_string(
+        device,
+        WebusbDeviceString::ProductName,
+        buffer.as_mut_ptr(),
+        buffer.len(),
+        &mut length,
+      );
+      assert_eq!(status, WebusbStatus::Success);
+      let name = std::ffi::CStr::from_ptr(buffer.as_ptr());
+      assert_eq!(name.to_bytes().len(), length);
+
+      let status = webusb_device_string(
+        device,
+        WebusbDeviceString::ProductName,
+        buffer.as_mut_ptr(),
+        length,
+        &mut length,
+      );
+      assert_eq!(status, WebusbStatus::BufferTooSmall);
+
+      webusb_device_free(device);
+    }
+  }
+
+  #[test]
+  fn test_transfers() {
+    let device = loopback();
+    let mut buffer = [0u8; 64];
+    let mut transferred = 0;
+    unsafe {
+      let status = webusb_transfer_in(
+        device,
+        1,
+        buffer.as_mut_ptr(),
+        buffer.len(),
+        &mut transferred,
+      );
+      assert_eq!(status, WebusbStatus::InvalidState);
+
+      assert_eq!(webusb_device_open(device), WebusbStatus::Success);
+      assert_eq!(
+        webusb_device_select_configuration(device, 1),
+        WebusbStatus::Success
+      );
+      assert_eq!(
+        webusb_device_claim_interface(device, 0),
+        WebusbStatus::Success
+      );
+      assert_eq!(
+        webusb_device_claim_interface(device, 1),
+        WebusbStatus::NotFound
+      );
+
+      let data = b"hello";
+      let status = webusb_transfer_out(
+        device,
+        2,
+        data.as_ptr(),
+        data.len(),
+        &mut transferred,
+      );
+      assert_eq!((status, transferred), (WebusbStatus::Success, data.len()));
+
+      let status = webusb_transfer_in(
+        device,
+        1,
+        buffer.as_mut_ptr(),
+        buffer.len(),
+        &mut transferred,
+      );
+      assert_eq!(status, WebusbStatus::Success);
+      assert_eq!(&buffer[..transferred], data);
+
+      let status =
+        webusb_transfer_in(device, 3, buffer.as_mut_ptr(), 8, null_mut());
+      assert_eq!(status, WebusbStatus::Stall);
+
+      assert_eq!(
+        webusb_transfer_in(device, 1, null_mut(), 8, null_mut()),
+        WebusbStatus::InvalidArgument
+      );
+
+      webusb_device_free(device);
+    }
+  }
+
+  #[test]
+  fn test_device_list() {
+    let devices = vec![loopback(), loopback()].into_boxed_slice();
+    let len = devices.len();
+    let devices = Box::into_raw(devices) as *mut *mut WebusbDevice;
+    unsafe {
+      // Keep the first device.
+      let device = *devices;
+      *devices = null_mut();
+      webusb_device_list_free(devices, len);
+      assert_eq!(webusb_device_close(device), WebusbStatus::Success);
+      webusb_device_free(device);
+    }
+  }
+}
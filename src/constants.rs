@@ -9,3 +9,31 @@ pub const WEB_USB_CAPABILITY_UUID: &[u8; 16] = &[
 pub const BOS_DESCRIPTOR_TYPE: u16 = 0x0F;
 pub const DESCRIPTOR_TYPE: u8 = 0x03;
 pub const DESCRIPTOR_MIN_LENGTH: u8 = 3;
+pub const SS_ENDPOINT_COMPANION_DESCRIPTOR_TYPE: u8 = 0x30;
+pub const SSP_ISOCHRONOUS_ENDPOINT_COMPANION_DESCRIPTOR_TYPE: u8 = 0x31;
+pub const GET_DESCRIPTOR_REQUEST: u8 = 0x06;
+pub const CONFIGURATION_DESCRIPTOR_TYPE: u8 = 0x02;
+pub const HUB_CLASS: u8 = 0x09;
+pub const HUB_DESCRIPTOR_TYPE: u8 = 0x29;
+pub const SS_HUB_DESCRIPTOR_TYPE: u8 = 0x2A;
+pub const GET_STATUS_REQUEST: u8 = 0x00;
+pub const CLEAR_FEATURE_REQUEST: u8 = 0x01;
+pub const SET_FEATURE_REQUEST: u8 = 0x03;
+pub const PORT_RESET_FEATURE: u16 = 4;
+pub const PORT_POWER_FEATURE: u16 = 8;
+pub const DEVICE_DESCRIPTOR_TYPE: u8 = 0x01;
+pub const STRING_DESCRIPTOR_TYPE: u8 = 0x03;
+pub const INTERFACE_DESCRIPTOR_TYPE: u8 = 0x04;
+pub const ENDPOINT_DESCRIPTOR_TYPE: u8 = 0x05;
+pub const GET_CONFIGURATION_REQUEST: u8 = 0x08;
+pub const SET_CONFIGURATION_REQUEST: u8 = 0x09;
+pub const SET_INTERFACE_REQUEST: u8 = 0x0B;
+pub const ENDPOINT_HALT_FEATURE: u16 = 0;
+pub const GET_INTERFACE_REQUEST: u8 = 0x0A;
+pub const USB_2_0_EXTENSION_CAPABILITY_TYPE: u8 = 0x02;
+/// Little-endian encoding of {d8dd60df-4589-4cc7-9cd2-659d9e648a9f}.
+pub const MS_OS_20_CAPABILITY_UUID: &[u8; 16] = &[
+  0xDF, 0x60, 0xDD, 0xD8, 0x89, 0x45, 0xC7, 0x4C, 0x9C, 0xD2, 0x65, 0x9D, 0x9E,
+  0x64, 0x8A, 0x9F,
+];
+pub const MS_OS_20_DESCRIPTOR_INDEX: u16 = 0x07;
@@ -96,9 +96,237 @@ pub(crate) fn parse_webusb_url(bytes: &[u8]) -> Option<String> {
   Some(url)
 }
 
+// USB 3.2 spec, Section 9.6.7 and 9.6.8.
+// Scans the extra bytes following an endpoint descriptor for a SuperSpeed
+// Endpoint Companion descriptor. Returns (bMaxBurst, bmAttributes, bytes per
+// interval). If a SuperSpeedPlus Isochronous Endpoint Companion descriptor
+// follows, its dwBytesPerInterval takes precedence.
+pub(crate) fn parse_ss_endpoint_companion(
+  bytes: &[u8],
+) -> Option<(u8, u8, u32)> {
+  let mut companion: Option<(u8, u8, u32)> = None;
+  let mut bytes = bytes;
+
+  while bytes.len() >= 2 {
+    let length = bytes[0] as usize;
+    assert_return!(length < 2 || length > bytes.len());
+
+    match bytes[1] {
+      SS_ENDPOINT_COMPANION_DESCRIPTOR_TYPE if companion.is_none() => {
+        assert_return!(length < 6);
+        let bytes_per_interval = u16::from_le_bytes([bytes[4], bytes[5]]);
+        companion = Some((bytes[2], bytes[3], bytes_per_interval as u32));
+      }
+      SSP_ISOCHRONOUS_ENDPOINT_COMPANION_DESCRIPTOR_TYPE => {
+        assert_return!(length < 8);
+        // Only valid when bit 7 of the companion's bmAttributes is set.
+        if let Some((max_burst, attributes, _)) = companion {
+          if attributes & 0x80 != 0 {
+            let bytes_per_interval =
+              u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+            companion = Some((max_burst, attributes, bytes_per_interval));
+          }
+        }
+      }
+      _ => {}
+    }
+
+    bytes = &bytes[length..];
+  }
+
+  companion
+}
+
+// USB 2.0 spec, Section 11.23.2.1 and USB 3.2 spec, Section 10.15.2.1.
+// Returns (bNbrPorts, wHubCharacteristics, bPwrOn2PwrGood).
+pub(crate) fn parse_hub_descriptor(bytes: &[u8]) -> Option<(u8, u16, u8)> {
+  // Fields up to bHubContrCurrent are common to both hub descriptors.
+  assert_return!(bytes.len() < 7);
+
+  let length = bytes[0];
+  assert_return!(length < 7);
+  assert_return!(length as usize > bytes.len());
+  assert_return!(
+    bytes[1] != HUB_DESCRIPTOR_TYPE && bytes[1] != SS_HUB_DESCRIPTOR_TYPE
+  );
+
+  let characteristics = u16::from_le_bytes([bytes[3], bytes[4]]);
+  Some((bytes[2], characteristics, bytes[5]))
+}
+
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+// USB 2.0 spec, Section 9.6.1.
+pub(crate) struct DeviceDescriptor {
+  pub usb_version: u16,
+  pub class: u8,
+  pub subclass: u8,
+  pub protocol: u8,
+  pub vendor_id: u16,
+  pub product_id: u16,
+  pub device_version: u16,
+  pub manufacturer_index: u8,
+  pub product_index: u8,
+  pub serial_number_index: u8,
+  pub num_configurations: u8,
+}
+
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+pub(crate) fn parse_device_descriptor(
+  bytes: &[u8],
+) -> Option<DeviceDescriptor> {
+  assert_return!(bytes.len() < 18);
+  assert_return!(bytes[0] < 18);
+  assert_return!(bytes[1] != DEVICE_DESCRIPTOR_TYPE);
+
+  Some(DeviceDescriptor {
+    usb_version: u16::from_le_bytes([bytes[2], bytes[3]]),
+    class: bytes[4],
+    subclass: bytes[5],
+    protocol: bytes[6],
+    vendor_id: u16::from_le_bytes([bytes[8], bytes[9]]),
+    product_id: u16::from_le_bytes([bytes[10], bytes[11]]),
+    device_version: u16::from_le_bytes([bytes[12], bytes[13]]),
+    manufacturer_index: bytes[14],
+    product_index: bytes[15],
+    serial_number_index: bytes[16],
+    num_configurations: bytes[17],
+  })
+}
+
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+// USB 2.0 spec, Section 9.6.3.
+pub(crate) struct ConfigDescriptor {
+  pub configuration_value: u8,
+  pub configuration_index: u8,
+  pub attributes: u8,
+  pub max_power: u8,
+  pub interfaces: Vec<InterfaceDescriptor>,
+}
+
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+// USB 2.0 spec, Section 9.6.5.
+pub(crate) struct InterfaceDescriptor {
+  pub interface_number: u8,
+  pub alternate_setting: u8,
+  pub class: u8,
+  pub subclass: u8,
+  pub protocol: u8,
+  pub interface_index: u8,
+  pub endpoints: Vec<EndpointDescriptor>,
+}
+
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+// USB 2.0 spec, Section 9.6.6.
+pub(crate) struct EndpointDescriptor {
+  pub address: u8,
+  pub attributes: u8,
+  pub max_packet_size: u16,
+  pub interval: u8,
+  // Descriptors following the endpoint descriptor, e.g. the SuperSpeed
+  // Endpoint Companion descriptor.
+  pub extra: Vec<u8>,
+}
+
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+// Parses a full configuration descriptor (wTotalLength bytes) into the
+// interface descriptors and their endpoints. Like libusb, class-specific
+// descriptors that follow an endpoint are kept in its `extra` bytes.
+pub(crate) fn parse_config_descriptor(
+  bytes: &[u8],
+) -> Option<ConfigDescriptor> {
+  assert_return!(bytes.len() < 9);
+  assert_return!(bytes[0] < 9);
+  assert_return!(bytes[1] != CONFIGURATION_DESCRIPTOR_TYPE);
+
+  let total_length = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+  assert_return!(
+    total_length < bytes[0] as usize || total_length > bytes.len()
+  );
+
+  let mut config = ConfigDescriptor {
+    configuration_value: bytes[5],
+    configuration_index: bytes[6],
+    attributes: bytes[7],
+    max_power: bytes[8],
+    interfaces: vec![],
+  };
+
+  let mut bytes = &bytes[bytes[0] as usize..total_length];
+  while bytes.len() >= 2 {
+    let length = bytes[0] as usize;
+    assert_return!(length < 2 || length > bytes.len());
+
+    match bytes[1] {
+      INTERFACE_DESCRIPTOR_TYPE => {
+        assert_return!(length < 9);
+        config.interfaces.push(InterfaceDescriptor {
+          interface_number: bytes[2],
+          alternate_setting: bytes[3],
+          class: bytes[5],
+          subclass: bytes[6],
+          protocol: bytes[7],
+          interface_index: bytes[8],
+          endpoints: vec![],
+        });
+      }
+      ENDPOINT_DESCRIPTOR_TYPE => {
+        assert_return!(length < 7);
+        // Endpoint descriptors outside of an interface are ignored.
+        if let Some(interface) = config.interfaces.last_mut() {
+          interface.endpoints.push(EndpointDescriptor {
+            address: bytes[2],
+            attributes: bytes[3],
+            max_packet_size: u16::from_le_bytes([bytes[4], bytes[5]]),
+            interval: bytes[6],
+            extra: vec![],
+          });
+        }
+      }
+      _ => {
+        let endpoint = config
+          .interfaces
+          .last_mut()
+          .and_then(|interface| interface.endpoints.last_mut());
+        if let Some(endpoint) = endpoint {
+          endpoint.extra.extend_from_slice(&bytes[..length]);
+        }
+      }
+    }
+
+    bytes = &bytes[length..];
+  }
+
+  Some(config)
+}
+
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+// USB 2.0 spec, Section 9.6.7.
+// String descriptors are UTF-16LE encoded.
+pub(crate) fn parse_string_descriptor(bytes: &[u8]) -> Option<String> {
+  assert_return!(bytes.len() < 2);
+
+  let length = bytes[0] as usize;
+  assert_return!(length < 2 || length > bytes.len());
+  assert_return!(bytes[1] != STRING_DESCRIPTOR_TYPE);
+
+  let utf16 = bytes[2..length]
+    .chunks_exact(2)
+    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+    .collect::<Vec<u16>>();
+  Some(String::from_utf16_lossy(&utf16))
+}
+
 #[cfg(test)]
 mod tests {
   use crate::descriptors::parse_bos;
+  #[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+  use crate::descriptors::parse_config_descriptor;
+  #[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+  use crate::descriptors::parse_device_descriptor;
+  use crate::descriptors::parse_hub_descriptor;
+  use crate::descriptors::parse_ss_endpoint_companion;
+  #[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+  use crate::descriptors::parse_string_descriptor;
   use crate::descriptors::parse_webusb_url;
 
   #[test]
@@ -227,4 +455,177 @@ mod tests {
     url[2] = 0x09; // Invalid protocol
     assert_eq!(parse_webusb_url(&url), None);
   }
+
+  #[test]
+  fn test_parse_ss_endpoint_companion() {
+    // Bulk endpoint, bMaxBurst = 15, MaxStreams = 2^4.
+    assert_eq!(
+      parse_ss_endpoint_companion(&[0x06, 0x30, 0x0F, 0x04, 0x00, 0x00]),
+      Some((0x0F, 0x04, 0))
+    );
+
+    // Class-specific descriptor before the companion is skipped.
+    assert_eq!(
+      parse_ss_endpoint_companion(&[
+        0x07, 0x25, 0x01, 0x00, 0x00, 0x00, 0x00, // CS_ENDPOINT
+        0x06, 0x30, 0x00, 0x02, 0x00, 0x0C,
+      ]),
+      Some((0x00, 0x02, 0x0C00))
+    );
+
+    // SuperSpeedPlus Isochronous Endpoint Companion overrides
+    // wBytesPerInterval.
+    assert_eq!(
+      parse_ss_endpoint_companion(&[
+        0x06, 0x30, 0x0F, 0x80, 0x00, 0x00, // SS companion
+        0x08, 0x31, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+      ]),
+      Some((0x0F, 0x80, 0x10000))
+    );
+  }
+
+  #[test]
+  fn test_bad_parse_ss_endpoint_companion() {
+    // No extra descriptors
+    assert_eq!(parse_ss_endpoint_companion(&[]), None);
+
+    // Companion too short
+    assert_eq!(parse_ss_endpoint_companion(&[0x04, 0x30, 0x00, 0x00]), None);
+
+    // bLength longer than the remaining bytes
+    assert_eq!(
+      parse_ss_endpoint_companion(&[0x06, 0x30, 0x00, 0x00, 0x00]),
+      None
+    );
+
+    // bLength of zero
+    assert_eq!(parse_ss_endpoint_companion(&[0x00, 0x30, 0x00]), None);
+  }
+
+  #[test]
+  fn test_parse_hub_descriptor() {
+    // 4-port USB 2.0 hub with per-port power switching.
+    assert_eq!(
+      parse_hub_descriptor(&[
+        0x09, 0x29, 0x04, 0x09, 0x00, 0x32, 0x64, 0x00, 0xFF
+      ]),
+      Some((4, 0x0009, 0x32))
+    );
+
+    // 4-port SuperSpeed hub.
+    assert_eq!(
+      parse_hub_descriptor(&[
+        0x0C, 0x2A, 0x04, 0x09, 0x00, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+      ]),
+      Some((4, 0x0009, 0x0A))
+    );
+  }
+
+  #[test]
+  fn test_bad_parse_hub_descriptor() {
+    // Too short
+    assert_eq!(parse_hub_descriptor(&[0x09, 0x29, 0x04]), None);
+
+    // bDescLength larger than the buffer
+    assert_eq!(
+      parse_hub_descriptor(&[0x09, 0x29, 0x04, 0x09, 0x00, 0x32, 0x64]),
+      None
+    );
+
+    // Not a hub descriptor
+    assert_eq!(
+      parse_hub_descriptor(&[
+        0x09, 0x02, 0x04, 0x09, 0x00, 0x32, 0x64, 0x00, 0xFF
+      ]),
+      None
+    );
+  }
+
+  #[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+  #[test]
+  fn test_parse_device_descriptor() {
+    // Arduino Leonardo.
+    let descriptor = parse_device_descriptor(&[
+      0x12, 0x01, 0x10, 0x02, 0xEF, 0x02, 0x01, 0x40, 0x41, 0x23, 0x36, 0x80,
+      0x00, 0x01, 0x01, 0x02, 0x03, 0x01,
+    ])
+    .unwrap();
+    assert_eq!(descriptor.usb_version, 0x0210);
+    assert_eq!(descriptor.class, 0xEF);
+    assert_eq!(descriptor.vendor_id, 0x2341);
+    assert_eq!(descriptor.product_id, 0x8036);
+    assert_eq!(descriptor.serial_number_index, 3);
+    assert_eq!(descriptor.num_configurations, 1);
+
+    // Too short
+    assert!(parse_device_descriptor(&[0x12, 0x01, 0x10, 0x02]).is_none());
+  }
+
+  #[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+  #[test]
+  fn test_parse_config_descriptor() {
+    let descriptor = parse_config_descriptor(&[
+      // Configuration descriptor.
+      0x09, 0x02, 0x2F, 0x00, 0x02, 0x01, 0x00, 0xA0, 0xFA,
+      // Interface 0, alternate setting 0.
+      0x09, 0x04, 0x00, 0x00, 0x01, 0x03, 0x00, 0x00, 0x00,
+      // Endpoint 0x81, interrupt.
+      0x07, 0x05, 0x81, 0x03, 0x08, 0x00, 0x0A,
+      // Interface 1, alternate setting 0.
+      0x09, 0x04, 0x01, 0x00, 0x01, 0xFF, 0x00, 0x00, 0x04,
+      // Endpoint 0x02, bulk.
+      0x07, 0x05, 0x02, 0x02, 0x00, 0x04, 0x00,
+      // SuperSpeed Endpoint Companion.
+      0x06, 0x30, 0x0F, 0x00, 0x00, 0x00,
+    ])
+    .unwrap();
+
+    assert_eq!(descriptor.configuration_value, 1);
+    assert_eq!(descriptor.attributes, 0xA0);
+    assert_eq!(descriptor.max_power, 0xFA);
+    assert_eq!(descriptor.interfaces.len(), 2);
+
+    let interface = &descriptor.interfaces[1];
+    assert_eq!(interface.interface_number, 1);
+    assert_eq!(interface.class, 0xFF);
+    assert_eq!(interface.interface_index, 4);
+
+    let endpoint = &interface.endpoints[0];
+    assert_eq!(endpoint.address, 0x02);
+    assert_eq!(endpoint.max_packet_size, 1024);
+    assert_eq!(endpoint.extra, vec![0x06, 0x30, 0x0F, 0x00, 0x00, 0x00]);
+  }
+
+  #[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+  #[test]
+  fn test_bad_parse_config_descriptor() {
+    // Too short
+    assert!(parse_config_descriptor(&[0x09, 0x02, 0x09, 0x00]).is_none());
+
+    // wTotalLength too large
+    assert!(parse_config_descriptor(&[
+      0x09, 0x02, 0x20, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32
+    ])
+    .is_none());
+
+    // Truncated interface descriptor
+    assert!(parse_config_descriptor(&[
+      0x09, 0x02, 0x0C, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32, 0x03, 0x04, 0x00,
+    ])
+    .is_none());
+  }
+
+  #[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+  #[test]
+  fn test_parse_string_descriptor() {
+    assert_eq!(
+      parse_string_descriptor(&[
+        0x0A, 0x03, b'U', 0, b'S', 0, b'B', 0, 0xE9, 0
+      ]),
+      Some("USB\u{e9}".to_string())
+    );
+
+    // Not a string descriptor
+    assert_eq!(parse_string_descriptor(&[0x04, 0x02, b'U', 0]), None);
+  }
 }
@@ -0,0 +1,570 @@
+//! Enumeration of devices through a `DeviceBackend`.
+//!
+//! Backends without libusb only provide control transfers, so the
+//! descriptors are read and parsed here instead.
+
+use crate::backend::BoxedBackend;
+use crate::constants::BOS_DESCRIPTOR_TYPE;
+use crate::constants::CONFIGURATION_DESCRIPTOR_TYPE;
+use crate::constants::DEVICE_DESCRIPTOR_TYPE;
+use crate::constants::GET_CONFIGURATION_REQUEST;
+use crate::constants::GET_DESCRIPTOR_REQUEST;
+use crate::constants::GET_URL_REQUEST;
+use crate::constants::STRING_DESCRIPTOR_TYPE;
+use crate::descriptors::parse_bos;
+use crate::descriptors::parse_config_descriptor;
+use crate::descriptors::parse_device_descriptor;
+use crate::descriptors::parse_ss_endpoint_companion;
+use crate::descriptors::parse_string_descriptor;
+use crate::descriptors::parse_webusb_url;
+use crate::descriptors::ConfigDescriptor;
+use crate::descriptors::DeviceDescriptor;
+use crate::descriptors::EndpointDescriptor;
+use crate::descriptors::InterfaceDescriptor;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbAlternateInterface;
+use crate::UsbConfiguration;
+use crate::UsbDevice;
+use crate::UsbEndpoint;
+use crate::UsbEndpointType;
+use crate::UsbInterface;
+use crate::UsbParentHub;
+use crate::UsbSpeed;
+use crate::UsbSuperSpeedEndpointCompanion;
+use crate::UsbSyncType;
+use crate::UsbUsageType;
+
+// Standard, device-to-host, recipient device.
+const REQUEST_TYPE_STANDARD_IN: u8 = 0x80;
+// Vendor, device-to-host, recipient device.
+const REQUEST_TYPE_VENDOR_IN: u8 = 0xC0;
+
+/// Where a device is attached, as reported by the backend.
+pub(crate) struct Topology {
+  pub bus_number: u8,
+  pub device_address: u8,
+  pub port_numbers: Vec<u8>,
+  pub speed: UsbSpeed,
+  pub parent_hub: Option<UsbParentHub>,
+}
+
+/// Reads the descriptors of the device behind `backend` and wraps both into
+/// a `UsbDevice`. The backend is closed again before returning.
+// usbfs reads the descriptors from sysfs instead.
+#[cfg_attr(not(any(feature = "usbip", feature = "proxy")), allow(dead_code))]
+pub(crate) fn read_device(
+  mut backend: BoxedBackend,
+  topology: Topology,
+) -> Result<UsbDevice> {
+  backend.open()?;
+  let device = read_descriptors(&mut backend, topology);
+  backend.close()?;
+  let mut device = device?;
+  device.backend = backend;
+  Ok(device)
+}
+
+fn read_descriptors(
+  backend: &mut BoxedBackend,
+  topology: Topology,
+) -> Result<UsbDevice> {
+  let bytes = get_descriptor(backend, DEVICE_DESCRIPTOR_TYPE, 0, 0, 18)?;
+  let device_descriptor =
+    parse_device_descriptor(&bytes).ok_or(Error::NotFound)?;
+
+  // String descriptors are read in the first language supported by the
+  // device.
+  let language_id = get_descriptor(backend, STRING_DESCRIPTOR_TYPE, 0, 0, 255)
+    .ok()
+    .filter(|bytes| bytes.len() >= 4)
+    .map(|bytes| u16::from_le_bytes([bytes[2], bytes[3]]));
+  let manufacturer_name =
+    read_string(backend, language_id, device_descriptor.manufacturer_index);
+  let product_name =
+    read_string(backend, language_id, device_descriptor.product_index);
+  let serial_number =
+    read_string(backend, language_id, device_descriptor.serial_number_index);
+
+  let mut configurations: Vec<UsbConfiguration> = vec![];
+  for idx in 0..device_descriptor.num_configurations {
+    let raw_descriptor = match read_config_descriptor(backend, idx) {
+      Ok(bytes) => bytes,
+      Err(_) => continue,
+    };
+    let config_descriptor = match parse_config_descriptor(&raw_descriptor) {
+      Some(config_descriptor) => config_descriptor,
+      None => continue,
+    };
+    configurations.push(UsbConfiguration::from_descriptor(
+      config_descriptor,
+      raw_descriptor,
+      topology.speed,
+      &mut |index| read_string(backend, language_id, index),
+    ));
+  }
+
+  let mut active_configuration = [0; 1];
+  let configuration = match backend.control_in(
+    REQUEST_TYPE_STANDARD_IN,
+    GET_CONFIGURATION_REQUEST,
+    0,
+    0,
+    &mut active_configuration,
+  ) {
+    Ok(1) => configurations
+      .iter()
+      .find(|c| c.configuration_value == active_configuration[0])
+      .cloned(),
+    _ => None,
+  };
+
+  let url = match device_descriptor.usb_version >= 0x0201 {
+    true => read_webusb_url(backend),
+    false => None,
+  };
+
+  Ok(new_device(
+    &device_descriptor,
+    DeviceStrings {
+      manufacturer_name,
+      product_name,
+      serial_number,
+    },
+    configurations,
+    configuration,
+    url,
+    topology,
+  ))
+}
+
+/// Builds a `UsbDevice` from the descriptors the kernel keeps for it, without
+/// talking to the device: the device descriptor followed by every
+/// configuration descriptor, as in the sysfs `descriptors` attribute.
+///
+/// Only the strings in `strings` are known, configuration and interface
+/// names are left out. So is the WebUSB landing page.
+#[cfg(all(feature = "usbfs", target_os = "linux"))]
+pub(crate) fn parse_device(
+  backend: BoxedBackend,
+  descriptors: &[u8],
+  strings: DeviceStrings,
+  active_configuration: Option<u8>,
+  topology: Topology,
+) -> Result<UsbDevice> {
+  let device_descriptor =
+    parse_device_descriptor(descriptors).ok_or(Error::NotFound)?;
+
+  let mut configurations: Vec<UsbConfiguration> = vec![];
+  let mut rest = descriptors.get(descriptors[0] as usize..).unwrap_or(&[]);
+  while rest.len() >= 4 {
+    let total_length = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+    let config_descriptor = match parse_config_descriptor(rest) {
+      Some(config_descriptor) => config_descriptor,
+      None => break,
+    };
+    configurations.push(UsbConfiguration::from_descriptor(
+      config_descriptor,
+      rest[..total_length].to_vec(),
+      topology.speed,
+      &mut |_| None,
+    ));
+    rest = &rest[total_length..];
+  }
+
+  let configuration = active_configuration.and_then(|value| {
+    configurations
+      .iter()
+      .find(|c| c.configuration_value == value)
+      .cloned()
+  });
+
+  let mut device = new_device(
+    &device_descriptor,
+    strings,
+    configurations,
+    configuration,
+    None,
+    topology,
+  );
+  device.backend = backend;
+  Ok(device)
+}
+
+/// String descriptors of a device, in the first language it supports.
+pub(crate) struct DeviceStrings {
+  pub manufacturer_name: Option<String>,
+  pub product_name: Option<String>,
+  pub serial_number: Option<String>,
+}
+
+fn new_device(
+  device_descriptor: &DeviceDescriptor,
+  strings: DeviceStrings,
+  configurations: Vec<UsbConfiguration>,
+  configuration: Option<UsbConfiguration>,
+  url: Option<String>,
+  topology: Topology,
+) -> UsbDevice {
+  let usb_version = device_descriptor.usb_version;
+  let device_version = device_descriptor.device_version;
+
+  UsbDevice {
+    configurations,
+    configuration,
+    device_class: device_descriptor.class,
+    device_subclass: device_descriptor.subclass,
+    device_protocol: device_descriptor.protocol,
+    device_version_major: bcd_major(device_version),
+    device_version_minor: ((device_version >> 4) & 0xF) as u8,
+    device_version_subminor: (device_version & 0xF) as u8,
+    product_id: device_descriptor.product_id,
+    usb_version_major: bcd_major(usb_version),
+    usb_version_minor: ((usb_version >> 4) & 0xF) as u8,
+    usb_version_subminor: (usb_version & 0xF) as u8,
+    vendor_id: device_descriptor.vendor_id,
+    manufacturer_name: strings.manufacturer_name,
+    product_name: strings.product_name,
+    serial_number: strings.serial_number,
+    opened: false,
+    bus_number: topology.bus_number,
+    device_address: topology.device_address,
+    port_numbers: topology.port_numbers,
+    speed: topology.speed,
+    parent_hub: topology.parent_hub,
+    url,
+    backend: crate::backend::Detached::boxed(),
+  }
+}
+
+// Major version JJ of a 0xJJMN binary-coded decimal.
+fn bcd_major(bcd: u16) -> u8 {
+  (((bcd >> 12) & 0xF) * 10 + ((bcd >> 8) & 0xF)) as u8
+}
+
+fn read_string(
+  backend: &mut BoxedBackend,
+  language_id: Option<u16>,
+  index: u8,
+) -> Option<String> {
+  if index == 0 {
+    return None;
+  }
+  let bytes =
+    get_descriptor(backend, STRING_DESCRIPTOR_TYPE, index, language_id?, 255)
+      .ok()?;
+  parse_string_descriptor(&bytes)
+}
+
+fn get_descriptor(
+  backend: &mut BoxedBackend,
+  descriptor_type: u8,
+  descriptor_index: u8,
+  language_id: u16,
+  length: usize,
+) -> Result<Vec<u8>> {
+  let mut buffer = vec![0; length];
+  let length = backend.control_in(
+    REQUEST_TYPE_STANDARD_IN,
+    GET_DESCRIPTOR_REQUEST,
+    (descriptor_type as u16) << 8 | descriptor_index as u16,
+    language_id,
+    &mut buffer,
+  )?;
+  buffer.truncate(length);
+  Ok(buffer)
+}
+
+fn read_config_descriptor(
+  backend: &mut BoxedBackend,
+  config_idx: u8,
+) -> Result<Vec<u8>> {
+  // Read the header first to learn wTotalLength.
+  let header =
+    get_descriptor(backend, CONFIGURATION_DESCRIPTOR_TYPE, config_idx, 0, 9)?;
+  if header.len() < 4 {
+    return Err(Error::NotFound);
+  }
+
+  let total_length = u16::from_le_bytes([header[2], header[3]]);
+  get_descriptor(
+    backend,
+    CONFIGURATION_DESCRIPTOR_TYPE,
+    config_idx,
+    0,
+    total_length as usize,
+  )
+}
+
+fn read_webusb_url(backend: &mut BoxedBackend) -> Option<String> {
+  // Read the BOS header first to learn wTotalLength.
+  let header =
+    get_descriptor(backend, BOS_DESCRIPTOR_TYPE as u8, 0, 0, 5).ok()?;
+  if header.len() < 5 {
+    return None;
+  }
+
+  let total_length = u16::from_le_bytes([header[2], header[3]]);
+  let bos = get_descriptor(
+    backend,
+    BOS_DESCRIPTOR_TYPE as u8,
+    0,
+    0,
+    total_length as usize,
+  )
+  .ok()?;
+  let (vendor_code, landing_page_id) = parse_bos(&bos)?;
+
+  let mut buffer = [0; 255];
+  backend
+    .control_in(
+      REQUEST_TYPE_VENDOR_IN,
+      vendor_code,
+      landing_page_id as u16,
+      GET_URL_REQUEST,
+      &mut buffer,
+    )
+    .ok()?;
+  parse_webusb_url(&buffer)
+}
+
+impl UsbConfiguration {
+  fn from_descriptor(
+    config_descriptor: ConfigDescriptor,
+    raw_descriptor: Vec<u8>,
+    speed: UsbSpeed,
+    read_string: &mut dyn FnMut(u8) -> Option<String>,
+  ) -> Self {
+    // bMaxPower is in 8mA units at SuperSpeed or faster, 2mA otherwise.
+    let max_power = match speed {
+      UsbSpeed::Super | UsbSpeed::SuperPlus => {
+        config_descriptor.max_power as u16 * 8
+      }
+      _ => config_descriptor.max_power as u16 * 2,
+    };
+
+    // Alternate settings of an interface are listed one after another.
+    let mut interfaces: Vec<UsbInterface> = vec![];
+    for descriptor in &config_descriptor.interfaces {
+      let alternate = UsbAlternateInterface::from_descriptor(
+        descriptor,
+        read_string(descriptor.interface_index),
+      );
+      match interfaces
+        .iter_mut()
+        .find(|itf| itf.interface_number == descriptor.interface_number)
+      {
+        Some(interface) => interface.alternates.push(alternate),
+        None => interfaces.push(UsbInterface {
+          interface_number: descriptor.interface_number,
+          alternate: alternate.clone(),
+          alternates: vec![alternate],
+          claimed: false,
+        }),
+      }
+    }
+
+    // By default, the alternate setting is for the interface with
+    // bAlternateSetting equal to 0.
+    for interface in interfaces.iter_mut() {
+      if let Some(alternate) = interface.alternate_setting(0) {
+        interface.alternate = alternate.clone();
+      }
+    }
+
+    UsbConfiguration {
+      configuration_name: read_string(config_descriptor.configuration_index),
+      configuration_value: config_descriptor.configuration_value,
+      interfaces,
+      self_powered: config_descriptor.attributes & (1 << 6) != 0,
+      remote_wakeup: config_descriptor.attributes & (1 << 5) != 0,
+      max_power,
+      raw_descriptor: Some(raw_descriptor),
+    }
+  }
+}
+
+impl UsbAlternateInterface {
+  fn from_descriptor(
+    descriptor: &InterfaceDescriptor,
+    interface_name: Option<String>,
+  ) -> Self {
+    UsbAlternateInterface {
+      alternate_setting: descriptor.alternate_setting,
+      interface_class: descriptor.class,
+      interface_subclass: descriptor.subclass,
+      interface_protocol: descriptor.protocol,
+      interface_name,
+      endpoints: descriptor
+        .endpoints
+        .iter()
+        .map(UsbEndpoint::from_descriptor)
+        .collect(),
+    }
+  }
+}
+
+impl UsbEndpoint {
+  fn from_descriptor(descriptor: &EndpointDescriptor) -> Self {
+    let r#type = match descriptor.attributes & 0x3 {
+      0 => UsbEndpointType::Control,
+      1 => UsbEndpointType::Isochronous,
+      2 => UsbEndpointType::Bulk,
+      _ => UsbEndpointType::Interrupt,
+    };
+
+    let max_packet_size = descriptor.max_packet_size;
+
+    UsbEndpoint {
+      endpoint_number: descriptor.address & 0xF,
+      packet_size: max_packet_size & 0x7FF,
+      transactions_per_microframe: ((max_packet_size >> 11) & 0x3) as u8 + 1,
+      direction: match descriptor.address & 0x80 {
+        0 => Direction::Out,
+        _ => Direction::In,
+      },
+      interval: descriptor.interval,
+      sync_type: match (descriptor.attributes >> 2) & 0x3 {
+        0 => UsbSyncType::None,
+        1 => UsbSyncType::Asynchronous,
+        2 => UsbSyncType::Adaptive,
+        _ => UsbSyncType::Synchronous,
+      },
+      usage_type: match (descriptor.attributes >> 4) & 0x3 {
+        0 => UsbUsageType::Data,
+        1 => UsbUsageType::Feedback,
+        2 => UsbUsageType::ImplicitFeedbackData,
+        _ => UsbUsageType::Reserved,
+      },
+      superspeed_companion: parse_ss_endpoint_companion(&descriptor.extra).map(
+        |companion| UsbSuperSpeedEndpointCompanion::from_raw(companion, r#type),
+      ),
+      r#type,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Interface 0 with alternate settings 0 (bulk endpoints 0x81 and 0x02)
+  // and 1 (interrupt endpoint 0x83), and a boot keyboard interface 1.
+  const CONFIG_DESCRIPTOR: &[u8] = &[
+    0x09, 0x02, 0x39, 0x00, 0x02, 0x01, 0x00, 0xE0, 0x32, // Configuration
+    0x09, 0x04, 0x00, 0x00, 0x02, 0xFF, 0x00, 0x00, 0x00, // Interface 0
+    0x07, 0x05, 0x81, 0x02, 0x40, 0x00, 0x00, // Bulk IN 1
+    0x07, 0x05, 0x02, 0x02, 0x40, 0x00, 0x00, // Bulk OUT 2
+    0x09, 0x04, 0x00, 0x01, 0x01, 0x0A, 0x00, 0x00, 0x00, // Alternate 1
+    0x07, 0x05, 0x83, 0x03, 0x08, 0x00, 0x0A, // Interrupt IN 3
+    0x09, 0x04, 0x01, 0x00, 0x00, 0x03, 0x01, 0x01, 0x00, // Interface 1
+  ];
+
+  fn configuration(speed: UsbSpeed) -> UsbConfiguration {
+    UsbConfiguration::from_descriptor(
+      parse_config_descriptor(CONFIG_DESCRIPTOR).unwrap(),
+      CONFIG_DESCRIPTOR.to_vec(),
+      speed,
+      &mut |_| None,
+    )
+  }
+
+  #[test]
+  fn test_configuration_attributes() {
+    let high_speed = configuration(UsbSpeed::High);
+    assert_eq!(high_speed.configuration_value(), 1);
+    assert!(high_speed.self_powered());
+    assert!(high_speed.remote_wakeup());
+    // bMaxPower is 0x32, in 2mA units.
+    assert_eq!(high_speed.max_power(), 100);
+    assert_eq!(high_speed.raw_descriptor(), Some(CONFIG_DESCRIPTOR));
+
+    // In 8mA units at SuperSpeed or faster.
+    assert_eq!(configuration(UsbSpeed::Super).max_power(), 400);
+    assert_eq!(configuration(UsbSpeed::SuperPlus).max_power(), 400);
+  }
+
+  #[test]
+  fn test_find_interface() {
+    let configuration = configuration(UsbSpeed::High);
+    assert_eq!(configuration.interfaces().len(), 2);
+    assert!(configuration.interface(2).is_none());
+
+    // Alternate settings other than the selected one are matched too.
+    let interface = configuration.find_interface(0x0A, 0x00, 0x00).unwrap();
+    assert_eq!(interface.interface_number(), 0);
+    assert_eq!(interface.alternate().alternate_setting, 0);
+    assert_eq!(interface.alternates().len(), 2);
+    let alternate = interface.alternate_setting(1).unwrap();
+    assert_eq!(alternate.endpoints.len(), 1);
+    assert_eq!(
+      alternate.endpoints[0].endpoint_type(),
+      UsbEndpointType::Interrupt
+    );
+    assert!(interface.alternate_setting(2).is_none());
+
+    let interface = configuration.find_interface(0x03, 0x01, 0x01).unwrap();
+    assert_eq!(interface.interface_number(), 1);
+    assert!(interface.endpoints().is_empty());
+    assert!(configuration.find_interface(0x03, 0x01, 0x02).is_none());
+  }
+
+  #[test]
+  fn test_endpoint() {
+    let configuration = configuration(UsbSpeed::High);
+    let endpoint = configuration.endpoint(0x81).unwrap();
+    assert_eq!(endpoint.endpoint_number(), 1);
+    assert_eq!(endpoint.direction(), Direction::In);
+    assert_eq!(endpoint.endpoint_type(), UsbEndpointType::Bulk);
+    assert_eq!(endpoint.packet_size(), 64);
+    let endpoint = configuration.endpoint(0x02).unwrap();
+    assert_eq!(endpoint.direction(), Direction::Out);
+    assert!(configuration.endpoint(0x82).is_none());
+    // Only the selected alternate settings are searched.
+    assert!(configuration.endpoint(0x83).is_none());
+  }
+
+  fn device(
+    bus_number: u8,
+    port_numbers: Vec<u8>,
+    speed: UsbSpeed,
+  ) -> UsbDevice {
+    // USB 3.20 device 1d6b:0104, release 2.01.
+    let device_descriptor = parse_device_descriptor(&[
+      0x12, 0x01, 0x20, 0x03, 0x00, 0x00, 0x00, 0x09, 0x6B, 0x1D, 0x04, 0x01,
+      0x01, 0x02, 0x00, 0x00, 0x00, 0x01,
+    ])
+    .unwrap();
+    let strings = DeviceStrings {
+      manufacturer_name: None,
+      product_name: None,
+      serial_number: None,
+    };
+    let topology = Topology {
+      bus_number,
+      device_address: 4,
+      port_numbers,
+      speed,
+      parent_hub: None,
+    };
+    new_device(&device_descriptor, strings, vec![], None, None, topology)
+  }
+
+  #[test]
+  fn test_port_path() {
+    let attached = device(1, vec![1, 3], UsbSpeed::SuperPlus);
+    assert_eq!(attached.port_path(), "1-1.3");
+    assert_eq!(attached.speed, UsbSpeed::SuperPlus);
+    assert_eq!(attached.device_address, 4);
+    assert_eq!(attached.usb_version_major, 3);
+    assert_eq!(attached.usb_version_minor, 2);
+    assert_eq!(attached.vendor_id, 0x1D6B);
+    assert_eq!(attached.product_id, 0x0104);
+
+    // Root hubs have no port numbers.
+    let root_hub = device(2, vec![], UsbSpeed::High);
+    assert_eq!(root_hub.port_path(), "usb2");
+    assert_eq!(root_hub.speed, UsbSpeed::High);
+  }
+}
@@ -0,0 +1,630 @@
+// `deno_bindgen` exports take buffers and structs as a pointer and a
+// length, turned into slices by the wrapper it generates around each
+// function. They are only called through the bindings it generates for
+// Deno, which pass the length of the `Uint8Array` along with it, so
+// functions filling a buffer (`transfer_in`, `read_chunk`, ...) stay
+// within `buffer.len()`.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use core::convert::TryFrom;
+use deno_bindgen::deno_bindgen;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::sync::Mutex;
+
+use super::resources;
+use super::resources::packet_statuses;
+use super::resources::read_into;
+use super::resources::read_packets;
+use super::resources::run_transfer;
+use super::resources::with_device;
+use super::resources::with_token;
+use super::resources::Resources;
+use super::resources::RESOURCES;
+use crate::constants::HUB_CLASS;
+use crate::transfer_status;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbConfiguration;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbDeviceFilter;
+
+/// Cancels the transfer started with `token`. It rejects with an
+/// AbortError.
+#[deno_bindgen]
+pub fn cancel(token: u32) {
+  resources::cancel(token);
+}
+
+/// Error of a call, as seen from JS. `name` is the name of the
+/// DOMException to reject with.
+#[deno_bindgen]
+pub struct FfiError {
+  name: String,
+  message: String,
+}
+
+impl From<Error> for FfiError {
+  fn from(err: Error) -> Self {
+    FfiError {
+      name: err.dom_exception_name().to_string(),
+      message: format!("{:?}", err),
+    }
+  }
+}
+
+// Splits `result` into the `ok` and `error` fields of the result types
+// below.
+fn split<T>(result: Result<T>) -> (Option<T>, Option<FfiError>) {
+  match result {
+    Ok(value) => (Some(value), None),
+    Err(err) => (None, Some(err.into())),
+  }
+}
+
+/// Attributes of a device as seen from JS.
+#[deno_bindgen]
+pub struct DeviceInfo {
+  rid: u32,
+  device: UsbDevice,
+}
+impl DeviceInfo {
+  fn new(rid: u32, device: &UsbDevice) -> Self {
+    DeviceInfo {
+      rid,
+      device: UsbDevice {
+        configurations: device.configurations.clone(),
+        configuration: device.configuration.clone(),
+        device_class: device.device_class,
+        device_subclass: device.device_subclass,
+        device_protocol: device.device_protocol,
+        device_version_major: device.device_version_major,
+        device_version_minor: device.device_version_minor,
+        device_version_subminor: device.device_version_subminor,
+        manufacturer_name: device.manufacturer_name.clone(),
+        product_id: device.product_id,
+        product_name: device.product_name.clone(),
+        serial_number: device.serial_number.clone(),
+        usb_version_major: device.usb_version_major,
+        usb_version_minor: device.usb_version_minor,
+        usb_version_subminor: device.usb_version_subminor,
+        vendor_id: device.vendor_id,
+        opened: device.opened,
+        bus_number: device.bus_number,
+        device_address: device.device_address,
+        port_numbers: device.port_numbers.clone(),
+        speed: device.speed,
+        parent_hub: device.parent_hub.clone(),
+        url: device.url.clone(),
+        backend: crate::backend::Detached::boxed(),
+      },
+    }
+  }
+}
+
+/// Attributes of a device that change with its state.
+#[deno_bindgen]
+pub struct DeviceState {
+  opened: bool,
+  configuration: Option<UsbConfiguration>,
+}
+
+impl From<&mut UsbDevice> for DeviceState {
+  fn from(device: &mut UsbDevice) -> Self {
+    DeviceState {
+      opened: device.opened,
+      configuration: device.configuration.clone(),
+    }
+  }
+}
+
+#[deno_bindgen]
+pub struct Devices {
+  devices: Vec<DeviceInfo>,
+}
+
+#[deno_bindgen]
+pub struct DevicesResult {
+  ok: Option<Devices>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<Devices>> for DevicesResult {
+  fn from(result: Result<Devices>) -> Self {
+    let (ok, error) = split(result);
+    DevicesResult { ok, error }
+  }
+}
+
+#[deno_bindgen]
+pub struct StateResult {
+  ok: Option<DeviceState>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<DeviceState>> for StateResult {
+  fn from(result: Result<DeviceState>) -> Self {
+    let (ok, error) = split(result);
+    StateResult { ok, error }
+  }
+}
+
+#[deno_bindgen]
+pub struct DeviceResult {
+  ok: Option<DeviceInfo>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<DeviceInfo>> for DeviceResult {
+  fn from(result: Result<DeviceInfo>) -> Self {
+    let (ok, error) = split(result);
+    DeviceResult { ok, error }
+  }
+}
+
+/// Outcome of a transfer, the `USBTransferStatus` and the number of bytes
+/// transferred.
+#[deno_bindgen]
+pub struct Transfer {
+  status: String,
+  length: usize,
+}
+
+impl Transfer {
+  fn new((status, length): (&'static str, usize)) -> Self {
+    Transfer {
+      status: status.to_string(),
+      length,
+    }
+  }
+}
+
+#[deno_bindgen]
+pub struct TransferResult {
+  ok: Option<Transfer>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<Transfer>> for TransferResult {
+  fn from(result: Result<Transfer>) -> Self {
+    let (ok, error) = split(result);
+    TransferResult { ok, error }
+  }
+}
+
+#[deno_bindgen]
+pub struct ReaderResult {
+  ok: Option<u32>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<u32>> for ReaderResult {
+  fn from(result: Result<u32>) -> Self {
+    let (ok, error) = split(result);
+    ReaderResult { ok, error }
+  }
+}
+
+/// Result of `read_chunk`, the length of the chunk.
+#[deno_bindgen]
+pub struct ChunkResult {
+  ok: Option<usize>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<usize>> for ChunkResult {
+  fn from(result: Result<usize>) -> Self {
+    let (ok, error) = split(result);
+    ChunkResult { ok, error }
+  }
+}
+
+/// Devices connected and resource IDs of devices disconnected since the
+/// last call to `hotplug`.
+#[deno_bindgen]
+pub struct HotplugEvents {
+  connected: Vec<DeviceInfo>,
+  disconnected: Vec<u32>,
+}
+
+#[deno_bindgen]
+pub struct HotplugResult {
+  ok: Option<HotplugEvents>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<HotplugEvents>> for HotplugResult {
+  fn from(result: Result<HotplugEvents>) -> Self {
+    let (ok, error) = split(result);
+    HotplugResult { ok, error }
+  }
+}
+
+#[deno_bindgen]
+pub struct EmptyResult {
+  error: Option<FfiError>,
+}
+
+impl From<Result<()>> for EmptyResult {
+  fn from(result: Result<()>) -> Self {
+    EmptyResult {
+      error: result.err().map(Into::into),
+    }
+  }
+}
+
+#[deno_bindgen]
+pub struct FfiDirection {
+  inner: Direction,
+}
+
+// Hands `device` out to JS.
+fn device_info(resources: &mut Resources, device: UsbDevice) -> DeviceInfo {
+  let info = DeviceInfo::new(0, &device);
+  DeviceInfo {
+    rid: resources.insert(device),
+    ..info
+  }
+}
+
+// Enumerates the devices, dropping the resources of those no longer
+// connected.
+fn devices(resources: &Mutex<Resources>) -> Result<Vec<UsbDevice>> {
+  let devices = crate::Context::init()?.devices()?;
+  let connected = devices
+    .iter()
+    .map(|device| (device.bus_number, device.device_address))
+    .collect::<Vec<_>>();
+  resources.lock().unwrap().retain_connected(&connected);
+  Ok(devices)
+}
+
+#[deno_bindgen(non_blocking)]
+pub fn get_devices() -> DevicesResult {
+  devices(&RESOURCES)
+    .map(|devices| {
+      let mut resources = RESOURCES.lock().unwrap();
+      let devices = devices
+        .into_iter()
+        .map(|device| device_info(&mut resources, device))
+        .collect();
+      Devices { devices }
+    })
+    .into()
+}
+
+#[deno_bindgen]
+pub struct FfiUsbDeviceRequestOptions {
+  filters: Vec<UsbDeviceFilter>,
+}
+
+/// There is no chooser, resolves to the first device matching one of the
+/// filters.
+#[deno_bindgen(non_blocking)]
+pub fn request_device(options: FfiUsbDeviceRequestOptions) -> DeviceResult {
+  devices(&RESOURCES)
+    .and_then(|devices| {
+      let device = devices
+        .into_iter()
+        .find(|device| {
+          options.filters.is_empty()
+            || options.filters.iter().any(|filter| filter.matches(device))
+        })
+        .ok_or(Error::NotFound)?;
+      Ok(device_info(&mut RESOURCES.lock().unwrap(), device))
+    })
+    .into()
+}
+
+// Bus number and address of the devices seen by a call to `hotplug`.
+type Present = HashSet<(u8, u8)>;
+
+// Devices seen by the last call to `hotplug`. None before the first call.
+static KNOWN: Lazy<Mutex<Option<Present>>> = Lazy::new(|| Mutex::new(None));
+
+/// Checks the device list for hotplug events. The first call only records
+/// the devices present. Lists devices through libusb without opening
+/// those already known, so it is cheap to poll.
+#[deno_bindgen(non_blocking)]
+pub fn hotplug() -> HotplugResult {
+  hotplug_events().into()
+}
+
+fn hotplug_events() -> Result<HotplugEvents> {
+  let context = rusb::Context::new()?;
+  // Hubs are not listed by `get_devices` either.
+  let devices = rusb::UsbContext::devices(&context)?
+    .iter()
+    .filter(|device| {
+      matches!(
+        device.device_descriptor(),
+        Ok(descriptor) if descriptor.class_code() != HUB_CLASS
+      )
+    })
+    .map(|device| ((device.bus_number(), device.address()), device))
+    .collect::<Vec<_>>();
+  let present = devices.iter().map(|(key, _)| *key).collect::<HashSet<_>>();
+
+  let mut known = KNOWN.lock().unwrap();
+  let connected = match known.as_ref() {
+    Some(known) => devices
+      .into_iter()
+      .filter(|(key, _)| !known.contains(key))
+      .filter_map(|(_, device)| UsbDevice::try_from(device).ok())
+      .collect(),
+    None => vec![],
+  };
+
+  let mut resources = RESOURCES.lock().unwrap();
+  resources.retain_connected(&present.iter().copied().collect::<Vec<_>>());
+  *known = Some(present);
+  Ok(HotplugEvents {
+    connected: connected
+      .into_iter()
+      .map(|device| device_info(&mut resources, device))
+      .collect(),
+    disconnected: resources.take_disconnected(),
+  })
+}
+
+/// Drops the reference to `rid` held by a `DeviceInfo`, once JS garbage
+/// collected the `UsbDevice` built from it.
+#[deno_bindgen]
+pub fn release(rid: u32) {
+  RESOURCES.lock().unwrap().release(rid);
+}
+
+/// Closes `rid` and removes it for every `UsbDevice` referring to it.
+#[deno_bindgen]
+pub fn forget(rid: u32) {
+  RESOURCES.lock().unwrap().remove(rid);
+}
+
+macro_rules! wrap_ffi_method {
+  ($method: ident) => {
+    #[deno_bindgen(non_blocking)]
+    pub fn $method(rid: u32) -> StateResult {
+      with_device(rid, |device| {
+        device.$method()?;
+        Ok(DeviceState::from(device))
+      })
+      .into()
+    }
+  };
+}
+
+wrap_ffi_method!(open);
+wrap_ffi_method!(close);
+wrap_ffi_method!(reset);
+
+#[deno_bindgen(non_blocking)]
+pub fn transfer_out(
+  rid: u32,
+  endpoint_number: u8,
+  data: &[u8],
+  token: u32,
+) -> TransferResult {
+  let result = with_token(token, |cancelled| {
+    let buffer = data.to_vec();
+    run_transfer(rid, Direction::Out, endpoint_number, buffer, cancelled)
+  });
+  transfer_status(result.map(|written| written.len()))
+    .map(Transfer::new)
+    .into()
+}
+
+/// Reads up to `buffer.len()` bytes from IN endpoint `endpoint_number`
+/// into `buffer`.
+#[deno_bindgen(non_blocking)]
+pub fn transfer_in(
+  rid: u32,
+  endpoint_number: u8,
+  buffer: &mut [u8],
+  token: u32,
+) -> TransferResult {
+  let result = with_token(token, |cancelled| {
+    let data = vec![0; buffer.len()];
+    run_transfer(rid, Direction::In, endpoint_number, data, cancelled)
+  });
+  transfer_status(result)
+    .map(|(status, data)| Transfer::new((status, read_into(buffer, data))))
+    .into()
+}
+
+#[deno_bindgen(non_blocking)]
+pub fn clear_halt(
+  rid: u32,
+  direction: FfiDirection,
+  endpoint_number: u8,
+) -> EmptyResult {
+  with_device(rid, |device| {
+    device.clear_halt(direction.inner, endpoint_number)
+  })
+  .into()
+}
+
+#[deno_bindgen]
+pub struct FfiPacketLengths {
+  inner: Vec<u32>,
+}
+
+/// Status and length of every packet of an isochronous transfer.
+#[deno_bindgen]
+pub struct IsochronousTransfer {
+  packets: Vec<Transfer>,
+}
+
+#[deno_bindgen]
+pub struct IsochronousTransferResult {
+  ok: Option<IsochronousTransfer>,
+  error: Option<FfiError>,
+}
+
+impl From<Result<Vec<Transfer>>> for IsochronousTransferResult {
+  fn from(result: Result<Vec<Transfer>>) -> Self {
+    let (ok, error) =
+      split(result.map(|packets| IsochronousTransfer { packets }));
+    IsochronousTransferResult { ok, error }
+  }
+}
+
+/// Receives one packet per entry of `packet_lengths` from IN endpoint
+/// `endpoint_number` into `buffer`, which holds all of them. Each packet
+/// is copied to the offset of its requested length. Isochronous transfers
+/// cannot be cancelled.
+#[deno_bindgen(non_blocking)]
+pub fn isochronous_transfer_in(
+  rid: u32,
+  endpoint_number: u8,
+  packet_lengths: FfiPacketLengths,
+  buffer: &mut [u8],
+) -> IsochronousTransferResult {
+  let packet_lengths = packet_lengths.inner;
+  with_device(rid, |device| {
+    device.isochronous_transfer_in(endpoint_number, &packet_lengths)
+  })
+  .and_then(|packets| read_packets(buffer, &packet_lengths, packets))
+  .map(|packets| packets.into_iter().map(Transfer::new).collect())
+  .into()
+}
+
+/// Sends `data` to OUT endpoint `endpoint_number`, one packet per entry of
+/// `packet_lengths`. Isochronous transfers cannot be cancelled.
+#[deno_bindgen(non_blocking)]
+pub fn isochronous_transfer_out(
+  rid: u32,
+  endpoint_number: u8,
+  data: &[u8],
+  packet_lengths: FfiPacketLengths,
+) -> IsochronousTransferResult {
+  with_device(rid, |device| {
+    device.isochronous_transfer_out(
+      endpoint_number,
+      data,
+      &packet_lengths.inner,
+    )
+  })
+  .and_then(packet_statuses)
+  .map(|packets| packets.into_iter().map(Transfer::new).collect())
+  .into()
+}
+
+#[deno_bindgen]
+pub struct FfiUsbControlTransferParameters {
+  inner: UsbControlTransferParameters,
+}
+
+/// Control transfers are made while holding the device, `token` only
+/// cancels them before they start.
+#[deno_bindgen(non_blocking)]
+pub fn control_transfer_out(
+  rid: u32,
+  setup: FfiUsbControlTransferParameters,
+  data: &[u8],
+  token: u32,
+) -> TransferResult {
+  with_token(token, |cancelled| {
+    if cancelled() {
+      return Err(Error::Io(ErrorKind::Interrupted));
+    }
+    with_device(rid, |device| {
+      let result = device.control_transfer_out(setup.inner, data);
+      transfer_status(result).map(Transfer::new)
+    })
+  })
+  .into()
+}
+
+/// Reads up to `buffer.len()` bytes of a control transfer into `buffer`.
+#[deno_bindgen(non_blocking)]
+pub fn control_transfer_in(
+  rid: u32,
+  setup: FfiUsbControlTransferParameters,
+  buffer: &mut [u8],
+  token: u32,
+) -> TransferResult {
+  with_token(token, |cancelled| {
+    if cancelled() {
+      return Err(Error::Io(ErrorKind::Interrupted));
+    }
+    with_device(rid, |device| {
+      let result = device.control_transfer_in(setup.inner, buffer.len());
+      let (status, data) = transfer_status(result)?;
+      Ok(Transfer::new((status, read_into(buffer, data))))
+    })
+  })
+  .into()
+}
+
+#[deno_bindgen(non_blocking)]
+fn select_alternate_interface(
+  rid: u32,
+  interface_number: u8,
+  alternate_setting: u8,
+) -> StateResult {
+  with_device(rid, |device| {
+    device.select_alternate_interface(interface_number, alternate_setting)?;
+    Ok(DeviceState::from(device))
+  })
+  .into()
+}
+
+#[deno_bindgen(non_blocking)]
+fn release_interface(rid: u32, interface_number: u8) -> StateResult {
+  with_device(rid, |device| {
+    device.release_interface(interface_number)?;
+    Ok(DeviceState::from(device))
+  })
+  .into()
+}
+
+#[deno_bindgen(non_blocking)]
+fn claim_interface(rid: u32, interface_number: u8) -> StateResult {
+  with_device(rid, |device| {
+    device.claim_interface(interface_number)?;
+    Ok(DeviceState::from(device))
+  })
+  .into()
+}
+
+#[deno_bindgen(non_blocking)]
+fn select_configuration(rid: u32, configuration_value: u8) -> StateResult {
+  with_device(rid, |device| {
+    device.select_configuration(configuration_value)?;
+    Ok(DeviceState::from(device))
+  })
+  .into()
+}
+
+/// Starts reading IN endpoint `endpoint_number` in transfers of
+/// `transfer_size` bytes on a separate thread. At most `queue_length`
+/// chunks are read ahead of `read_chunk`, after that the thread waits, so
+/// the endpoint is only read as fast as JS consumes the data. Returns the
+/// ID of the reader.
+#[deno_bindgen]
+pub fn open_reader(
+  rid: u32,
+  endpoint_number: u8,
+  transfer_size: usize,
+  queue_length: usize,
+) -> ReaderResult {
+  resources::open_reader(rid, endpoint_number, transfer_size, queue_length)
+    .into()
+}
+
+/// Waits for the next chunk of `reader` and copies it into `buffer`,
+/// which should hold `transfer_size` bytes. Fails with the error that
+/// stopped the reader, if any.
+#[deno_bindgen(non_blocking)]
+pub fn read_chunk(reader: u32, buffer: &mut [u8]) -> ChunkResult {
+  resources::read_chunk(reader, buffer).into()
+}
+
+/// Stops `reader`. Chunks read ahead are dropped.
+#[deno_bindgen]
+pub fn close_reader(reader: u32) {
+  resources::close_reader(reader);
+}
@@ -0,0 +1,543 @@
+// State behind the exports: the devices handed out to JS, the tokens of
+// cancelled transfers and the readers of `ReadableStream`s. Kept apart
+// from `deno_bindgen`, which is not part of test builds.
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::transfer_status;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbDevice;
+
+struct Resource {
+  device: Arc<Mutex<UsbDevice>>,
+  bus_number: u8,
+  device_address: u8,
+  // Number of `DeviceInfo`s handed out to JS that were not released yet.
+  refs: usize,
+}
+
+/// Devices handed out to JS, keyed by resource ID. JS only holds the ID,
+/// the device and its state (configuration, claimed interfaces) live here.
+#[derive(Default)]
+pub struct Resources {
+  next_rid: u32,
+  devices: HashMap<u32, Resource>,
+  // Devices removed by `retain_connected` that were not yet reported by
+  // `hotplug`.
+  disconnected: Vec<u32>,
+}
+
+impl Resources {
+  /// Returns the resource ID of `device`, reusing the existing one when
+  /// the device at the same bus and address was handed out before.
+  pub fn insert(&mut self, device: UsbDevice) -> u32 {
+    let existing = self.devices.iter_mut().find(|(_, resource)| {
+      resource.bus_number == device.bus_number
+        && resource.device_address == device.device_address
+    });
+    if let Some((rid, resource)) = existing {
+      resource.refs += 1;
+      return *rid;
+    }
+
+    let rid = self.next_rid;
+    self.next_rid = self.next_rid.wrapping_add(1);
+    self.devices.insert(
+      rid,
+      Resource {
+        bus_number: device.bus_number,
+        device_address: device.device_address,
+        device: Arc::new(Mutex::new(device)),
+        refs: 1,
+      },
+    );
+    rid
+  }
+
+  /// Drops one reference to `rid`, removing the device once JS no longer
+  /// holds any.
+  pub fn release(&mut self, rid: u32) {
+    let unused = match self.devices.get_mut(&rid) {
+      Some(resource) => {
+        resource.refs -= 1;
+        resource.refs == 0
+      }
+      None => false,
+    };
+    if unused {
+      self.remove(rid);
+    }
+  }
+
+  /// Removes `rid`, closing the device if it is still open.
+  pub fn remove(&mut self, rid: u32) {
+    if let Some(resource) = self.devices.remove(&rid) {
+      // A pending call keeps its own reference, the device is closed
+      // when that call returns and the last reference is dropped.
+      if let Ok(mut device) = resource.device.try_lock() {
+        let _ = device.close();
+      }
+    }
+  }
+
+  /// Removes all devices not at one of the bus and address pairs in
+  /// `connected`.
+  pub fn retain_connected(&mut self, connected: &[(u8, u8)]) {
+    let disconnected = self
+      .devices
+      .iter()
+      .filter(|(_, resource)| {
+        !connected.contains(&(resource.bus_number, resource.device_address))
+      })
+      .map(|(rid, _)| *rid)
+      .collect::<Vec<_>>();
+    for rid in disconnected {
+      self.remove(rid);
+      self.disconnected.push(rid);
+    }
+  }
+
+  /// Resource IDs of the devices removed by `retain_connected` since the
+  /// last call.
+  pub fn take_disconnected(&mut self) -> Vec<u32> {
+    self.disconnected.drain(..).collect()
+  }
+}
+
+pub static RESOURCES: Lazy<Mutex<Resources>> =
+  Lazy::new(|| Mutex::new(Resources::default()));
+
+// Runs `f` on the device with resource ID `rid`. Other devices stay
+// available meanwhile.
+pub(super) fn with_device<T>(
+  rid: u32,
+  f: impl FnOnce(&mut UsbDevice) -> Result<T>,
+) -> Result<T> {
+  let device = RESOURCES
+    .lock()
+    .unwrap()
+    .devices
+    .get(&rid)
+    .ok_or(Error::NotFound)?
+    .device
+    .clone();
+  let mut device = device.lock().unwrap();
+  f(&mut device)
+}
+
+// How often pending bulk and interrupt transfers check whether they were
+// cancelled.
+const CANCEL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Tokens of the transfers JS asked to cancel.
+static CANCELLED: Lazy<Mutex<HashSet<u32>>> =
+  Lazy::new(|| Mutex::new(HashSet::new()));
+
+// Starts a bulk or interrupt transfer on the device with resource ID
+// `rid` and waits for it without holding the device, so other calls on
+// the device run meanwhile. The transfer is cancelled, failing with
+// `ErrorKind::Interrupted`, once `cancelled` returns true. It times out
+// like `UsbDevice::transfer_in` and `UsbDevice::transfer_out`.
+pub(super) fn run_transfer(
+  rid: u32,
+  direction: Direction,
+  endpoint_number: u8,
+  buffer: Vec<u8>,
+  cancelled: impl Fn() -> bool,
+) -> Result<Vec<u8>> {
+  if cancelled() {
+    return Err(Error::Io(ErrorKind::Interrupted));
+  }
+  let transfer = with_device(rid, |device| {
+    device.submit_transfer(direction, endpoint_number, buffer)
+  })?;
+  while !transfer.wait(Some(CANCEL_INTERVAL))? {
+    if cancelled() {
+      transfer.cancel();
+    }
+  }
+  transfer.take()
+}
+
+// Runs `f` with `token`, which `cancel(token)` may have been called with.
+pub(super) fn with_token<T>(
+  token: u32,
+  f: impl FnOnce(&dyn Fn() -> bool) -> T,
+) -> T {
+  let result = f(&|| CANCELLED.lock().unwrap().remove(&token));
+  // `cancel` may have come in after the last check.
+  CANCELLED.lock().unwrap().remove(&token);
+  result
+}
+
+// Cancels the call running `with_token(token, ...)`, or the next one.
+pub(super) fn cancel(token: u32) {
+  CANCELLED.lock().unwrap().insert(token);
+}
+
+// Copies `data` into the start of the JS-owned `buffer`, returning the
+// number of bytes received.
+pub(super) fn read_into(buffer: &mut [u8], data: Vec<u8>) -> usize {
+  let len = data.len().min(buffer.len());
+  buffer[..len].copy_from_slice(&data[..len]);
+  len
+}
+
+// Copies the packets of an isochronous IN transfer into the JS-owned
+// `buffer`, each at the offset of its requested length, returning the
+// status and number of bytes received of every packet.
+pub(super) fn read_packets(
+  buffer: &mut [u8],
+  packet_lengths: &[u32],
+  packets: Vec<Result<Vec<u8>>>,
+) -> Result<Vec<(&'static str, usize)>> {
+  let mut offset = 0;
+  packets
+    .into_iter()
+    .zip(packet_lengths)
+    .map(|(packet, length)| {
+      let start = offset.min(buffer.len());
+      offset += *length as usize;
+      let (status, data) = transfer_status(packet)?;
+      Ok((status, read_into(&mut buffer[start..], data)))
+    })
+    .collect()
+}
+
+// Status and number of bytes sent of every packet of an isochronous OUT
+// transfer.
+pub(super) fn packet_statuses(
+  packets: Vec<Result<usize>>,
+) -> Result<Vec<(&'static str, usize)>> {
+  packets.into_iter().map(transfer_status).collect()
+}
+
+// Reads an IN endpoint ahead of JS, for `ReadableStream`s.
+struct Reader {
+  chunks: Mutex<mpsc::Receiver<Result<Vec<u8>>>>,
+  stopped: Arc<AtomicBool>,
+}
+
+static READERS: Lazy<Mutex<HashMap<u32, Arc<Reader>>>> =
+  Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_READER: AtomicU32 = AtomicU32::new(0);
+
+// See `exports::open_reader`.
+pub(super) fn open_reader(
+  rid: u32,
+  endpoint_number: u8,
+  transfer_size: usize,
+  queue_length: usize,
+) -> Result<u32> {
+  with_device(rid, |_| Ok(()))?;
+  let (sender, receiver) = mpsc::sync_channel(queue_length);
+  let stopped = Arc::new(AtomicBool::new(false));
+  let reader = NEXT_READER.fetch_add(1, Ordering::SeqCst);
+  READERS.lock().unwrap().insert(
+    reader,
+    Arc::new(Reader {
+      chunks: Mutex::new(receiver),
+      stopped: stopped.clone(),
+    }),
+  );
+
+  std::thread::spawn(move || loop {
+    let result = run_transfer(
+      rid,
+      Direction::In,
+      endpoint_number,
+      vec![0; transfer_size],
+      || stopped.load(Ordering::SeqCst),
+    );
+    // Stops after the first error, or once the reader is closed.
+    let failed = result.is_err();
+    if sender.send(result).is_err() || failed {
+      break;
+    }
+  });
+  Ok(reader)
+}
+
+// See `exports::read_chunk`.
+pub(super) fn read_chunk(reader: u32, buffer: &mut [u8]) -> Result<usize> {
+  let reader = READERS.lock().unwrap().get(&reader).cloned();
+  let reader = reader.ok_or(Error::NotFound)?;
+  let chunks = reader.chunks.lock().unwrap();
+  loop {
+    if reader.stopped.load(Ordering::SeqCst) {
+      return Err(Error::Io(ErrorKind::Interrupted));
+    }
+    match chunks.recv_timeout(CANCEL_INTERVAL) {
+      Ok(chunk) => return chunk.map(|data| read_into(buffer, data)),
+      Err(mpsc::RecvTimeoutError::Timeout) => continue,
+      // The error that stopped the reader was already returned.
+      Err(mpsc::RecvTimeoutError::Disconnected) => {
+        return Err(Error::InvalidState)
+      }
+    }
+  }
+}
+
+// See `exports::close_reader`.
+pub(super) fn close_reader(reader: u32) {
+  if let Some(reader) = READERS.lock().unwrap().remove(&reader) {
+    reader.stopped.store(true, Ordering::SeqCst);
+  }
+}
+
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod tests {
+  use std::sync::atomic::AtomicU8;
+
+  use super::*;
+  use crate::loopback::Loopback;
+
+  // Hands out an open loopback device with interface 0 claimed.
+  fn loopback() -> u32 {
+    let mut device = Loopback::device();
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+    // Devices at the same bus and address share a resource ID.
+    device.device_address = NEXT_ADDRESS.fetch_add(1, Ordering::SeqCst);
+    RESOURCES.lock().unwrap().insert(device)
+  }
+
+  static NEXT_ADDRESS: AtomicU8 = AtomicU8::new(1);
+
+  fn opened(device: &Arc<Mutex<UsbDevice>>) -> bool {
+    device.lock().unwrap().opened
+  }
+
+  #[test]
+  fn test_release() {
+    let mut resources = Resources::default();
+    let mut device = Loopback::device();
+    device.open().unwrap();
+    let rid = resources.insert(device);
+    assert_eq!(resources.insert(Loopback::device()), rid);
+    let device = resources.devices[&rid].device.clone();
+
+    resources.release(rid);
+    assert!(resources.devices.contains_key(&rid));
+    resources.release(rid);
+    assert!(!resources.devices.contains_key(&rid));
+    assert!(!opened(&device));
+
+    // Released twice by JS.
+    resources.release(rid);
+    assert_ne!(resources.insert(Loopback::device()), rid);
+  }
+
+  #[test]
+  fn test_remove() {
+    let mut resources = Resources::default();
+    let rid = resources.insert(Loopback::device());
+    assert_eq!(resources.insert(Loopback::device()), rid);
+    let device = resources.devices[&rid].device.clone();
+    device.lock().unwrap().open().unwrap();
+
+    // A pending call closes the device once it returns.
+    let pending = device.lock().unwrap();
+    resources.remove(rid);
+    assert!(!resources.devices.contains_key(&rid));
+    assert!(pending.opened);
+  }
+
+  #[test]
+  fn test_retain_connected() {
+    let mut resources = Resources::default();
+    let first = resources.insert(Loopback::device());
+    let mut device = Loopback::device();
+    device.device_address = 6;
+    let second = resources.insert(device);
+
+    resources.retain_connected(&[(1, 5), (1, 7)]);
+    assert!(resources.devices.contains_key(&first));
+    assert!(!resources.devices.contains_key(&second));
+    assert_eq!(resources.take_disconnected(), vec![second]);
+    assert!(resources.take_disconnected().is_empty());
+  }
+
+  #[test]
+  fn test_transfers() {
+    let rid = loopback();
+    let data =
+      run_transfer(rid, Direction::Out, 2, b"hello".to_vec(), || false);
+    assert_eq!(data.unwrap(), b"hello");
+
+    let data = run_transfer(rid, Direction::In, 1, vec![0; 64], || false);
+    let mut buffer = [0; 4];
+    assert_eq!(read_into(&mut buffer, data.unwrap()), 4);
+    assert_eq!(&buffer, b"hell");
+
+    assert_eq!(
+      run_transfer(rid, Direction::In, 3, vec![0; 64], || false).err(),
+      Some(Error::Stall)
+    );
+    assert_eq!(
+      with_device(u32::MAX, |_| Ok(())).err(),
+      Some(Error::NotFound)
+    );
+  }
+
+  #[test]
+  fn test_isochronous_packets() {
+    let rid = loopback();
+    let packets = with_device(rid, |device| {
+      device.select_alternate_interface(0, 1)?;
+      device.isochronous_transfer_out(5, b"abcdefgh", &[3, 0, 5])
+    });
+    assert_eq!(
+      packet_statuses(packets.unwrap()).unwrap(),
+      vec![("ok", 3), ("ok", 0), ("ok", 5)]
+    );
+
+    // Short packets keep the offsets of the requested lengths.
+    let packet_lengths = [4, 4, 8];
+    let packets = with_device(rid, |device| {
+      device.isochronous_transfer_in(4, &packet_lengths)
+    });
+    let mut buffer = [0; 16];
+    assert_eq!(
+      read_packets(&mut buffer, &packet_lengths, packets.unwrap()).unwrap(),
+      vec![("ok", 3), ("ok", 0), ("ok", 5)]
+    );
+    assert_eq!(&buffer, b"abc\0\0\0\0\0defgh\0\0\0");
+
+    // The buffer JS passed may be shorter than the packets.
+    let packets = vec![Ok(b"ab".to_vec()), Ok(b"cd".to_vec())];
+    assert_eq!(
+      read_packets(&mut buffer[..3], &[2, 2], packets).unwrap(),
+      vec![("ok", 2), ("ok", 1)]
+    );
+  }
+
+  #[test]
+  fn test_isochronous_packet_status() {
+    let packets = vec![Ok(b"ab".to_vec()), Err(Error::Stall), Ok(vec![])];
+    let mut buffer = [0; 6];
+    assert_eq!(
+      read_packets(&mut buffer, &[2, 2, 2], packets).unwrap(),
+      vec![("ok", 2), ("stall", 0), ("ok", 0)]
+    );
+    assert_eq!(
+      packet_statuses(vec![Ok(2), Err(Error::Stall)]).unwrap(),
+      vec![("ok", 2), ("stall", 0)]
+    );
+
+    // Other errors fail the whole transfer.
+    let rid = loopback();
+    let packets = with_device(rid, |device| {
+      device.select_alternate_interface(0, 1)?;
+      device.isochronous_transfer_out(5, &[0; 17], &[17])
+    });
+    assert_eq!(
+      packet_statuses(packets.unwrap()).err(),
+      Some(Error::Io(ErrorKind::InvalidInput))
+    );
+  }
+
+  #[test]
+  fn test_cancel() {
+    let rid = loopback();
+
+    // Cancelled before it starts.
+    cancel(1);
+    let result = with_token(1, |cancelled| {
+      run_transfer(rid, Direction::In, 1, vec![0; 64], cancelled)
+    });
+    assert_eq!(result.err(), Some(Error::Io(ErrorKind::Interrupted)));
+
+    // Cancelled while pending, nothing is ever written.
+    std::thread::spawn(|| {
+      std::thread::sleep(Duration::from_millis(50));
+      cancel(2);
+    });
+    let result = with_token(2, |cancelled| {
+      run_transfer(rid, Direction::In, 1, vec![0; 64], cancelled)
+    });
+    assert_eq!(result.err(), Some(Error::Io(ErrorKind::Interrupted)));
+
+    // Tokens are not left behind for the next call.
+    assert!(!CANCELLED.lock().unwrap().contains(&2));
+    let result = with_token(2, |cancelled| {
+      run_transfer(rid, Direction::Out, 2, b"hello".to_vec(), cancelled)
+    });
+    assert!(result.is_ok());
+  }
+
+  #[test]
+  fn test_reader() {
+    let rid = loopback();
+    let reader = open_reader(rid, 1, 64, 1).unwrap();
+    let mut buffer = [0; 64];
+
+    for chunk in [&b"hello"[..], b"world"] {
+      run_transfer(rid, Direction::Out, 2, chunk.to_vec(), || false).unwrap();
+      let length = read_chunk(reader, &mut buffer).unwrap();
+      assert_eq!(&buffer[..length], chunk);
+    }
+
+    close_reader(reader);
+    assert_eq!(read_chunk(reader, &mut buffer).err(), Some(Error::NotFound));
+    assert_eq!(open_reader(u32::MAX, 1, 64, 1).err(), Some(Error::NotFound));
+  }
+
+  #[test]
+  fn test_reader_error() {
+    let rid = loopback();
+    let reader = open_reader(rid, 3, 64, 1).unwrap();
+    let mut buffer = [0; 64];
+
+    // The reader stops after the first error.
+    assert_eq!(read_chunk(reader, &mut buffer).err(), Some(Error::Stall));
+    assert_eq!(
+      read_chunk(reader, &mut buffer).err(),
+      Some(Error::InvalidState)
+    );
+    close_reader(reader);
+  }
+
+  #[test]
+  fn test_reader_backpressure() {
+    let rid = loopback();
+    let timeout = Some(Duration::from_millis(200));
+    with_device(rid, |device| Ok(device.set_transfer_timeout(timeout)))
+      .unwrap();
+    let reader = open_reader(rid, 1, 64, 1).unwrap();
+    let write = |chunk: &[u8]| {
+      std::thread::sleep(Duration::from_millis(100));
+      run_transfer(rid, Direction::Out, 2, chunk.to_vec(), || false).unwrap();
+    };
+
+    // One chunk is queued, the reader waits to hand over the next one and
+    // leaves the third to other reads.
+    write(b"first");
+    write(b"second");
+    write(b"third");
+    std::thread::sleep(Duration::from_millis(100));
+    let data = run_transfer(rid, Direction::In, 1, vec![0; 64], || false);
+    assert_eq!(data.unwrap(), b"third");
+
+    let mut buffer = [0; 64];
+    for chunk in [&b"first"[..], b"second"] {
+      let length = read_chunk(reader, &mut buffer).unwrap();
+      assert_eq!(&buffer[..length], chunk);
+    }
+    close_reader(reader);
+  }
+}
@@ -0,0 +1,468 @@
+//! Descriptors presented by a `Gadget`, serialized from its
+//! `GadgetDescriptor`.
+
+use core::convert::TryFrom;
+
+use crate::constants::*;
+use crate::gadget::GadgetDescriptor;
+use crate::gadget::GadgetInterface;
+use crate::Error;
+use crate::Result;
+use crate::UsbEndpointType;
+
+/// bRequest of GET_URL, announced in the WebUSB capability.
+pub(crate) const WEBUSB_VENDOR_CODE: u8 = 0x01;
+/// bRequest of the MS OS 2.0 descriptor request, announced in the MS OS 2.0
+/// capability.
+pub(crate) const MS_OS_20_VENDOR_CODE: u8 = 0x02;
+/// wValue of GET_URL for the landing page.
+pub(crate) const LANDING_PAGE_INDEX: u8 = 1;
+
+const LANGUAGE_ID_EN_US: u16 = 0x0409;
+const MAX_PACKET_SIZE_0: u8 = 64;
+// Windows 8.1, the first version that supports MS OS 2.0 descriptors.
+const WINDOWS_VERSION: u32 = 0x0603_0000;
+
+const MS_OS_20_SET_HEADER_DESCRIPTOR: u16 = 0x00;
+const MS_OS_20_SUBSET_HEADER_CONFIGURATION: u16 = 0x01;
+const MS_OS_20_SUBSET_HEADER_FUNCTION: u16 = 0x02;
+const MS_OS_20_FEATURE_COMPATIBLE_ID: u16 = 0x03;
+
+fn invalid() -> Error {
+  Error::Io(std::io::ErrorKind::InvalidInput)
+}
+
+/// Everything the gadget answers GET_DESCRIPTOR and the WebUSB and MS OS
+/// 2.0 vendor requests with.
+pub(crate) struct Descriptors {
+  pub device: Vec<u8>,
+  pub configuration: Vec<u8>,
+  pub bos: Vec<u8>,
+  pub url: Option<Vec<u8>>,
+  pub ms_os_20: Option<Vec<u8>>,
+  // String descriptors 1.., index 0 lists the supported languages.
+  strings: Vec<Vec<u8>>,
+}
+
+impl Descriptors {
+  pub(crate) fn new(gadget: &GadgetDescriptor) -> Result<Self> {
+    let mut strings = vec![];
+    let mut string_index = |string: &Option<String>| -> Result<u8> {
+      match string {
+        Some(string) => {
+          strings.push(string_descriptor(string)?);
+          u8::try_from(strings.len()).map_err(|_| invalid())
+        }
+        None => Ok(0),
+      }
+    };
+
+    let manufacturer_index = string_index(&gadget.manufacturer_name)?;
+    let product_index = string_index(&gadget.product_name)?;
+    let serial_number_index = string_index(&gadget.serial_number)?;
+    let interface_indices = gadget
+      .interfaces
+      .iter()
+      .map(|interface| string_index(&interface.interface_name))
+      .collect::<Result<Vec<u8>>>()?;
+
+    let mut device = vec![18, DEVICE_DESCRIPTOR_TYPE];
+    // 2.1, the lowest version with a BOS descriptor.
+    device.extend_from_slice(&0x0210u16.to_le_bytes());
+    // Class, subclass and protocol are defined per interface.
+    device.extend_from_slice(&[0, 0, 0, MAX_PACKET_SIZE_0]);
+    device.extend_from_slice(&gadget.vendor_id.to_le_bytes());
+    device.extend_from_slice(&gadget.product_id.to_le_bytes());
+    device.extend_from_slice(&gadget.device_version.to_le_bytes());
+    device.extend_from_slice(&[
+      manufacturer_index,
+      product_index,
+      serial_number_index,
+      1,
+    ]);
+
+    let url = match &gadget.landing_page {
+      Some(landing_page) => Some(url_descriptor(landing_page)?),
+      None => None,
+    };
+    let ms_os_20 = ms_os_20_descriptor_set(&gadget.interfaces);
+
+    Ok(Descriptors {
+      device,
+      configuration: configuration_descriptor(gadget, &interface_indices)?,
+      bos: bos_descriptor(url.is_some(), ms_os_20.as_deref()),
+      url,
+      ms_os_20,
+      strings,
+    })
+  }
+
+  pub(crate) fn string(&self, index: u8) -> Option<Vec<u8>> {
+    match index {
+      0 => {
+        let mut languages = vec![4, STRING_DESCRIPTOR_TYPE];
+        languages.extend_from_slice(&LANGUAGE_ID_EN_US.to_le_bytes());
+        Some(languages)
+      }
+      index => self.strings.get(index as usize - 1).cloned(),
+    }
+  }
+}
+
+fn string_descriptor(string: &str) -> Result<Vec<u8>> {
+  let mut bytes = vec![0, STRING_DESCRIPTOR_TYPE];
+  for c in string.encode_utf16() {
+    bytes.extend_from_slice(&c.to_le_bytes());
+  }
+  bytes[0] = u8::try_from(bytes.len()).map_err(|_| invalid())?;
+  Ok(bytes)
+}
+
+// https://wicg.github.io/webusb/#url-descriptor
+fn url_descriptor(url: &str) -> Result<Vec<u8>> {
+  let (scheme, url) = if let Some(url) = url.strip_prefix("https://") {
+    (1, url)
+  } else if let Some(url) = url.strip_prefix("http://") {
+    (0, url)
+  } else {
+    // The scheme is part of the URL.
+    (255, url)
+  };
+
+  let mut bytes = vec![0, DESCRIPTOR_TYPE, scheme];
+  bytes.extend_from_slice(url.as_bytes());
+  bytes[0] = u8::try_from(bytes.len()).map_err(|_| invalid())?;
+  Ok(bytes)
+}
+
+// USB 2.0 spec, Section 9.6.3 to 9.6.6.
+fn configuration_descriptor(
+  gadget: &GadgetDescriptor,
+  interface_indices: &[u8],
+) -> Result<Vec<u8>> {
+  let num_interfaces =
+    u8::try_from(gadget.interfaces.len()).map_err(|_| invalid())?;
+  let attributes = match gadget.self_powered {
+    true => 0xC0,
+    false => 0x80,
+  };
+  // bMaxPower is in 2mA units.
+  let max_power = u8::try_from(gadget.max_power / 2).map_err(|_| invalid())?;
+
+  let mut bytes = vec![9, CONFIGURATION_DESCRIPTOR_TYPE, 0, 0];
+  bytes.extend_from_slice(&[num_interfaces, 1, 0, attributes, max_power]);
+
+  let mut addresses = vec![];
+  for (interface_number, interface) in gadget.interfaces.iter().enumerate() {
+    let num_endpoints =
+      u8::try_from(interface.endpoints.len()).map_err(|_| invalid())?;
+    bytes.extend_from_slice(&[
+      9,
+      INTERFACE_DESCRIPTOR_TYPE,
+      interface_number as u8,
+      0,
+      num_endpoints,
+      interface.interface_class,
+      interface.interface_subclass,
+      interface.interface_protocol,
+      interface_indices[interface_number],
+    ]);
+
+    for endpoint in &interface.endpoints {
+      // Endpoint 0 is the control endpoint of the device.
+      if endpoint.address & 0x0F == 0 || addresses.contains(&endpoint.address) {
+        return Err(invalid());
+      }
+      addresses.push(endpoint.address);
+
+      let attributes = match endpoint.r#type {
+        UsbEndpointType::Isochronous => 0x01,
+        UsbEndpointType::Bulk => 0x02,
+        UsbEndpointType::Interrupt => 0x03,
+        UsbEndpointType::Control => return Err(invalid()),
+      };
+      bytes.extend_from_slice(&[
+        7,
+        ENDPOINT_DESCRIPTOR_TYPE,
+        endpoint.address,
+        attributes,
+      ]);
+      bytes.extend_from_slice(&endpoint.packet_size.to_le_bytes());
+      bytes.push(endpoint.interval);
+    }
+  }
+
+  let total_length = u16::try_from(bytes.len()).map_err(|_| invalid())?;
+  bytes[2..4].copy_from_slice(&total_length.to_le_bytes());
+  Ok(bytes)
+}
+
+// USB 3.2 spec, Section 9.6.2, with the WebUSB and MS OS 2.0 platform
+// capabilities.
+fn bos_descriptor(landing_page: bool, ms_os_20: Option<&[u8]>) -> Vec<u8> {
+  let mut capabilities = vec![];
+
+  // USB 2.0 Extension, no LPM.
+  capabilities.push(vec![
+    7,
+    DEVICE_CAPABILITY_DESCRIPTOR_TYPE,
+    USB_2_0_EXTENSION_CAPABILITY_TYPE,
+    0,
+    0,
+    0,
+    0,
+  ]);
+
+  // https://wicg.github.io/webusb/#webusb-platform-capability-descriptor
+  let mut webusb = vec![
+    24,
+    DEVICE_CAPABILITY_DESCRIPTOR_TYPE,
+    PLATFORM_DEV_CAPABILITY_TYPE,
+    0,
+  ];
+  webusb.extend_from_slice(WEB_USB_CAPABILITY_UUID);
+  webusb.extend_from_slice(&0x0100u16.to_le_bytes());
+  webusb.push(WEBUSB_VENDOR_CODE);
+  webusb.push(match landing_page {
+    true => LANDING_PAGE_INDEX,
+    false => 0,
+  });
+  capabilities.push(webusb);
+
+  if let Some(ms_os_20) = ms_os_20 {
+    let mut capability = vec![
+      28,
+      DEVICE_CAPABILITY_DESCRIPTOR_TYPE,
+      PLATFORM_DEV_CAPABILITY_TYPE,
+      0,
+    ];
+    capability.extend_from_slice(MS_OS_20_CAPABILITY_UUID);
+    capability.extend_from_slice(&WINDOWS_VERSION.to_le_bytes());
+    capability.extend_from_slice(&(ms_os_20.len() as u16).to_le_bytes());
+    // No alternate enumeration.
+    capability.extend_from_slice(&[MS_OS_20_VENDOR_CODE, 0]);
+    capabilities.push(capability);
+  }
+
+  let total_length: usize =
+    5 + capabilities.iter().map(|c| c.len()).sum::<usize>();
+  let mut bytes = vec![5, BOS_DESCRIPTOR_TYPE as u8];
+  bytes.extend_from_slice(&(total_length as u16).to_le_bytes());
+  bytes.push(capabilities.len() as u8);
+  for capability in capabilities {
+    bytes.extend_from_slice(&capability);
+  }
+  bytes
+}
+
+// Microsoft OS 2.0 Descriptors Specification, Section 9.22. Binds WinUSB
+// to the interfaces with `winusb` set, so that Windows hosts can use them
+// without an INF file.
+fn ms_os_20_descriptor_set(interfaces: &[GadgetInterface]) -> Option<Vec<u8>> {
+  let compatible_id = {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&20u16.to_le_bytes());
+    bytes.extend_from_slice(&MS_OS_20_FEATURE_COMPATIBLE_ID.to_le_bytes());
+    bytes.extend_from_slice(b"WINUSB\0\0");
+    // SubCompatibleID
+    bytes.extend_from_slice(&[0; 8]);
+    bytes
+  };
+
+  let mut features = vec![];
+  if interfaces.len() == 1 {
+    // A non-composite device, the feature applies to the whole device.
+    if !interfaces[0].winusb {
+      return None;
+    }
+    features.extend_from_slice(&compatible_id);
+  } else {
+    let mut functions = vec![];
+    for (interface_number, _) in
+      interfaces.iter().enumerate().filter(|(_, i)| i.winusb)
+    {
+      functions.extend_from_slice(&8u16.to_le_bytes());
+      functions
+        .extend_from_slice(&MS_OS_20_SUBSET_HEADER_FUNCTION.to_le_bytes());
+      functions.extend_from_slice(&[interface_number as u8, 0]);
+      functions
+        .extend_from_slice(&(8 + compatible_id.len() as u16).to_le_bytes());
+      functions.extend_from_slice(&compatible_id);
+    }
+    if functions.is_empty() {
+      return None;
+    }
+
+    features.extend_from_slice(&8u16.to_le_bytes());
+    features
+      .extend_from_slice(&MS_OS_20_SUBSET_HEADER_CONFIGURATION.to_le_bytes());
+    // bConfigurationValue is the index of the configuration, despite its
+    // name.
+    features.extend_from_slice(&[0, 0]);
+    features.extend_from_slice(&(8 + functions.len() as u16).to_le_bytes());
+    features.extend_from_slice(&functions);
+  }
+
+  let mut bytes = vec![];
+  bytes.extend_from_slice(&10u16.to_le_bytes());
+  bytes.extend_from_slice(&MS_OS_20_SET_HEADER_DESCRIPTOR.to_le_bytes());
+  bytes.extend_from_slice(&WINDOWS_VERSION.to_le_bytes());
+  bytes.extend_from_slice(&(10 + features.len() as u16).to_le_bytes());
+  bytes.extend_from_slice(&features);
+  Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::descriptors::parse_bos;
+  use crate::descriptors::parse_webusb_url;
+  use crate::gadget::descriptors::Descriptors;
+  use crate::gadget::descriptors::LANDING_PAGE_INDEX;
+  use crate::gadget::descriptors::WEBUSB_VENDOR_CODE;
+  use crate::gadget::GadgetDescriptor;
+  use crate::gadget::GadgetEndpoint;
+  use crate::gadget::GadgetInterface;
+  use crate::Error;
+  use crate::UsbEndpointType;
+
+  fn loopback() -> GadgetDescriptor {
+    GadgetDescriptor {
+      vendor_id: 0x1209,
+      product_id: 0x0001,
+      device_version: 0x0100,
+      manufacturer_name: Some("Acme".to_string()),
+      product_name: Some("Loopback".to_string()),
+      max_power: 100,
+      landing_page: Some("https://example.com".to_string()),
+      interfaces: vec![GadgetInterface {
+        interface_class: 0xFF,
+        endpoints: vec![
+          GadgetEndpoint::bulk(0x81, 512),
+          GadgetEndpoint::bulk(0x02, 512),
+        ],
+        winusb: true,
+        ..Default::default()
+      }],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_device_descriptor() {
+    let descriptors = Descriptors::new(&loopback()).unwrap();
+    assert_eq!(
+      descriptors.device,
+      vec![
+        0x12, 0x01, 0x10, 0x02, 0x00, 0x00, 0x00, 0x40, 0x09, 0x12, 0x01, 0x00,
+        0x00, 0x01, 0x01, 0x02, 0x00, 0x01,
+      ]
+    );
+    assert_eq!(descriptors.string(0), Some(vec![0x04, 0x03, 0x09, 0x04]));
+    assert_eq!(
+      descriptors.string(1),
+      Some(vec![0x0A, 0x03, b'A', 0, b'c', 0, b'm', 0, b'e', 0])
+    );
+    assert_eq!(descriptors.string(3), None);
+  }
+
+  #[test]
+  fn test_configuration_descriptor() {
+    let descriptors = Descriptors::new(&loopback()).unwrap();
+    assert_eq!(
+      descriptors.configuration,
+      vec![
+        0x09, 0x02, 0x20, 0x00, 0x01, 0x01, 0x00, 0x80,
+        0x32, // Configuration
+        0x09, 0x04, 0x00, 0x00, 0x02, 0xFF, 0x00, 0x00,
+        0x00, // Interface 0
+        0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00, // Bulk IN 1
+        0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00, // Bulk OUT 2
+      ]
+    );
+  }
+
+  #[test]
+  fn test_bad_configuration_descriptor() {
+    let mut gadget = loopback();
+    gadget.interfaces[0]
+      .endpoints
+      .push(GadgetEndpoint::bulk(0x81, 512));
+    assert_eq!(
+      Descriptors::new(&gadget).err(),
+      Some(Error::Io(std::io::ErrorKind::InvalidInput))
+    );
+
+    let mut gadget = loopback();
+    gadget.interfaces[0].endpoints[0].r#type = UsbEndpointType::Control;
+    assert!(Descriptors::new(&gadget).is_err());
+
+    let mut gadget = loopback();
+    gadget.max_power = 1000;
+    assert!(Descriptors::new(&gadget).is_err());
+  }
+
+  #[test]
+  fn test_webusb() {
+    let descriptors = Descriptors::new(&loopback()).unwrap();
+    assert_eq!(
+      parse_bos(&descriptors.bos),
+      Some((WEBUSB_VENDOR_CODE, LANDING_PAGE_INDEX))
+    );
+    assert_eq!(
+      parse_webusb_url(descriptors.url.as_ref().unwrap()),
+      Some("https://example.com".to_string())
+    );
+
+    let mut gadget = loopback();
+    gadget.landing_page = None;
+    let descriptors = Descriptors::new(&gadget).unwrap();
+    assert_eq!(parse_bos(&descriptors.bos), Some((WEBUSB_VENDOR_CODE, 0)));
+    assert!(descriptors.url.is_none());
+  }
+
+  #[test]
+  fn test_ms_os_20() {
+    let descriptors = Descriptors::new(&loopback()).unwrap();
+    let ms_os_20 = descriptors.ms_os_20.unwrap();
+    assert_eq!(
+      ms_os_20,
+      vec![
+        0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x06, 0x1E,
+        0x00, // Header
+        0x14, 0x00, 0x03, 0x00, b'W', b'I', b'N', b'U', b'S', b'B', 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Compatible ID
+      ]
+    );
+    // The MS OS 2.0 capability follows the WebUSB one and announces the
+    // length of the descriptor set.
+    assert_eq!(descriptors.bos.len(), 5 + 7 + 24 + 28);
+    assert_eq!(descriptors.bos[60..62], [0x1E, 0x00]);
+
+    // A composite device gets one function subset per WinUSB interface.
+    let mut gadget = loopback();
+    gadget.interfaces.insert(
+      0,
+      GadgetInterface {
+        interface_class: 0x03,
+        ..Default::default()
+      },
+    );
+    let ms_os_20 = Descriptors::new(&gadget).unwrap().ms_os_20.unwrap();
+    assert_eq!(ms_os_20.len(), 10 + 8 + 8 + 20);
+    // Configuration subset
+    assert_eq!(
+      ms_os_20[10..18],
+      [0x08, 0x00, 0x01, 0x00, 0x00, 0x00, 0x24, 0x00]
+    );
+    // Function subset of interface 1
+    assert_eq!(
+      ms_os_20[18..26],
+      [0x08, 0x00, 0x02, 0x00, 0x01, 0x00, 0x1C, 0x00]
+    );
+
+    gadget.interfaces[1].winusb = false;
+    let descriptors = Descriptors::new(&gadget).unwrap();
+    assert!(descriptors.ms_os_20.is_none());
+    assert_eq!(descriptors.bos.len(), 5 + 7 + 24);
+  }
+}
@@ -0,0 +1,669 @@
+//! Device side of WebUSB: turns a Linux machine with a USB device
+//! controller (UDC) into a WebUSB device.
+//!
+//! Built on the kernel's raw-gadget interface (`CONFIG_USB_RAW_GADGET`),
+//! which hands every control request to userspace. The gadget answers the
+//! standard requests from its `GadgetDescriptor`, announces its landing page
+//! through the WebUSB platform capability and, for interfaces with `winusb`
+//! set, binds WinUSB on Windows hosts through MS OS 2.0 descriptors.
+//!
+//! ```no_run
+//! use webusb::gadget::Gadget;
+//! use webusb::gadget::GadgetDescriptor;
+//! use webusb::gadget::GadgetEndpoint;
+//! use webusb::gadget::GadgetInterface;
+//! use webusb::gadget::Udc;
+//!
+//! let udc = Udc::list().unwrap().remove(0);
+//! let gadget = Gadget::start(
+//!   &udc,
+//!   GadgetDescriptor {
+//!     vendor_id: 0x1209,
+//!     product_id: 0x0001,
+//!     product_name: Some("Echo".to_string()),
+//!     landing_page: Some("https://example.com".to_string()),
+//!     interfaces: vec![GadgetInterface {
+//!       interface_class: 0xFF,
+//!       endpoints: vec![
+//!         GadgetEndpoint::bulk(0x81, 512),
+//!         GadgetEndpoint::bulk(0x02, 512),
+//!       ],
+//!       winusb: true,
+//!       ..Default::default()
+//!     }],
+//!     ..Default::default()
+//!   },
+//! )
+//! .unwrap();
+//!
+//! gadget.wait_configured(None).unwrap();
+//! let mut buffer = [0; 512];
+//! loop {
+//!   let length = gadget.read(0x02, &mut buffer).unwrap();
+//!   gadget.write(0x81, &buffer[..length]).unwrap();
+//! }
+//! ```
+
+use core::convert::TryFrom;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::time::Duration;
+
+use crate::constants::*;
+use crate::ioctl::ioc;
+use crate::ioctl::ioctl;
+use crate::ioctl::IOC_NONE;
+use crate::ioctl::IOC_READ;
+use crate::ioctl::IOC_WRITE;
+use crate::ioctl::UINT_SIZE;
+use crate::Error;
+use crate::Result;
+use crate::UsbEndpointType;
+
+mod descriptors;
+
+use descriptors::Descriptors;
+use descriptors::LANDING_PAGE_INDEX;
+use descriptors::MS_OS_20_VENDOR_CODE;
+use descriptors::WEBUSB_VENDOR_CODE;
+
+const RAW_GADGET: &str = "/dev/raw-gadget";
+const SYSFS_UDC: &str = "/sys/class/udc";
+
+// linux/usb/raw_gadget.h
+const UDC_NAME_LENGTH_MAX: usize = 128;
+const EP_IO_HEADER_SIZE: usize = 8;
+
+const USB_RAW_IOCTL_INIT: u32 = ioc(IOC_WRITE, 0, 2 * UDC_NAME_LENGTH_MAX + 1);
+const USB_RAW_IOCTL_RUN: u32 = ioc(IOC_NONE, 1, 0);
+const USB_RAW_IOCTL_EVENT_FETCH: u32 = ioc(IOC_READ, 2, 8);
+const USB_RAW_IOCTL_EP0_WRITE: u32 = ioc(IOC_WRITE, 3, EP_IO_HEADER_SIZE);
+const USB_RAW_IOCTL_EP0_READ: u32 =
+  ioc(IOC_READ | IOC_WRITE, 4, EP_IO_HEADER_SIZE);
+const USB_RAW_IOCTL_EP_ENABLE: u32 = ioc(IOC_WRITE, 5, 9);
+const USB_RAW_IOCTL_EP_DISABLE: u32 = ioc(IOC_WRITE, 6, UINT_SIZE);
+const USB_RAW_IOCTL_EP_WRITE: u32 = ioc(IOC_WRITE, 7, EP_IO_HEADER_SIZE);
+const USB_RAW_IOCTL_EP_READ: u32 =
+  ioc(IOC_READ | IOC_WRITE, 8, EP_IO_HEADER_SIZE);
+const USB_RAW_IOCTL_CONFIGURE: u32 = ioc(IOC_NONE, 9, 0);
+const USB_RAW_IOCTL_VBUS_DRAW: u32 = ioc(IOC_WRITE, 10, UINT_SIZE);
+const USB_RAW_IOCTL_EP0_STALL: u32 = ioc(IOC_NONE, 12, 0);
+
+const USB_RAW_EVENT_CONTROL: u32 = 2;
+const USB_RAW_EVENT_RESET: u32 = 5;
+const USB_RAW_EVENT_DISCONNECT: u32 = 6;
+
+const USB_SPEED_HIGH: u8 = 3;
+
+#[repr(C)]
+struct UsbRawInit {
+  driver_name: [u8; UDC_NAME_LENGTH_MAX],
+  device_name: [u8; UDC_NAME_LENGTH_MAX],
+  speed: u8,
+}
+
+#[repr(C)]
+struct UsbRawControlEvent {
+  r#type: u32,
+  length: u32,
+  // The setup packet.
+  data: [u8; 8],
+}
+
+/// A USB device controller, see `Udc::list`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Udc {
+  /// Name of the UDC driver, e.g. `dummy_udc`.
+  pub driver_name: String,
+  /// Name of the UDC, e.g. `dummy_udc.0`.
+  pub device_name: String,
+}
+
+impl Udc {
+  /// Lists the device controllers in `/sys/class/udc`.
+  pub fn list() -> Result<Vec<Udc>> {
+    let mut udcs = vec![];
+    for entry in std::fs::read_dir(SYSFS_UDC)? {
+      let path = entry?.path();
+      let driver = std::fs::read_link(path.join("device/driver"))?;
+      let name = |path: &Path| {
+        path
+          .file_name()
+          .map(|name| name.to_string_lossy().into_owned())
+          .unwrap_or_default()
+      };
+      udcs.push(Udc {
+        driver_name: name(&driver),
+        device_name: name(&path),
+      });
+    }
+    udcs.sort_by(|a, b| a.device_name.cmp(&b.device_name));
+    Ok(udcs)
+  }
+}
+
+/// The device a `Gadget` presents to the host. It has a single
+/// configuration, and every interface has a single alternate setting.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GadgetDescriptor {
+  pub vendor_id: u16,
+  pub product_id: u16,
+  /// bcdDevice
+  pub device_version: u16,
+  pub manufacturer_name: Option<String>,
+  pub product_name: Option<String>,
+  pub serial_number: Option<String>,
+  /// Maximum power drawn from the bus, in mA.
+  pub max_power: u16,
+  pub self_powered: bool,
+  pub interfaces: Vec<GadgetInterface>,
+  /// URL announced to browsers through the WebUSB platform capability.
+  pub landing_page: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GadgetInterface {
+  pub interface_class: u8,
+  pub interface_subclass: u8,
+  pub interface_protocol: u8,
+  pub interface_name: Option<String>,
+  pub endpoints: Vec<GadgetEndpoint>,
+  /// If true, Windows hosts bind WinUSB to the interface, which Chrome
+  /// needs to access it.
+  pub winusb: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GadgetEndpoint {
+  /// bEndpointAddress, bit 7 is set for IN endpoints.
+  pub address: u8,
+  pub r#type: UsbEndpointType,
+  pub packet_size: u16,
+  /// bInterval
+  pub interval: u8,
+}
+
+impl GadgetEndpoint {
+  pub fn bulk(address: u8, packet_size: u16) -> Self {
+    Self {
+      address,
+      r#type: UsbEndpointType::Bulk,
+      packet_size,
+      interval: 0,
+    }
+  }
+
+  pub fn interrupt(address: u8, packet_size: u16, interval: u8) -> Self {
+    Self {
+      address,
+      r#type: UsbEndpointType::Interrupt,
+      packet_size,
+      interval,
+    }
+  }
+}
+
+#[derive(Default)]
+struct State {
+  configured: bool,
+  // Endpoint address to raw-gadget endpoint handle.
+  endpoints: HashMap<u8, u32>,
+}
+
+// The raw-gadget file. The event thread keeps its own reference, so the fd
+// it waits on is never closed, and reused, under it.
+struct RawGadget {
+  file: File,
+  // Set once the last `Gadget` is dropped.
+  stopped: AtomicBool,
+}
+
+struct Shared {
+  raw: Arc<RawGadget>,
+  descriptor: GadgetDescriptor,
+  descriptors: Descriptors,
+  state: Mutex<State>,
+  changed: Condvar,
+}
+
+/// A running gadget. Clones share the same device.
+///
+/// Control requests are answered by a background thread. The device is
+/// disconnected once the last clone is dropped and the host sends its next
+/// request, or immediately if the host is gone.
+#[derive(Clone)]
+pub struct Gadget {
+  shared: Arc<Shared>,
+}
+
+impl Gadget {
+  /// Binds a new gadget to `udc` and connects it to the host.
+  pub fn start(udc: &Udc, descriptor: GadgetDescriptor) -> Result<Self> {
+    let descriptors = Descriptors::new(&descriptor)?;
+
+    let mut init = UsbRawInit {
+      driver_name: [0; UDC_NAME_LENGTH_MAX],
+      device_name: [0; UDC_NAME_LENGTH_MAX],
+      speed: USB_SPEED_HIGH,
+    };
+    copy_name(&mut init.driver_name, &udc.driver_name)?;
+    copy_name(&mut init.device_name, &udc.device_name)?;
+
+    let file = OpenOptions::new().read(true).write(true).open(RAW_GADGET)?;
+    let fd = file.as_raw_fd();
+    ioctl(fd, USB_RAW_IOCTL_INIT, &mut init as *mut _ as *mut _)?;
+    ioctl(fd, USB_RAW_IOCTL_RUN, std::ptr::null_mut())?;
+
+    let raw = Arc::new(RawGadget {
+      file,
+      stopped: AtomicBool::new(false),
+    });
+    let shared = Arc::new(Shared {
+      raw: raw.clone(),
+      descriptor,
+      descriptors,
+      state: Mutex::new(State::default()),
+      changed: Condvar::new(),
+    });
+    let weak = Arc::downgrade(&shared);
+    std::thread::spawn(move || handle_events(raw, weak));
+
+    Ok(Self { shared })
+  }
+
+  pub fn descriptor(&self) -> &GadgetDescriptor {
+    &self.shared.descriptor
+  }
+
+  pub fn configured(&self) -> bool {
+    self.shared.state.lock().unwrap().configured
+  }
+
+  /// Blocks until the host has selected the configuration. Fails with
+  /// `TimedOut` if that does not happen within `timeout`.
+  pub fn wait_configured(&self, timeout: Option<Duration>) -> Result<()> {
+    let state = self.shared.state.lock().unwrap();
+    match timeout {
+      Some(timeout) => {
+        let (state, _) = self
+          .shared
+          .changed
+          .wait_timeout_while(state, timeout, |state| !state.configured)
+          .unwrap();
+        match state.configured {
+          true => Ok(()),
+          false => Err(Error::Io(std::io::ErrorKind::TimedOut)),
+        }
+      }
+      None => {
+        let _state = self
+          .shared
+          .changed
+          .wait_while(state, |state| !state.configured)
+          .unwrap();
+        Ok(())
+      }
+    }
+  }
+
+  /// Reads the next transfer from the host on the OUT endpoint `address`.
+  /// Blocks until the host sends data.
+  pub fn read(&self, address: u8, buffer: &mut [u8]) -> Result<usize> {
+    if address & 0x80 != 0 {
+      return Err(Error::InvalidAccess);
+    }
+    let handle = self.endpoint(address)?;
+
+    let mut io = ep_io(handle, &vec![0; buffer.len()])?;
+    let length = ioctl(
+      self.shared.fd(),
+      USB_RAW_IOCTL_EP_READ,
+      io.as_mut_ptr() as *mut _,
+    )? as usize;
+    buffer[..length]
+      .copy_from_slice(&io[EP_IO_HEADER_SIZE..EP_IO_HEADER_SIZE + length]);
+    Ok(length)
+  }
+
+  /// Sends `data` to the host on the IN endpoint `address`. Blocks until
+  /// the host has read it.
+  pub fn write(&self, address: u8, data: &[u8]) -> Result<usize> {
+    if address & 0x80 == 0 {
+      return Err(Error::InvalidAccess);
+    }
+    let handle = self.endpoint(address)?;
+
+    let mut io = ep_io(handle, data)?;
+    let length = ioctl(
+      self.shared.fd(),
+      USB_RAW_IOCTL_EP_WRITE,
+      io.as_mut_ptr() as *mut _,
+    )?;
+    Ok(length as usize)
+  }
+
+  fn endpoint(&self, address: u8) -> Result<u32> {
+    let state = self.shared.state.lock().unwrap();
+    if !state.configured {
+      return Err(Error::InvalidState);
+    }
+    state
+      .endpoints
+      .get(&address)
+      .copied()
+      .ok_or(Error::NotFound)
+  }
+}
+
+fn copy_name(buffer: &mut [u8], name: &str) -> Result<()> {
+  // Keep the terminating NUL.
+  if name.len() >= buffer.len() {
+    return Err(Error::Io(std::io::ErrorKind::InvalidInput));
+  }
+  buffer[..name.len()].copy_from_slice(name.as_bytes());
+  Ok(())
+}
+
+// struct usb_raw_ep_io followed by its data.
+fn ep_io(handle: u32, data: &[u8]) -> Result<Vec<u8>> {
+  let length = u32::try_from(data.len())
+    .map_err(|_| Error::Io(std::io::ErrorKind::InvalidInput))?;
+  let mut io = Vec::with_capacity(EP_IO_HEADER_SIZE + data.len());
+  io.extend_from_slice(&(handle as u16).to_ne_bytes());
+  // flags
+  io.extend_from_slice(&0u16.to_ne_bytes());
+  io.extend_from_slice(&length.to_ne_bytes());
+  io.extend_from_slice(data);
+  Ok(io)
+}
+
+// The thread only holds on to the gadget while it handles an event. It
+// stops, closing the raw-gadget file, at the first event after the last
+// `Gadget` is dropped: raw-gadget has no way to wake a pending fetch.
+fn handle_events(raw: Arc<RawGadget>, shared: Weak<Shared>) {
+  let fd = raw.file.as_raw_fd();
+  while !raw.stopped.load(Ordering::SeqCst) {
+    let mut event = UsbRawControlEvent {
+      r#type: 0,
+      length: 8,
+      data: [0; 8],
+    };
+    let fetched = ioctl(
+      fd,
+      USB_RAW_IOCTL_EVENT_FETCH,
+      &mut event as *mut _ as *mut _,
+    );
+    let shared = match shared.upgrade() {
+      Some(shared) => shared,
+      None => return,
+    };
+    if fetched.is_err() {
+      // The file is still open, the UDC is gone.
+      shared.set_unconfigured();
+      return;
+    }
+
+    match event.r#type {
+      USB_RAW_EVENT_CONTROL if shared.handle_control(&event.data).is_err() => {
+        let _ = shared.ep0_stall();
+      }
+      USB_RAW_EVENT_RESET | USB_RAW_EVENT_DISCONNECT => {
+        shared.set_unconfigured()
+      }
+      _ => {}
+    }
+  }
+}
+
+impl Shared {
+  fn fd(&self) -> libc::c_int {
+    self.raw.file.as_raw_fd()
+  }
+
+  fn handle_control(&self, setup: &[u8; 8]) -> Result<()> {
+    let request_type = setup[0];
+    let request = setup[1];
+    let value = u16::from_le_bytes([setup[2], setup[3]]);
+    let index = u16::from_le_bytes([setup[4], setup[5]]);
+    let length = u16::from_le_bytes([setup[6], setup[7]]) as usize;
+
+    let response = match (request_type, request) {
+      (0x80, GET_DESCRIPTOR_REQUEST) => {
+        let [descriptor_index, descriptor_type] = value.to_le_bytes();
+        match descriptor_type {
+          DEVICE_DESCRIPTOR_TYPE => Some(self.descriptors.device.clone()),
+          CONFIGURATION_DESCRIPTOR_TYPE if descriptor_index == 0 => {
+            Some(self.descriptors.configuration.clone())
+          }
+          STRING_DESCRIPTOR_TYPE => self.descriptors.string(descriptor_index),
+          t if t as u16 == BOS_DESCRIPTOR_TYPE => {
+            Some(self.descriptors.bos.clone())
+          }
+          _ => None,
+        }
+      }
+      (0x80, GET_CONFIGURATION_REQUEST) => {
+        Some(vec![self.state.lock().unwrap().configured as u8])
+      }
+      (0x80, GET_STATUS_REQUEST) => {
+        Some(vec![self.descriptor.self_powered as u8, 0])
+      }
+      (0x81, GET_STATUS_REQUEST) | (0x82, GET_STATUS_REQUEST) => {
+        Some(vec![0, 0])
+      }
+      (0x81, GET_INTERFACE_REQUEST)
+        if (index as usize) < self.descriptor.interfaces.len() =>
+      {
+        Some(vec![0])
+      }
+      (0x00, SET_CONFIGURATION_REQUEST) => {
+        match value {
+          0 => self.set_unconfigured(),
+          1 => self.configure()?,
+          _ => return self.ep0_stall(),
+        }
+        return self.ep0_ack();
+      }
+      (0x01, SET_INTERFACE_REQUEST)
+        if value == 0
+          && (index as usize) < self.descriptor.interfaces.len() =>
+      {
+        return self.ep0_ack();
+      }
+      (0x00, CLEAR_FEATURE_REQUEST)
+      | (0x00, SET_FEATURE_REQUEST)
+      | (0x02, CLEAR_FEATURE_REQUEST)
+      | (0x02, SET_FEATURE_REQUEST) => return self.ep0_ack(),
+      (0xC0, WEBUSB_VENDOR_CODE)
+        if index == GET_URL_REQUEST && value == LANDING_PAGE_INDEX as u16 =>
+      {
+        self.descriptors.url.clone()
+      }
+      (0xC0, MS_OS_20_VENDOR_CODE) if index == MS_OS_20_DESCRIPTOR_INDEX => {
+        self.descriptors.ms_os_20.clone()
+      }
+      _ => None,
+    };
+
+    match response {
+      Some(mut data) => {
+        data.truncate(length);
+        let mut io = ep_io(0, &data)?;
+        ioctl(
+          self.fd(),
+          USB_RAW_IOCTL_EP0_WRITE,
+          io.as_mut_ptr() as *mut _,
+        )?;
+        Ok(())
+      }
+      None => self.ep0_stall(),
+    }
+  }
+
+  // Completes the status stage of a request without data.
+  fn ep0_ack(&self) -> Result<()> {
+    let mut io = ep_io(0, &[])?;
+    ioctl(self.fd(), USB_RAW_IOCTL_EP0_READ, io.as_mut_ptr() as *mut _)?;
+    Ok(())
+  }
+
+  fn ep0_stall(&self) -> Result<()> {
+    ioctl(self.fd(), USB_RAW_IOCTL_EP0_STALL, std::ptr::null_mut())?;
+    Ok(())
+  }
+
+  fn configure(&self) -> Result<()> {
+    let mut state = self.state.lock().unwrap();
+    if state.configured {
+      return Ok(());
+    }
+
+    for interface in &self.descriptor.interfaces {
+      for endpoint in &interface.endpoints {
+        // struct usb_endpoint_descriptor, including the audio fields.
+        let [packet_size_low, packet_size_high] =
+          endpoint.packet_size.to_le_bytes();
+        let mut descriptor = [
+          9,
+          ENDPOINT_DESCRIPTOR_TYPE,
+          endpoint.address,
+          match endpoint.r#type {
+            UsbEndpointType::Isochronous => 0x01,
+            UsbEndpointType::Bulk => 0x02,
+            _ => 0x03,
+          },
+          packet_size_low,
+          packet_size_high,
+          endpoint.interval,
+          0,
+          0,
+        ];
+        let handle = ioctl(
+          self.fd(),
+          USB_RAW_IOCTL_EP_ENABLE,
+          descriptor.as_mut_ptr() as *mut _,
+        )?;
+        state.endpoints.insert(endpoint.address, handle as u32);
+      }
+    }
+
+    // VBUS_DRAW takes the value itself rather than a pointer, in 2mA units.
+    let power = (self.descriptor.max_power / 2) as usize;
+    ioctl(self.fd(), USB_RAW_IOCTL_VBUS_DRAW, power as *mut _)?;
+    ioctl(self.fd(), USB_RAW_IOCTL_CONFIGURE, std::ptr::null_mut())?;
+
+    state.configured = true;
+    self.changed.notify_all();
+    Ok(())
+  }
+
+  fn set_unconfigured(&self) {
+    let mut state = self.state.lock().unwrap();
+    for (_, handle) in state.endpoints.drain() {
+      // Fails if the UDC already disabled the endpoint.
+      let _ = ioctl(
+        self.fd(),
+        USB_RAW_IOCTL_EP_DISABLE,
+        handle as usize as *mut _,
+      );
+    }
+    state.configured = false;
+    self.changed.notify_all();
+  }
+}
+
+impl Drop for Shared {
+  fn drop(&mut self) {
+    self.raw.stopped.store(true, Ordering::SeqCst);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::gadget::*;
+
+  #[test]
+  fn test_ioctl_numbers() {
+    assert_eq!(USB_RAW_IOCTL_INIT, 0x4101_5500);
+    assert_eq!(USB_RAW_IOCTL_RUN, 0x0000_5501);
+    assert_eq!(USB_RAW_IOCTL_EVENT_FETCH, 0x8008_5502);
+    assert_eq!(USB_RAW_IOCTL_EP0_READ, 0xC008_5504);
+    assert_eq!(USB_RAW_IOCTL_EP_ENABLE, 0x4009_5505);
+    assert_eq!(USB_RAW_IOCTL_EP0_STALL, 0x0000_550C);
+  }
+
+  // Needs root and the dummy_hcd and raw_gadget modules:
+  //
+  //   modprobe dummy_hcd raw_gadget
+  #[test]
+  #[ignore]
+  #[cfg(any(feature = "libusb", feature = "usbfs"))]
+  fn test_dummy_hcd() {
+    let udc = Udc::list()
+      .unwrap()
+      .into_iter()
+      .find(|udc| udc.driver_name == "dummy_udc")
+      .expect("dummy_udc not found");
+    let gadget = Gadget::start(
+      &udc,
+      GadgetDescriptor {
+        vendor_id: 0x1209,
+        product_id: 0x0001,
+        product_name: Some("Echo".to_string()),
+        max_power: 100,
+        landing_page: Some("https://example.com".to_string()),
+        interfaces: vec![GadgetInterface {
+          interface_class: 0xFF,
+          endpoints: vec![
+            GadgetEndpoint::bulk(0x81, 512),
+            GadgetEndpoint::bulk(0x02, 512),
+          ],
+          winusb: true,
+          ..Default::default()
+        }],
+        ..Default::default()
+      },
+    )
+    .unwrap();
+
+    let echo = gadget.clone();
+    std::thread::spawn(move || {
+      echo.wait_configured(None).unwrap();
+      let mut buffer = [0; 512];
+      while let Ok(length) = echo.read(0x02, &mut buffer) {
+        echo.write(0x81, &buffer[..length]).unwrap();
+      }
+    });
+
+    // Give the host time to enumerate the gadget.
+    gadget
+      .wait_configured(Some(Duration::from_secs(5)))
+      .unwrap();
+    let mut device = crate::Context::init()
+      .unwrap()
+      .devices()
+      .unwrap()
+      .into_iter()
+      .find(|d| d.vendor_id == 0x1209 && d.product_id == 0x0001)
+      .expect("gadget not found");
+    assert_eq!(device.product_name, Some("Echo".to_string()));
+    assert_eq!(device.url, Some("https://example.com".to_string()));
+
+    device.open().unwrap();
+    device.claim_interface(0).unwrap();
+    let data: Vec<u8> = (0..64).collect();
+    assert_eq!(device.transfer_out(2, &data).unwrap(), 64);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), data);
+    device.release_interface(0).unwrap();
+    device.close().unwrap();
+  }
+}
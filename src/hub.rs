@@ -0,0 +1,339 @@
+//! Hub class-specific requests.
+//!
+//! Hubs are not listed by `Context::devices`. Use
+//! `Context::devices_with_options` with `EnumerationOptions::include_hubs` to
+//! obtain them.
+//!
+//! ```no_run
+//! # fn main() -> webusb::Result<()> {
+//! use webusb::hub::UsbHub;
+//! use webusb::Context;
+//! use webusb::EnumerationOptions;
+//!
+//! let ctx = Context::init()?;
+//! let options = EnumerationOptions { include_hubs: true };
+//! let device = ctx
+//!   .devices_with_options(&options)?
+//!   .into_iter()
+//!   .find(|d| d.port_path() == "1-2")
+//!   .unwrap();
+//!
+//! let mut hub = UsbHub::new(device)?;
+//! hub.power_cycle(3, std::time::Duration::from_secs(1))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! NOTE: A SuperSpeed hub shows up as two devices, one on the USB 2.0 bus and
+//! one on the SuperSpeed bus. Port power has to be switched off on both of
+//! them for the port to actually lose power.
+
+use std::time::Duration;
+
+use crate::constants::CLEAR_FEATURE_REQUEST;
+use crate::constants::GET_DESCRIPTOR_REQUEST;
+use crate::constants::GET_STATUS_REQUEST;
+use crate::constants::HUB_CLASS;
+use crate::constants::HUB_DESCRIPTOR_TYPE;
+use crate::constants::PORT_POWER_FEATURE;
+use crate::constants::PORT_RESET_FEATURE;
+use crate::constants::SET_FEATURE_REQUEST;
+use crate::constants::SS_HUB_DESCRIPTOR_TYPE;
+use crate::descriptors::parse_hub_descriptor;
+use crate::Error;
+use crate::Result;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbRecipient;
+use crate::UsbRequestType;
+use crate::UsbSpeed;
+
+/// Logical power switching mode, bits 1..0 of wHubCharacteristics.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UsbHubPowerSwitching {
+  /// All ports are powered at once.
+  Ganged,
+  /// Each port is powered individually.
+  PerPort,
+  /// Ports are always powered. Used by USB 1.0 hubs only.
+  None,
+}
+
+/// wPortStatus and wPortChange of a hub port.
+/// USB 2.0 spec, Section 11.24.2.7 and USB 3.2 spec, Section 10.16.2.6.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UsbPortStatus {
+  pub status: u16,
+  pub change: u16,
+  superspeed: bool,
+}
+
+impl UsbPortStatus {
+  /// PORT_CONNECTION. A device is present on this port.
+  pub fn connected(&self) -> bool {
+    self.status & (1 << 0) != 0
+  }
+
+  /// PORT_ENABLE.
+  pub fn enabled(&self) -> bool {
+    self.status & (1 << 1) != 0
+  }
+
+  /// PORT_SUSPEND. Always false on SuperSpeed hubs.
+  pub fn suspended(&self) -> bool {
+    !self.superspeed && self.status & (1 << 2) != 0
+  }
+
+  /// PORT_OVER_CURRENT.
+  pub fn over_current(&self) -> bool {
+    self.status & (1 << 3) != 0
+  }
+
+  /// PORT_RESET. The port is being reset.
+  pub fn resetting(&self) -> bool {
+    self.status & (1 << 4) != 0
+  }
+
+  /// PORT_POWER. Bit 8 on USB 2.0 hubs, bit 9 on SuperSpeed hubs.
+  pub fn powered(&self) -> bool {
+    match self.superspeed {
+      true => self.status & (1 << 9) != 0,
+      false => self.status & (1 << 8) != 0,
+    }
+  }
+}
+
+/// A hub device with its hub descriptor.
+pub struct UsbHub {
+  device: UsbDevice,
+  num_ports: u8,
+  characteristics: u16,
+  power_on_delay: Duration,
+}
+
+impl UsbHub {
+  /// Opens `device` and reads its hub descriptor.
+  /// Fails with `Error::InvalidAccess` if the device is not a hub.
+  pub fn new(mut device: UsbDevice) -> Result<Self> {
+    if device.device_class != HUB_CLASS {
+      return Err(Error::InvalidAccess);
+    }
+
+    device.open()?;
+
+    let descriptor_type = match device.speed {
+      UsbSpeed::Super | UsbSpeed::SuperPlus => SS_HUB_DESCRIPTOR_TYPE,
+      _ => HUB_DESCRIPTOR_TYPE,
+    };
+    let bytes = device.control_transfer_in(
+      UsbControlTransferParameters {
+        request_type: UsbRequestType::Class,
+        recipient: UsbRecipient::Device,
+        request: GET_DESCRIPTOR_REQUEST,
+        value: (descriptor_type as u16) << 8,
+        index: 0,
+      },
+      // Large enough for a hub with 255 ports.
+      71,
+    )?;
+    let (num_ports, characteristics, power_on_to_power_good) =
+      parse_hub_descriptor(&bytes).ok_or(Error::NotFound)?;
+
+    Ok(UsbHub {
+      device,
+      num_ports,
+      characteristics,
+      // bPwrOn2PwrGood is in 2ms units.
+      power_on_delay: Duration::from_millis(power_on_to_power_good as u64 * 2),
+    })
+  }
+
+  pub fn device(&self) -> &UsbDevice {
+    &self.device
+  }
+
+  pub fn into_device(self) -> UsbDevice {
+    self.device
+  }
+
+  /// bNbrPorts field of the hub descriptor.
+  pub fn num_ports(&self) -> u8 {
+    self.num_ports
+  }
+
+  pub fn power_switching(&self) -> UsbHubPowerSwitching {
+    match self.characteristics & 0x3 {
+      0 => UsbHubPowerSwitching::Ganged,
+      1 => UsbHubPowerSwitching::PerPort,
+      _ => UsbHubPowerSwitching::None,
+    }
+  }
+
+  /// Time from powering on a port until its power is good
+  /// (bPwrOn2PwrGood).
+  pub fn power_on_delay(&self) -> Duration {
+    self.power_on_delay
+  }
+
+  /// GET_PORT_STATUS. Ports are numbered starting at 1.
+  pub fn port_status(&mut self, port: u8) -> Result<UsbPortStatus> {
+    self.validate_port(port)?;
+
+    let bytes = self
+      .device
+      .control_transfer_in(port_request(GET_STATUS_REQUEST, 0, port), 4)?;
+    if bytes.len() < 4 {
+      return Err(Error::InvalidState);
+    }
+
+    Ok(UsbPortStatus {
+      status: u16::from_le_bytes([bytes[0], bytes[1]]),
+      change: u16::from_le_bytes([bytes[2], bytes[3]]),
+      superspeed: matches!(
+        self.device.speed,
+        UsbSpeed::Super | UsbSpeed::SuperPlus
+      ),
+    })
+  }
+
+  /// SET_FEATURE or CLEAR_FEATURE PORT_POWER.
+  ///
+  /// Hubs with ganged power switching switch all ports at once, and some
+  /// hubs ignore the request entirely.
+  pub fn set_port_power(&mut self, port: u8, on: bool) -> Result<()> {
+    self.validate_port(port)?;
+
+    let request = match on {
+      true => SET_FEATURE_REQUEST,
+      false => CLEAR_FEATURE_REQUEST,
+    };
+    self.device.control_transfer_out(
+      port_request(request, PORT_POWER_FEATURE, port),
+      &[],
+    )?;
+    Ok(())
+  }
+
+  /// SET_FEATURE PORT_RESET.
+  pub fn reset_port(&mut self, port: u8) -> Result<()> {
+    self.validate_port(port)?;
+
+    self.device.control_transfer_out(
+      port_request(SET_FEATURE_REQUEST, PORT_RESET_FEATURE, port),
+      &[],
+    )?;
+    Ok(())
+  }
+
+  /// Switches port power off for `off_time`, then back on and waits for
+  /// the power to be good.
+  pub fn power_cycle(&mut self, port: u8, off_time: Duration) -> Result<()> {
+    self.set_port_power(port, false)?;
+    std::thread::sleep(off_time);
+    self.set_port_power(port, true)?;
+    std::thread::sleep(self.power_on_delay);
+    Ok(())
+  }
+
+  fn validate_port(&self, port: u8) -> Result<()> {
+    if port == 0 || port > self.num_ports {
+      return Err(Error::NotFound);
+    }
+    Ok(())
+  }
+}
+
+fn port_request(
+  request: u8,
+  feature: u16,
+  port: u8,
+) -> UsbControlTransferParameters {
+  UsbControlTransferParameters {
+    request_type: UsbRequestType::Class,
+    recipient: UsbRecipient::Other,
+    request,
+    value: feature,
+    index: port as u16,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  #[cfg(any(feature = "usbip", feature = "proxy"))]
+  use std::time::Duration;
+
+  #[cfg(any(feature = "usbip", feature = "proxy"))]
+  use crate::constants::HUB_CLASS;
+  #[cfg(any(feature = "usbip", feature = "proxy"))]
+  use crate::hub::UsbHub;
+  use crate::hub::UsbPortStatus;
+  #[cfg(any(feature = "usbip", feature = "proxy"))]
+  use crate::loopback::Loopback;
+
+  #[test]
+  fn test_port_status() {
+    // Connected, enabled and powered high-speed device on a USB 2.0 hub.
+    let status = UsbPortStatus {
+      status: 0x0503,
+      change: 0x0000,
+      superspeed: false,
+    };
+    assert!(status.connected());
+    assert!(status.enabled());
+    assert!(status.powered());
+    assert!(!status.over_current());
+
+    // Powered but empty port on a SuperSpeed hub.
+    let status = UsbPortStatus {
+      status: 0x02A0,
+      change: 0x0000,
+      superspeed: true,
+    };
+    assert!(!status.connected());
+    assert!(status.powered());
+    assert!(!status.suspended());
+
+    // Port power switched off on a USB 2.0 hub.
+    let status = UsbPortStatus {
+      status: 0x0000,
+      change: 0x0000,
+      superspeed: false,
+    };
+    assert!(!status.powered());
+  }
+
+  #[cfg(any(feature = "usbip", feature = "proxy"))]
+  #[test]
+  fn test_port_requests() {
+    let (mut device, setups) = Loopback::device_with_setups();
+    device.device_class = HUB_CLASS;
+    let mut hub = UsbHub::new(device).unwrap();
+    assert_eq!(hub.num_ports(), 4);
+    assert_eq!(hub.power_on_delay(), Duration::from_millis(2));
+    setups.lock().unwrap().clear();
+
+    hub.set_port_power(2, false).unwrap();
+    hub.set_port_power(2, true).unwrap();
+    hub.reset_port(3).unwrap();
+    hub.power_cycle(4, Duration::from_millis(1)).unwrap();
+    assert_eq!(
+      *setups.lock().unwrap(),
+      vec![
+        // CLEAR_FEATURE(PORT_POWER), port 2
+        [0x23, 0x01, 0x08, 0x00, 0x02, 0x00, 0x00, 0x00],
+        // SET_FEATURE(PORT_POWER), port 2
+        [0x23, 0x03, 0x08, 0x00, 0x02, 0x00, 0x00, 0x00],
+        // SET_FEATURE(PORT_RESET), port 3
+        [0x23, 0x03, 0x04, 0x00, 0x03, 0x00, 0x00, 0x00],
+        // Power cycle of port 4
+        [0x23, 0x01, 0x08, 0x00, 0x04, 0x00, 0x00, 0x00],
+        [0x23, 0x03, 0x08, 0x00, 0x04, 0x00, 0x00, 0x00],
+      ]
+    );
+
+    // Ports are numbered from 1 to bNbrPorts.
+    assert!(hub.set_port_power(0, true).is_err());
+    assert!(hub.reset_port(5).is_err());
+    assert_eq!(setups.lock().unwrap().len(), 5);
+  }
+}
@@ -0,0 +1,50 @@
+//! ioctl helpers shared by the usbfs backend and the raw-gadget device
+//! stack. Both use the ioctl type 'U'.
+
+use crate::Error;
+use crate::Result;
+
+// _IOC encoding of asm-generic/ioctl.h.
+pub(crate) const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+  dir << 30 | (size as u32) << 16 | (b'U' as u32) << 8 | nr
+}
+
+pub(crate) const IOC_NONE: u32 = 0;
+pub(crate) const IOC_WRITE: u32 = 1;
+pub(crate) const IOC_READ: u32 = 2;
+
+pub(crate) const UINT_SIZE: usize = std::mem::size_of::<libc::c_uint>();
+
+pub(crate) fn error_from_errno(errno: i32) -> Error {
+  match errno {
+    libc::EPIPE => Error::Stall,
+    libc::ENODEV | libc::ESHUTDOWN => Error::NotFound,
+    libc::EBUSY => Error::InvalidState,
+    libc::ENOENT | libc::ECONNRESET => {
+      Error::Io(std::io::ErrorKind::Interrupted)
+    }
+    errno => Error::Io(std::io::Error::from_raw_os_error(errno).kind()),
+  }
+}
+
+pub(crate) fn last_errno() -> i32 {
+  std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+// Retries calls interrupted by a signal.
+pub(crate) fn ioctl(
+  fd: libc::c_int,
+  request: u32,
+  arg: *mut libc::c_void,
+) -> Result<libc::c_int> {
+  loop {
+    let ret = unsafe { libc::ioctl(fd, request as _, arg) };
+    if ret >= 0 {
+      return Ok(ret);
+    }
+    match last_errno() {
+      libc::EINTR => continue,
+      errno => return Err(error_from_errno(errno)),
+    }
+  }
+}
@@ -8,8 +8,8 @@
 //! The crate is designed to be as close to the WebUSB specification as possible.
 //! There are two "backends" available, Native and WASM.
 //!
-//! The native backend (`libusb`) supports parsing webusb descriptors. The wasm backend will
-//! make use of the runtime's WebUSB implementation.
+//! The native backend (`libusb`) supports parsing webusb descriptors. The wasm backend
+//! makes use of the runtime's WebUSB implementation, see `wasm`.
 //!
 //! see [usbd-webusb](https://github.com/redpfire/usbd-webusb) for WebUSB compatible firmware
 //! for the device.
@@ -21,39 +21,96 @@
 
 #[cfg(feature = "serde_derive")]
 use serde::Deserialize;
-#[cfg(feature = "deno_ffi")]
+#[cfg(all(feature = "deno_ffi", not(test)))]
 use serde::Deserialize;
 #[cfg(feature = "serde_derive")]
 use serde::Serialize;
-#[cfg(feature = "deno_ffi")]
+#[cfg(all(feature = "deno_ffi", not(test)))]
 use serde::Serialize;
 
-use std::ops::DerefMut;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 
 #[cfg(feature = "libusb")]
 use rusb::UsbContext;
 
+#[cfg(feature = "libusb")]
 use core::convert::TryFrom;
 
 #[cfg(feature = "libusb")]
 pub use rusb;
 
+pub mod backend;
+#[cfg(feature = "bridge")]
+pub mod bridge;
+#[cfg(feature = "capi")]
+pub mod capi;
 pub mod constants;
+// Browsers parse the descriptors themselves.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
 mod descriptors;
+#[cfg(any(feature = "usbip", feature = "usbfs", feature = "proxy"))]
+mod enumeration;
 #[cfg(feature = "deno_ffi")]
 pub mod ffi;
-
+#[cfg(all(feature = "gadget", target_os = "linux"))]
+pub mod gadget;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod hub;
+#[cfg(all(any(feature = "usbfs", feature = "gadget"), target_os = "linux"))]
+mod ioctl;
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod loopback;
+#[cfg(feature = "node")]
+pub mod node;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pipe;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(all(feature = "usbfs", target_os = "linux"))]
+mod usbfs;
+#[cfg(feature = "usbip")]
+pub mod usbip;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::backend::BoxedBackend;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::backend::Transfer;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::backend::TransferQueue;
+#[cfg(feature = "libusb")]
 use crate::constants::BOS_DESCRIPTOR_TYPE;
+#[cfg(feature = "libusb")]
+use crate::constants::CONFIGURATION_DESCRIPTOR_TYPE;
+#[cfg(feature = "libusb")]
+use crate::constants::GET_DESCRIPTOR_REQUEST;
+#[cfg(feature = "libusb")]
 use crate::constants::GET_URL_REQUEST;
+#[cfg(any(feature = "libusb", feature = "proxy"))]
+use crate::constants::HUB_CLASS;
+#[cfg(feature = "libusb")]
 use crate::descriptors::parse_bos;
+#[cfg(feature = "libusb")]
+use crate::descriptors::parse_ss_endpoint_companion;
+#[cfg(feature = "libusb")]
 use crate::descriptors::parse_webusb_url;
 
-#[cfg(feature = "deno_ffi")]
+#[cfg(all(feature = "deno_ffi", not(test)))]
 use deno_bindgen::deno_bindgen;
 
 const EP_DIR_IN: u8 = 0x80;
 const EP_DIR_OUT: u8 = 0x0;
 
+// Not yet exported by libusb1-sys.
+#[cfg(feature = "libusb")]
+const LIBUSB_SPEED_SUPER_PLUS: std::os::raw::c_int = 5;
+
 #[derive(Debug, PartialEq)]
 #[non_exhaustive]
 pub enum Error {
@@ -62,6 +119,11 @@ pub enum Error {
   NotFound,
   InvalidState,
   InvalidAccess,
+  /// The endpoint is halted. Clear it with `UsbDevice::clear_halt`.
+  Stall,
+  /// Error of the transport to the device, e.g. the network connection of
+  /// a USB/IP device.
+  Io(std::io::ErrorKind),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -73,19 +135,74 @@ impl From<rusb::Error> for Error {
   }
 }
 
+impl From<std::io::Error> for Error {
+  fn from(err: std::io::Error) -> Self {
+    Self::Io(err.kind())
+  }
+}
+
+impl From<Error> for std::io::Error {
+  fn from(err: Error) -> Self {
+    match err {
+      Error::Io(kind) => kind.into(),
+      err => std::io::Error::other(format!("{:?}", err)),
+    }
+  }
+}
+
 impl<T> From<Option<T>> for Error {
   fn from(_: Option<T>) -> Self {
     Self::NotFound
   }
 }
 
+impl Error {
+  /// Name of the DOMException a WebUSB method rejects with on this error.
+  pub fn dom_exception_name(&self) -> &'static str {
+    match self {
+      Error::NotFound => "NotFoundError",
+      Error::InvalidState => "InvalidStateError",
+      Error::InvalidAccess => "InvalidAccessError",
+      Error::Io(std::io::ErrorKind::PermissionDenied) => "SecurityError",
+      Error::Io(std::io::ErrorKind::Interrupted) => "AbortError",
+      Error::Io(std::io::ErrorKind::InvalidInput) => "TypeError",
+      Error::Io(std::io::ErrorKind::Unsupported) => "NotSupportedError",
+      #[cfg(feature = "libusb")]
+      Error::Usb(rusb::Error::Access) => "SecurityError",
+      #[cfg(feature = "libusb")]
+      Error::Usb(rusb::Error::NoDevice) => "NotFoundError",
+      _ => "NetworkError",
+    }
+  }
+}
+
+// Stalls and babble are part of the result of a transfer in WebUSB rather
+// than errors.
+// https://wicg.github.io/webusb/#enumdef-usbtransferstatus
+#[cfg(any(feature = "bridge", feature = "node", feature = "deno_ffi"))]
+pub(crate) fn transfer_status<T: Default>(
+  result: Result<T>,
+) -> Result<(&'static str, T)> {
+  match result {
+    Ok(value) => Ok(("ok", value)),
+    Err(Error::Stall) => Ok(("stall", T::default())),
+    #[cfg(feature = "libusb")]
+    Err(Error::Usb(rusb::Error::Overflow)) => Ok(("babble", T::default())),
+    Err(err) => Err(err),
+  }
+}
+
 #[derive(Clone)]
 #[cfg_attr(
   feature = "serde_derive",
   derive(Serialize, Deserialize),
   serde(rename_all = "camelCase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "camelCase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
 pub struct UsbConfiguration {
   // Index of String Descriptor describing this configuration.
   configuration_name: Option<String>,
@@ -93,6 +210,91 @@ pub struct UsbConfiguration {
   // https://www.beyondlogic.org/usbnutshell/usb5.shtml#ConfigurationDescriptors
   configuration_value: u8,
   interfaces: Vec<UsbInterface>,
+  // Bit 6 of bmAttributes.
+  self_powered: bool,
+  // Bit 5 of bmAttributes.
+  remote_wakeup: bool,
+  // bMaxPower in milliamps. The unit of bMaxPower is 2mA at high-speed or
+  // lower and 8mA at SuperSpeed or faster.
+  max_power: u16,
+  // Full configuration descriptor as returned by GET_DESCRIPTOR, including
+  // interface, endpoint and class-specific descriptors.
+  raw_descriptor: Option<Vec<u8>>,
+}
+
+impl UsbConfiguration {
+  /// Optional property of the string descriptor.
+  /// Indexed by the iConfiguration field of the configuration descriptor.
+  pub fn configuration_name(&self) -> Option<&str> {
+    self.configuration_name.as_deref()
+  }
+
+  /// bConfigurationValue field of the configuration descriptor.
+  pub fn configuration_value(&self) -> u8 {
+    self.configuration_value
+  }
+
+  /// List of interfaces exposed by this configuration.
+  pub fn interfaces(&self) -> &[UsbInterface] {
+    &self.interfaces
+  }
+
+  /// Finds the interface with the given bInterfaceNumber.
+  pub fn interface(&self, interface_number: u8) -> Option<&UsbInterface> {
+    self
+      .interfaces
+      .iter()
+      .find(|itf| itf.interface_number == interface_number)
+  }
+
+  /// Finds the first interface with an alternate setting matching the
+  /// given class, subclass and protocol triple.
+  pub fn find_interface(
+    &self,
+    class: u8,
+    subclass: u8,
+    protocol: u8,
+  ) -> Option<&UsbInterface> {
+    self.interfaces.iter().find(|itf| {
+      itf.alternates.iter().any(|alt| {
+        alt.interface_class == class
+          && alt.interface_subclass == subclass
+          && alt.interface_protocol == protocol
+      })
+    })
+  }
+
+  /// Finds an endpoint in the currently selected alternate settings by its
+  /// bEndpointAddress (endpoint number with direction bit).
+  pub fn endpoint(&self, address: u8) -> Option<&UsbEndpoint> {
+    self
+      .interfaces
+      .iter()
+      .flat_map(|itf| itf.endpoints())
+      .find(|endpoint| endpoint.address() == address)
+  }
+
+  /// True, if the device is self-powered in this configuration.
+  pub fn self_powered(&self) -> bool {
+    self.self_powered
+  }
+
+  /// True, if the device supports remote wakeup in this configuration.
+  pub fn remote_wakeup(&self) -> bool {
+    self.remote_wakeup
+  }
+
+  /// Maximum power consumption from the bus in this configuration,
+  /// in milliamps.
+  pub fn max_power(&self) -> u16 {
+    self.max_power
+  }
+
+  /// Raw bytes of the configuration descriptor (wTotalLength bytes).
+  /// None, if the descriptor could not be read from the device.
+  pub fn raw_descriptor(&self) -> Option<&[u8]> {
+    self.raw_descriptor.as_deref()
+  }
 }
 
 #[cfg(feature = "libusb")]
@@ -101,6 +303,14 @@ impl UsbConfiguration {
     config_descriptor: rusb::ConfigDescriptor,
     handle: &rusb::DeviceHandle<rusb::Context>,
   ) -> Result<Self> {
+    // rusb reports bMaxPower in 2mA units regardless of the device speed.
+    let max_power = match UsbSpeed::from(&handle.device()) {
+      UsbSpeed::Super | UsbSpeed::SuperPlus => {
+        config_descriptor.max_power() * 4
+      }
+      _ => config_descriptor.max_power(),
+    };
+
     Ok(UsbConfiguration {
       configuration_name: match config_descriptor.description_string_index() {
         None => None,
@@ -111,17 +321,84 @@ impl UsbConfiguration {
         .interfaces()
         .map(|i| UsbInterface::from(i, &handle))
         .collect::<Vec<UsbInterface>>(),
+      self_powered: config_descriptor.self_powered(),
+      remote_wakeup: config_descriptor.remote_wakeup(),
+      max_power,
+      raw_descriptor: read_raw_config_descriptor(
+        handle,
+        config_descriptor.number(),
+      ),
     })
   }
 }
 
+#[cfg(feature = "libusb")]
+fn read_raw_config_descriptor(
+  handle: &rusb::DeviceHandle<rusb::Context>,
+  configuration_value: u8,
+) -> Option<Vec<u8>> {
+  // GET_DESCRIPTOR is indexed by the position of the configuration, not by
+  // its bConfigurationValue.
+  let device = handle.device();
+  let num_configurations =
+    device.device_descriptor().ok()?.num_configurations();
+  let config_idx = (0..num_configurations).find(|idx| {
+    device
+      .config_descriptor(*idx)
+      .map(|c| c.number() == configuration_value)
+      .unwrap_or(false)
+  })?;
+
+  let request_type = rusb::request_type(
+    rusb::Direction::In,
+    rusb::RequestType::Standard,
+    rusb::Recipient::Device,
+  );
+  let value = (CONFIGURATION_DESCRIPTOR_TYPE as u16) << 8 | config_idx as u16;
+
+  // Read the header first to learn wTotalLength.
+  let mut header = [0; 9];
+  let length = handle
+    .read_control(
+      request_type,
+      GET_DESCRIPTOR_REQUEST,
+      value,
+      0,
+      &mut header,
+      core::time::Duration::new(2, 0),
+    )
+    .ok()?;
+  if length < 4 || header[1] != CONFIGURATION_DESCRIPTOR_TYPE {
+    return None;
+  }
+
+  let total_length = u16::from_le_bytes([header[2], header[3]]);
+  let mut buffer = vec![0; total_length as usize];
+  let length = handle
+    .read_control(
+      request_type,
+      GET_DESCRIPTOR_REQUEST,
+      value,
+      0,
+      &mut buffer,
+      core::time::Duration::new(2, 0),
+    )
+    .ok()?;
+  buffer.truncate(length);
+  Some(buffer)
+}
+
 #[derive(Clone)]
 #[cfg_attr(
   feature = "serde_derive",
   derive(Serialize, Deserialize),
   serde(rename_all = "camelCase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "camelCase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
 pub struct UsbInterface {
   interface_number: u8,
   alternate: UsbAlternateInterface,
@@ -129,6 +406,45 @@ pub struct UsbInterface {
   claimed: bool,
 }
 
+impl UsbInterface {
+  /// bInterfaceNumber field of the interface descriptor.
+  pub fn interface_number(&self) -> u8 {
+    self.interface_number
+  }
+
+  /// Currently selected alternate setting of the interface.
+  pub fn alternate(&self) -> &UsbAlternateInterface {
+    &self.alternate
+  }
+
+  /// List of all alternate settings of the interface.
+  pub fn alternates(&self) -> &[UsbAlternateInterface] {
+    &self.alternates
+  }
+
+  /// Finds the alternate setting with the given bAlternateSetting.
+  pub fn alternate_setting(
+    &self,
+    alternate_setting: u8,
+  ) -> Option<&UsbAlternateInterface> {
+    self
+      .alternates
+      .iter()
+      .find(|alt| alt.alternate_setting == alternate_setting)
+  }
+
+  /// Endpoints of the currently selected alternate setting.
+  pub fn endpoints(&self) -> &[UsbEndpoint] {
+    &self.alternate.endpoints
+  }
+
+  /// True, if the interface has been claimed with
+  /// `UsbDevice::claim_interface`.
+  pub fn claimed(&self) -> bool {
+    self.claimed
+  }
+}
+
 #[cfg(feature = "libusb")]
 impl UsbInterface {
   pub fn from(
@@ -154,13 +470,17 @@ impl UsbInterface {
   }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[cfg_attr(
   feature = "serde_derive",
   derive(Serialize, Deserialize),
   serde(rename_all = "camelCase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "camelCase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
 pub enum UsbEndpointType {
   Bulk,
   Interrupt,
@@ -168,13 +488,17 @@ pub enum UsbEndpointType {
   Control,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[cfg_attr(
   feature = "serde_derive",
   derive(Serialize, Deserialize),
   serde(rename_all = "lowercase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "lowercase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "lowercase")
+)]
 pub enum Direction {
   In,
   Out,
@@ -186,13 +510,228 @@ pub enum Direction {
   derive(Serialize, Deserialize),
   serde(rename_all = "camelCase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "camelCase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
 pub struct UsbEndpoint {
   endpoint_number: u8,
   direction: Direction,
   // TODO(@littledivy): Get rid of reserved `type` key somehow?
   r#type: UsbEndpointType,
+  // Bits 10..0 of wMaxPacketSize.
   packet_size: u16,
+  // Bits 12..11 of wMaxPacketSize plus one. Number of transactions per
+  // microframe for high-speed, high-bandwidth periodic endpoints.
+  transactions_per_microframe: u8,
+  // bInterval. Interpretation depends on the endpoint type and device speed.
+  interval: u8,
+  // Bits 3..2 of bmAttributes. Only meaningful for isochronous endpoints.
+  sync_type: UsbSyncType,
+  // Bits 5..4 of bmAttributes. Only meaningful for isochronous endpoints.
+  usage_type: UsbUsageType,
+  // SuperSpeed Endpoint Companion descriptor, if the device provides one.
+  superspeed_companion: Option<UsbSuperSpeedEndpointCompanion>,
+}
+
+impl UsbEndpoint {
+  /// Endpoint number (bits 3..0 of bEndpointAddress).
+  pub fn endpoint_number(&self) -> u8 {
+    self.endpoint_number
+  }
+
+  /// Direction of the endpoint (bit 7 of bEndpointAddress).
+  pub fn direction(&self) -> Direction {
+    self.direction
+  }
+
+  /// bEndpointAddress of the endpoint, combining the endpoint number and
+  /// the direction bit.
+  pub fn address(&self) -> u8 {
+    match self.direction {
+      Direction::In => EP_DIR_IN | self.endpoint_number,
+      Direction::Out => EP_DIR_OUT | self.endpoint_number,
+    }
+  }
+
+  /// Transfer type of the endpoint (bits 1..0 of bmAttributes).
+  pub fn endpoint_type(&self) -> UsbEndpointType {
+    self.r#type
+  }
+
+  /// Maximum packet size of the endpoint (bits 10..0 of wMaxPacketSize).
+  pub fn packet_size(&self) -> u16 {
+    self.packet_size
+  }
+
+  /// bInterval value of the endpoint descriptor.
+  ///
+  /// For full-speed interrupt endpoints this is the polling period in frames.
+  /// For isochronous and high-speed or faster interrupt endpoints the period
+  /// is 2^(interval - 1) (micro)frames.
+  pub fn interval(&self) -> u8 {
+    self.interval
+  }
+
+  /// Synchronization type of an isochronous endpoint.
+  pub fn sync_type(&self) -> UsbSyncType {
+    self.sync_type
+  }
+
+  /// Usage type of an isochronous endpoint.
+  pub fn usage_type(&self) -> UsbUsageType {
+    self.usage_type
+  }
+
+  /// Number of transactions per microframe (1-3) for high-speed,
+  /// high-bandwidth endpoints. Always 1 for other endpoints.
+  pub fn transactions_per_microframe(&self) -> u8 {
+    self.transactions_per_microframe
+  }
+
+  /// SuperSpeed Endpoint Companion information.
+  /// None, if the device is not operating at SuperSpeed or faster.
+  pub fn superspeed_companion(
+    &self,
+  ) -> Option<&UsbSuperSpeedEndpointCompanion> {
+    self.superspeed_companion.as_ref()
+  }
+}
+
+#[cfg(feature = "libusb")]
+impl UsbEndpoint {
+  pub fn from(e: rusb::EndpointDescriptor) -> Self {
+    let r#type = match e.transfer_type() {
+      rusb::TransferType::Control => UsbEndpointType::Control,
+      rusb::TransferType::Isochronous => UsbEndpointType::Isochronous,
+      rusb::TransferType::Bulk => UsbEndpointType::Bulk,
+      rusb::TransferType::Interrupt => UsbEndpointType::Interrupt,
+    };
+
+    let max_packet_size = e.max_packet_size();
+
+    UsbEndpoint {
+      endpoint_number: e.number(),
+      packet_size: max_packet_size & 0x7FF,
+      transactions_per_microframe: ((max_packet_size >> 11) & 0x3) as u8 + 1,
+      direction: match e.direction() {
+        rusb::Direction::In => Direction::In,
+        rusb::Direction::Out => Direction::Out,
+      },
+      interval: e.interval(),
+      sync_type: match e.sync_type() {
+        rusb::SyncType::NoSync => UsbSyncType::None,
+        rusb::SyncType::Asynchronous => UsbSyncType::Asynchronous,
+        rusb::SyncType::Adaptive => UsbSyncType::Adaptive,
+        rusb::SyncType::Synchronous => UsbSyncType::Synchronous,
+      },
+      usage_type: match e.usage_type() {
+        rusb::UsageType::Data => UsbUsageType::Data,
+        rusb::UsageType::Feedback => UsbUsageType::Feedback,
+        rusb::UsageType::FeedbackData => UsbUsageType::ImplicitFeedbackData,
+        rusb::UsageType::Reserved => UsbUsageType::Reserved,
+      },
+      superspeed_companion: e
+        .extra()
+        .and_then(parse_ss_endpoint_companion)
+        .map(|companion| {
+          UsbSuperSpeedEndpointCompanion::from_raw(companion, r#type)
+        }),
+      r#type,
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(
+  feature = "serde_derive",
+  derive(Serialize, Deserialize),
+  serde(rename_all = "camelCase")
+)]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
+pub enum UsbSyncType {
+  None,
+  Asynchronous,
+  Adaptive,
+  Synchronous,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(
+  feature = "serde_derive",
+  derive(Serialize, Deserialize),
+  serde(rename_all = "camelCase")
+)]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
+pub enum UsbUsageType {
+  Data,
+  Feedback,
+  ImplicitFeedbackData,
+  Reserved,
+}
+
+/// SuperSpeed Endpoint Companion descriptor.
+/// https://www.usb.org/document-library/usb-32-revision-11-june-2022 (9.6.7)
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+  feature = "serde_derive",
+  derive(Serialize, Deserialize),
+  serde(rename_all = "camelCase")
+)]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
+pub struct UsbSuperSpeedEndpointCompanion {
+  /// Maximum number of packets the endpoint can send or receive as part of
+  /// a burst (1-16). Derived from bMaxBurst.
+  pub max_burst: u8,
+  /// Maximum number of streams supported by a bulk endpoint.
+  /// 0, if the endpoint does not support streams.
+  pub max_streams: u32,
+  /// Maximum number of bursts within a service interval for isochronous
+  /// endpoints (1-3). Always 1 for other endpoints.
+  pub mult: u8,
+  /// Total number of bytes the endpoint will transfer every service
+  /// interval. Only meaningful for periodic endpoints.
+  pub bytes_per_interval: u32,
+}
+
+impl UsbSuperSpeedEndpointCompanion {
+  #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+  fn from_raw(
+    (max_burst, attributes, bytes_per_interval): (u8, u8, u32),
+    r#type: UsbEndpointType,
+  ) -> Self {
+    let (max_streams, mult) = match r#type {
+      UsbEndpointType::Bulk => match attributes & 0x1F {
+        0 => (0, 1),
+        n => (1 << n, 1),
+      },
+      // SuperSpeedPlus isochronous endpoints (bit 7) ignore Mult.
+      UsbEndpointType::Isochronous if attributes & 0x80 == 0 => {
+        (0, (attributes & 0x3) + 1)
+      }
+      _ => (0, 1),
+    };
+
+    UsbSuperSpeedEndpointCompanion {
+      max_burst: max_burst + 1,
+      max_streams,
+      mult,
+      bytes_per_interval,
+    }
+  }
 }
 
 #[derive(Clone)]
@@ -201,7 +740,11 @@ pub struct UsbEndpoint {
   derive(Serialize, Deserialize),
   serde(rename_all = "camelCase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "camelCase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
 pub struct UsbAlternateInterface {
   pub alternate_setting: u8,
   pub interface_class: u8,
@@ -225,46 +768,15 @@ impl UsbAlternateInterface {
       interface_name: d
         .description_string_index()
         .map(|idx| handle.read_string_descriptor_ascii(idx).unwrap()),
-      endpoints: d
-        .endpoint_descriptors()
-        .map(|e| UsbEndpoint {
-          endpoint_number: e.number(),
-          packet_size: e.max_packet_size(),
-          direction: match e.direction() {
-            rusb::Direction::In => Direction::In,
-            rusb::Direction::Out => Direction::Out,
-          },
-          r#type: match e.transfer_type() {
-            rusb::TransferType::Control => UsbEndpointType::Control,
-            rusb::TransferType::Isochronous => UsbEndpointType::Isochronous,
-            rusb::TransferType::Bulk => UsbEndpointType::Bulk,
-            rusb::TransferType::Interrupt => UsbEndpointType::Interrupt,
-          },
-        })
-        .collect(),
+      endpoints: d.endpoint_descriptors().map(UsbEndpoint::from).collect(),
     }
   }
 }
 
-#[cfg(feature = "deno_ffi")]
-macro_rules! get_device_handle {
-  ($self: expr) => {
-    ffi::RESOURCES
-      .lock()
-      .unwrap()
-      .get_mut(&$self.rid)
-      .unwrap()
-      .lock()
-      .unwrap()
-      .device_handle
-      .as_mut()
-  };
-}
-
-#[cfg(not(feature = "deno_ffi"))]
-macro_rules! get_device_handle {
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! backend {
   ($self: expr) => {
-    $self.device_handle.as_mut()
+    $self.backend.as_mut()
   };
 }
 
@@ -276,7 +788,11 @@ macro_rules! get_device_handle {
   derive(Serialize, Deserialize),
   serde(rename_all = "camelCase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "camelCase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
 pub struct UsbDevice {
   /// List of configurations supported by the device.
   /// Populated from the configuration descriptor.
@@ -328,6 +844,19 @@ pub struct UsbDevice {
   pub vendor_id: u16,
   /// If true, the underlying device handle is owned by this object.
   pub opened: bool,
+  /// Number of the bus the device is connected to.
+  pub bus_number: u8,
+  /// Address of the device on its bus. Reassigned by the host controller
+  /// every time the device is (re-)enumerated.
+  pub device_address: u8,
+  /// Port numbers from the root hub down to the device. Stable across
+  /// re-enumeration as long as the physical topology does not change.
+  pub port_numbers: Vec<u8>,
+  /// Negotiated connection speed of the device.
+  pub speed: UsbSpeed,
+  /// The hub this device is plugged into.
+  /// None, if the device is a root hub.
+  pub parent_hub: Option<UsbParentHub>,
 
   /// WEBUSB_URL value of the WebUSB Platform Capability Descriptor.
   #[cfg_attr(
@@ -337,21 +866,121 @@ pub struct UsbDevice {
   )]
   pub url: Option<String>,
 
-  #[cfg(feature = "deno_ffi")]
-  /// Resource ID associated with this Device instance.
-  pub rid: i32,
+  #[cfg_attr(
+    any(feature = "serde_derive", all(feature = "deno_ffi", not(test))),
+    serde(skip, default = "backend::Detached::boxed")
+  )]
+  #[cfg(not(target_arch = "wasm32"))]
+  backend: BoxedBackend,
 
   #[cfg_attr(feature = "serde_derive", serde(skip))]
-  #[cfg(feature = "libusb")]
-  #[cfg(not(feature = "deno_ffi"))]
-  device: rusb::Device<rusb::Context>,
+  #[cfg(target_arch = "wasm32")]
+  device: Option<web_sys::UsbDevice>,
+}
 
-  #[cfg_attr(feature = "serde_derive", serde(skip))]
-  #[cfg(feature = "libusb")]
-  #[cfg(not(feature = "deno_ffi"))]
-  device_handle: Option<rusb::DeviceHandle<rusb::Context>>,
+/// Negotiated connection speed of a device.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(
+  feature = "serde_derive",
+  derive(Serialize, Deserialize),
+  serde(rename_all = "camelCase")
+)]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
+pub enum UsbSpeed {
+  Unknown,
+  /// 1.5 Mbit/s
+  Low,
+  /// 12 Mbit/s
+  Full,
+  /// 480 Mbit/s
+  High,
+  /// 5 Gbit/s
+  Super,
+  /// 10 Gbit/s or faster
+  SuperPlus,
+}
+
+#[cfg(feature = "libusb")]
+impl UsbSpeed {
+  pub fn from(device: &rusb::Device<rusb::Context>) -> Self {
+    // `rusb::Speed` does not know about SuperSpeedPlus, so ask libusb
+    // directly.
+    match unsafe { libusb1_sys::libusb_get_device_speed(device.as_raw()) } {
+      libusb1_sys::constants::LIBUSB_SPEED_LOW => UsbSpeed::Low,
+      libusb1_sys::constants::LIBUSB_SPEED_FULL => UsbSpeed::Full,
+      libusb1_sys::constants::LIBUSB_SPEED_HIGH => UsbSpeed::High,
+      libusb1_sys::constants::LIBUSB_SPEED_SUPER => UsbSpeed::Super,
+      LIBUSB_SPEED_SUPER_PLUS => UsbSpeed::SuperPlus,
+      _ => UsbSpeed::Unknown,
+    }
+  }
+}
+
+/// Identifies the hub a device is plugged into.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+  feature = "serde_derive",
+  derive(Serialize, Deserialize),
+  serde(rename_all = "camelCase")
+)]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
+pub struct UsbParentHub {
+  /// Number of the bus the hub is connected to.
+  pub bus_number: u8,
+  /// Address of the hub on its bus.
+  pub device_address: u8,
+  /// Port numbers from the root hub down to the hub.
+  /// Empty, if the hub is a root hub.
+  pub port_numbers: Vec<u8>,
+  /// idVendor field of the hub's device descriptor.
+  pub vendor_id: u16,
+  /// idProduct field of the hub's device descriptor.
+  pub product_id: u16,
+}
+
+#[cfg(feature = "libusb")]
+impl UsbParentHub {
+  pub fn from(device: &rusb::Device<rusb::Context>) -> Option<Self> {
+    let parent = device.get_parent()?;
+    let device_descriptor = parent.device_descriptor().ok()?;
+
+    Some(UsbParentHub {
+      bus_number: parent.bus_number(),
+      device_address: parent.address(),
+      port_numbers: parent.port_numbers().unwrap_or_default(),
+      vendor_id: device_descriptor.vendor_id(),
+      product_id: device_descriptor.product_id(),
+    })
+  }
 }
 
+impl UsbDevice {
+  /// Physical location of the device in the Linux sysfs notation,
+  /// `<bus>-<port>.<port>...` (e.g. `1-2.4`). Root hubs are `usb<bus>`.
+  pub fn port_path(&self) -> String {
+    if self.port_numbers.is_empty() {
+      return format!("usb{}", self.bus_number);
+    }
+
+    let ports = self
+      .port_numbers
+      .iter()
+      .map(|port| port.to_string())
+      .collect::<Vec<String>>()
+      .join(".");
+    format!("{}-{}", self.bus_number, ports)
+  }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl UsbDevice {
   // https://wicg.github.io/webusb/#check-the-validity-of-the-control-transfer-parameters
   fn validate_control_setup(
@@ -417,17 +1046,35 @@ impl UsbDevice {
 
     Ok(())
   }
-}
 
-impl UsbDevice {
-  pub fn isochronous_transfer_in(&mut self) {
-    unimplemented!()
-  }
-
-  pub fn isochronous_transfer_out(&mut self) {
-    unimplemented!()
+  // Endpoint `endpoint_number` in `direction` of any alternate setting of
+  // the selected configuration.
+  fn endpoint(
+    &self,
+    direction: Direction,
+    endpoint_number: u8,
+  ) -> Result<&UsbEndpoint> {
+    self
+      .configuration
+      .as_ref()
+      .ok_or(Error::NotFound)?
+      .interfaces
+      .iter()
+      .find_map(|itf| {
+        itf.alternates.iter().find_map(|alt| {
+          alt.endpoints.iter().find(|endpoint| {
+            endpoint.endpoint_number == endpoint_number
+              && endpoint.direction == direction
+          })
+        })
+      })
+      .ok_or(Error::NotFound)
   }
+}
 
+// See `wasm` for the browser's async counterparts.
+#[cfg(not(target_arch = "wasm32"))]
+impl UsbDevice {
   pub fn open(&mut self) -> Result<()> {
     // 3. device is already open?
     if self.opened {
@@ -435,37 +1082,7 @@ impl UsbDevice {
     }
 
     // 4.
-    #[cfg(feature = "libusb")]
-    {
-      #[cfg(feature = "deno_ffi")]
-      {
-        // TODO: ugly but works
-        let mut handle = ffi::RESOURCES
-          .lock()
-          .unwrap()
-          .get_mut(&self.rid)
-          .unwrap()
-          .lock()
-          .unwrap()
-          .device
-          .open()?;
-        ffi::RESOURCES
-          .lock()
-          .unwrap()
-          .get_mut(&self.rid)
-          .unwrap()
-          .lock()
-          .unwrap()
-          .device_handle
-          .replace(handle);
-      }
-
-      #[cfg(not(feature = "deno_ffi"))]
-      {
-        let handle = self.device.open()?;
-        self.device_handle = Some(handle);
-      }
-    }
+    backend!(self).open()?;
 
     // 5.
     self.opened = true;
@@ -478,20 +1095,13 @@ impl UsbDevice {
       return Ok(());
     }
 
-    #[cfg(feature = "libusb")]
-    {
-      match get_device_handle!(self) {
-        Some(handle_ref) => {
-          // 5-6.
-          // release claimed interfaces, close device and release handle
-          drop(handle_ref);
-        }
-        None => unreachable!(),
-      };
+    // 5-6.
+    // release claimed interfaces, close device and release handle
+    backend!(self).close()?;
 
-      #[cfg(not(feature = "deno_ffi"))]
-      {
-        self.device_handle = None;
+    if let Some(configuration) = self.configuration.as_mut() {
+      for interface in configuration.interfaces.iter_mut() {
+        interface.claimed = false;
       }
     }
 
@@ -505,134 +1115,85 @@ impl UsbDevice {
     &mut self,
     configuration_value: u8,
   ) -> Result<()> {
-    #[cfg(feature = "libusb")]
-    {
-      // 3.
-      let configuration = match self
-        .configurations
-        .iter()
-        .position(|c| c.configuration_value == configuration_value)
-      {
-        Some(config_idx) => {
-          #[cfg(not(feature = "deno_ffi"))]
-          {
-            self.device.config_descriptor(config_idx as u8)?
-          }
-
-          #[cfg(feature = "deno_ffi")]
-          ffi::RESOURCES
-            .lock()
-            .unwrap()
-            .get_mut(&self.rid)
-            .unwrap()
-            .lock()
-            .unwrap()
-            .device
-            .config_descriptor(config_idx as u8)?
-        }
-        None => return Err(Error::NotFound),
-      };
+    // 3.
+    let configuration = self
+      .configurations
+      .iter()
+      .find(|c| c.configuration_value == configuration_value)
+      .ok_or(Error::NotFound)?
+      .clone();
 
-      // 4.
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
+    // 4.
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
 
-      // 5-6.
-      let handle = match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          // Calls `libusb_set_configuration`
-          handle_ref.set_active_configuration(configuration_value)?;
-          handle_ref
-        }
-        None => unreachable!(),
-      };
+    // 5-6.
+    backend!(self).set_configuration(configuration_value)?;
 
-      // 7.
-      self.configuration = Some(UsbConfiguration::from(
-        configuration,
-        &get_device_handle!(self).unwrap(),
-      )?);
-    }
+    // 7.
+    self.configuration = Some(configuration);
     Ok(())
   }
 
   pub fn claim_interface(&mut self, interface_number: u8) -> Result<()> {
-
-    #[cfg(feature = "libusb")]
+    // 2.
+    let active_configuration =
+      self.configuration.as_mut().ok_or(Error::NotFound)?;
+    let interface = match active_configuration
+      .interfaces
+      .iter_mut()
+      .find(|i| i.interface_number == interface_number)
     {
-      // 2.
-      let active_configuration =
-        self.configuration.as_mut().ok_or(Error::NotFound)?;
-      let mut interface = match active_configuration
-        .interfaces
-        .iter_mut()
-        .find(|i| i.interface_number == interface_number)
-      {
-        Some(i) => i,
-        None => return Err(Error::NotFound),
-      };
-      // 3.
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
-
-      // 4.
-      if interface.claimed {
-        return Ok(());
-      }
-      // 6.
-      interface.claimed = true;
+      Some(i) => i,
+      None => return Err(Error::NotFound),
+    };
+    // 3.
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
 
-      // 5.
-      match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          handle_ref.claim_interface(interface_number)?;
-        }
-        None => unreachable!(),
-      };
+    // 4.
+    if interface.claimed {
+      return Ok(());
     }
 
+    // 5.
+    backend!(self).claim_interface(interface_number)?;
+
+    // 6.
+    interface.claimed = true;
     Ok(())
   }
 
   pub fn release_interface(&mut self, interface_number: u8) -> Result<()> {
-    #[cfg(feature = "libusb")]
+    // 3.
+    let active_configuration =
+      self.configuration.as_mut().ok_or(Error::NotFound)?;
+    let interface = match active_configuration
+      .interfaces
+      .iter_mut()
+      .find(|i| i.interface_number == interface_number)
     {
-      // 3.
-      let active_configuration =
-        self.configuration.as_mut().ok_or(Error::NotFound)?;
-      let mut interface = match active_configuration
-        .interfaces
-        .iter_mut()
-        .find(|i| i.interface_number == interface_number)
-      {
-        Some(i) => i,
-        None => return Err(Error::NotFound),
-      };
-
-      // 4.
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
-
-      // 5.
-      if !interface.claimed {
-        return Ok(());
-      }
+      Some(i) => i,
+      None => return Err(Error::NotFound),
+    };
 
-      // 6.
-      interface.claimed = false;
+    // 4.
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
 
-      // 5.
-      match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          handle_ref.release_interface(interface_number)?;
-        }
-        None => unreachable!(),
-      };
+    // 5.
+    if !interface.claimed {
+      return Ok(());
     }
 
+    // 5.
+    backend!(self).release_interface(interface_number)?;
+
+    // 6.
+    interface.claimed = false;
     Ok(())
   }
 
@@ -641,36 +1202,34 @@ impl UsbDevice {
     interface_number: u8,
     alternate_setting: u8,
   ) -> Result<()> {
-    #[cfg(feature = "libusb")]
+    // 3.
+    let active_configuration =
+      self.configuration.as_mut().ok_or(Error::NotFound)?;
+    let interface = match active_configuration
+      .interfaces
+      .iter_mut()
+      .find(|i| i.interface_number == interface_number)
     {
-      // 3.
-      let active_configuration =
-        self.configuration.as_mut().ok_or(Error::NotFound)?;
-      let interface = match active_configuration
-        .interfaces
-        .iter_mut()
-        .find(|i| i.interface_number == interface_number)
-      {
-        Some(i) => i,
-        None => return Err(Error::NotFound),
-      };
-
-      // 4.
-      if !self.opened || !interface.claimed {
-        return Err(Error::InvalidState);
-      }
+      Some(i) => i,
+      None => return Err(Error::NotFound),
+    };
+    let alternate = interface
+      .alternate_setting(alternate_setting)
+      .ok_or(Error::NotFound)?
+      .clone();
 
-      // 5-6.
-      match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          handle_ref
-            .set_alternate_setting(interface_number, alternate_setting)?;
-        }
-        None => unreachable!(),
-      };
+    // 4.
+    if !self.opened || !interface.claimed {
+      return Err(Error::InvalidState);
     }
+
+    // 5-6.
+    backend!(self)
+      .set_alternate_setting(interface_number, alternate_setting)?;
+
     // 7.
-    return Ok(());
+    interface.alternate = alternate;
+    Ok(())
   }
 
   pub fn control_transfer_in(
@@ -678,319 +1237,339 @@ impl UsbDevice {
     setup: UsbControlTransferParameters,
     length: usize,
   ) -> Result<Vec<u8>> {
-    #[cfg(feature = "libusb")]
-    {
-      // 3.
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
+    // 3.
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
+
+    // 4.
+    self.validate_control_setup(&setup)?;
+
+    // 5.
+    let mut buffer = vec![0u8; length];
+
+    // 6-7.
+    let bytes_transferred = backend!(self).control_in(
+      setup.bm_request_type(Direction::In),
+      setup.request,
+      setup.value,
+      setup.index,
+      &mut buffer,
+    )?;
+
+    // 8-9.
+    // Returns the buffer containing first bytes_transferred instead of returning
+    // a UsbInTransferResult.
+    buffer.truncate(bytes_transferred);
+
+    // 10-11. TODO: Will need to handle `read_control` Err
+
+    // 13.
+    Ok(buffer)
+  }
+
+  pub fn control_transfer_out(
+    &mut self,
+    setup: UsbControlTransferParameters,
+    data: &[u8],
+  ) -> Result<usize> {
+    // 2.
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
+
+    // 3.
+    self.validate_control_setup(&setup)?;
+
+    // 4-8.
+    let bytes_written = backend!(self).control_out(
+      setup.bm_request_type(Direction::Out),
+      setup.request,
+      setup.value,
+      setup.index,
+      data,
+    )?;
+
+    // 9.
+    Ok(bytes_written)
+  }
 
-      // 4.
-      self.validate_control_setup(&setup)?;
+  pub fn clear_halt(
+    &mut self,
+    direction: Direction,
+    endpoint_number: u8,
+  ) -> Result<()> {
+    let active_configuration =
+      self.configuration.as_ref().ok_or(Error::NotFound)?;
+
+    // 2.
+    let interface = active_configuration
+      .interfaces
+      .iter()
+      .find(|itf| {
+        itf.alternates.iter().any(|alt| {
+          alt.endpoints.iter().any(|endpoint| {
+            endpoint.endpoint_number == endpoint_number
+              && endpoint.direction == direction
+          })
+        })
+      })
+      .ok_or(Error::NotFound)?;
+
+    // 3.
+    if !self.opened || !interface.claimed {
+      return Err(Error::InvalidState);
+    }
+
+    // 4-5.
+    let endpoint = match direction {
+      Direction::In => EP_DIR_IN | endpoint_number,
+      Direction::Out => EP_DIR_OUT | endpoint_number,
+    };
+    backend!(self).clear_halt(endpoint)?;
+    Ok(())
+  }
+
+  pub fn transfer_in(
+    &mut self,
+    endpoint_number: u8,
+    length: usize,
+  ) -> Result<Vec<u8>> {
+    // 3.
+    let endpoint = self.endpoint(Direction::In, endpoint_number)?;
 
-      // 5.
-      let mut buffer = vec![0u8; length];
+    // 4.
+    match endpoint.r#type {
+      UsbEndpointType::Bulk | UsbEndpointType::Interrupt => {}
+      _ => return Err(Error::InvalidAccess),
+    }
 
-      // 6-7.
-      let bytes_transferred = match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          let req = match setup.request_type {
-            UsbRequestType::Standard => rusb::RequestType::Standard,
-            UsbRequestType::Class => rusb::RequestType::Class,
-            UsbRequestType::Vendor => rusb::RequestType::Vendor,
-          };
+    // 5.
+    // FIXME: Check if interface is claimed
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
 
-          let recipient = match setup.recipient {
-            UsbRecipient::Device => rusb::Recipient::Device,
-            UsbRecipient::Interface => rusb::Recipient::Interface,
-            UsbRecipient::Endpoint => rusb::Recipient::Endpoint,
-            UsbRecipient::Other => rusb::Recipient::Other,
-          };
+    // 6.
+    let mut buffer = vec![0u8; length];
 
-          let req_type =
-            rusb::request_type(rusb::Direction::In, req, recipient);
-
-          handle_ref.read_control(
-            req_type,
-            setup.request,
-            setup.value,
-            setup.index,
-            &mut buffer,
-            std::time::Duration::new(0, 0),
-          )?
-        }
-        None => unreachable!(),
-      };
+    // 7-8.
+    let ty = endpoint.r#type;
+    let bytes_transferred = backend!(self).transfer_in(
+      ty,
+      EP_DIR_IN | endpoint_number,
+      &mut buffer,
+    )?;
 
-      // 8-9.
-      // Returns the buffer containing first bytes_transferred instead of returning
-      // a UsbInTransferResult.
-      let result = &buffer[0..bytes_transferred];
+    // 10.
+    buffer.truncate(bytes_transferred);
 
-      // 10-11. TODO: Will need to handle `read_control` Err
+    // 11-14. See `control_transfer_in` TODO comment
 
-      // 13.
-      Ok(result.to_vec())
-    }
+    // 15.
+    Ok(buffer)
   }
 
-  pub fn control_transfer_out(
+  pub fn transfer_out(
     &mut self,
-    setup: UsbControlTransferParameters,
+    endpoint_number: u8,
     data: &[u8],
   ) -> Result<usize> {
-    #[cfg(feature = "libusb")]
-    {
-      // 2.
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
-
-      // 3.
-      self.validate_control_setup(&setup)?;
+    // 2.
+    let endpoint = self.endpoint(Direction::Out, endpoint_number)?;
 
-      // 4-8.
-      let bytes_written = match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          let req = match setup.request_type {
-            UsbRequestType::Standard => rusb::RequestType::Standard,
-            UsbRequestType::Class => rusb::RequestType::Class,
-            UsbRequestType::Vendor => rusb::RequestType::Vendor,
-          };
+    // 3.
+    match endpoint.r#type {
+      UsbEndpointType::Bulk | UsbEndpointType::Interrupt => {}
+      _ => return Err(Error::InvalidAccess),
+    }
 
-          let recipient = match setup.recipient {
-            UsbRecipient::Device => rusb::Recipient::Device,
-            UsbRecipient::Interface => rusb::Recipient::Interface,
-            UsbRecipient::Endpoint => rusb::Recipient::Endpoint,
-            UsbRecipient::Other => rusb::Recipient::Other,
-          };
+    // 4.
+    // FIXME: Check if interface is claimed
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
 
-          let req_type =
-            rusb::request_type(rusb::Direction::Out, req, recipient);
-
-          handle_ref.write_control(
-            req_type,
-            setup.request,
-            setup.value,
-            setup.index,
-            data,
-            std::time::Duration::new(0, 0),
-          )?
-        }
-        None => unreachable!(),
-      };
+    // 5.
+    let ty = endpoint.r#type;
+    let bytes_written =
+      backend!(self).transfer_out(ty, EP_DIR_OUT | endpoint_number, data)?;
 
-      // 9.
-      Ok(bytes_written)
-    }
+    Ok(bytes_written)
   }
 
-  pub fn clear_halt(
+  /// Receives one packet per entry of `packet_lengths` from isochronous
+  /// IN endpoint `endpoint_number`. Returns the data of every packet, or
+  /// the error the packet failed with.
+  pub fn isochronous_transfer_in(
     &mut self,
-    direction: Direction,
     endpoint_number: u8,
-  ) -> Result<()> {
-    #[cfg(feature = "libusb")]
-    {
-      let active_configuration =
-        self.configuration.as_ref().ok_or(Error::NotFound)?;
-
-      // 2.
-      let interface = active_configuration
-        .interfaces
+    packet_lengths: &[u32],
+  ) -> Result<Vec<Result<Vec<u8>>>> {
+    // 3-5.
+    let endpoint = self.isochronous_endpoint(Direction::In, endpoint_number)?;
+
+    // 6.
+    let length = packet_lengths.iter().map(|length| *length as usize).sum();
+    let mut buffer = vec![0u8; length];
+
+    // 7-8.
+    let packets = backend!(self).isochronous_transfer(
+      endpoint,
+      &mut buffer,
+      packet_lengths,
+    )?;
+
+    // 9. Packets are received at the offset of their requested length.
+    let mut offset = 0;
+    Ok(
+      packet_lengths
         .iter()
-        .find(|itf| {
-          itf
-            .alternates
-            .iter()
-            .find(|alt| {
-              alt
-                .endpoints
-                .iter()
-                .find(|endpoint| {
-                  endpoint.endpoint_number == endpoint_number
-                    && endpoint.direction == direction
-                })
-                .is_some()
-            })
-            .is_some()
+        .zip(packets)
+        .map(|(length, packet)| {
+          let start = offset;
+          offset += *length as usize;
+          packet.map(|received| {
+            buffer[start..start + received.min(*length as usize)].to_vec()
+          })
         })
-        .ok_or(Error::NotFound)?;
-
-      // 3.
-      if !self.opened || !interface.claimed {
-        return Err(Error::InvalidState);
-      }
-
-      // 4-5.
-      match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          let mut endpoint = endpoint_number;
-
-          match direction {
-            Direction::In => endpoint |= EP_DIR_IN,
-            Direction::Out => endpoint |= EP_DIR_OUT,
-          };
+        .collect(),
+    )
+  }
 
-          handle_ref.clear_halt(endpoint)?
-        }
-        None => unreachable!(),
-      };
+  /// Sends `data` to isochronous OUT endpoint `endpoint_number`, split into
+  /// one packet per entry of `packet_lengths`. Returns the number of bytes
+  /// written by every packet, or the error the packet failed with.
+  pub fn isochronous_transfer_out(
+    &mut self,
+    endpoint_number: u8,
+    data: &[u8],
+    packet_lengths: &[u32],
+  ) -> Result<Vec<Result<usize>>> {
+    // 3-5.
+    let endpoint =
+      self.isochronous_endpoint(Direction::Out, endpoint_number)?;
+
+    // 6.
+    let length: usize =
+      packet_lengths.iter().map(|length| *length as usize).sum();
+    if length != data.len() {
+      return Err(Error::Io(std::io::ErrorKind::InvalidInput));
     }
-    Ok(())
+
+    // 7-8.
+    // The backend only reads from the buffer of OUT transfers.
+    let mut buffer = data.to_vec();
+    backend!(self).isochronous_transfer(endpoint, &mut buffer, packet_lengths)
   }
 
-  pub fn transfer_in(
-    &mut self,
+  // Address of isochronous endpoint `endpoint_number`, once the device is
+  // ready for transfers on it.
+  fn isochronous_endpoint(
+    &self,
+    direction: Direction,
     endpoint_number: u8,
-    length: usize,
-  ) -> Result<Vec<u8>> {
-    #[cfg(feature = "libusb")]
-    {
-      // 3.
-      let endpoint = self
-        .configuration
-        .as_ref()
-        .ok_or(Error::NotFound)?
-        .interfaces
-        .iter()
-        .find_map(|itf| {
-          itf.alternates.iter().find_map(|alt| {
-            alt.endpoints.iter().find(|endpoint| {
-              endpoint.endpoint_number == endpoint_number
-                && endpoint.direction == Direction::In
-            })
-          })
-        })
-        .ok_or(Error::NotFound)?;
+  ) -> Result<u8> {
+    let endpoint = self.endpoint(direction, endpoint_number)?;
 
-      // 4.
-      match endpoint.r#type {
-        UsbEndpointType::Bulk | UsbEndpointType::Interrupt => {}
-        _ => return Err(Error::InvalidAccess),
-      }
+    if endpoint.r#type != UsbEndpointType::Isochronous {
+      return Err(Error::InvalidAccess);
+    }
 
-      // 5.
-      // FIXME: Check if interface is claimed
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
+    // FIXME: Check if interface is claimed
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
 
-      // 6.
-      let mut buffer = vec![0u8; length];
-
-      // 7-8.
-      let ty = endpoint.r#type;
-      let bytes_transferred = match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          let endpoint_addr = EP_DIR_IN | endpoint_number;
-
-          match ty {
-            UsbEndpointType::Bulk => handle_ref.read_bulk(
-              endpoint_addr,
-              &mut buffer,
-              std::time::Duration::new(0, 0),
-            )?,
-            UsbEndpointType::Interrupt => handle_ref.read_interrupt(
-              endpoint_addr,
-              &mut buffer,
-              std::time::Duration::new(0, 0),
-            )?,
-            _ => unreachable!(),
-          }
-        }
-        None => unreachable!(),
-      };
+    Ok(match direction {
+      Direction::In => EP_DIR_IN | endpoint_number,
+      Direction::Out => EP_DIR_OUT | endpoint_number,
+    })
+  }
 
-      // 10.
-      let result = &buffer[0..bytes_transferred];
+  /// Starts a transfer on `endpoint_number` without waiting for it: up to
+  /// `buffer.len()` bytes are received on IN endpoints, `buffer` is sent on
+  /// OUT endpoints. The device is not borrowed while the transfer is
+  /// pending, so other transfers can be made meanwhile. Wait for the
+  /// returned `backend::Transfer` and take its result, or cancel it.
+  pub fn submit_transfer(
+    &mut self,
+    direction: Direction,
+    endpoint_number: u8,
+    buffer: Vec<u8>,
+  ) -> Result<Arc<dyn Transfer>> {
+    let endpoint = self.endpoint(direction, endpoint_number)?;
 
-      // 11-14. See `control_transfer_in` TODO comment
+    match endpoint.r#type {
+      UsbEndpointType::Bulk | UsbEndpointType::Interrupt => {}
+      _ => return Err(Error::InvalidAccess),
+    }
 
-      // 15.
-      Ok(result.to_vec())
+    if !self.opened {
+      return Err(Error::InvalidState);
     }
+
+    let ty = endpoint.r#type;
+    let endpoint = match direction {
+      Direction::In => EP_DIR_IN | endpoint_number,
+      Direction::Out => EP_DIR_OUT | endpoint_number,
+    };
+    backend!(self).submit(ty, endpoint, buffer)
   }
 
-  pub fn transfer_out(
+  /// Keeps `count` IN transfers of `length` bytes in flight on
+  /// `endpoint_number`. See `pipe::QueuedReader`.
+  pub(crate) fn queue_in(
     &mut self,
     endpoint_number: u8,
-    data: &[u8],
-  ) -> Result<usize> {
-    #[cfg(feature = "libusb")]
-    {
-      // 2.
-      let endpoint = self
-        .configuration
-        .as_ref()
-        .ok_or(Error::NotFound)?
-        .interfaces
-        .iter()
-        .find_map(|itf| {
-          itf.alternates.iter().find_map(|alt| {
-            alt.endpoints.iter().find(|endpoint| {
-              endpoint.endpoint_number == endpoint_number
-                && endpoint.direction == Direction::Out
-            })
-          })
-        })
-        .ok_or(Error::NotFound)?;
+    length: usize,
+    count: usize,
+  ) -> Result<Box<dyn TransferQueue>> {
+    let endpoint = self.endpoint(Direction::In, endpoint_number)?;
 
-      // 3.
-      match endpoint.r#type {
-        UsbEndpointType::Bulk | UsbEndpointType::Interrupt => {}
-        _ => return Err(Error::InvalidAccess),
-      }
+    match endpoint.r#type {
+      UsbEndpointType::Bulk | UsbEndpointType::Interrupt => {}
+      _ => return Err(Error::InvalidAccess),
+    }
 
-      // 4.
-      // FIXME: Check if interface is claimed
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
+    if !self.opened {
+      return Err(Error::InvalidState);
+    }
 
-      // 5.
-      let ty = endpoint.r#type;
-      let bytes_written = match get_device_handle!(self) {
-        Some(ref mut handle_ref) => {
-          let endpoint_addr = EP_DIR_OUT | endpoint_number;
-
-          match ty {
-            UsbEndpointType::Bulk => handle_ref.write_bulk(
-              endpoint_addr,
-              data,
-              std::time::Duration::new(0, 0),
-            )?,
-            UsbEndpointType::Interrupt => handle_ref.write_interrupt(
-              endpoint_addr,
-              data,
-              std::time::Duration::new(0, 0),
-            )?,
-            _ => unreachable!(),
-          }
-        }
-        None => unreachable!(),
-      };
+    let ty = endpoint.r#type;
+    backend!(self).queue_in(ty, EP_DIR_IN | endpoint_number, length, count)
+  }
 
-      Ok(bytes_written)
-    }
+  /// Data of the oldest transfer of `queue`, swapped into `buffer`.
+  pub(crate) fn next_queued(
+    &mut self,
+    queue: &mut dyn TransferQueue,
+    buffer: &mut Vec<u8>,
+  ) -> Result<()> {
+    queue.next(backend!(self), buffer)
   }
 
   pub fn reset(&mut self) -> Result<()> {
-    #[cfg(feature = "libusb")]
-    {
-      // 3.
-      if !self.opened {
-        return Err(Error::InvalidState);
-      }
-
-      // 4-6.
-      match get_device_handle!(self) {
-        Some(ref mut handle_ref) => handle_ref.reset()?,
-        None => unreachable!(),
-      };
+    // 3.
+    if !self.opened {
+      return Err(Error::InvalidState);
     }
+
+    // 4-6.
+    backend!(self).reset()?;
     Ok(())
   }
+
+  /// Bulk and interrupt transfers that do not complete within `timeout`
+  /// fail with `Error::Io(ErrorKind::TimedOut)`. None, the default, waits
+  /// forever.
+  ///
+  /// NOTE: If some data was received before the timeout expired, libusb
+  /// returns it as a short but successful transfer instead of the error.
+  /// Backends that cannot recover partial data, such as USB/IP, drop it.
+  pub fn set_transfer_timeout(&mut self, timeout: Option<Duration>) {
+    backend!(self).set_transfer_timeout(timeout);
+  }
 }
 
 #[derive(Clone)]
@@ -999,7 +1578,11 @@ impl UsbDevice {
   derive(Serialize, Deserialize),
   serde(rename_all = "lowercase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "lowercase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "lowercase")
+)]
 pub enum UsbRequestType {
   Standard,
   Class,
@@ -1012,7 +1595,11 @@ pub enum UsbRequestType {
   derive(Serialize, Deserialize),
   serde(rename_all = "lowercase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "lowercase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "lowercase")
+)]
 pub enum UsbRecipient {
   Device,
   Interface,
@@ -1026,7 +1613,11 @@ pub enum UsbRecipient {
   derive(Serialize, Deserialize),
   serde(rename_all = "camelCase")
 )]
-#[cfg_attr(feature = "deno_ffi", deno_bindgen, serde(rename_all = "camelCase"))]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
 pub struct UsbControlTransferParameters {
   pub request_type: UsbRequestType,
   pub recipient: UsbRecipient,
@@ -1035,6 +1626,29 @@ pub struct UsbControlTransferParameters {
   pub index: u16,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl UsbControlTransferParameters {
+  // bmRequestType of the setup packet.
+  fn bm_request_type(&self, direction: Direction) -> u8 {
+    let direction = match direction {
+      Direction::In => EP_DIR_IN,
+      Direction::Out => EP_DIR_OUT,
+    };
+    let request_type = match self.request_type {
+      UsbRequestType::Standard => 0x00,
+      UsbRequestType::Class => 0x20,
+      UsbRequestType::Vendor => 0x40,
+    };
+    let recipient = match self.recipient {
+      UsbRecipient::Device => 0x00,
+      UsbRecipient::Interface => 0x01,
+      UsbRecipient::Endpoint => 0x02,
+      UsbRecipient::Other => 0x03,
+    };
+    direction | request_type | recipient
+  }
+}
+
 #[cfg(feature = "libusb")]
 impl TryFrom<rusb::Device<rusb::Context>> for UsbDevice {
   type Error = Error;
@@ -1140,9 +1754,6 @@ impl TryFrom<rusb::Device<rusb::Context>> for UsbDevice {
       .read_serial_number_string_ascii(&device_descriptor)
       .ok();
 
-    #[cfg(feature = "deno_ffi")]
-    let rid = ffi::RESOURCES.lock().unwrap().len() as i32; // TODO
-
     let usb_device = UsbDevice {
       configurations,
       configuration,
@@ -1161,18 +1772,15 @@ impl TryFrom<rusb::Device<rusb::Context>> for UsbDevice {
       product_name,
       serial_number,
       opened: false,
+      bus_number: device.bus_number(),
+      device_address: device.address(),
+      port_numbers: device.port_numbers()?,
+      speed: UsbSpeed::from(&device),
+      parent_hub: UsbParentHub::from(&device),
       url,
-      #[cfg(not(feature = "deno_ffi"))]
-      device,
-      #[cfg(not(feature = "deno_ffi"))]
-      device_handle: None,
-      #[cfg(feature = "deno_ffi")]
-      rid, // TODO
+      backend: Box::new(backend::Libusb::new(device)),
     };
 
-    #[cfg(feature = "deno_ffi")]
-    ffi::insert_device(rid, device);
-
     // Explicitly close the device.
     drop(handle);
 
@@ -1181,39 +1789,223 @@ impl TryFrom<rusb::Device<rusb::Context>> for UsbDevice {
 }
 
 /// A WebUSB Context. Provides APIs for device enumaration.
-#[cfg(feature = "libusb")]
-pub struct Context(rusb::Context);
+pub struct Context {
+  #[cfg(feature = "libusb")]
+  libusb: rusb::Context,
+  #[cfg(feature = "usbip")]
+  usbip_hosts: Vec<usbip::client::UsbIpHost>,
+  #[cfg(feature = "proxy")]
+  proxy_hosts: Vec<proxy::client::ProxyHost>,
+}
 
-#[cfg(feature = "libusb")]
 impl Context {
   pub fn init() -> Result<Self> {
-    let ctx = rusb::Context::new()?;
-    Ok(Self(ctx))
+    Ok(Self {
+      #[cfg(feature = "libusb")]
+      libusb: rusb::Context::new()?,
+      #[cfg(feature = "usbip")]
+      usbip_hosts: vec![],
+      #[cfg(feature = "proxy")]
+      proxy_hosts: vec![],
+    })
+  }
+
+  /// Lists the devices exported by `host` along with local devices.
+  ///
+  /// Remote devices are only imported while they are open, see
+  /// `UsbIpHost::devices`. Devices imported by someone else are skipped,
+  /// and so are hosts that cannot be reached.
+  #[cfg(feature = "usbip")]
+  pub fn add_usbip_host(&mut self, host: usbip::client::UsbIpHost) {
+    self.usbip_hosts.push(host);
+  }
+
+  /// Lists the devices shared by the proxy daemon at `host` along with
+  /// local devices. Hosts that cannot be reached are skipped.
+  #[cfg(feature = "proxy")]
+  pub fn add_proxy_host(&mut self, host: proxy::client::ProxyHost) {
+    self.proxy_hosts.push(host);
   }
 
+  #[cfg(not(target_arch = "wasm32"))]
   pub fn devices(&self) -> Result<Vec<UsbDevice>> {
-    let devices = self.0.devices()?;
+    self.devices_with_options(&EnumerationOptions::default())
+  }
+
+  /// Lists the devices at `port_paths`, see `UsbDevice::port_path`, or
+  /// every device if `port_paths` is empty.
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn devices_by_port_path(
+    &self,
+    port_paths: &[String],
+  ) -> Result<Vec<UsbDevice>> {
+    Ok(
+      self
+        .devices()?
+        .into_iter()
+        .filter(|device| {
+          port_paths.is_empty() || port_paths.contains(&device.port_path())
+        })
+        .collect(),
+    )
+  }
+
+  #[cfg(not(target_arch = "wasm32"))]
+  pub fn devices_with_options(
+    &self,
+    options: &EnumerationOptions,
+  ) -> Result<Vec<UsbDevice>> {
+    #[allow(unused_mut)]
+    let mut usb_devices: Vec<UsbDevice> = vec![];
+
+    #[cfg(feature = "libusb")]
+    for device in self.libusb.devices()?.iter() {
+      let device_descriptor = match device.device_descriptor() {
+        Ok(device_descriptor) => device_descriptor,
+        Err(_) => continue,
+      };
+
+      if !options.include_hubs && device_descriptor.class_code() == HUB_CLASS {
+        continue;
+      }
+
+      match UsbDevice::try_from(device) {
+        Ok(usb_device) => usb_devices.push(usb_device),
+        // Skip devices we are not allowed to open.
+        Err(Error::Usb(rusb::Error::Access)) => {}
+        // Hubs are only listed on request. One that cannot be opened or
+        // queried, e.g. a root hub on some platforms, is left out rather
+        // than failing the whole enumeration.
+        Err(_) if device_descriptor.class_code() == HUB_CLASS => {}
+        Err(err) => return Err(err),
+      }
+    }
+
+    // libusb already covers local devices when both are enabled.
+    #[cfg(all(
+      feature = "usbfs",
+      target_os = "linux",
+      not(feature = "libusb")
+    ))]
+    usb_devices.extend(usbfs::devices(options)?);
+
+    // A remote host that cannot be reached does not keep the other
+    // devices from being listed.
+    #[cfg(feature = "usbip")]
+    for host in &self.usbip_hosts {
+      if let Ok(devices) = host.devices(options) {
+        usb_devices.extend(devices);
+      }
+    }
 
-    let usb_devices: Vec<UsbDevice> = devices
+    #[cfg(feature = "proxy")]
+    for host in &self.proxy_hosts {
+      let devices = match host.devices() {
+        Ok(devices) => devices,
+        Err(_) => continue,
+      };
+      for device in devices {
+        match host.connect(&device) {
+          Ok(usb_device) => {
+            if options.include_hubs || usb_device.device_class != HUB_CLASS {
+              usb_devices.push(usb_device);
+            }
+          }
+          // Skip devices that went away since they were listed.
+          Err(Error::NotFound) => {}
+          Err(err) => return Err(err),
+        }
+      }
+    }
+
+    #[cfg(not(any(
+      feature = "libusb",
+      feature = "usbip",
+      feature = "proxy",
+      all(feature = "usbfs", target_os = "linux")
+    )))]
+    let _ = options;
+
+    Ok(usb_devices)
+  }
+}
+
+/// Options for `Context::devices_with_options`.
+#[derive(Clone, Debug, Default)]
+pub struct EnumerationOptions {
+  /// If true, hubs (bDeviceClass 9) are listed along with other devices.
+  /// Hubs are not listed by default, see `hub::UsbHub`.
+  pub include_hubs: bool,
+}
+
+/// Matches devices the way `navigator.usb.requestDevice` does. A device
+/// matches if all of the present fields match.
+/// https://wicg.github.io/webusb/#dictdef-usbdevicefilter
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(
+  feature = "serde_derive",
+  derive(Serialize, Deserialize),
+  serde(rename_all = "camelCase")
+)]
+#[cfg_attr(
+  all(feature = "deno_ffi", not(test)),
+  deno_bindgen,
+  serde(rename_all = "camelCase")
+)]
+pub struct UsbDeviceFilter {
+  pub vendor_id: Option<u16>,
+  pub product_id: Option<u16>,
+  /// Matches the device class or the class of any of its interfaces.
+  pub class_code: Option<u8>,
+  pub subclass_code: Option<u8>,
+  pub protocol_code: Option<u8>,
+  pub serial_number: Option<String>,
+}
+
+impl UsbDeviceFilter {
+  // https://wicg.github.io/webusb/#device-filter-match
+  pub fn matches(&self, device: &UsbDevice) -> bool {
+    if !matches_field(&self.vendor_id, &device.vendor_id)
+      || !matches_field(&self.product_id, &device.product_id)
+      || self.serial_number.is_some()
+        && self.serial_number != device.serial_number
+    {
+      return false;
+    }
+    if self.class_code.is_none() {
+      return true;
+    }
+
+    self.matches_class(
+      device.device_class,
+      device.device_subclass,
+      device.device_protocol,
+    ) || device
+      .configurations
       .iter()
-      .filter(|d| {
-        // Do not list hubs.
-        // TODO(@littledivy): WTF is this code
-        d.device_descriptor().is_ok()
-          && d.device_descriptor().unwrap().class_code() != 9
+      .flat_map(|configuration| configuration.interfaces())
+      .flat_map(|interface| interface.alternates())
+      .any(|alternate| {
+        self.matches_class(
+          alternate.interface_class,
+          alternate.interface_subclass,
+          alternate.interface_protocol,
+        )
       })
-      .map(|d| UsbDevice::try_from(d))
-      .filter(|d| {
-        d.is_ok()
-          || d.as_ref().err().unwrap() != &Error::Usb(rusb::Error::Access)
-      })
-      .map(|d| d.unwrap())
-      .collect::<Vec<UsbDevice>>();
-    Ok(usb_devices)
+  }
+
+  fn matches_class(&self, class: u8, subclass: u8, protocol: u8) -> bool {
+    matches_field(&self.class_code, &class)
+      && matches_field(&self.subclass_code, &subclass)
+      && matches_field(&self.protocol_code, &protocol)
   }
 }
 
-#[cfg(test)]
+fn matches_field<T: PartialEq>(member: &Option<T>, value: &T) -> bool {
+  member.is_none() || member.as_ref() == Some(value)
+}
+
+#[cfg(all(test, feature = "libusb"))]
 mod tests {
   // These tests depends on real hardware.
   // TODO(@littledivy): Document running tests locally.
@@ -1224,6 +2016,7 @@ mod tests {
   use crate::UsbDevice;
   use crate::UsbRecipient;
   use crate::UsbRequestType;
+  use crate::EP_DIR_IN;
 
   use std::sync::Arc;
   use std::sync::Mutex;
@@ -1500,19 +2293,29 @@ mod tests {
   }
 
   #[test]
-  #[should_panic]
-  // IMPORTANT! These are meant to fail when the methods are implemented.
-  fn test_unimplemented1() {
+  fn test_isochronous_transfer() -> crate::Result<()> {
     let mut device = test_device();
-    device.isochronous_transfer_in();
-  }
 
-  #[test]
-  #[should_panic]
-  // IMPORTANT! These are meant to fail when the methods are implemented.
-  fn test_unimplemented2() {
-    let mut device = test_device();
-    device.isochronous_transfer_out();
+    device.open()?;
+
+    // The sketch has no isochronous endpoints.
+    assert_eq!(
+      device.isochronous_transfer_in(5, &[64]).unwrap_err(),
+      Error::InvalidAccess
+    );
+    assert_eq!(
+      device
+        .isochronous_transfer_out(4, &[0; 64], &[64])
+        .unwrap_err(),
+      Error::InvalidAccess
+    );
+    assert_eq!(
+      device.isochronous_transfer_in(15, &[64]).unwrap_err(),
+      Error::NotFound
+    );
+
+    device.close()?;
+    Ok(())
   }
 
   #[test]
@@ -1553,9 +2356,150 @@ mod tests {
     standard_ctrl_req(&mut device).unwrap();
   }
 
+  #[test]
+  fn test_configuration_attributes() {
+    let device = test_device();
+    let configuration = device.configuration.as_ref().unwrap();
+
+    // Arduino Leonardo is bus-powered and asks for 500mA.
+    assert!(!configuration.self_powered());
+    assert_eq!(configuration.max_power(), 500);
+
+    let raw = configuration.raw_descriptor().unwrap();
+    assert_eq!(raw[1], crate::constants::CONFIGURATION_DESCRIPTOR_TYPE);
+    assert_eq!(u16::from_le_bytes([raw[2], raw[3]]) as usize, raw.len());
+  }
+
+  #[test]
+  fn test_descriptor_navigation() {
+    let device = test_device();
+    let configuration = device.configuration.as_ref().unwrap();
+
+    // The WebUSB console interface is vendor specific.
+    let interface = configuration.find_interface(0xFF, 0x00, 0x00).unwrap();
+    assert_eq!(interface.interface_number(), 2);
+    assert_eq!(interface.alternate().alternate_setting, 0);
+    assert!(!interface.claimed());
+    assert_eq!(interface.endpoints().len(), 2);
+
+    let endpoint = configuration.endpoint(EP_DIR_IN | 5).unwrap();
+    assert_eq!(endpoint.endpoint_number(), 5);
+    assert_eq!(endpoint.direction(), Direction::In);
+    assert_eq!(endpoint.endpoint_type(), crate::UsbEndpointType::Bulk);
+    assert_eq!(endpoint.packet_size(), 64);
+
+    assert!(configuration.interface(255).is_none());
+  }
+
+  #[test]
+  fn test_device_topology() {
+    let device = test_device();
+
+    // Arduino Leonardo is a full-speed device.
+    assert_eq!(device.speed, crate::UsbSpeed::Full);
+    assert!(!device.port_numbers.is_empty());
+    assert!(device.parent_hub.is_some());
+    assert!(device
+      .port_path()
+      .starts_with(&format!("{}-", device.bus_number)));
+  }
+
   #[test]
   fn test_error_impl() {
     let nope: Option<()> = None;
     assert_eq!(Error::from(nope), Error::NotFound);
   }
 }
+
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod loopback_tests {
+  use std::io::ErrorKind;
+
+  use crate::loopback::Loopback;
+  use crate::Error;
+  use crate::UsbDevice;
+
+  // The loopback device with its isochronous endpoints selected.
+  fn isochronous_device() -> UsbDevice {
+    let mut device = Loopback::device();
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+    device.select_alternate_interface(0, 1).unwrap();
+    device
+  }
+
+  #[test]
+  fn test_isochronous_transfer() {
+    let mut device = isochronous_device();
+    let written = device
+      .isochronous_transfer_out(5, b"abcdefgh", &[3, 0, 5])
+      .unwrap();
+    assert_eq!(written, vec![Ok(3), Ok(0), Ok(5)]);
+
+    // Short packets are received at the offset of their requested length.
+    let packets = device.isochronous_transfer_in(4, &[8, 8, 8, 8]).unwrap();
+    assert_eq!(
+      packets,
+      vec![
+        Ok(b"abc".to_vec()),
+        Ok(vec![]),
+        Ok(b"defgh".to_vec()),
+        Ok(vec![])
+      ]
+    );
+  }
+
+  #[test]
+  fn test_isochronous_packet_errors() {
+    let mut device = isochronous_device();
+
+    // Packets fail on their own, the others are still transferred.
+    let data = [0x55; 40];
+    let written = device
+      .isochronous_transfer_out(5, &data, &[16, 17, 7])
+      .unwrap();
+    assert_eq!(
+      written,
+      vec![Ok(16), Err(Error::Io(ErrorKind::InvalidInput)), Ok(7)]
+    );
+
+    let packets = device.isochronous_transfer_in(4, &[8, 8]).unwrap();
+    assert_eq!(
+      packets,
+      vec![Err(Error::Io(ErrorKind::InvalidData)), Ok(vec![0x55; 7])]
+    );
+  }
+
+  #[test]
+  fn test_isochronous_transfer_rejected() {
+    let mut device = Loopback::device();
+    assert_eq!(
+      device.isochronous_transfer_in(4, &[8]).err(),
+      Some(Error::InvalidState)
+    );
+
+    let mut device = isochronous_device();
+    // The packet lengths do not add up to the length of the data.
+    assert_eq!(
+      device.isochronous_transfer_out(5, b"abcd", &[2, 3]).err(),
+      Some(Error::Io(ErrorKind::InvalidInput))
+    );
+    assert_eq!(
+      device.isochronous_transfer_out(5, b"abcd", &[2, 1]).err(),
+      Some(Error::Io(ErrorKind::InvalidInput))
+    );
+    assert_eq!(
+      device.isochronous_transfer_in(1, &[8]).err(),
+      Some(Error::InvalidAccess)
+    );
+    assert_eq!(
+      device.isochronous_transfer_in(5, &[8]).err(),
+      Some(Error::NotFound)
+    );
+
+    // Nothing was sent.
+    let packets = device.isochronous_transfer_in(4, &[8]).unwrap();
+    assert_eq!(packets, vec![Ok(vec![])]);
+  }
+}
@@ -0,0 +1,413 @@
+//! Loopback device shared by the USB/IP and proxy tests. Data written to
+//! endpoint 2 can be read back from endpoint 1. Endpoint 3 always stalls.
+//! Isochronous packets written to endpoint 5 can be read back from
+//! endpoint 4, one per packet. It also answers the hub class requests, for
+//! the `hub` tests.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::backend::DeviceBackend;
+use crate::backend::Transfer;
+use crate::enumeration::read_device;
+use crate::enumeration::Topology;
+use crate::Error;
+use crate::Result;
+use crate::UsbDevice;
+use crate::UsbEndpointType;
+use crate::UsbSpeed;
+
+pub const DEVICE_DESCRIPTOR: &[u8] = &[
+  0x12, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x40, 0x34, 0x12, 0x78, 0x56, 0x00,
+  0x01, 0x01, 0x02, 0x00, 0x01,
+];
+
+pub const CONFIG_DESCRIPTOR: &[u8] = &[
+  0x09, 0x02, 0x3E, 0x00, 0x01, 0x01, 0x00, 0x80, 0x32, // Configuration
+  0x09, 0x04, 0x00, 0x00, 0x03, 0xFF, 0x00, 0x00, 0x00, // Interface 0
+  0x07, 0x05, 0x81, 0x02, 0x40, 0x00, 0x00, // Bulk IN 1
+  0x07, 0x05, 0x02, 0x02, 0x40, 0x00, 0x00, // Bulk OUT 2
+  0x07, 0x05, 0x83, 0x02, 0x40, 0x00, 0x00, // Bulk IN 3, always stalls
+  0x09, 0x04, 0x00, 0x01, 0x02, 0xFF, 0x00, 0x00, 0x00, // Alternate 1
+  0x07, 0x05, 0x84, 0x05, 0x10, 0x00, 0x01, // Isochronous IN 4
+  0x07, 0x05, 0x05, 0x05, 0x10, 0x00, 0x01, // Isochronous OUT 5
+];
+
+// wMaxPacketSize of the isochronous endpoints.
+const ISOCHRONOUS_PACKET_SIZE: usize = 0x10;
+
+fn string_descriptor(string: &str) -> Vec<u8> {
+  let mut bytes = vec![0, 0x03];
+  for c in string.encode_utf16() {
+    bytes.extend_from_slice(&c.to_le_bytes());
+  }
+  bytes[0] = bytes.len() as u8;
+  bytes
+}
+
+/// Answers a control transfer on endpoint 0. Returns the status (0 or
+/// -EPIPE) and IN data.
+pub fn control(setup: &[u8; 8]) -> (i32, Vec<u8>) {
+  let value = u16::from_le_bytes([setup[2], setup[3]]);
+  match (setup[0], setup[1], value >> 8) {
+    (0x80, 0x06, 0x01) => (0, DEVICE_DESCRIPTOR.to_vec()),
+    (0x80, 0x06, 0x02) => (0, CONFIG_DESCRIPTOR.to_vec()),
+    (0x80, 0x06, 0x03) => match value & 0xFF {
+      0 => (0, vec![0x04, 0x03, 0x09, 0x04]),
+      1 => (0, string_descriptor("Stand-in")),
+      2 => (0, string_descriptor("Loopback")),
+      _ => (-32, vec![]),
+    },
+    // GET_CONFIGURATION
+    (0x80, 0x08, _) => (0, vec![1]),
+    // SET_CONFIGURATION
+    (0x00, 0x09, _) => (0, vec![]),
+    // GET_DESCRIPTOR of a 4 port hub with per-port power switching.
+    (0xA0, 0x06, 0x29) => (
+      0,
+      vec![0x09, 0x29, 0x04, 0x01, 0x00, 0x01, 0x00, 0x00, 0xFF],
+    ),
+    // SET_FEATURE and CLEAR_FEATURE of a hub port
+    (0x23, 0x01, _) | (0x23, 0x03, _) => (0, vec![]),
+    _ => (-32, vec![]),
+  }
+}
+
+/// Lengths of the transfers to endpoint 2, in order.
+pub type Transfers = Arc<Mutex<Vec<usize>>>;
+
+/// Setup packets of the control transfers, in order.
+pub type Setups = Arc<Mutex<Vec<[u8; 8]>>>;
+
+/// The loopback device as a `DeviceBackend`.
+#[derive(Default)]
+pub struct Loopback {
+  buffered: VecDeque<u8>,
+  timeout: Option<Duration>,
+  transfers: Transfers,
+  setups: Setups,
+  // Submitted reads from endpoint 1 waiting for data, in order.
+  waiting: VecDeque<Arc<Pending>>,
+  // Packets written to endpoint 5 and not yet read from endpoint 4.
+  packets: VecDeque<Vec<u8>>,
+}
+
+enum State {
+  Pending,
+  Done(Result<Vec<u8>>),
+  Taken,
+}
+
+// A submitted transfer. Reads from endpoint 1 stay pending until data is
+// written to endpoint 2, other transfers complete right away.
+struct Pending {
+  state: Mutex<State>,
+  changed: Condvar,
+  length: usize,
+  deadline: Option<Instant>,
+}
+
+impl Pending {
+  fn done(result: Result<Vec<u8>>) -> Arc<Self> {
+    Arc::new(Pending {
+      state: Mutex::new(State::Done(result)),
+      changed: Condvar::new(),
+      length: 0,
+      deadline: None,
+    })
+  }
+
+  // Completes the transfer unless it completed already.
+  fn complete(&self, result: Result<Vec<u8>>) -> bool {
+    let mut state = self.state.lock().unwrap();
+    if !matches!(*state, State::Pending) {
+      return false;
+    }
+    *state = State::Done(result);
+    self.changed.notify_all();
+    true
+  }
+}
+
+impl Transfer for Pending {
+  fn wait(&self, timeout: Option<Duration>) -> Result<bool> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut state = self.state.lock().unwrap();
+    while matches!(*state, State::Pending) {
+      let now = Instant::now();
+      if self.deadline.is_some_and(|deadline| deadline <= now) {
+        *state = State::Done(Err(Error::Io(std::io::ErrorKind::TimedOut)));
+        break;
+      }
+      if deadline.is_some_and(|deadline| deadline <= now) {
+        return Ok(false);
+      }
+      let until = deadline.into_iter().chain(self.deadline).min();
+      state = match until {
+        Some(until) => self.changed.wait_timeout(state, until - now).unwrap().0,
+        None => self.changed.wait(state).unwrap(),
+      };
+    }
+    Ok(true)
+  }
+
+  fn cancel(&self) {
+    self.complete(Err(Error::Io(std::io::ErrorKind::Interrupted)));
+  }
+
+  fn take(&self) -> Result<Vec<u8>> {
+    let mut state = self.state.lock().unwrap();
+    match std::mem::replace(&mut *state, State::Taken) {
+      State::Done(result) => result,
+      other => {
+        *state = other;
+        Err(Error::InvalidState)
+      }
+    }
+  }
+}
+
+impl Loopback {
+  pub fn device() -> UsbDevice {
+    Self::device_with_transfers().0
+  }
+
+  /// The loopback device and a log of the transfers written to it.
+  pub fn device_with_transfers() -> (UsbDevice, Transfers) {
+    let loopback = Loopback::default();
+    let transfers = loopback.transfers.clone();
+    (Self::device_from(loopback), transfers)
+  }
+
+  /// The loopback device and a log of the control transfers made after
+  /// enumeration.
+  pub fn device_with_setups() -> (UsbDevice, Setups) {
+    let loopback = Loopback::default();
+    let setups = loopback.setups.clone();
+    let device = Self::device_from(loopback);
+    setups.lock().unwrap().clear();
+    (device, setups)
+  }
+
+  fn device_from(loopback: Loopback) -> UsbDevice {
+    let topology = Topology {
+      bus_number: 1,
+      device_address: 5,
+      port_numbers: vec![1, 3],
+      speed: UsbSpeed::High,
+      parent_hub: None,
+    };
+    read_device(Box::new(loopback), topology).unwrap()
+  }
+
+  // Hands the buffered data to the submitted reads.
+  fn fill(&mut self) {
+    while !self.buffered.is_empty() {
+      let pending = match self.waiting.pop_front() {
+        Some(pending) => pending,
+        None => break,
+      };
+      let length = self.buffered.len().min(pending.length);
+      let data = self.buffered.iter().take(length).copied().collect();
+      if pending.complete(Ok(data)) {
+        self.buffered.drain(..length);
+      }
+    }
+  }
+
+  fn control(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: usize,
+  ) -> Result<Vec<u8>> {
+    let mut setup = [request_type, request, 0, 0, 0, 0, 0, 0];
+    setup[2..4].copy_from_slice(&value.to_le_bytes());
+    setup[4..6].copy_from_slice(&index.to_le_bytes());
+    setup[6..8].copy_from_slice(&(length as u16).to_le_bytes());
+    self.setups.lock().unwrap().push(setup);
+    match control(&setup) {
+      (0, data) => Ok(data),
+      _ => Err(Error::Stall),
+    }
+  }
+}
+
+impl DeviceBackend for Loopback {
+  fn open(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  fn close(&mut self) -> Result<()> {
+    // As with libusb, pending transfers fail once the device is closed.
+    for pending in self.waiting.drain(..) {
+      pending.cancel();
+    }
+    Ok(())
+  }
+
+  fn set_configuration(&mut self, _: u8) -> Result<()> {
+    Ok(())
+  }
+
+  fn claim_interface(&mut self, _: u8) -> Result<()> {
+    Ok(())
+  }
+
+  fn release_interface(&mut self, _: u8) -> Result<()> {
+    Ok(())
+  }
+
+  fn set_alternate_setting(&mut self, _: u8, _: u8) -> Result<()> {
+    Ok(())
+  }
+
+  fn control_in(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    let data =
+      self.control(request_type, request, value, index, buffer.len())?;
+    let length = data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&data[..length]);
+    Ok(length)
+  }
+
+  fn control_out(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+  ) -> Result<usize> {
+    self.control(request_type, request, value, index, data.len())?;
+    Ok(data.len())
+  }
+
+  fn clear_halt(&mut self, _: u8) -> Result<()> {
+    Ok(())
+  }
+
+  fn transfer_in(
+    &mut self,
+    _: UsbEndpointType,
+    endpoint: u8,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    match endpoint {
+      0x81 if self.buffered.is_empty() => {
+        // Nothing to read. Tests always set a timeout before getting here.
+        std::thread::sleep(self.timeout.unwrap());
+        Err(Error::Io(std::io::ErrorKind::TimedOut))
+      }
+      0x81 => {
+        let length = self.buffered.len().min(buffer.len());
+        for (i, byte) in self.buffered.drain(..length).enumerate() {
+          buffer[i] = byte;
+        }
+        Ok(length)
+      }
+      _ => Err(Error::Stall),
+    }
+  }
+
+  fn transfer_out(
+    &mut self,
+    _: UsbEndpointType,
+    endpoint: u8,
+    data: &[u8],
+  ) -> Result<usize> {
+    match endpoint {
+      0x02 => {
+        self.transfers.lock().unwrap().push(data.len());
+        self.buffered.extend(data);
+        self.fill();
+        Ok(data.len())
+      }
+      _ => Err(Error::Stall),
+    }
+  }
+
+  fn reset(&mut self) -> Result<()> {
+    Ok(())
+  }
+
+  fn set_transfer_timeout(&mut self, timeout: Option<Duration>) {
+    self.timeout = timeout;
+  }
+
+  // Packets larger than wMaxPacketSize fail to be sent. Received packets
+  // are empty once all packets were read back, and fail if they are larger
+  // than requested.
+  fn isochronous_transfer(
+    &mut self,
+    endpoint: u8,
+    buffer: &mut [u8],
+    packet_lengths: &[u32],
+  ) -> Result<Vec<Result<usize>>> {
+    let mut offset = 0;
+    let mut packets = vec![];
+    for length in packet_lengths {
+      let packet = &mut buffer[offset..offset + *length as usize];
+      offset += packet.len();
+      packets.push(match endpoint {
+        0x84 => match self.packets.pop_front() {
+          Some(data) if data.len() > packet.len() => {
+            Err(Error::Io(std::io::ErrorKind::InvalidData))
+          }
+          Some(data) => {
+            packet[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+          }
+          None => Ok(0),
+        },
+        0x05 if packet.len() > ISOCHRONOUS_PACKET_SIZE => {
+          Err(Error::Io(std::io::ErrorKind::InvalidInput))
+        }
+        0x05 => {
+          self.packets.push_back(packet.to_vec());
+          Ok(packet.len())
+        }
+        _ => return Err(Error::InvalidAccess),
+      });
+    }
+    Ok(packets)
+  }
+
+  fn submit(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    mut buffer: Vec<u8>,
+  ) -> Result<Arc<dyn Transfer>> {
+    let result = match endpoint {
+      0x81 => {
+        let pending = Arc::new(Pending {
+          state: Mutex::new(State::Pending),
+          changed: Condvar::new(),
+          length: buffer.len(),
+          deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+        });
+        self.waiting.push_back(pending.clone());
+        self.fill();
+        return Ok(pending);
+      }
+      0x02 => self.transfer_out(r#type, endpoint, &buffer).map(|_| buffer),
+      _ => self
+        .transfer_in(r#type, endpoint, &mut buffer)
+        .map(|_| buffer),
+    };
+    Ok(Pending::done(result))
+  }
+}
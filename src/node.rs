@@ -0,0 +1,913 @@
+//! Node.js bindings over N-API. Built into the cdylib with the `node`
+//! feature and loaded by `node/index.js`, which polyfills `navigator.usb`
+//! on top of them.
+//!
+//! The methods of `USBDevice` return Promises and are carried out on the
+//! libuv thread pool, except for `transferIn` and `transferOut`: their
+//! transfers are submitted and waited for on a thread of their own, which
+//! only holds the device while submitting. Attributes are served from a
+//! snapshot taken after every method that changes them, so reading them
+//! never waits for a transfer.
+//!
+//! Hotplug events are found by polling the device list of libusb, which
+//! does not open any device.
+
+use core::convert::TryFrom;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use napi::bindgen_prelude::AsyncTask;
+use napi::bindgen_prelude::Buffer;
+use napi::bindgen_prelude::ToNapiValue;
+use napi::bindgen_prelude::TypeName;
+use napi::threadsafe_function::ErrorStrategy;
+use napi::threadsafe_function::ThreadSafeCallContext;
+use napi::threadsafe_function::ThreadsafeFunction;
+use napi::threadsafe_function::ThreadsafeFunctionCallMode;
+use napi::Env;
+use napi::JsFunction;
+use napi::JsObject;
+use napi::Task;
+use napi_derive::napi;
+
+use crate::constants::HUB_CLASS;
+use crate::transfer_status;
+use crate::Context;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbAlternateInterface;
+use crate::UsbConfiguration;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbDeviceFilter;
+use crate::UsbEndpoint;
+use crate::UsbEndpointType;
+use crate::UsbInterface;
+use crate::UsbRecipient;
+use crate::UsbRequestType;
+
+// How often the device list is checked for hotplug events.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// `node/index.js` turns these into DOMExceptions.
+impl From<Error> for napi::Error {
+  fn from(err: Error) -> Self {
+    napi::Error::from_reason(format!("{}: {:?}", err.dom_exception_name(), err))
+  }
+}
+
+fn type_error(message: &str) -> napi::Error {
+  napi::Error::from_reason(format!("TypeError: {}", message))
+}
+
+#[napi(object, js_name = "USBEndpoint", object_from_js = false)]
+pub struct JsUsbEndpoint {
+  pub endpoint_number: u8,
+  pub direction: String,
+  #[napi(js_name = "type")]
+  pub endpoint_type: String,
+  pub packet_size: u16,
+}
+
+#[napi(object, js_name = "USBAlternateInterface", object_from_js = false)]
+pub struct JsUsbAlternateInterface {
+  pub alternate_setting: u8,
+  pub interface_class: u8,
+  pub interface_subclass: u8,
+  pub interface_protocol: u8,
+  pub interface_name: Option<String>,
+  pub endpoints: Vec<JsUsbEndpoint>,
+}
+
+#[napi(object, js_name = "USBInterface", object_from_js = false)]
+pub struct JsUsbInterface {
+  pub interface_number: u8,
+  pub alternate: JsUsbAlternateInterface,
+  pub alternates: Vec<JsUsbAlternateInterface>,
+  pub claimed: bool,
+}
+
+#[napi(object, js_name = "USBConfiguration", object_from_js = false)]
+pub struct JsUsbConfiguration {
+  pub configuration_value: u8,
+  pub configuration_name: Option<String>,
+  pub interfaces: Vec<JsUsbInterface>,
+}
+
+#[napi(object, js_name = "USBInTransferResult", object_from_js = false)]
+pub struct JsUsbInTransferResult {
+  pub data: Buffer,
+  pub status: String,
+}
+
+#[napi(object, js_name = "USBOutTransferResult", object_from_js = false)]
+pub struct JsUsbOutTransferResult {
+  pub bytes_written: u32,
+  pub status: String,
+}
+
+#[napi(object, js_name = "USBControlTransferParameters", object_to_js = false)]
+pub struct JsUsbControlTransferParameters {
+  pub request_type: String,
+  pub recipient: String,
+  pub request: u8,
+  pub value: u16,
+  pub index: u16,
+}
+
+#[napi(object, js_name = "USBDeviceFilter", object_to_js = false)]
+pub struct JsUsbDeviceFilter {
+  pub vendor_id: Option<u16>,
+  pub product_id: Option<u16>,
+  pub class_code: Option<u8>,
+  pub subclass_code: Option<u8>,
+  pub protocol_code: Option<u8>,
+  pub serial_number: Option<String>,
+}
+
+#[napi(object, js_name = "USBDeviceRequestOptions", object_to_js = false)]
+pub struct JsUsbDeviceRequestOptions {
+  pub filters: Vec<JsUsbDeviceFilter>,
+}
+
+/// Argument of the `watch` callback. `device` is only set on connect,
+/// disconnected devices are identified by their bus and address.
+#[napi(object, js_name = "USBHotplugEvent", object_from_js = false)]
+pub struct JsUsbHotplugEvent {
+  #[napi(js_name = "type")]
+  pub event_type: String,
+  pub bus_number: u8,
+  pub device_address: u8,
+  pub device: Option<JsUsbDevice>,
+}
+
+impl From<&UsbEndpoint> for JsUsbEndpoint {
+  fn from(endpoint: &UsbEndpoint) -> Self {
+    let direction = match endpoint.direction() {
+      Direction::In => "in",
+      Direction::Out => "out",
+    };
+    let endpoint_type = match endpoint.endpoint_type() {
+      UsbEndpointType::Bulk => "bulk",
+      UsbEndpointType::Interrupt => "interrupt",
+      UsbEndpointType::Isochronous => "isochronous",
+      UsbEndpointType::Control => "control",
+    };
+    JsUsbEndpoint {
+      endpoint_number: endpoint.endpoint_number(),
+      direction: direction.to_string(),
+      endpoint_type: endpoint_type.to_string(),
+      packet_size: endpoint.packet_size(),
+    }
+  }
+}
+
+impl From<&UsbAlternateInterface> for JsUsbAlternateInterface {
+  fn from(alternate: &UsbAlternateInterface) -> Self {
+    JsUsbAlternateInterface {
+      alternate_setting: alternate.alternate_setting,
+      interface_class: alternate.interface_class,
+      interface_subclass: alternate.interface_subclass,
+      interface_protocol: alternate.interface_protocol,
+      interface_name: alternate.interface_name.clone(),
+      endpoints: alternate.endpoints.iter().map(Into::into).collect(),
+    }
+  }
+}
+
+impl From<&UsbInterface> for JsUsbInterface {
+  fn from(interface: &UsbInterface) -> Self {
+    JsUsbInterface {
+      interface_number: interface.interface_number(),
+      alternate: interface.alternate().into(),
+      alternates: interface.alternates().iter().map(Into::into).collect(),
+      claimed: interface.claimed(),
+    }
+  }
+}
+
+impl From<&UsbConfiguration> for JsUsbConfiguration {
+  fn from(configuration: &UsbConfiguration) -> Self {
+    JsUsbConfiguration {
+      configuration_value: configuration.configuration_value(),
+      configuration_name: configuration
+        .configuration_name()
+        .map(str::to_string),
+      interfaces: configuration.interfaces().iter().map(Into::into).collect(),
+    }
+  }
+}
+
+impl JsUsbControlTransferParameters {
+  fn parse(self) -> napi::Result<UsbControlTransferParameters> {
+    let request_type = match self.request_type.as_str() {
+      "standard" => UsbRequestType::Standard,
+      "class" => UsbRequestType::Class,
+      "vendor" => UsbRequestType::Vendor,
+      _ => return Err(type_error("Invalid requestType")),
+    };
+    let recipient = match self.recipient.as_str() {
+      "device" => UsbRecipient::Device,
+      "interface" => UsbRecipient::Interface,
+      "endpoint" => UsbRecipient::Endpoint,
+      "other" => UsbRecipient::Other,
+      _ => return Err(type_error("Invalid recipient")),
+    };
+    Ok(UsbControlTransferParameters {
+      request_type,
+      recipient,
+      request: self.request,
+      value: self.value,
+      index: self.index,
+    })
+  }
+}
+
+impl From<JsUsbDeviceFilter> for UsbDeviceFilter {
+  fn from(filter: JsUsbDeviceFilter) -> Self {
+    UsbDeviceFilter {
+      vendor_id: filter.vendor_id,
+      product_id: filter.product_id,
+      class_code: filter.class_code,
+      subclass_code: filter.subclass_code,
+      protocol_code: filter.protocol_code,
+      serial_number: filter.serial_number,
+    }
+  }
+}
+
+type Change = AsyncTask<Job<(), ()>>;
+type InTransfer =
+  AsyncTask<Job<(&'static str, Vec<u8>), JsUsbInTransferResult>>;
+type OutTransfer =
+  AsyncTask<Job<(&'static str, usize), JsUsbOutTransferResult>>;
+
+/// Work carried out on the libuv thread pool. Resolves to the result of
+/// `work` as converted by `resolve`.
+pub struct Job<R, J> {
+  work: Option<Box<dyn FnOnce() -> Result<R> + Send>>,
+  resolve: fn(R) -> J,
+}
+
+impl<R, J> Job<R, J> {
+  fn new(
+    work: impl FnOnce() -> Result<R> + Send + 'static,
+    resolve: fn(R) -> J,
+  ) -> Self {
+    Job {
+      work: Some(Box::new(work)),
+      resolve,
+    }
+  }
+
+  fn spawn(
+    work: impl FnOnce() -> Result<R> + Send + 'static,
+    resolve: fn(R) -> J,
+  ) -> AsyncTask<Self>
+  where
+    Self: Task,
+  {
+    AsyncTask::new(Job::new(work, resolve))
+  }
+}
+
+impl<R, J> Task for Job<R, J>
+where
+  R: Send + 'static,
+  J: ToNapiValue + TypeName,
+{
+  type Output = R;
+  type JsValue = J;
+
+  fn compute(&mut self) -> napi::Result<R> {
+    let work = self.work.take().unwrap();
+    Ok(work()?)
+  }
+
+  fn resolve(&mut self, _: Env, output: R) -> napi::Result<J> {
+    Ok((self.resolve)(output))
+  }
+}
+
+// Attributes of a device that change with its state.
+struct Attributes {
+  opened: bool,
+  configuration: Option<UsbConfiguration>,
+  configurations: Vec<UsbConfiguration>,
+}
+
+impl From<&UsbDevice> for Attributes {
+  fn from(device: &UsbDevice) -> Self {
+    Attributes {
+      opened: device.opened,
+      configuration: device.configuration.clone(),
+      configurations: device.configurations.clone(),
+    }
+  }
+}
+
+#[derive(Clone)]
+struct Handle {
+  device: Arc<Mutex<UsbDevice>>,
+  attributes: Arc<Mutex<Attributes>>,
+}
+
+impl Handle {
+  fn run<T>(&self, f: impl FnOnce(&mut UsbDevice) -> Result<T>) -> Result<T> {
+    f(&mut self.device.lock().unwrap())
+  }
+
+  // Runs `f` and takes a new snapshot of the attributes.
+  fn change(&self, f: impl FnOnce(&mut UsbDevice) -> Result<()>) -> Result<()> {
+    let mut device = self.device.lock().unwrap();
+    let result = f(&mut device);
+    *self.attributes.lock().unwrap() = Attributes::from(&*device);
+    result
+  }
+
+  // Submits a transfer and waits for it. The device is only held while
+  // submitting.
+  fn complete(
+    &self,
+    direction: Direction,
+    endpoint_number: u8,
+    buffer: Vec<u8>,
+  ) -> Result<(&'static str, Vec<u8>)> {
+    let result = self
+      .run(|device| device.submit_transfer(direction, endpoint_number, buffer))
+      .and_then(|transfer| {
+        transfer.wait(None)?;
+        transfer.take()
+      });
+    transfer_status(result)
+  }
+
+  // Submits a transfer and resolves the returned Promise with its result,
+  // as converted by `resolve`, once it completes.
+  fn transfer<J>(
+    &self,
+    env: Env,
+    direction: Direction,
+    endpoint_number: u8,
+    buffer: Vec<u8>,
+    resolve: fn((&'static str, Vec<u8>)) -> J,
+  ) -> napi::Result<JsObject>
+  where
+    J: ToNapiValue + 'static,
+  {
+    let (deferred, promise) = env.create_deferred()?;
+    let handle = self.clone();
+    std::thread::spawn(move || {
+      match handle.complete(direction, endpoint_number, buffer) {
+        Ok(output) => deferred.resolve(move |_| Ok(resolve(output))),
+        Err(err) => deferred.reject(err.into()),
+      }
+    });
+    Ok(promise)
+  }
+}
+
+/// https://wicg.github.io/webusb/#device-usage
+#[napi(js_name = "USBDevice")]
+pub struct JsUsbDevice {
+  #[napi(readonly)]
+  pub usb_version_major: u8,
+  #[napi(readonly)]
+  pub usb_version_minor: u8,
+  #[napi(readonly)]
+  pub usb_version_subminor: u8,
+  #[napi(readonly)]
+  pub device_class: u8,
+  #[napi(readonly)]
+  pub device_subclass: u8,
+  #[napi(readonly)]
+  pub device_protocol: u8,
+  #[napi(readonly)]
+  pub vendor_id: u16,
+  #[napi(readonly)]
+  pub product_id: u16,
+  #[napi(readonly)]
+  pub device_version_major: u8,
+  #[napi(readonly)]
+  pub device_version_minor: u8,
+  #[napi(readonly)]
+  pub device_version_subminor: u8,
+  #[napi(readonly)]
+  pub manufacturer_name: Option<String>,
+  #[napi(readonly)]
+  pub product_name: Option<String>,
+  #[napi(readonly)]
+  pub serial_number: Option<String>,
+  /// Not part of WebUSB. Identifies the device in hotplug events.
+  #[napi(readonly)]
+  pub bus_number: u8,
+  /// Not part of WebUSB. Identifies the device in hotplug events.
+  #[napi(readonly)]
+  pub device_address: u8,
+  handle: Handle,
+}
+
+impl From<UsbDevice> for JsUsbDevice {
+  fn from(device: UsbDevice) -> Self {
+    JsUsbDevice {
+      usb_version_major: device.usb_version_major,
+      usb_version_minor: device.usb_version_minor,
+      usb_version_subminor: device.usb_version_subminor,
+      device_class: device.device_class,
+      device_subclass: device.device_subclass,
+      device_protocol: device.device_protocol,
+      vendor_id: device.vendor_id,
+      product_id: device.product_id,
+      device_version_major: device.device_version_major,
+      device_version_minor: device.device_version_minor,
+      device_version_subminor: device.device_version_subminor,
+      manufacturer_name: device.manufacturer_name.clone(),
+      product_name: device.product_name.clone(),
+      serial_number: device.serial_number.clone(),
+      bus_number: device.bus_number,
+      device_address: device.device_address,
+      handle: Handle {
+        attributes: Arc::new(Mutex::new(Attributes::from(&device))),
+        device: Arc::new(Mutex::new(device)),
+      },
+    }
+  }
+}
+
+#[napi]
+impl JsUsbDevice {
+  /// Devices are only handed out by `USB`, as in browsers. Declaring the
+  /// constructor also exports the class under its `js_name`.
+  #[napi(constructor)]
+  pub fn new() -> napi::Result<Self> {
+    Err(type_error("Illegal constructor"))
+  }
+
+  #[napi(getter)]
+  pub fn opened(&self) -> bool {
+    self.handle.attributes.lock().unwrap().opened
+  }
+
+  #[napi(getter)]
+  pub fn configuration(&self) -> Option<JsUsbConfiguration> {
+    let attributes = self.handle.attributes.lock().unwrap();
+    attributes.configuration.as_ref().map(Into::into)
+  }
+
+  #[napi(getter)]
+  pub fn configurations(&self) -> Vec<JsUsbConfiguration> {
+    let attributes = self.handle.attributes.lock().unwrap();
+    attributes.configurations.iter().map(Into::into).collect()
+  }
+
+  fn change(
+    &self,
+    f: impl FnOnce(&mut UsbDevice) -> Result<()> + Send + 'static,
+  ) -> Change {
+    let handle = self.handle.clone();
+    Job::spawn(move || handle.change(f), |()| ())
+  }
+
+  #[napi]
+  pub fn open(&self) -> Change {
+    self.change(|device| device.open())
+  }
+
+  #[napi]
+  pub fn close(&self) -> Change {
+    self.change(|device| device.close())
+  }
+
+  #[napi]
+  pub fn reset(&self) -> Change {
+    self.change(|device| device.reset())
+  }
+
+  #[napi]
+  pub fn select_configuration(&self, configuration_value: u8) -> Change {
+    self.change(move |device| device.select_configuration(configuration_value))
+  }
+
+  #[napi]
+  pub fn claim_interface(&self, interface_number: u8) -> Change {
+    self.change(move |device| device.claim_interface(interface_number))
+  }
+
+  #[napi]
+  pub fn release_interface(&self, interface_number: u8) -> Change {
+    self.change(move |device| device.release_interface(interface_number))
+  }
+
+  #[napi]
+  pub fn select_alternate_interface(
+    &self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Change {
+    self.change(move |device| {
+      device.select_alternate_interface(interface_number, alternate_setting)
+    })
+  }
+
+  #[napi]
+  pub fn clear_halt(
+    &self,
+    direction: String,
+    endpoint_number: u8,
+  ) -> napi::Result<Change> {
+    let direction = match direction.as_str() {
+      "in" => Direction::In,
+      "out" => Direction::Out,
+      _ => return Err(type_error("Invalid direction")),
+    };
+    let handle = self.handle.clone();
+    Ok(Job::spawn(
+      move || {
+        handle.run(|device| device.clear_halt(direction, endpoint_number))
+      },
+      |()| (),
+    ))
+  }
+
+  #[napi]
+  pub fn control_transfer_in(
+    &self,
+    setup: JsUsbControlTransferParameters,
+    length: u16,
+  ) -> napi::Result<InTransfer> {
+    let setup = setup.parse()?;
+    let handle = self.handle.clone();
+    Ok(Job::spawn(
+      move || {
+        transfer_status(
+          handle
+            .run(|device| device.control_transfer_in(setup, length as usize)),
+        )
+      },
+      in_result,
+    ))
+  }
+
+  #[napi]
+  pub fn control_transfer_out(
+    &self,
+    setup: JsUsbControlTransferParameters,
+    data: Option<Buffer>,
+  ) -> napi::Result<OutTransfer> {
+    let setup = setup.parse()?;
+    let data = data.map(|data| data.to_vec()).unwrap_or_default();
+    let handle = self.handle.clone();
+    Ok(Job::spawn(
+      move || {
+        transfer_status(
+          handle.run(|device| device.control_transfer_out(setup, &data)),
+        )
+      },
+      out_result,
+    ))
+  }
+
+  #[napi(ts_return_type = "Promise<USBInTransferResult>")]
+  pub fn transfer_in(
+    &self,
+    env: Env,
+    endpoint_number: u8,
+    length: u32,
+  ) -> napi::Result<JsObject> {
+    let buffer = vec![0; length as usize];
+    let handle = &self.handle;
+    handle.transfer(env, Direction::In, endpoint_number, buffer, in_result)
+  }
+
+  #[napi(ts_return_type = "Promise<USBOutTransferResult>")]
+  pub fn transfer_out(
+    &self,
+    env: Env,
+    endpoint_number: u8,
+    data: Buffer,
+  ) -> napi::Result<JsObject> {
+    self.handle.transfer(
+      env,
+      Direction::Out,
+      endpoint_number,
+      data.to_vec(),
+      |(status, data)| out_result((status, data.len())),
+    )
+  }
+}
+
+fn in_result((status, data): (&'static str, Vec<u8>)) -> JsUsbInTransferResult {
+  JsUsbInTransferResult {
+    data: data.into(),
+    status: status.to_string(),
+  }
+}
+
+fn out_result(
+  (status, length): (&'static str, usize),
+) -> JsUsbOutTransferResult {
+  JsUsbOutTransferResult {
+    bytes_written: length as u32,
+    status: status.to_string(),
+  }
+}
+
+enum Hotplug {
+  Connect(Box<UsbDevice>),
+  Disconnect(u8, u8),
+}
+
+impl From<Hotplug> for JsUsbHotplugEvent {
+  fn from(hotplug: Hotplug) -> Self {
+    match hotplug {
+      Hotplug::Connect(device) => JsUsbHotplugEvent {
+        event_type: "connect".to_string(),
+        bus_number: device.bus_number,
+        device_address: device.device_address,
+        device: Some((*device).into()),
+      },
+      Hotplug::Disconnect(bus_number, device_address) => JsUsbHotplugEvent {
+        event_type: "disconnect".to_string(),
+        bus_number,
+        device_address,
+        device: None,
+      },
+    }
+  }
+}
+
+/// https://wicg.github.io/webusb/#usb
+#[napi(js_name = "USB")]
+pub struct JsUsb {
+  watching: Arc<AtomicBool>,
+}
+
+#[napi]
+impl JsUsb {
+  #[napi(constructor)]
+  #[allow(clippy::new_without_default)]
+  pub fn new() -> Self {
+    JsUsb {
+      watching: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  #[napi]
+  pub fn get_devices(
+    &self,
+  ) -> AsyncTask<Job<Vec<UsbDevice>, Vec<JsUsbDevice>>> {
+    Job::spawn(
+      || Context::init()?.devices(),
+      |devices| devices.into_iter().map(Into::into).collect(),
+    )
+  }
+
+  /// There is no chooser, resolves to the first device matching one of the
+  /// filters.
+  #[napi]
+  pub fn request_device(
+    &self,
+    options: JsUsbDeviceRequestOptions,
+  ) -> AsyncTask<Job<UsbDevice, JsUsbDevice>> {
+    let filters = options
+      .filters
+      .into_iter()
+      .map(UsbDeviceFilter::from)
+      .collect::<Vec<_>>();
+    Job::spawn(
+      move || {
+        Context::init()?
+          .devices()?
+          .into_iter()
+          .find(|device| {
+            filters.is_empty()
+              || filters.iter().any(|filter| filter.matches(device))
+          })
+          .ok_or(Error::NotFound)
+      },
+      Into::into,
+    )
+  }
+
+  /// Calls `callback` with a `USBHotplugEvent` whenever a device is
+  /// connected or disconnected, until `unwatch` is called. Does not keep
+  /// the process alive.
+  #[napi]
+  pub fn watch(&self, env: Env, callback: JsFunction) -> napi::Result<()> {
+    if self.watching.swap(true, Ordering::SeqCst) {
+      return Err(Error::InvalidState.into());
+    }
+
+    let mut callback: ThreadsafeFunction<Hotplug, ErrorStrategy::Fatal> =
+      callback.create_threadsafe_function(
+        0,
+        |ctx: ThreadSafeCallContext<Hotplug>| {
+          Ok(vec![JsUsbHotplugEvent::from(ctx.value)])
+        },
+      )?;
+    callback.unref(&env)?;
+
+    let watching = self.watching.clone();
+    std::thread::spawn(move || watch(&watching, &callback));
+    Ok(())
+  }
+
+  #[napi]
+  pub fn unwatch(&self) {
+    self.watching.store(false, Ordering::SeqCst);
+  }
+}
+
+fn watch(
+  watching: &AtomicBool,
+  callback: &ThreadsafeFunction<Hotplug, ErrorStrategy::Fatal>,
+) {
+  let context = match rusb::Context::new() {
+    Ok(context) => context,
+    Err(_) => return,
+  };
+  // Bus number and address of the devices seen so far.
+  let mut known: Option<HashSet<(u8, u8)>> = None;
+
+  while watching.load(Ordering::SeqCst) {
+    if let Ok(list) = rusb::UsbContext::devices(&context) {
+      // Hubs are not listed by `getDevices` either.
+      let devices = list
+        .iter()
+        .filter(|device| {
+          matches!(
+            device.device_descriptor(),
+            Ok(descriptor) if descriptor.class_code() != HUB_CLASS
+          )
+        })
+        .map(|device| ((device.bus_number(), device.address()), device))
+        .collect::<Vec<_>>();
+      let present = devices.iter().map(|(key, _)| *key).collect();
+
+      // Devices present when watching started are not reported.
+      if let Some(known) = &known {
+        for (key, device) in devices {
+          if known.contains(&key) {
+            continue;
+          }
+          if let Ok(device) = UsbDevice::try_from(device) {
+            callback.call(
+              Hotplug::Connect(Box::new(device)),
+              ThreadsafeFunctionCallMode::NonBlocking,
+            );
+          }
+        }
+        for (bus_number, device_address) in known.difference(&present) {
+          callback.call(
+            Hotplug::Disconnect(*bus_number, *device_address),
+            ThreadsafeFunctionCallMode::NonBlocking,
+          );
+        }
+      }
+      known = Some(present);
+    }
+    std::thread::sleep(WATCH_INTERVAL);
+  }
+}
+
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod tests {
+  use super::*;
+  use crate::loopback::Loopback;
+  use crate::loopback::DEVICE_DESCRIPTOR;
+
+  // Carries out `job` the way the libuv thread pool does. No `Env` is
+  // needed to resolve the jobs of this module.
+  fn run<R, J>(mut job: Job<R, J>) -> napi::Result<J>
+  where
+    R: Send + 'static,
+    J: ToNapiValue + TypeName,
+  {
+    let output = job.compute()?;
+    job.resolve(unsafe { Env::from_raw(std::ptr::null_mut()) }, output)
+  }
+
+  fn change(
+    device: &JsUsbDevice,
+    f: impl FnOnce(&mut UsbDevice) -> Result<()> + Send + 'static,
+  ) -> napi::Result<()> {
+    let handle = device.handle.clone();
+    run(Job::new(move || handle.change(f), |()| ()))
+  }
+
+  fn parameters(request_type: &str) -> JsUsbControlTransferParameters {
+    JsUsbControlTransferParameters {
+      request_type: request_type.to_string(),
+      recipient: "device".to_string(),
+      request: 0x06,
+      value: 0x0100,
+      index: 0,
+    }
+  }
+
+  #[test]
+  fn test_attributes() {
+    let device = JsUsbDevice::from(Loopback::device());
+    assert_eq!((device.vendor_id, device.product_id), (0x1234, 0x5678));
+    assert_eq!(device.product_name, Some("Loopback".to_string()));
+    assert!(!device.opened());
+
+    change(&device, |device| device.open()).unwrap();
+    change(&device, |device| device.select_configuration(1)).unwrap();
+    change(&device, |device| device.claim_interface(0)).unwrap();
+    assert!(device.opened());
+
+    let configuration = device.configuration().unwrap();
+    assert_eq!(configuration.configuration_value, 1);
+    let interface = &configuration.interfaces[0];
+    assert!(interface.claimed);
+    let endpoint = &interface.alternate.endpoints[0];
+    assert_eq!(endpoint.endpoint_number, 1);
+    assert_eq!(endpoint.direction, "in");
+    assert_eq!(endpoint.endpoint_type, "bulk");
+    assert_eq!(endpoint.packet_size, 64);
+    assert_eq!(device.configurations().len(), 1);
+
+    // The snapshot is taken even if the method fails.
+    let err = change(&device, |device| device.claim_interface(1)).unwrap_err();
+    assert!(err.reason.starts_with("NotFoundError: "));
+    change(&device, |device| device.close()).unwrap();
+    assert!(!device.opened());
+  }
+
+  #[test]
+  fn test_control_transfer() {
+    let device = JsUsbDevice::from(Loopback::device());
+    change(&device, |device| device.open()).unwrap();
+
+    let setup = parameters("standard").parse().unwrap();
+    let handle = device.handle.clone();
+    let result = run(Job::new(
+      move || {
+        transfer_status(
+          handle.run(|device| device.control_transfer_in(setup, 18)),
+        )
+      },
+      in_result,
+    ))
+    .unwrap();
+    assert_eq!(result.status, "ok");
+    assert_eq!(&*result.data, DEVICE_DESCRIPTOR);
+
+    let err = parameters("reserved").parse().err().unwrap();
+    assert_eq!(err.reason, "TypeError: Invalid requestType");
+  }
+
+  #[test]
+  fn test_transfers() {
+    let device = JsUsbDevice::from(Loopback::device());
+    let handle = &device.handle;
+
+    let err = handle.complete(Direction::In, 1, vec![0; 64]).unwrap_err();
+    assert_eq!(
+      napi::Error::from(err).reason,
+      "InvalidStateError: InvalidState"
+    );
+
+    change(&device, |device| device.open()).unwrap();
+    change(&device, |device| device.select_configuration(1)).unwrap();
+    change(&device, |device| device.claim_interface(0)).unwrap();
+
+    let output = handle
+      .complete(Direction::Out, 2, b"hello".to_vec())
+      .unwrap();
+    let result = out_result((output.0, output.1.len()));
+    assert_eq!((result.status.as_str(), result.bytes_written), ("ok", 5));
+
+    let result =
+      in_result(handle.complete(Direction::In, 1, vec![0; 64]).unwrap());
+    assert_eq!(result.status, "ok");
+    assert_eq!(&*result.data, b"hello");
+
+    // Stalls resolve rather than reject.
+    let result =
+      in_result(handle.complete(Direction::In, 3, vec![0; 8]).unwrap());
+    assert_eq!(result.status, "stall");
+    assert!(result.data.is_empty());
+  }
+
+  #[test]
+  fn test_hotplug_event() {
+    let event =
+      JsUsbHotplugEvent::from(Hotplug::Connect(Box::new(Loopback::device())));
+    assert_eq!(event.event_type, "connect");
+    assert_eq!((event.bus_number, event.device_address), (1, 5));
+    let device = event.device.unwrap();
+    assert_eq!((device.bus_number, device.device_address), (1, 5));
+
+    let event = JsUsbHotplugEvent::from(Hotplug::Disconnect(1, 5));
+    assert_eq!(event.event_type, "disconnect");
+    assert_eq!((event.bus_number, event.device_address), (1, 5));
+    assert!(event.device.is_none());
+  }
+}
@@ -0,0 +1,516 @@
+//! `std::io` adapters for bulk and interrupt endpoints.
+//!
+//! ```no_run
+//! use std::io::BufRead;
+//! use webusb::pipe::EndpointReader;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let context = webusb::Context::init()?;
+//! let mut device = context.devices()?.remove(0);
+//! device.open()?;
+//! device.claim_interface(0)?;
+//!
+//! let reader = EndpointReader::new(&mut device, 1)?;
+//! for line in reader.lines() {
+//!   println!("{}", line?);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Errors of the device are turned into `std::io::Error`s. Timeouts set
+//! with `UsbDevice::set_transfer_timeout` apply to every transfer.
+//!
+//! With the `tokio` or `futures-io` feature, `AsyncEndpointReader`,
+//! `AsyncEndpointWriter` and `AsyncEndpointPipe` implement the async I/O
+//! traits of the respective crate.
+
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+
+use crate::backend::TransferQueue;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbDevice;
+use crate::UsbEndpointType;
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+mod nonblocking;
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub use nonblocking::AsyncEndpointPipe;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub use nonblocking::AsyncEndpointReader;
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+pub use nonblocking::AsyncEndpointWriter;
+
+// Default size of a transfer, in packets.
+const TRANSFER_PACKETS: usize = 32;
+// Default number of transfers in flight.
+const QUEUE_LENGTH: usize = 4;
+
+// Packet size of the bulk or interrupt endpoint `endpoint_number`.
+fn packet_size(
+  device: &UsbDevice,
+  endpoint_number: u8,
+  direction: Direction,
+) -> Result<usize> {
+  let endpoint = device.endpoint(direction, endpoint_number)?;
+
+  match endpoint.r#type {
+    UsbEndpointType::Bulk | UsbEndpointType::Interrupt => {
+      Ok(usize::from(endpoint.packet_size).max(1))
+    }
+    _ => Err(Error::InvalidAccess),
+  }
+}
+
+// Rounds `size` up to a multiple of `packet_size`, at least one packet.
+fn transfer_size(size: usize, packet_size: usize) -> usize {
+  size.div_ceil(packet_size).max(1) * packet_size
+}
+
+/// Reads an IN endpoint as a byte stream.
+///
+/// Data is read in transfers of a multiple of the packet size, so a device
+/// sending full packets never overflows the buffer. A short packet ends a
+/// transfer early; its data is returned as is. A zero length packet reads
+/// as end of file once, later reads continue with the next transfer. That
+/// way `read_to_end` reads a single ZLP terminated message.
+pub struct EndpointReader<'a> {
+  device: &'a mut UsbDevice,
+  endpoint_number: u8,
+  packet_size: usize,
+  transfer_size: usize,
+  buffer: Vec<u8>,
+  position: usize,
+}
+
+impl<'a> EndpointReader<'a> {
+  /// Reads IN endpoint `endpoint_number` of the selected configuration.
+  pub fn new(device: &'a mut UsbDevice, endpoint_number: u8) -> Result<Self> {
+    let packet_size = packet_size(device, endpoint_number, Direction::In)?;
+    Ok(EndpointReader {
+      device,
+      endpoint_number,
+      packet_size,
+      transfer_size: TRANSFER_PACKETS * packet_size,
+      buffer: vec![],
+      position: 0,
+    })
+  }
+
+  /// Sets the size of the transfers, rounded up to a multiple of the
+  /// packet size. Defaults to 32 packets.
+  pub fn with_transfer_size(mut self, size: usize) -> Self {
+    self.transfer_size = transfer_size(size, self.packet_size);
+    self
+  }
+
+  pub fn packet_size(&self) -> usize {
+    self.packet_size
+  }
+
+  pub fn transfer_size(&self) -> usize {
+    self.transfer_size
+  }
+}
+
+impl Read for EndpointReader<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let available = self.fill_buf()?;
+    let length = available.len().min(buf.len());
+    buf[..length].copy_from_slice(&available[..length]);
+    self.consume(length);
+    Ok(length)
+  }
+}
+
+impl BufRead for EndpointReader<'_> {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    if self.position == self.buffer.len() {
+      // Empty after a zero length packet.
+      self.buffer = self
+        .device
+        .transfer_in(self.endpoint_number, self.transfer_size)?;
+      self.position = 0;
+    }
+    Ok(&self.buffer[self.position..])
+  }
+
+  fn consume(&mut self, amount: usize) {
+    self.position = (self.position + amount).min(self.buffer.len());
+  }
+}
+
+/// Reads an IN endpoint with several transfers in flight.
+///
+/// `EndpointReader` makes one transfer at a time, leaving the bus idle in
+/// between. `QueuedReader` keeps a queue of transfers submitted, submits
+/// each one again as soon as its data is taken, and returns the data in
+/// order. Otherwise it reads like `EndpointReader`.
+///
+/// Only libusb submits transfers asynchronously. With other backends,
+/// transfers are made one at a time.
+pub struct QueuedReader<'a> {
+  device: &'a mut UsbDevice,
+  endpoint_number: u8,
+  packet_size: usize,
+  transfer_size: usize,
+  queue_length: usize,
+  // Started by the first read.
+  queue: Option<Box<dyn TransferQueue>>,
+  buffer: Vec<u8>,
+  position: usize,
+}
+
+impl<'a> QueuedReader<'a> {
+  /// Reads IN endpoint `endpoint_number` of the selected configuration.
+  pub fn new(device: &'a mut UsbDevice, endpoint_number: u8) -> Result<Self> {
+    let packet_size = packet_size(device, endpoint_number, Direction::In)?;
+    Ok(QueuedReader {
+      device,
+      endpoint_number,
+      packet_size,
+      transfer_size: TRANSFER_PACKETS * packet_size,
+      queue_length: QUEUE_LENGTH,
+      queue: None,
+      buffer: vec![],
+      position: 0,
+    })
+  }
+
+  /// Sets the size of the transfers, rounded up to a multiple of the
+  /// packet size. Defaults to 32 packets.
+  pub fn with_transfer_size(mut self, size: usize) -> Self {
+    self.transfer_size = transfer_size(size, self.packet_size);
+    self.queue = None;
+    self
+  }
+
+  /// Sets the number of transfers in flight, at least one. Defaults to 4.
+  pub fn with_queue_length(mut self, length: usize) -> Self {
+    self.queue_length = length.max(1);
+    self.queue = None;
+    self
+  }
+
+  pub fn packet_size(&self) -> usize {
+    self.packet_size
+  }
+
+  pub fn transfer_size(&self) -> usize {
+    self.transfer_size
+  }
+
+  pub fn queue_length(&self) -> usize {
+    self.queue_length
+  }
+
+  /// Waits for the next transfer and returns its data. Data of the
+  /// current transfer not consumed through `Read` comes first.
+  pub fn next_transfer(&mut self) -> Result<Vec<u8>> {
+    if self.position == self.buffer.len() {
+      self.fill()?;
+    }
+    let mut data = std::mem::take(&mut self.buffer);
+    data.drain(..self.position);
+    self.position = 0;
+    Ok(data)
+  }
+
+  fn fill(&mut self) -> Result<()> {
+    let queue = match &mut self.queue {
+      Some(queue) => queue,
+      queue => queue.insert(self.device.queue_in(
+        self.endpoint_number,
+        self.transfer_size,
+        self.queue_length,
+      )?),
+    };
+    self.position = 0;
+    let result = self.device.next_queued(queue.as_mut(), &mut self.buffer);
+    if result.is_err() {
+      self.buffer.clear();
+    }
+    result
+  }
+}
+
+impl Read for QueuedReader<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let available = self.fill_buf()?;
+    let length = available.len().min(buf.len());
+    buf[..length].copy_from_slice(&available[..length]);
+    self.consume(length);
+    Ok(length)
+  }
+}
+
+impl BufRead for QueuedReader<'_> {
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    if self.position == self.buffer.len() {
+      // Empty after a zero length packet.
+      self.fill()?;
+    }
+    Ok(&self.buffer[self.position..])
+  }
+
+  fn consume(&mut self, amount: usize) {
+    self.position = (self.position + amount).min(self.buffer.len());
+  }
+}
+
+/// Writes an OUT endpoint as a byte stream.
+///
+/// Data is buffered and sent in transfers of full packets. `flush` sends
+/// the rest as a short packet, or a zero length packet if the data ended
+/// with a full one, so the device sees the end of the transfer. Like
+/// `std::io::BufWriter`, the writer is flushed on drop, ignoring errors.
+pub struct EndpointWriter<'a> {
+  device: &'a mut UsbDevice,
+  endpoint_number: u8,
+  packet_size: usize,
+  transfer_size: usize,
+  buffer: Vec<u8>,
+  zero_length_packets: bool,
+  // The last transfer ended with a full packet.
+  unterminated: bool,
+}
+
+impl<'a> EndpointWriter<'a> {
+  /// Writes OUT endpoint `endpoint_number` of the selected configuration.
+  pub fn new(device: &'a mut UsbDevice, endpoint_number: u8) -> Result<Self> {
+    let packet_size = packet_size(device, endpoint_number, Direction::Out)?;
+    Ok(EndpointWriter {
+      device,
+      endpoint_number,
+      packet_size,
+      transfer_size: TRANSFER_PACKETS * packet_size,
+      buffer: vec![],
+      zero_length_packets: true,
+      unterminated: false,
+    })
+  }
+
+  /// Sets the size of the transfers, rounded up to a multiple of the
+  /// packet size. Defaults to 32 packets.
+  pub fn with_transfer_size(mut self, size: usize) -> Self {
+    self.transfer_size = transfer_size(size, self.packet_size);
+    self
+  }
+
+  /// If false, `flush` does not send zero length packets. For devices
+  /// that know the length of the data in advance.
+  pub fn with_zero_length_packets(mut self, enabled: bool) -> Self {
+    self.zero_length_packets = enabled;
+    self
+  }
+
+  pub fn packet_size(&self) -> usize {
+    self.packet_size
+  }
+
+  pub fn transfer_size(&self) -> usize {
+    self.transfer_size
+  }
+
+  // Sends `data` in a single transfer, returning the number of bytes
+  // written.
+  fn transfer(&mut self, data: &[u8]) -> io::Result<usize> {
+    let length = self.device.transfer_out(self.endpoint_number, data)?;
+    if length == 0 && !data.is_empty() {
+      return Err(io::ErrorKind::WriteZero.into());
+    }
+    self.unterminated = length > 0 && length % self.packet_size == 0;
+    Ok(length)
+  }
+
+  // Sends the first `length` bytes of the buffer.
+  fn send(&mut self, length: usize) -> io::Result<()> {
+    let buffer = std::mem::take(&mut self.buffer);
+    let mut sent = 0;
+    let mut result = Ok(());
+    while sent < length {
+      let end = length.min(sent + self.transfer_size);
+      match self.transfer(&buffer[sent..end]) {
+        Ok(written) => sent += written,
+        Err(err) => {
+          result = Err(err);
+          break;
+        }
+      }
+    }
+    self.buffer = buffer;
+    self.buffer.drain(..sent);
+    result
+  }
+
+  // Sends the full packets in the buffer.
+  fn send_packets(&mut self) -> io::Result<()> {
+    let length = self.buffer.len() - self.buffer.len() % self.packet_size;
+    self.send(length)
+  }
+}
+
+impl Write for EndpointWriter<'_> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self.buffer.len() + buf.len() > self.transfer_size {
+      self.send_packets()?;
+    }
+    if self.buffer.is_empty() && buf.len() >= self.transfer_size {
+      // Large writes bypass the buffer.
+      return self.transfer(&buf[..self.transfer_size]);
+    }
+    let length = buf.len().min(self.transfer_size - self.buffer.len());
+    self.buffer.extend_from_slice(&buf[..length]);
+    Ok(length)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.send(self.buffer.len())?;
+    if self.unterminated && self.zero_length_packets {
+      self.transfer(&[])?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for EndpointWriter<'_> {
+  fn drop(&mut self) {
+    let _ = self.flush();
+  }
+}
+
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::loopback::Loopback;
+  use crate::loopback::Transfers;
+
+  fn loopback() -> (UsbDevice, Transfers) {
+    let (mut device, transfers) = Loopback::device_with_transfers();
+    device.open().unwrap();
+    device.claim_interface(0).unwrap();
+    device.set_transfer_timeout(Some(Duration::from_millis(10)));
+    (device, transfers)
+  }
+
+  #[test]
+  fn test_lines() {
+    let (mut device, _) = loopback();
+    let mut writer = EndpointWriter::new(&mut device, 2).unwrap();
+    writer.write_all(b"first\nsecond\n").unwrap();
+    drop(writer);
+
+    let reader = EndpointReader::new(&mut device, 1).unwrap();
+    let lines = reader.lines().take(2).collect::<io::Result<Vec<_>>>();
+    assert_eq!(lines.unwrap(), vec!["first", "second"]);
+
+    // Nothing left to read.
+    let mut reader = EndpointReader::new(&mut device, 1).unwrap();
+    let err = reader.read(&mut [0; 8]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+  }
+
+  #[test]
+  fn test_transfer_size() {
+    let (mut device, _) = loopback();
+    let reader = EndpointReader::new(&mut device, 1).unwrap();
+    assert_eq!((reader.packet_size(), reader.transfer_size()), (64, 2048));
+    let reader = reader.with_transfer_size(100);
+    assert_eq!(reader.transfer_size(), 128);
+    let reader = reader.with_transfer_size(0);
+    assert_eq!(reader.transfer_size(), 64);
+
+    assert!(matches!(
+      EndpointReader::new(&mut device, 2),
+      Err(Error::NotFound)
+    ));
+    assert!(matches!(
+      EndpointWriter::new(&mut device, 1),
+      Err(Error::NotFound)
+    ));
+  }
+
+  #[test]
+  fn test_zero_length_packets() {
+    let (mut device, transfers) = loopback();
+    let mut writer = EndpointWriter::new(&mut device, 2).unwrap();
+
+    // Ends with a full packet.
+    writer.write_all(&[1; 64]).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(*transfers.lock().unwrap(), vec![64, 0]);
+    transfers.lock().unwrap().clear();
+
+    // Ends with a short packet.
+    writer.write_all(&[2; 70]).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(*transfers.lock().unwrap(), vec![70]);
+    transfers.lock().unwrap().clear();
+
+    // Nothing to terminate.
+    writer.flush().unwrap();
+    assert!(transfers.lock().unwrap().is_empty());
+
+    let mut writer = writer.with_zero_length_packets(false);
+    writer.write_all(&[3; 128]).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(*transfers.lock().unwrap(), vec![128]);
+  }
+
+  #[test]
+  fn test_large_writes() {
+    let (mut device, transfers) = loopback();
+    let data = (0..5000).map(|i| i as u8).collect::<Vec<_>>();
+    let mut writer = EndpointWriter::new(&mut device, 2)
+      .unwrap()
+      .with_transfer_size(1024);
+    writer.write_all(&data[..10]).unwrap();
+    writer.write_all(&data[10..]).unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+    assert_eq!(
+      *transfers.lock().unwrap(),
+      vec![1024, 1024, 1024, 1024, 904]
+    );
+
+    let mut reader = EndpointReader::new(&mut device, 1).unwrap();
+    let mut received = vec![0; data.len()];
+    reader.read_exact(&mut received).unwrap();
+    assert_eq!(received, data);
+  }
+
+  #[test]
+  fn test_queued_reader() {
+    let (mut device, _) = loopback();
+    let data = (0..200).map(|i| i as u8).collect::<Vec<_>>();
+    device.transfer_out(2, &data).unwrap();
+
+    let mut reader = QueuedReader::new(&mut device, 1)
+      .unwrap()
+      .with_transfer_size(64)
+      .with_queue_length(3);
+    assert_eq!(reader.queue_length(), 3);
+    let mut first = [0; 10];
+    reader.read_exact(&mut first).unwrap();
+    assert_eq!(first, data[..10]);
+    assert_eq!(reader.next_transfer().unwrap(), data[10..64]);
+    assert_eq!(reader.next_transfer().unwrap(), data[64..128]);
+    let mut rest = vec![0; 72];
+    reader.read_exact(&mut rest).unwrap();
+    assert_eq!(rest, data[128..]);
+    let err = reader.next_transfer().unwrap_err();
+    assert_eq!(err, Error::Io(io::ErrorKind::TimedOut));
+    drop(reader);
+
+    let mut reader = QueuedReader::new(&mut device, 3).unwrap();
+    assert_eq!(reader.next_transfer().unwrap_err(), Error::Stall);
+  }
+}
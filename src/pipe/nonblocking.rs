@@ -0,0 +1,732 @@
+//! Async adapters for bulk and interrupt endpoints.
+//!
+//! Transfers are submitted to the device and waited for on a thread, so
+//! the adapters work with any executor. Each reader and writer keeps one
+//! such thread while it exists, for all of its transfers. Reader and writer
+//! share the device through an `Arc<Mutex<UsbDevice>>`, which is only
+//! locked to submit a transfer, so both directions run at once. Transfers
+//! that time out are retried; use the timers of the runtime for deadlines.
+//! Dropping a reader or writer cancels its transfer in progress.
+//!
+//! With the `codec` feature, line or length delimited protocols are
+//! framed with `tokio_util::codec`:
+//!
+//! ```no_run
+//! # #[cfg(feature = "codec")]
+//! # async fn ping() -> std::io::Result<()> {
+//! use std::sync::Arc;
+//! use std::sync::Mutex;
+//!
+//! use futures_util::SinkExt;
+//! use futures_util::StreamExt;
+//! use tokio_util::codec::LinesCodec;
+//! use webusb::pipe::AsyncEndpointPipe;
+//!
+//! let context = webusb::Context::init()?;
+//! let mut device = context.devices()?.remove(0);
+//! device.open()?;
+//! device.claim_interface(0)?;
+//!
+//! let pipe = AsyncEndpointPipe::new(Arc::new(Mutex::new(device)), 1, 2)?;
+//! let mut lines = pipe.framed(LinesCodec::new());
+//! lines.send("ping").await.unwrap();
+//! println!("{:?}", lines.next().await);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::ready;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread;
+
+use super::packet_size;
+use super::transfer_size;
+use super::TRANSFER_PACKETS;
+use crate::backend::Transfer;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbDevice;
+
+struct State {
+  // Result of waiting for the transfer, once it completed.
+  waited: Option<Result<bool>>,
+  waker: Option<Waker>,
+}
+
+// Waits for the transfers of a reader or writer, in the order they were
+// submitted, and wakes the task polling them. Its thread exits once the
+// waiter is dropped and the last transfer completed.
+struct Waiter {
+  transfers: mpsc::Sender<Waiting>,
+}
+
+// A transfer and the state to complete once it was waited for.
+type Waiting = (Arc<dyn Transfer>, Arc<Mutex<State>>);
+
+impl Waiter {
+  fn new() -> Self {
+    let (transfers, submitted) = mpsc::channel::<Waiting>();
+    thread::spawn(move || {
+      for (transfer, state) in submitted {
+        let waited = transfer.wait(None);
+        let mut state = state.lock().unwrap();
+        state.waited = Some(waited);
+        if let Some(waker) = state.waker.take() {
+          waker.wake();
+        }
+      }
+    });
+    Waiter { transfers }
+  }
+}
+
+// A transfer of a reader or writer, waited for by its `Waiter`. Dropping
+// it cancels the transfer.
+struct Pending {
+  transfer: Arc<dyn Transfer>,
+  state: Arc<Mutex<State>>,
+}
+
+impl Pending {
+  fn submit(
+    device: &Mutex<UsbDevice>,
+    waiter: &Waiter,
+    direction: Direction,
+    endpoint_number: u8,
+    buffer: Vec<u8>,
+  ) -> Result<Self> {
+    let transfer = device.lock().unwrap().submit_transfer(
+      direction,
+      endpoint_number,
+      buffer,
+    )?;
+    let state = Arc::new(Mutex::new(State {
+      waited: None,
+      waker: None,
+    }));
+    // The thread only exits once the waiter is dropped.
+    waiter
+      .transfers
+      .send((transfer.clone(), state.clone()))
+      .unwrap();
+    Ok(Pending { transfer, state })
+  }
+
+  // Data received, or the part of the buffer that was sent.
+  fn poll(&self, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>>> {
+    let mut state = self.state.lock().unwrap();
+    match state.waited.take() {
+      Some(waited) => Poll::Ready(waited.and_then(|_| self.transfer.take())),
+      None => {
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+impl Drop for Pending {
+  fn drop(&mut self) {
+    // The waiter moves on to the next transfer once this one completed.
+    self.transfer.cancel();
+  }
+}
+
+/// Reads an IN endpoint as an async byte stream.
+///
+/// Same as `EndpointReader`: a zero length packet reads as end of file
+/// once, later reads continue with the next transfer.
+pub struct AsyncEndpointReader {
+  device: Arc<Mutex<UsbDevice>>,
+  endpoint_number: u8,
+  packet_size: usize,
+  transfer_size: usize,
+  buffer: Vec<u8>,
+  position: usize,
+  pending: Option<Pending>,
+  waiter: Waiter,
+}
+
+impl AsyncEndpointReader {
+  /// Reads IN endpoint `endpoint_number` of the selected configuration.
+  pub fn new(
+    device: Arc<Mutex<UsbDevice>>,
+    endpoint_number: u8,
+  ) -> Result<Self> {
+    let packet_size =
+      packet_size(&device.lock().unwrap(), endpoint_number, Direction::In)?;
+    Ok(AsyncEndpointReader {
+      device,
+      endpoint_number,
+      packet_size,
+      transfer_size: TRANSFER_PACKETS * packet_size,
+      buffer: vec![],
+      position: 0,
+      pending: None,
+      waiter: Waiter::new(),
+    })
+  }
+
+  /// Sets the size of the transfers, rounded up to a multiple of the
+  /// packet size. Defaults to 32 packets.
+  pub fn with_transfer_size(mut self, size: usize) -> Self {
+    self.transfer_size = transfer_size(size, self.packet_size);
+    self
+  }
+
+  pub fn packet_size(&self) -> usize {
+    self.packet_size
+  }
+
+  pub fn transfer_size(&self) -> usize {
+    self.transfer_size
+  }
+
+  fn fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+    while self.position == self.buffer.len() {
+      let pending = match self.pending.take() {
+        Some(pending) => pending,
+        None => Pending::submit(
+          &self.device,
+          &self.waiter,
+          Direction::In,
+          self.endpoint_number,
+          vec![0; self.transfer_size],
+        )?,
+      };
+      let result = match pending.poll(cx) {
+        Poll::Ready(result) => result,
+        Poll::Pending => {
+          self.pending = Some(pending);
+          return Poll::Pending;
+        }
+      };
+      match result {
+        Err(Error::Io(ErrorKind::TimedOut)) => continue,
+        result => {
+          // Empty after a zero length packet.
+          self.buffer = result?;
+          self.position = 0;
+          break;
+        }
+      }
+    }
+    Poll::Ready(Ok(&self.buffer[self.position..]))
+  }
+
+  fn consume(&mut self, amount: usize) {
+    self.position = (self.position + amount).min(self.buffer.len());
+  }
+
+  fn read(
+    &mut self,
+    cx: &mut Context<'_>,
+    buf: &mut [u8],
+  ) -> Poll<io::Result<usize>> {
+    let available = ready!(self.fill_buf(cx))?;
+    let length = available.len().min(buf.len());
+    buf[..length].copy_from_slice(&available[..length]);
+    self.consume(length);
+    Poll::Ready(Ok(length))
+  }
+}
+
+/// Writes an OUT endpoint as an async byte stream.
+///
+/// Same as `EndpointWriter`, except that dropping the writer discards
+/// buffered data and cancels the transfer in progress. Flush or shut it
+/// down first.
+pub struct AsyncEndpointWriter {
+  device: Arc<Mutex<UsbDevice>>,
+  endpoint_number: u8,
+  packet_size: usize,
+  transfer_size: usize,
+  buffer: Vec<u8>,
+  zero_length_packets: bool,
+  // The last transfer ended with a full packet.
+  unterminated: bool,
+  // Transfer in progress and its length, from the start of the buffer.
+  pending: Option<(Pending, usize)>,
+  waiter: Waiter,
+}
+
+impl AsyncEndpointWriter {
+  /// Writes OUT endpoint `endpoint_number` of the selected configuration.
+  pub fn new(
+    device: Arc<Mutex<UsbDevice>>,
+    endpoint_number: u8,
+  ) -> Result<Self> {
+    let packet_size =
+      packet_size(&device.lock().unwrap(), endpoint_number, Direction::Out)?;
+    Ok(AsyncEndpointWriter {
+      device,
+      endpoint_number,
+      packet_size,
+      transfer_size: TRANSFER_PACKETS * packet_size,
+      buffer: vec![],
+      zero_length_packets: true,
+      unterminated: false,
+      pending: None,
+      waiter: Waiter::new(),
+    })
+  }
+
+  /// Sets the size of the transfers, rounded up to a multiple of the
+  /// packet size. Defaults to 32 packets.
+  pub fn with_transfer_size(mut self, size: usize) -> Self {
+    self.transfer_size = transfer_size(size, self.packet_size);
+    self
+  }
+
+  /// If false, `flush` does not send zero length packets. For devices
+  /// that know the length of the data in advance.
+  pub fn with_zero_length_packets(mut self, enabled: bool) -> Self {
+    self.zero_length_packets = enabled;
+    self
+  }
+
+  pub fn packet_size(&self) -> usize {
+    self.packet_size
+  }
+
+  pub fn transfer_size(&self) -> usize {
+    self.transfer_size
+  }
+
+  // Sends the first `length` bytes of the buffer in a single transfer,
+  // unless one is already in progress.
+  fn transfer(
+    &mut self,
+    cx: &mut Context<'_>,
+    length: usize,
+  ) -> Poll<io::Result<()>> {
+    let mut length = length;
+    let result = loop {
+      let pending = match self.pending.take() {
+        Some((pending, pending_length)) => {
+          length = pending_length;
+          pending
+        }
+        None => Pending::submit(
+          &self.device,
+          &self.waiter,
+          Direction::Out,
+          self.endpoint_number,
+          self.buffer[..length].to_vec(),
+        )?,
+      };
+      match pending.poll(cx) {
+        // Nothing was written, send the same data again.
+        Poll::Ready(Err(Error::Io(ErrorKind::TimedOut))) => continue,
+        Poll::Ready(result) => break result,
+        Poll::Pending => {
+          self.pending = Some((pending, length));
+          return Poll::Pending;
+        }
+      }
+    };
+    let written = result?.len();
+    if written == 0 && length > 0 {
+      return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+    }
+    self.buffer.drain(..written);
+    self.unterminated = written > 0 && written % self.packet_size == 0;
+    Poll::Ready(Ok(()))
+  }
+
+  // Sends the full packets in the buffer.
+  fn send_packets(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    if self.pending.is_some() {
+      ready!(self.transfer(cx, 0))?;
+    }
+    loop {
+      let length = self.buffer.len() - self.buffer.len() % self.packet_size;
+      if length == 0 {
+        return Poll::Ready(Ok(()));
+      }
+      ready!(self.transfer(cx, length.min(self.transfer_size)))?;
+    }
+  }
+
+  fn write(
+    &mut self,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    if self.pending.is_some()
+      || self.buffer.len() + buf.len() > self.transfer_size
+    {
+      ready!(self.send_packets(cx))?;
+    }
+    let length = buf.len().min(self.transfer_size - self.buffer.len());
+    self.buffer.extend_from_slice(&buf[..length]);
+    Poll::Ready(Ok(length))
+  }
+
+  fn flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    while self.pending.is_some() || !self.buffer.is_empty() {
+      let length = self.buffer.len().min(self.transfer_size);
+      ready!(self.transfer(cx, length))?;
+    }
+    if self.unterminated && self.zero_length_packets {
+      ready!(self.transfer(cx, 0))?;
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// An IN and an OUT endpoint as a single async byte stream, e.g. for
+/// `tokio_util::codec::Framed`.
+pub struct AsyncEndpointPipe {
+  reader: AsyncEndpointReader,
+  writer: AsyncEndpointWriter,
+}
+
+impl AsyncEndpointPipe {
+  /// Reads IN endpoint `in_endpoint` and writes OUT endpoint
+  /// `out_endpoint` of the selected configuration.
+  pub fn new(
+    device: Arc<Mutex<UsbDevice>>,
+    in_endpoint: u8,
+    out_endpoint: u8,
+  ) -> Result<Self> {
+    Ok(Self::from_parts(
+      AsyncEndpointReader::new(device.clone(), in_endpoint)?,
+      AsyncEndpointWriter::new(device, out_endpoint)?,
+    ))
+  }
+
+  pub fn from_parts(
+    reader: AsyncEndpointReader,
+    writer: AsyncEndpointWriter,
+  ) -> Self {
+    AsyncEndpointPipe { reader, writer }
+  }
+
+  pub fn into_parts(self) -> (AsyncEndpointReader, AsyncEndpointWriter) {
+    (self.reader, self.writer)
+  }
+
+  /// Frames the pipe with `codec`.
+  #[cfg(feature = "codec")]
+  pub fn framed<C>(self, codec: C) -> tokio_util::codec::Framed<Self, C> {
+    tokio_util::codec::Framed::new(self, codec)
+  }
+}
+
+#[cfg(feature = "tokio")]
+mod tokio_io {
+  use tokio::io::AsyncBufRead;
+  use tokio::io::AsyncRead;
+  use tokio::io::AsyncWrite;
+  use tokio::io::ReadBuf;
+
+  use super::*;
+
+  impl AsyncRead for AsyncEndpointReader {
+    fn poll_read(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+      let length = ready!(self.get_mut().read(cx, buf.initialize_unfilled()))?;
+      buf.advance(length);
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  impl AsyncBufRead for AsyncEndpointReader {
+    fn poll_fill_buf(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<&[u8]>> {
+      self.get_mut().fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+      self.get_mut().consume(amount)
+    }
+  }
+
+  impl AsyncWrite for AsyncEndpointWriter {
+    fn poll_write(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+      self.get_mut().write(cx, buf)
+    }
+
+    fn poll_flush(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().flush(cx)
+    }
+
+    fn poll_shutdown(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().flush(cx)
+    }
+  }
+
+  impl AsyncRead for AsyncEndpointPipe {
+    fn poll_read(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+      Pin::new(&mut self.get_mut().reader).poll_read(cx, buf)
+    }
+  }
+
+  impl AsyncWrite for AsyncEndpointPipe {
+    fn poll_write(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+      self.get_mut().writer.write(cx, buf)
+    }
+
+    fn poll_flush(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().writer.flush(cx)
+    }
+
+    fn poll_shutdown(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().writer.flush(cx)
+    }
+  }
+}
+
+#[cfg(feature = "futures-io")]
+mod futures_io_impls {
+  use futures_io::AsyncBufRead;
+  use futures_io::AsyncRead;
+  use futures_io::AsyncWrite;
+
+  use super::*;
+
+  impl AsyncRead for AsyncEndpointReader {
+    fn poll_read(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+      self.get_mut().read(cx, buf)
+    }
+  }
+
+  impl AsyncBufRead for AsyncEndpointReader {
+    fn poll_fill_buf(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<&[u8]>> {
+      self.get_mut().fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amount: usize) {
+      self.get_mut().consume(amount)
+    }
+  }
+
+  impl AsyncWrite for AsyncEndpointWriter {
+    fn poll_write(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+      self.get_mut().write(cx, buf)
+    }
+
+    fn poll_flush(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().flush(cx)
+    }
+
+    fn poll_close(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().flush(cx)
+    }
+  }
+
+  impl AsyncRead for AsyncEndpointPipe {
+    fn poll_read(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+      self.get_mut().reader.read(cx, buf)
+    }
+  }
+
+  impl AsyncWrite for AsyncEndpointPipe {
+    fn poll_write(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+      self.get_mut().writer.write(cx, buf)
+    }
+
+    fn poll_flush(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().writer.flush(cx)
+    }
+
+    fn poll_close(
+      self: Pin<&mut Self>,
+      cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+      self.get_mut().writer.flush(cx)
+    }
+  }
+}
+
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::loopback::Loopback;
+  use crate::loopback::Transfers;
+
+  fn loopback() -> (Arc<Mutex<UsbDevice>>, Transfers) {
+    let (mut device, transfers) = Loopback::device_with_transfers();
+    device.open().unwrap();
+    device.claim_interface(0).unwrap();
+    device.set_transfer_timeout(Some(Duration::from_millis(10)));
+    (Arc::new(Mutex::new(device)), transfers)
+  }
+
+  #[cfg(feature = "tokio")]
+  #[tokio::test]
+  async fn test_tokio() {
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    let (device, transfers) = loopback();
+    let mut writer = AsyncEndpointWriter::new(device.clone(), 2).unwrap();
+    writer.write_all(&[1; 64]).await.unwrap();
+    writer.write_all(b"\nsecond\n").await.unwrap();
+    writer.shutdown().await.unwrap();
+    assert_eq!(*transfers.lock().unwrap(), vec![72]);
+
+    let mut reader = AsyncEndpointReader::new(device, 1).unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut String::new()).await.unwrap();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "second\n");
+  }
+
+  // A reader waiting for data does not keep the writer from sending it.
+  #[cfg(feature = "tokio")]
+  #[tokio::test]
+  async fn test_full_duplex() {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    let (device, _) = loopback();
+    device.lock().unwrap().set_transfer_timeout(None);
+    let mut reader = AsyncEndpointReader::new(device.clone(), 1).unwrap();
+    let mut writer = AsyncEndpointWriter::new(device, 2).unwrap();
+    let mut received = [0; 5];
+    let (read, written) =
+      tokio::join!(reader.read_exact(&mut received), async {
+        writer.write_all(b"hello").await?;
+        writer.shutdown().await
+      });
+    read.unwrap();
+    written.unwrap();
+    assert_eq!(&received, b"hello");
+  }
+
+  #[cfg(feature = "tokio")]
+  #[tokio::test]
+  async fn test_drop_pending_read() {
+    use futures_util::FutureExt;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+
+    let (device, _) = loopback();
+    device.lock().unwrap().set_transfer_timeout(None);
+    let mut reader = AsyncEndpointReader::new(device.clone(), 1).unwrap();
+    assert!(reader.read_exact(&mut [0; 5]).now_or_never().is_none());
+    // Cancels the read, which neither holds the device nor takes the data.
+    drop(reader);
+
+    let mut writer = AsyncEndpointWriter::new(device.clone(), 2).unwrap();
+    writer.write_all(b"hello").await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut reader = AsyncEndpointReader::new(device, 1).unwrap();
+    let mut received = [0; 5];
+    reader.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"hello");
+  }
+
+  #[cfg(feature = "futures-io")]
+  #[tokio::test]
+  async fn test_futures_io() {
+    use futures_util::AsyncReadExt;
+    use futures_util::AsyncWriteExt;
+
+    let (device, transfers) = loopback();
+    let data = (0..5000).map(|i| i as u8).collect::<Vec<_>>();
+    let mut pipe = AsyncEndpointPipe::from_parts(
+      AsyncEndpointReader::new(device.clone(), 1).unwrap(),
+      AsyncEndpointWriter::new(device, 2)
+        .unwrap()
+        .with_transfer_size(1024),
+    );
+    pipe.write_all(&data).await.unwrap();
+    pipe.write_all(&[0; 56]).await.unwrap();
+    pipe.close().await.unwrap();
+    assert_eq!(
+      *transfers.lock().unwrap(),
+      vec![1024, 1024, 1024, 1024, 960, 0]
+    );
+
+    let mut received = vec![0; data.len()];
+    pipe.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, data);
+  }
+
+  #[cfg(feature = "codec")]
+  #[tokio::test]
+  async fn test_codec() {
+    use futures_util::SinkExt;
+    use futures_util::StreamExt;
+    use tokio_util::codec::LinesCodec;
+
+    let (device, _) = loopback();
+    let pipe = AsyncEndpointPipe::new(device, 1, 2).unwrap();
+    let mut lines = pipe.framed(LinesCodec::new());
+    lines.send("first").await.unwrap();
+    lines.send("second").await.unwrap();
+    assert_eq!(lines.next().await.unwrap().unwrap(), "first");
+    assert_eq!(lines.next().await.unwrap().unwrap(), "second");
+  }
+}
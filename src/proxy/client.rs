@@ -0,0 +1,275 @@
+//! Proxy client. Drives the devices of a proxy daemon as ordinary
+//! `UsbDevice`s.
+//!
+//! ```no_run
+//! # fn main() -> webusb::Result<()> {
+//! use webusb::proxy::client::ProxyHost;
+//! use webusb::proxy::ProxyAddress;
+//! use webusb::Context;
+//!
+//! let mut ctx = Context::init()?;
+//! ctx.add_proxy_host(ProxyHost::new(ProxyAddress::unix(
+//!   "/run/webusb-proxyd.sock",
+//! )));
+//!
+//! // Local devices followed by the devices of the daemon.
+//! let devices = ctx.devices()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use core::convert::TryFrom;
+use std::time::Duration;
+
+use crate::backend::DeviceBackend;
+use crate::enumeration::read_device;
+use crate::enumeration::Topology;
+use crate::proxy::read_reply;
+use crate::proxy::ProxiedDevice;
+use crate::proxy::ProxyAddress;
+use crate::proxy::Request;
+use crate::proxy::Stream;
+use crate::Error;
+use crate::Result;
+use crate::UsbDevice;
+use crate::UsbEndpointType;
+
+/// A proxy daemon.
+#[derive(Clone, Debug)]
+pub struct ProxyHost {
+  address: ProxyAddress,
+}
+
+impl ProxyHost {
+  pub fn new(address: ProxyAddress) -> Self {
+    ProxyHost { address }
+  }
+
+  /// Lists the devices of the daemon (OP_LIST).
+  pub fn devices(&self) -> Result<Vec<ProxiedDevice>> {
+    let mut stream = self.address.connect()?;
+    Request::List.write_to(&mut stream)?;
+    let reply = read_reply(&mut stream)?;
+
+    let mut reply = reply.as_slice();
+    let mut num_devices = [0; 4];
+    std::io::Read::read_exact(&mut reply, &mut num_devices)?;
+    let mut devices = vec![];
+    for _ in 0..u32::from_be_bytes(num_devices) {
+      devices.push(ProxiedDevice::read_from(&mut reply)?);
+    }
+    Ok(devices)
+  }
+
+  /// Reads the descriptors of `device` through the daemon.
+  ///
+  /// Every opened `UsbDevice` has its own connection to the daemon, and
+  /// the interfaces it claimed are released when the connection is closed.
+  pub fn connect(&self, device: &ProxiedDevice) -> Result<UsbDevice> {
+    let topology = Topology {
+      bus_number: device.bus_number,
+      device_address: device.device_address,
+      port_numbers: device.port_numbers.clone(),
+      speed: device.speed,
+      // The hub on the daemon's side is not visible to the client.
+      parent_hub: None,
+    };
+    let backend = Proxy {
+      address: self.address.clone(),
+      device: device.id,
+      stream: None,
+      timeout: None,
+    };
+    read_device(Box::new(backend), topology)
+  }
+}
+
+/// Backend of a proxied device. Connects to the daemon when the device is
+/// opened and disconnects when it is closed.
+struct Proxy {
+  address: ProxyAddress,
+  device: u32,
+  stream: Option<Box<dyn Stream>>,
+  timeout: Option<Duration>,
+}
+
+impl Proxy {
+  fn call(&mut self, request: Request) -> Result<Vec<u8>> {
+    let stream = self.stream.as_mut().ok_or(Error::InvalidState)?;
+    request.write_to(stream)?;
+    read_reply(stream)
+  }
+
+  // Length returned by OP_CONTROL_OUT and OP_TRANSFER_OUT.
+  fn call_out(&mut self, request: Request) -> Result<usize> {
+    let reply = self.call(request)?;
+    let length = <[u8; 4]>::try_from(reply.as_slice())
+      .map_err(|_| Error::Io(std::io::ErrorKind::InvalidData))?;
+    Ok(u32::from_be_bytes(length) as usize)
+  }
+
+  // In milliseconds, 0 waits forever.
+  fn timeout(&self) -> u32 {
+    match self.timeout {
+      Some(timeout) => timeout.as_millis().clamp(1, u32::MAX as u128) as u32,
+      None => 0,
+    }
+  }
+}
+
+impl DeviceBackend for Proxy {
+  fn open(&mut self) -> Result<()> {
+    if self.stream.is_none() {
+      self.stream = Some(self.address.connect()?);
+    }
+    let device = self.device;
+    if let Err(err) = self.call(Request::Open { device }) {
+      self.stream = None;
+      return Err(err);
+    }
+    Ok(())
+  }
+
+  fn close(&mut self) -> Result<()> {
+    let device = self.device;
+    let result = self.call(Request::Close { device });
+    // The daemon cleans up after closed connections anyway.
+    self.stream = None;
+    result.map(|_| ())
+  }
+
+  fn set_configuration(&mut self, configuration_value: u8) -> Result<()> {
+    let device = self.device;
+    self.call(Request::SetConfiguration {
+      device,
+      configuration_value,
+    })?;
+    Ok(())
+  }
+
+  fn claim_interface(&mut self, interface_number: u8) -> Result<()> {
+    let device = self.device;
+    self.call(Request::ClaimInterface {
+      device,
+      interface_number,
+    })?;
+    Ok(())
+  }
+
+  fn release_interface(&mut self, interface_number: u8) -> Result<()> {
+    let device = self.device;
+    self.call(Request::ReleaseInterface {
+      device,
+      interface_number,
+    })?;
+    Ok(())
+  }
+
+  fn set_alternate_setting(
+    &mut self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Result<()> {
+    let device = self.device;
+    self.call(Request::SetAlternateSetting {
+      device,
+      interface_number,
+      alternate_setting,
+    })?;
+    Ok(())
+  }
+
+  fn control_in(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    let device = self.device;
+    let data = self.call(Request::ControlIn {
+      device,
+      request_type,
+      request,
+      value,
+      index,
+      length: buffer.len().min(u16::MAX as usize) as u16,
+    })?;
+    let length = data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&data[..length]);
+    Ok(length)
+  }
+
+  fn control_out(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+  ) -> Result<usize> {
+    let device = self.device;
+    self.call_out(Request::ControlOut {
+      device,
+      request_type,
+      request,
+      value,
+      index,
+      data: data.to_vec(),
+    })
+  }
+
+  fn clear_halt(&mut self, endpoint: u8) -> Result<()> {
+    let device = self.device;
+    self.call(Request::ClearHalt { device, endpoint })?;
+    Ok(())
+  }
+
+  fn transfer_in(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    let device = self.device;
+    let timeout = self.timeout();
+    let data = self.call(Request::TransferIn {
+      device,
+      r#type,
+      endpoint,
+      length: buffer.len() as u32,
+      timeout,
+    })?;
+    let length = data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&data[..length]);
+    Ok(length)
+  }
+
+  fn transfer_out(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    data: &[u8],
+  ) -> Result<usize> {
+    let device = self.device;
+    let timeout = self.timeout();
+    self.call_out(Request::TransferOut {
+      device,
+      r#type,
+      endpoint,
+      timeout,
+      data: data.to_vec(),
+    })
+  }
+
+  fn reset(&mut self) -> Result<()> {
+    let device = self.device;
+    self.call(Request::Reset { device })?;
+    Ok(())
+  }
+
+  fn set_transfer_timeout(&mut self, timeout: Option<Duration>) {
+    self.timeout = timeout;
+  }
+}
@@ -0,0 +1,675 @@
+//! Device proxy. A daemon (`server::ProxyServer`, `webusb-proxyd`) owns the
+//! local devices and shares them between processes, which reach them
+//! through `client::ProxyHost` as ordinary `UsbDevice`s.
+//!
+//! Devices are shared per interface: any number of clients may open a
+//! device, but an interface belongs to the client that claimed it until it
+//! is released or the client disconnects. Claiming an interface that is
+//! claimed by another client, and using its endpoints, fails with
+//! `Error::InvalidState`.
+//!
+//! Every message is a big-endian u32 length followed by the body. A request
+//! is an opcode, the ID of the device and the arguments of the
+//! `DeviceBackend` method of the same name. A reply is a status byte
+//! (`STATUS_OK` or the encoded `Error`) followed by the returned data.
+
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use crate::Error;
+use crate::Result;
+use crate::UsbEndpointType;
+use crate::UsbSpeed;
+
+pub mod client;
+pub mod server;
+
+pub const PROXY_PORT: u16 = 3250;
+
+pub const OP_LIST: u8 = 0x01;
+pub const OP_OPEN: u8 = 0x02;
+pub const OP_CLOSE: u8 = 0x03;
+pub const OP_SET_CONFIGURATION: u8 = 0x04;
+pub const OP_CLAIM_INTERFACE: u8 = 0x05;
+pub const OP_RELEASE_INTERFACE: u8 = 0x06;
+pub const OP_SET_ALTERNATE_SETTING: u8 = 0x07;
+pub const OP_CONTROL_IN: u8 = 0x08;
+pub const OP_CONTROL_OUT: u8 = 0x09;
+pub const OP_CLEAR_HALT: u8 = 0x0A;
+pub const OP_TRANSFER_IN: u8 = 0x0B;
+pub const OP_TRANSFER_OUT: u8 = 0x0C;
+pub const OP_RESET: u8 = 0x0D;
+
+pub const STATUS_OK: u8 = 0;
+const STATUS_NOT_FOUND: u8 = 1;
+const STATUS_INVALID_STATE: u8 = 2;
+const STATUS_INVALID_ACCESS: u8 = 3;
+const STATUS_STALL: u8 = 4;
+// Followed by the `std::io::ErrorKind`, see `io_error_kind`.
+const STATUS_IO: u8 = 5;
+
+// Larger messages are treated as garbage.
+pub(crate) const MAX_MESSAGE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Where a proxy daemon listens.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProxyAddress {
+  Tcp(SocketAddr),
+  #[cfg(unix)]
+  Unix(PathBuf),
+}
+
+impl ProxyAddress {
+  pub fn tcp(addr: impl ToSocketAddrs) -> Result<Self> {
+    let addr = addr
+      .to_socket_addrs()?
+      .next()
+      .ok_or(Error::Io(std::io::ErrorKind::AddrNotAvailable))?;
+    Ok(ProxyAddress::Tcp(addr))
+  }
+
+  #[cfg(unix)]
+  pub fn unix(path: impl Into<PathBuf>) -> Self {
+    ProxyAddress::Unix(path.into())
+  }
+
+  pub(crate) fn connect(&self) -> Result<Box<dyn Stream>> {
+    match self {
+      ProxyAddress::Tcp(addr) => {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Box::new(stream))
+      }
+      #[cfg(unix)]
+      ProxyAddress::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+    }
+  }
+}
+
+/// Connection to or from a proxy daemon.
+pub(crate) trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Device record of the reply to OP_LIST. The descriptors are read by the
+/// client through OP_CONTROL_IN.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxiedDevice {
+  /// Identifies the device in requests, stable for the lifetime of the
+  /// daemon.
+  pub id: u32,
+  pub bus_number: u8,
+  pub device_address: u8,
+  pub port_numbers: Vec<u8>,
+  pub speed: UsbSpeed,
+}
+
+impl ProxiedDevice {
+  pub fn read_from(r: &mut impl Read) -> std::io::Result<Self> {
+    let id = read_u32(r)?;
+    let bus_number = read_u8(r)?;
+    let device_address = read_u8(r)?;
+    let speed = speed_from_u8(read_u8(r)?);
+    let mut port_numbers = vec![0; read_u8(r)? as usize];
+    r.read_exact(&mut port_numbers)?;
+    Ok(ProxiedDevice {
+      id,
+      bus_number,
+      device_address,
+      port_numbers,
+      speed,
+    })
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    w.write_all(&self.id.to_be_bytes())?;
+    w.write_all(&[
+      self.bus_number,
+      self.device_address,
+      speed_to_u8(self.speed),
+      self.port_numbers.len() as u8,
+    ])?;
+    w.write_all(&self.port_numbers)
+  }
+}
+
+/// A request to the daemon. `device` is a `ProxiedDevice::id`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+  List,
+  Open {
+    device: u32,
+  },
+  Close {
+    device: u32,
+  },
+  SetConfiguration {
+    device: u32,
+    configuration_value: u8,
+  },
+  ClaimInterface {
+    device: u32,
+    interface_number: u8,
+  },
+  ReleaseInterface {
+    device: u32,
+    interface_number: u8,
+  },
+  SetAlternateSetting {
+    device: u32,
+    interface_number: u8,
+    alternate_setting: u8,
+  },
+  ControlIn {
+    device: u32,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: u16,
+  },
+  ControlOut {
+    device: u32,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: Vec<u8>,
+  },
+  ClearHalt {
+    device: u32,
+    endpoint: u8,
+  },
+  /// `timeout` is in milliseconds, 0 waits forever.
+  TransferIn {
+    device: u32,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    length: u32,
+    timeout: u32,
+  },
+  /// `timeout` is in milliseconds, 0 waits forever.
+  TransferOut {
+    device: u32,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    timeout: u32,
+    data: Vec<u8>,
+  },
+  Reset {
+    device: u32,
+  },
+}
+
+impl Request {
+  pub fn read_from(r: &mut impl Read) -> std::io::Result<Self> {
+    let body = read_message(r)?;
+    let mut r = body.as_slice();
+    let r = &mut r;
+
+    let op = read_u8(r)?;
+    if op == OP_LIST {
+      return Ok(Request::List);
+    }
+    let device = read_u32(r)?;
+    let request = match op {
+      OP_OPEN => Request::Open { device },
+      OP_CLOSE => Request::Close { device },
+      OP_SET_CONFIGURATION => Request::SetConfiguration {
+        device,
+        configuration_value: read_u8(r)?,
+      },
+      OP_CLAIM_INTERFACE => Request::ClaimInterface {
+        device,
+        interface_number: read_u8(r)?,
+      },
+      OP_RELEASE_INTERFACE => Request::ReleaseInterface {
+        device,
+        interface_number: read_u8(r)?,
+      },
+      OP_SET_ALTERNATE_SETTING => Request::SetAlternateSetting {
+        device,
+        interface_number: read_u8(r)?,
+        alternate_setting: read_u8(r)?,
+      },
+      OP_CONTROL_IN => Request::ControlIn {
+        device,
+        request_type: read_u8(r)?,
+        request: read_u8(r)?,
+        value: read_u16(r)?,
+        index: read_u16(r)?,
+        length: read_u16(r)?,
+      },
+      OP_CONTROL_OUT => Request::ControlOut {
+        device,
+        request_type: read_u8(r)?,
+        request: read_u8(r)?,
+        value: read_u16(r)?,
+        index: read_u16(r)?,
+        data: r.to_vec(),
+      },
+      OP_CLEAR_HALT => Request::ClearHalt {
+        device,
+        endpoint: read_u8(r)?,
+      },
+      OP_TRANSFER_IN => Request::TransferIn {
+        device,
+        r#type: endpoint_type_from_u8(read_u8(r)?)?,
+        endpoint: read_u8(r)?,
+        length: read_u32(r)?,
+        timeout: read_u32(r)?,
+      },
+      OP_TRANSFER_OUT => Request::TransferOut {
+        device,
+        r#type: endpoint_type_from_u8(read_u8(r)?)?,
+        endpoint: read_u8(r)?,
+        timeout: read_u32(r)?,
+        data: r.to_vec(),
+      },
+      OP_RESET => Request::Reset { device },
+      _ => return Err(std::io::ErrorKind::InvalidData.into()),
+    };
+    Ok(request)
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    let mut body = vec![];
+    match self {
+      Request::List => body.push(OP_LIST),
+      Request::Open { device } => {
+        body.push(OP_OPEN);
+        body.extend_from_slice(&device.to_be_bytes());
+      }
+      Request::Close { device } => {
+        body.push(OP_CLOSE);
+        body.extend_from_slice(&device.to_be_bytes());
+      }
+      Request::SetConfiguration {
+        device,
+        configuration_value,
+      } => {
+        body.push(OP_SET_CONFIGURATION);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.push(*configuration_value);
+      }
+      Request::ClaimInterface {
+        device,
+        interface_number,
+      } => {
+        body.push(OP_CLAIM_INTERFACE);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.push(*interface_number);
+      }
+      Request::ReleaseInterface {
+        device,
+        interface_number,
+      } => {
+        body.push(OP_RELEASE_INTERFACE);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.push(*interface_number);
+      }
+      Request::SetAlternateSetting {
+        device,
+        interface_number,
+        alternate_setting,
+      } => {
+        body.push(OP_SET_ALTERNATE_SETTING);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.extend_from_slice(&[*interface_number, *alternate_setting]);
+      }
+      Request::ControlIn {
+        device,
+        request_type,
+        request,
+        value,
+        index,
+        length,
+      } => {
+        body.push(OP_CONTROL_IN);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.extend_from_slice(&[*request_type, *request]);
+        body.extend_from_slice(&value.to_be_bytes());
+        body.extend_from_slice(&index.to_be_bytes());
+        body.extend_from_slice(&length.to_be_bytes());
+      }
+      Request::ControlOut {
+        device,
+        request_type,
+        request,
+        value,
+        index,
+        data,
+      } => {
+        body.push(OP_CONTROL_OUT);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.extend_from_slice(&[*request_type, *request]);
+        body.extend_from_slice(&value.to_be_bytes());
+        body.extend_from_slice(&index.to_be_bytes());
+        body.extend_from_slice(data);
+      }
+      Request::ClearHalt { device, endpoint } => {
+        body.push(OP_CLEAR_HALT);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.push(*endpoint);
+      }
+      Request::TransferIn {
+        device,
+        r#type,
+        endpoint,
+        length,
+        timeout,
+      } => {
+        body.push(OP_TRANSFER_IN);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.extend_from_slice(&[endpoint_type_to_u8(*r#type), *endpoint]);
+        body.extend_from_slice(&length.to_be_bytes());
+        body.extend_from_slice(&timeout.to_be_bytes());
+      }
+      Request::TransferOut {
+        device,
+        r#type,
+        endpoint,
+        timeout,
+        data,
+      } => {
+        body.push(OP_TRANSFER_OUT);
+        body.extend_from_slice(&device.to_be_bytes());
+        body.extend_from_slice(&[endpoint_type_to_u8(*r#type), *endpoint]);
+        body.extend_from_slice(&timeout.to_be_bytes());
+        body.extend_from_slice(data);
+      }
+      Request::Reset { device } => {
+        body.push(OP_RESET);
+        body.extend_from_slice(&device.to_be_bytes());
+      }
+    }
+    write_message(w, &body)
+  }
+}
+
+/// Reads a reply. Errors reported by the daemon are returned as `Err`,
+/// like errors of the connection.
+pub fn read_reply(r: &mut impl Read) -> Result<Vec<u8>> {
+  let mut body = read_message(r)?;
+  if body.is_empty() {
+    return Err(Error::Io(std::io::ErrorKind::InvalidData));
+  }
+  let err = match body[0] {
+    STATUS_OK => {
+      body.remove(0);
+      return Ok(body);
+    }
+    STATUS_NOT_FOUND => Error::NotFound,
+    STATUS_INVALID_STATE => Error::InvalidState,
+    STATUS_INVALID_ACCESS => Error::InvalidAccess,
+    STATUS_STALL => Error::Stall,
+    STATUS_IO => Error::Io(io_error_kind(body.get(1).copied().unwrap_or(0))),
+    _ => Error::Io(std::io::ErrorKind::InvalidData),
+  };
+  Err(err)
+}
+
+pub fn write_reply(
+  w: &mut impl Write,
+  reply: &Result<Vec<u8>>,
+) -> std::io::Result<()> {
+  let body = match reply {
+    Ok(data) => {
+      let mut body = vec![STATUS_OK];
+      body.extend_from_slice(data);
+      body
+    }
+    Err(err) => status_from_error(err),
+  };
+  write_message(w, &body)
+}
+
+fn status_from_error(err: &Error) -> Vec<u8> {
+  match err {
+    Error::NotFound => vec![STATUS_NOT_FOUND],
+    Error::InvalidState => vec![STATUS_INVALID_STATE],
+    Error::InvalidAccess => vec![STATUS_INVALID_ACCESS],
+    Error::Stall => vec![STATUS_STALL],
+    Error::Io(kind) => vec![STATUS_IO, io_error_code(*kind)],
+    // Clients may be built without libusb.
+    #[cfg(feature = "libusb")]
+    Error::Usb(err) => match err {
+      rusb::Error::NoDevice | rusb::Error::NotFound => vec![STATUS_NOT_FOUND],
+      rusb::Error::Busy => vec![STATUS_INVALID_STATE],
+      rusb::Error::Access => {
+        vec![
+          STATUS_IO,
+          io_error_code(std::io::ErrorKind::PermissionDenied),
+        ]
+      }
+      rusb::Error::Timeout => {
+        vec![STATUS_IO, io_error_code(std::io::ErrorKind::TimedOut)]
+      }
+      rusb::Error::Interrupted => {
+        vec![STATUS_IO, io_error_code(std::io::ErrorKind::Interrupted)]
+      }
+      rusb::Error::InvalidParam => {
+        vec![STATUS_IO, io_error_code(std::io::ErrorKind::InvalidInput)]
+      }
+      _ => vec![STATUS_IO, io_error_code(std::io::ErrorKind::Other)],
+    },
+  }
+}
+
+// The kinds a backend reports. Everything else is sent as `Other`.
+const IO_ERROR_KINDS: &[std::io::ErrorKind] = &[
+  std::io::ErrorKind::Other,
+  std::io::ErrorKind::TimedOut,
+  std::io::ErrorKind::Interrupted,
+  std::io::ErrorKind::InvalidInput,
+  std::io::ErrorKind::InvalidData,
+  std::io::ErrorKind::PermissionDenied,
+  std::io::ErrorKind::BrokenPipe,
+  std::io::ErrorKind::UnexpectedEof,
+  std::io::ErrorKind::ConnectionReset,
+];
+
+fn io_error_code(kind: std::io::ErrorKind) -> u8 {
+  IO_ERROR_KINDS.iter().position(|k| *k == kind).unwrap_or(0) as u8
+}
+
+fn io_error_kind(code: u8) -> std::io::ErrorKind {
+  IO_ERROR_KINDS
+    .get(code as usize)
+    .copied()
+    .unwrap_or(std::io::ErrorKind::Other)
+}
+
+fn speed_to_u8(speed: UsbSpeed) -> u8 {
+  match speed {
+    UsbSpeed::Unknown => 0,
+    UsbSpeed::Low => 1,
+    UsbSpeed::Full => 2,
+    UsbSpeed::High => 3,
+    UsbSpeed::Super => 4,
+    UsbSpeed::SuperPlus => 5,
+  }
+}
+
+fn speed_from_u8(speed: u8) -> UsbSpeed {
+  match speed {
+    1 => UsbSpeed::Low,
+    2 => UsbSpeed::Full,
+    3 => UsbSpeed::High,
+    4 => UsbSpeed::Super,
+    5 => UsbSpeed::SuperPlus,
+    _ => UsbSpeed::Unknown,
+  }
+}
+
+// Transfer type bits of bmAttributes.
+fn endpoint_type_to_u8(r#type: UsbEndpointType) -> u8 {
+  match r#type {
+    UsbEndpointType::Control => 0,
+    UsbEndpointType::Isochronous => 1,
+    UsbEndpointType::Bulk => 2,
+    UsbEndpointType::Interrupt => 3,
+  }
+}
+
+fn endpoint_type_from_u8(r#type: u8) -> std::io::Result<UsbEndpointType> {
+  match r#type {
+    0 => Ok(UsbEndpointType::Control),
+    1 => Ok(UsbEndpointType::Isochronous),
+    2 => Ok(UsbEndpointType::Bulk),
+    3 => Ok(UsbEndpointType::Interrupt),
+    _ => Err(std::io::ErrorKind::InvalidData.into()),
+  }
+}
+
+fn read_message(r: &mut impl Read) -> std::io::Result<Vec<u8>> {
+  let length = read_u32(r)? as usize;
+  if length > MAX_MESSAGE_LENGTH {
+    return Err(std::io::ErrorKind::InvalidData.into());
+  }
+  let mut body = vec![0; length];
+  r.read_exact(&mut body)?;
+  Ok(body)
+}
+
+// Writes the whole message at once, so that it is not split into several
+// packets on TCP connections.
+fn write_message(w: &mut impl Write, body: &[u8]) -> std::io::Result<()> {
+  let mut message = Vec::with_capacity(4 + body.len());
+  message.extend_from_slice(&(body.len() as u32).to_be_bytes());
+  message.extend_from_slice(body);
+  w.write_all(&message)
+}
+
+fn read_u8(r: &mut impl Read) -> std::io::Result<u8> {
+  let mut bytes = [0; 1];
+  r.read_exact(&mut bytes)?;
+  Ok(bytes[0])
+}
+
+fn read_u16(r: &mut impl Read) -> std::io::Result<u16> {
+  let mut bytes = [0; 2];
+  r.read_exact(&mut bytes)?;
+  Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+  let mut bytes = [0; 4];
+  r.read_exact(&mut bytes)?;
+  Ok(u32::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::proxy::read_reply;
+  use crate::proxy::write_reply;
+  use crate::proxy::ProxiedDevice;
+  use crate::proxy::Request;
+  use crate::Error;
+  use crate::UsbEndpointType;
+  use crate::UsbSpeed;
+
+  #[test]
+  fn test_request() {
+    let request = Request::TransferOut {
+      device: 7,
+      r#type: UsbEndpointType::Bulk,
+      endpoint: 0x02,
+      timeout: 1000,
+      data: vec![1, 2, 3],
+    };
+    let mut bytes = vec![];
+    request.write_to(&mut bytes).unwrap();
+    assert_eq!(
+      bytes,
+      vec![
+        0x00, 0x00, 0x00, 0x0E, // Length
+        0x0C, 0x00, 0x00, 0x00, 0x07, // OP_TRANSFER_OUT, device
+        0x02, 0x02, 0x00, 0x00, 0x03, 0xE8, // Bulk, endpoint, timeout
+        0x01, 0x02, 0x03,
+      ]
+    );
+    assert_eq!(Request::read_from(&mut bytes.as_slice()).unwrap(), request);
+
+    let request = Request::ControlIn {
+      device: 1,
+      request_type: 0x80,
+      request: 0x06,
+      value: 0x0100,
+      index: 0,
+      length: 18,
+    };
+    let mut bytes = vec![];
+    request.write_to(&mut bytes).unwrap();
+    assert_eq!(Request::read_from(&mut bytes.as_slice()).unwrap(), request);
+
+    let mut bytes = vec![];
+    Request::List.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes, vec![0x00, 0x00, 0x00, 0x01, 0x01]);
+
+    // Unknown opcode
+    let bytes = [0x00, 0x00, 0x00, 0x05, 0x7F, 0x00, 0x00, 0x00, 0x01];
+    assert!(Request::read_from(&mut &bytes[..]).is_err());
+  }
+
+  #[test]
+  fn test_reply() {
+    let mut bytes = vec![];
+    write_reply(&mut bytes, &Ok(vec![0xAA])).unwrap();
+    assert_eq!(bytes, vec![0x00, 0x00, 0x00, 0x02, 0x00, 0xAA]);
+    assert_eq!(read_reply(&mut bytes.as_slice()), Ok(vec![0xAA]));
+
+    let errors = || {
+      vec![
+        Error::NotFound,
+        Error::InvalidState,
+        Error::InvalidAccess,
+        Error::Stall,
+        Error::Io(std::io::ErrorKind::TimedOut),
+        Error::Io(std::io::ErrorKind::Other),
+      ]
+    };
+    for (err, expected) in errors().into_iter().zip(errors()) {
+      let mut bytes = vec![];
+      write_reply(&mut bytes, &Err(err)).unwrap();
+      assert_eq!(read_reply(&mut bytes.as_slice()), Err(expected));
+    }
+
+    // Kinds without a code are sent as `Other`.
+    let mut bytes = vec![];
+    write_reply(&mut bytes, &Err(Error::Io(std::io::ErrorKind::WouldBlock)))
+      .unwrap();
+    assert_eq!(
+      read_reply(&mut bytes.as_slice()),
+      Err(Error::Io(std::io::ErrorKind::Other))
+    );
+  }
+
+  #[test]
+  fn test_proxied_device() {
+    let device = ProxiedDevice {
+      id: 3,
+      bus_number: 1,
+      device_address: 5,
+      port_numbers: vec![1, 3],
+      speed: UsbSpeed::High,
+    };
+    let mut bytes = vec![];
+    device.write_to(&mut bytes).unwrap();
+    assert_eq!(
+      bytes,
+      vec![0x00, 0x00, 0x00, 0x03, 0x01, 0x05, 0x03, 0x02, 0x01, 0x03]
+    );
+    assert_eq!(
+      ProxiedDevice::read_from(&mut bytes.as_slice()).unwrap(),
+      device
+    );
+  }
+}
@@ -0,0 +1,563 @@
+//! Proxy daemon. Owns `UsbDevice`s and serves them to `ProxyHost` clients
+//! over TCP or a Unix socket.
+//!
+//! ```no_run
+//! # fn main() -> webusb::Result<()> {
+//! use std::os::unix::net::UnixListener;
+//! use webusb::proxy::server::ProxyServer;
+//! use webusb::Context;
+//!
+//! let ctx = Context::init()?;
+//! let server = ProxyServer::new(ctx.devices()?);
+//! server.serve_unix(UnixListener::bind("/run/webusb-proxyd.sock")?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every client connection is served on its own thread. Requests for the
+//! same device are carried out one at a time, except that bulk and
+//! interrupt transfers only hold the device while they are submitted: a
+//! client waiting for a transfer does not hold up the others.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::backend::BoxedBackend;
+use crate::backend::Transfer;
+use crate::proxy::write_reply;
+use crate::proxy::ProxiedDevice;
+use crate::proxy::Request;
+use crate::proxy::MAX_MESSAGE_LENGTH;
+use crate::Error;
+use crate::Result;
+use crate::UsbConfiguration;
+use crate::UsbDevice;
+use crate::UsbEndpointType;
+
+/// Shares devices between the clients of a proxy daemon.
+#[derive(Clone)]
+pub struct ProxyServer {
+  devices: Arc<Vec<Mutex<Shared>>>,
+  next_client: Arc<AtomicU32>,
+}
+
+struct Shared {
+  info: ProxiedDevice,
+  configurations: Vec<UsbConfiguration>,
+  configuration_value: Option<u8>,
+  backend: BoxedBackend,
+  // Number of clients that opened the device.
+  open_count: usize,
+  // Interface number to the client that claimed it.
+  claims: HashMap<u8, u32>,
+}
+
+impl ProxyServer {
+  /// Devices are identified by their index in `devices`.
+  pub fn new(devices: Vec<UsbDevice>) -> Self {
+    let devices = devices
+      .into_iter()
+      .enumerate()
+      .map(|(id, device)| {
+        Mutex::new(Shared {
+          info: ProxiedDevice {
+            id: id as u32,
+            bus_number: device.bus_number,
+            device_address: device.device_address,
+            port_numbers: device.port_numbers,
+            speed: device.speed,
+          },
+          configurations: device.configurations,
+          configuration_value: device
+            .configuration
+            .as_ref()
+            .map(|configuration| configuration.configuration_value()),
+          backend: device.backend,
+          open_count: 0,
+          claims: HashMap::new(),
+        })
+      })
+      .collect();
+    ProxyServer {
+      devices: Arc::new(devices),
+      next_client: Arc::new(AtomicU32::new(0)),
+    }
+  }
+
+  /// Device records as listed by OP_LIST.
+  pub fn devices(&self) -> Vec<ProxiedDevice> {
+    self
+      .devices
+      .iter()
+      .map(|device| device.lock().unwrap().info.clone())
+      .collect()
+  }
+
+  /// Accepts connections on `listener` until it fails.
+  pub fn serve(&self, listener: TcpListener) -> Result<()> {
+    for stream in listener.incoming() {
+      let stream = stream?;
+      stream.set_nodelay(true)?;
+      let server = self.clone();
+      std::thread::spawn(move || server.handle_connection(stream));
+    }
+    Ok(())
+  }
+
+  /// Accepts connections on `listener` until it fails.
+  #[cfg(unix)]
+  pub fn serve_unix(&self, listener: UnixListener) -> Result<()> {
+    for stream in listener.incoming() {
+      let stream = stream?;
+      let server = self.clone();
+      std::thread::spawn(move || server.handle_connection(stream));
+    }
+    Ok(())
+  }
+
+  fn handle_connection(&self, mut stream: impl Read + Write) {
+    let client = self.next_client.fetch_add(1, Ordering::Relaxed);
+    let mut opened = HashSet::new();
+
+    while let Ok(request) = Request::read_from(&mut stream) {
+      let reply = self.handle_request(client, &mut opened, request);
+      if write_reply(&mut stream, &reply).is_err() {
+        break;
+      }
+    }
+
+    // Give up whatever the client left behind.
+    for id in opened {
+      let _ = self.devices[id as usize].lock().unwrap().close(client);
+    }
+  }
+
+  fn handle_request(
+    &self,
+    client: u32,
+    opened: &mut HashSet<u32>,
+    request: Request,
+  ) -> Result<Vec<u8>> {
+    let id = match request {
+      Request::List => {
+        let devices = self.devices();
+        let mut reply = (devices.len() as u32).to_be_bytes().to_vec();
+        for device in devices {
+          device.write_to(&mut reply)?;
+        }
+        return Ok(reply);
+      }
+      Request::Open { device }
+      | Request::Close { device }
+      | Request::SetConfiguration { device, .. }
+      | Request::ClaimInterface { device, .. }
+      | Request::ReleaseInterface { device, .. }
+      | Request::SetAlternateSetting { device, .. }
+      | Request::ControlIn { device, .. }
+      | Request::ControlOut { device, .. }
+      | Request::ClearHalt { device, .. }
+      | Request::TransferIn { device, .. }
+      | Request::TransferOut { device, .. }
+      | Request::Reset { device } => device,
+    };
+    let device = self.devices.get(id as usize).ok_or(Error::NotFound)?;
+
+    if let Request::Open { .. } = request {
+      if opened.insert(id) {
+        if let Err(err) = device.lock().unwrap().open() {
+          opened.remove(&id);
+          return Err(err);
+        }
+      }
+      return Ok(vec![]);
+    }
+    if !opened.contains(&id) {
+      return Err(Error::InvalidState);
+    }
+
+    match request {
+      Request::Close { .. } => {
+        opened.remove(&id);
+        device.lock().unwrap().close(client)?;
+      }
+      Request::SetConfiguration {
+        configuration_value,
+        ..
+      } => {
+        let mut device = device.lock().unwrap();
+        if device.claims.values().any(|owner| *owner != client) {
+          return Err(Error::InvalidState);
+        }
+        device.backend.set_configuration(configuration_value)?;
+        device.configuration_value = Some(configuration_value);
+      }
+      Request::ClaimInterface {
+        interface_number, ..
+      } => {
+        let mut device = device.lock().unwrap();
+        match device.claims.get(&interface_number) {
+          Some(owner) if *owner == client => {}
+          Some(_) => return Err(Error::InvalidState),
+          None => {
+            device.backend.claim_interface(interface_number)?;
+            device.claims.insert(interface_number, client);
+          }
+        }
+      }
+      Request::ReleaseInterface {
+        interface_number, ..
+      } => {
+        let mut device = device.lock().unwrap();
+        device.check_claimed(client, interface_number)?;
+        device.backend.release_interface(interface_number)?;
+        device.claims.remove(&interface_number);
+      }
+      Request::SetAlternateSetting {
+        interface_number,
+        alternate_setting,
+        ..
+      } => {
+        let mut device = device.lock().unwrap();
+        device.check_claimed(client, interface_number)?;
+        device
+          .backend
+          .set_alternate_setting(interface_number, alternate_setting)?;
+      }
+      Request::ControlIn {
+        request_type,
+        request,
+        value,
+        index,
+        length,
+        ..
+      } => {
+        let mut device = device.lock().unwrap();
+        device.check_recipient(client, request_type, index)?;
+        let mut buffer = vec![0; length as usize];
+        let length = device.backend.control_in(
+          request_type,
+          request,
+          value,
+          index,
+          &mut buffer,
+        )?;
+        buffer.truncate(length);
+        return Ok(buffer);
+      }
+      Request::ControlOut {
+        request_type,
+        request,
+        value,
+        index,
+        data,
+        ..
+      } => {
+        let mut device = device.lock().unwrap();
+        device.check_recipient(client, request_type, index)?;
+        let length = device.backend.control_out(
+          request_type,
+          request,
+          value,
+          index,
+          &data,
+        )?;
+        return Ok((length as u32).to_be_bytes().to_vec());
+      }
+      Request::ClearHalt { endpoint, .. } => {
+        let mut device = device.lock().unwrap();
+        device.check_endpoint(client, endpoint)?;
+        device.backend.clear_halt(endpoint)?;
+      }
+      Request::TransferIn {
+        r#type,
+        endpoint,
+        length,
+        timeout,
+        ..
+      } => {
+        // The reply, with its status byte, has to fit in a message.
+        if length as usize >= MAX_MESSAGE_LENGTH {
+          return Err(Error::Io(std::io::ErrorKind::InvalidInput));
+        }
+        let buffer = vec![0; length as usize];
+        let transfer = device
+          .lock()
+          .unwrap()
+          .submit(client, r#type, endpoint, timeout, buffer)?;
+        transfer.wait(None)?;
+        return transfer.take();
+      }
+      Request::TransferOut {
+        r#type,
+        endpoint,
+        timeout,
+        data,
+        ..
+      } => {
+        let transfer = device
+          .lock()
+          .unwrap()
+          .submit(client, r#type, endpoint, timeout, data)?;
+        transfer.wait(None)?;
+        let length = transfer.take()?.len();
+        return Ok((length as u32).to_be_bytes().to_vec());
+      }
+      Request::Reset { .. } => {
+        let mut device = device.lock().unwrap();
+        if device.claims.values().any(|owner| *owner != client) {
+          return Err(Error::InvalidState);
+        }
+        device.backend.reset()?;
+      }
+      Request::List | Request::Open { .. } => unreachable!(),
+    }
+    Ok(vec![])
+  }
+}
+
+impl Shared {
+  fn open(&mut self) -> Result<()> {
+    if self.open_count == 0 {
+      self.backend.open()?;
+    }
+    self.open_count += 1;
+    Ok(())
+  }
+
+  // Releases the interfaces of `client`. The device is closed once the last
+  // client is gone.
+  fn close(&mut self, client: u32) -> Result<()> {
+    let claimed = self
+      .claims
+      .iter()
+      .filter(|(_, owner)| **owner == client)
+      .map(|(interface_number, _)| *interface_number)
+      .collect::<Vec<u8>>();
+    for interface_number in claimed {
+      self.claims.remove(&interface_number);
+      let _ = self.backend.release_interface(interface_number);
+    }
+
+    self.open_count -= 1;
+    match self.open_count {
+      0 => self.backend.close(),
+      _ => Ok(()),
+    }
+  }
+
+  // Starts a transfer on an endpoint claimed by `client`, timing out after
+  // `timeout` ms unless it is 0. Only the submission holds the device.
+  fn submit(
+    &mut self,
+    client: u32,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    timeout: u32,
+    buffer: Vec<u8>,
+  ) -> Result<Arc<dyn Transfer>> {
+    self.check_endpoint(client, endpoint)?;
+    self.backend.set_transfer_timeout(match timeout {
+      0 => None,
+      ms => Some(Duration::from_millis(ms as u64)),
+    });
+    self.backend.submit(r#type, endpoint, buffer)
+  }
+
+  fn check_claimed(&self, client: u32, interface_number: u8) -> Result<()> {
+    match self.claims.get(&interface_number) {
+      Some(owner) if *owner == client => Ok(()),
+      _ => Err(Error::InvalidState),
+    }
+  }
+
+  // Endpoints can only be used by the client that claimed their interface.
+  fn check_endpoint(&self, client: u32, endpoint: u8) -> Result<()> {
+    let interface_number =
+      self.endpoint_interface(endpoint).ok_or(Error::NotFound)?;
+    self.check_claimed(client, interface_number)
+  }
+
+  // Control transfers to an interface or endpoint are refused if another
+  // client claimed it. Requests to the device are always let through.
+  fn check_recipient(
+    &self,
+    client: u32,
+    request_type: u8,
+    index: u16,
+  ) -> Result<()> {
+    let interface_number = match request_type & 0x1F {
+      1 => index as u8,
+      2 => match self.endpoint_interface(index as u8) {
+        Some(interface_number) => interface_number,
+        None => return Ok(()),
+      },
+      _ => return Ok(()),
+    };
+    match self.claims.get(&interface_number) {
+      Some(owner) if *owner != client => Err(Error::InvalidState),
+      _ => Ok(()),
+    }
+  }
+
+  // Interface of the active configuration that `endpoint` belongs to, in
+  // any of its alternate settings.
+  fn endpoint_interface(&self, endpoint: u8) -> Option<u8> {
+    let configuration = self.configurations.iter().find(|configuration| {
+      Some(configuration.configuration_value()) == self.configuration_value
+    })?;
+    configuration
+      .interfaces()
+      .iter()
+      .find(|interface| {
+        interface.alternates().iter().any(|alternate| {
+          alternate.endpoints.iter().any(|e| e.address() == endpoint)
+        })
+      })
+      .map(|interface| interface.interface_number())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::TcpListener;
+  use std::time::Duration;
+
+  use crate::loopback::Loopback;
+  use crate::proxy::client::ProxyHost;
+  use crate::proxy::server::ProxyServer;
+  use crate::proxy::ProxyAddress;
+  use crate::proxy::MAX_MESSAGE_LENGTH;
+  use crate::Error;
+  use crate::UsbControlTransferParameters;
+  use crate::UsbDevice;
+  use crate::UsbRecipient;
+  use crate::UsbRequestType;
+
+  fn serve_loopback() -> ProxyHost {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = ProxyAddress::Tcp(listener.local_addr().unwrap());
+
+    let server = ProxyServer::new(vec![Loopback::device()]);
+    std::thread::spawn(move || server.serve(listener));
+    ProxyHost::new(address)
+  }
+
+  fn connect(host: &ProxyHost) -> UsbDevice {
+    let devices = host.devices().unwrap();
+    let mut device = host.connect(&devices[0]).unwrap();
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device
+  }
+
+  #[test]
+  fn test_devices() {
+    let host = serve_loopback();
+    let devices = host.devices().unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].id, 0);
+    assert_eq!(devices[0].port_numbers, vec![1, 3]);
+
+    let device = host.connect(&devices[0]).unwrap();
+    assert_eq!(device.port_path(), "1-1.3");
+    assert_eq!(device.vendor_id, 0x1234);
+    assert_eq!(device.product_name, Some("Loopback".to_string()));
+    assert!(device.configuration.is_some());
+  }
+
+  #[test]
+  fn test_transfers() {
+    let host = serve_loopback();
+    let mut device = connect(&host);
+    device.claim_interface(0).unwrap();
+
+    assert_eq!(device.transfer_out(2, b"hello").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"hello");
+    assert_eq!(device.transfer_in(3, 64).err(), Some(Error::Stall));
+
+    device.set_transfer_timeout(Some(Duration::from_millis(200)));
+    assert_eq!(
+      device.transfer_in(1, 64).err(),
+      Some(Error::Io(std::io::ErrorKind::TimedOut))
+    );
+  }
+
+  #[test]
+  fn test_transfer_length() {
+    let host = serve_loopback();
+    let mut device = connect(&host);
+    device.claim_interface(0).unwrap();
+
+    // Rejected before the buffer is allocated.
+    assert_eq!(
+      device.transfer_in(1, MAX_MESSAGE_LENGTH).err(),
+      Some(Error::Io(std::io::ErrorKind::InvalidInput))
+    );
+  }
+
+  #[test]
+  fn test_pending_transfer() {
+    let host = serve_loopback();
+    let mut first = connect(&host);
+    let mut second = connect(&host);
+    first.claim_interface(0).unwrap();
+
+    // A read without data or timeout does not keep the device busy.
+    let reader = std::thread::spawn(move || first.transfer_in(1, 64));
+    std::thread::sleep(Duration::from_millis(100));
+    let descriptor = second
+      .control_transfer_in(
+        UsbControlTransferParameters {
+          request_type: UsbRequestType::Standard,
+          recipient: UsbRecipient::Device,
+          request: 0x06,
+          value: 0x0100,
+          index: 0,
+        },
+        18,
+      )
+      .unwrap();
+    assert_eq!(descriptor.len(), 18);
+    assert!(!reader.is_finished());
+    drop(reader);
+  }
+
+  #[test]
+  fn test_arbitration() {
+    let host = serve_loopback();
+    let mut first = connect(&host);
+    let mut second = connect(&host);
+
+    first.claim_interface(0).unwrap();
+    assert_eq!(second.claim_interface(0).err(), Some(Error::InvalidState));
+    // Resetting would pull the interface away from the first client.
+    assert_eq!(second.reset().err(), Some(Error::InvalidState));
+
+    first.release_interface(0).unwrap();
+    second.claim_interface(0).unwrap();
+    assert_eq!(second.transfer_out(2, b"second").unwrap(), 6);
+    assert_eq!(second.transfer_in(1, 64).unwrap(), b"second");
+
+    // Claims are given up when the client disconnects.
+    drop(second);
+    let mut claimed = false;
+    for _ in 0..50 {
+      if first.claim_interface(0).is_ok() {
+        claimed = true;
+        break;
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(claimed);
+  }
+}
@@ -0,0 +1,764 @@
+//! Python bindings over PyO3. Built into the cdylib with the `python`
+//! feature, see `python/pyproject.toml` for building a wheel with maturin.
+//!
+//! ```python
+//! import webusb
+//!
+//! device = webusb.Context().devices()[0]
+//! device.open()
+//! device.select_configuration(1)
+//! device.claim_interface(0)
+//! device.transfer_out(2, b"ping")
+//! print(device.transfer_in(1, 64))
+//! ```
+//!
+//! The methods of `UsbDevice` release the GIL while they wait for the
+//! device. They are carried out one at a time per device, so a pending
+//! `transfer_in` holds up the other methods of the same device until it
+//! completes or times out (see `set_transfer_timeout`).
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::exceptions::PyOSError;
+use pyo3::exceptions::PyPermissionError;
+use pyo3::exceptions::PyTimeoutError;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::Context;
+use crate::Direction;
+use crate::EnumerationOptions;
+use crate::Error;
+use crate::UsbAlternateInterface;
+use crate::UsbConfiguration;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbEndpoint;
+use crate::UsbEndpointType;
+use crate::UsbInterface;
+use crate::UsbRecipient;
+use crate::UsbRequestType;
+use crate::UsbSyncType;
+use crate::UsbUsageType;
+
+create_exception!(webusb, UsbError, PyException, "Base class of USB errors.");
+create_exception!(webusb, NotFoundError, UsbError);
+create_exception!(webusb, InvalidStateError, UsbError);
+create_exception!(webusb, InvalidAccessError, UsbError);
+create_exception!(webusb, StallError, UsbError);
+
+impl From<Error> for PyErr {
+  fn from(err: Error) -> Self {
+    let message = format!("{:?}", err);
+    match err {
+      Error::NotFound => NotFoundError::new_err(message),
+      Error::InvalidState => InvalidStateError::new_err(message),
+      Error::InvalidAccess => InvalidAccessError::new_err(message),
+      Error::Stall => StallError::new_err(message),
+      Error::Io(std::io::ErrorKind::TimedOut) => {
+        PyTimeoutError::new_err(message)
+      }
+      Error::Io(std::io::ErrorKind::PermissionDenied) => {
+        PyPermissionError::new_err(message)
+      }
+      Error::Io(_) => PyOSError::new_err(message),
+      Error::Usb(rusb::Error::NotFound | rusb::Error::NoDevice) => {
+        NotFoundError::new_err(message)
+      }
+      Error::Usb(rusb::Error::Timeout) => PyTimeoutError::new_err(message),
+      Error::Usb(rusb::Error::Access) => PyPermissionError::new_err(message),
+      Error::Usb(_) => UsbError::new_err(message),
+    }
+  }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+  match direction {
+    Direction::In => "in",
+    Direction::Out => "out",
+  }
+}
+
+fn parse_direction(direction: &str) -> PyResult<Direction> {
+  match direction {
+    "in" => Ok(Direction::In),
+    "out" => Ok(Direction::Out),
+    _ => Err(PyValueError::new_err("Invalid direction")),
+  }
+}
+
+/// https://wicg.github.io/webusb/#usbendpoint
+#[pyclass(name = "UsbEndpoint", module = "webusb", frozen)]
+pub struct PyUsbEndpoint(UsbEndpoint);
+
+#[pymethods]
+impl PyUsbEndpoint {
+  #[getter]
+  fn endpoint_number(&self) -> u8 {
+    self.0.endpoint_number()
+  }
+
+  /// "in" or "out".
+  #[getter]
+  fn direction(&self) -> &'static str {
+    direction_name(self.0.direction())
+  }
+
+  /// "bulk", "interrupt", "isochronous" or "control".
+  #[getter(r#type)]
+  fn endpoint_type(&self) -> &'static str {
+    match self.0.endpoint_type() {
+      UsbEndpointType::Bulk => "bulk",
+      UsbEndpointType::Interrupt => "interrupt",
+      UsbEndpointType::Isochronous => "isochronous",
+      UsbEndpointType::Control => "control",
+    }
+  }
+
+  #[getter]
+  fn address(&self) -> u8 {
+    self.0.address()
+  }
+
+  #[getter]
+  fn packet_size(&self) -> u16 {
+    self.0.packet_size()
+  }
+
+  #[getter]
+  fn interval(&self) -> u8 {
+    self.0.interval()
+  }
+
+  #[getter]
+  fn transactions_per_microframe(&self) -> u8 {
+    self.0.transactions_per_microframe()
+  }
+
+  /// "none", "asynchronous", "adaptive" or "synchronous".
+  #[getter]
+  fn sync_type(&self) -> &'static str {
+    match self.0.sync_type() {
+      UsbSyncType::None => "none",
+      UsbSyncType::Asynchronous => "asynchronous",
+      UsbSyncType::Adaptive => "adaptive",
+      UsbSyncType::Synchronous => "synchronous",
+    }
+  }
+
+  /// "data", "feedback", "implicitFeedbackData" or "reserved".
+  #[getter]
+  fn usage_type(&self) -> &'static str {
+    match self.0.usage_type() {
+      UsbUsageType::Data => "data",
+      UsbUsageType::Feedback => "feedback",
+      UsbUsageType::ImplicitFeedbackData => "implicitFeedbackData",
+      UsbUsageType::Reserved => "reserved",
+    }
+  }
+}
+
+/// https://wicg.github.io/webusb/#usbalternateinterface
+#[pyclass(name = "UsbAlternateInterface", module = "webusb", frozen)]
+pub struct PyUsbAlternateInterface(UsbAlternateInterface);
+
+#[pymethods]
+impl PyUsbAlternateInterface {
+  #[getter]
+  fn alternate_setting(&self) -> u8 {
+    self.0.alternate_setting
+  }
+
+  #[getter]
+  fn interface_class(&self) -> u8 {
+    self.0.interface_class
+  }
+
+  #[getter]
+  fn interface_subclass(&self) -> u8 {
+    self.0.interface_subclass
+  }
+
+  #[getter]
+  fn interface_protocol(&self) -> u8 {
+    self.0.interface_protocol
+  }
+
+  #[getter]
+  fn interface_name(&self) -> Option<String> {
+    self.0.interface_name.clone()
+  }
+
+  #[getter]
+  fn endpoints(&self) -> Vec<PyUsbEndpoint> {
+    self
+      .0
+      .endpoints
+      .iter()
+      .cloned()
+      .map(PyUsbEndpoint)
+      .collect()
+  }
+}
+
+/// https://wicg.github.io/webusb/#usbinterface
+#[pyclass(name = "UsbInterface", module = "webusb", frozen)]
+pub struct PyUsbInterface(UsbInterface);
+
+#[pymethods]
+impl PyUsbInterface {
+  #[getter]
+  fn interface_number(&self) -> u8 {
+    self.0.interface_number()
+  }
+
+  #[getter]
+  fn alternate(&self) -> PyUsbAlternateInterface {
+    PyUsbAlternateInterface(self.0.alternate().clone())
+  }
+
+  #[getter]
+  fn alternates(&self) -> Vec<PyUsbAlternateInterface> {
+    let alternates = self.0.alternates().iter().cloned();
+    alternates.map(PyUsbAlternateInterface).collect()
+  }
+
+  #[getter]
+  fn claimed(&self) -> bool {
+    self.0.claimed()
+  }
+}
+
+/// https://wicg.github.io/webusb/#usbconfiguration
+#[pyclass(name = "UsbConfiguration", module = "webusb", frozen)]
+pub struct PyUsbConfiguration(UsbConfiguration);
+
+#[pymethods]
+impl PyUsbConfiguration {
+  #[getter]
+  fn configuration_value(&self) -> u8 {
+    self.0.configuration_value()
+  }
+
+  #[getter]
+  fn configuration_name(&self) -> Option<&str> {
+    self.0.configuration_name()
+  }
+
+  #[getter]
+  fn interfaces(&self) -> Vec<PyUsbInterface> {
+    self
+      .0
+      .interfaces()
+      .iter()
+      .cloned()
+      .map(PyUsbInterface)
+      .collect()
+  }
+
+  #[getter]
+  fn self_powered(&self) -> bool {
+    self.0.self_powered()
+  }
+
+  #[getter]
+  fn remote_wakeup(&self) -> bool {
+    self.0.remote_wakeup()
+  }
+
+  /// Maximum power consumption in milliamps.
+  #[getter]
+  fn max_power(&self) -> u16 {
+    self.0.max_power()
+  }
+
+  /// Full configuration descriptor, including class-specific descriptors.
+  #[getter]
+  fn raw_descriptor<'py>(
+    &self,
+    py: Python<'py>,
+  ) -> Option<Bound<'py, PyBytes>> {
+    let descriptor = self.0.raw_descriptor()?;
+    Some(PyBytes::new(py, descriptor))
+  }
+}
+
+/// https://wicg.github.io/webusb/#dictdef-usbcontroltransferparameters
+#[pyclass(name = "UsbControlTransferParameters", module = "webusb")]
+#[derive(Clone)]
+pub struct PyUsbControlTransferParameters {
+  /// "standard", "class" or "vendor".
+  #[pyo3(get, set)]
+  request_type: String,
+  /// "device", "interface", "endpoint" or "other".
+  #[pyo3(get, set)]
+  recipient: String,
+  #[pyo3(get, set)]
+  request: u8,
+  #[pyo3(get, set)]
+  value: u16,
+  #[pyo3(get, set)]
+  index: u16,
+}
+
+#[pymethods]
+impl PyUsbControlTransferParameters {
+  #[new]
+  fn new(
+    request_type: String,
+    recipient: String,
+    request: u8,
+    value: u16,
+    index: u16,
+  ) -> PyResult<Self> {
+    let setup = PyUsbControlTransferParameters {
+      request_type,
+      recipient,
+      request,
+      value,
+      index,
+    };
+    setup.parse()?;
+    Ok(setup)
+  }
+}
+
+impl PyUsbControlTransferParameters {
+  fn parse(&self) -> PyResult<UsbControlTransferParameters> {
+    let request_type = match self.request_type.as_str() {
+      "standard" => UsbRequestType::Standard,
+      "class" => UsbRequestType::Class,
+      "vendor" => UsbRequestType::Vendor,
+      _ => return Err(PyValueError::new_err("Invalid request_type")),
+    };
+    let recipient = match self.recipient.as_str() {
+      "device" => UsbRecipient::Device,
+      "interface" => UsbRecipient::Interface,
+      "endpoint" => UsbRecipient::Endpoint,
+      "other" => UsbRecipient::Other,
+      _ => return Err(PyValueError::new_err("Invalid recipient")),
+    };
+    Ok(UsbControlTransferParameters {
+      request_type,
+      recipient,
+      request: self.request,
+      value: self.value,
+      index: self.index,
+    })
+  }
+}
+
+/// https://wicg.github.io/webusb/#device-usage
+#[pyclass(name = "UsbDevice", module = "webusb", frozen)]
+pub struct PyUsbDevice {
+  #[pyo3(get)]
+  usb_version_major: u8,
+  #[pyo3(get)]
+  usb_version_minor: u8,
+  #[pyo3(get)]
+  usb_version_subminor: u8,
+  #[pyo3(get)]
+  device_class: u8,
+  #[pyo3(get)]
+  device_subclass: u8,
+  #[pyo3(get)]
+  device_protocol: u8,
+  #[pyo3(get)]
+  vendor_id: u16,
+  #[pyo3(get)]
+  product_id: u16,
+  #[pyo3(get)]
+  device_version_major: u8,
+  #[pyo3(get)]
+  device_version_minor: u8,
+  #[pyo3(get)]
+  device_version_subminor: u8,
+  #[pyo3(get)]
+  manufacturer_name: Option<String>,
+  #[pyo3(get)]
+  product_name: Option<String>,
+  #[pyo3(get)]
+  serial_number: Option<String>,
+  #[pyo3(get)]
+  bus_number: u8,
+  #[pyo3(get)]
+  device_address: u8,
+  #[pyo3(get)]
+  port_path: String,
+  device: Mutex<UsbDevice>,
+}
+
+impl From<UsbDevice> for PyUsbDevice {
+  fn from(device: UsbDevice) -> Self {
+    PyUsbDevice {
+      usb_version_major: device.usb_version_major,
+      usb_version_minor: device.usb_version_minor,
+      usb_version_subminor: device.usb_version_subminor,
+      device_class: device.device_class,
+      device_subclass: device.device_subclass,
+      device_protocol: device.device_protocol,
+      vendor_id: device.vendor_id,
+      product_id: device.product_id,
+      device_version_major: device.device_version_major,
+      device_version_minor: device.device_version_minor,
+      device_version_subminor: device.device_version_subminor,
+      manufacturer_name: device.manufacturer_name.clone(),
+      product_name: device.product_name.clone(),
+      serial_number: device.serial_number.clone(),
+      bus_number: device.bus_number,
+      device_address: device.device_address,
+      port_path: device.port_path(),
+      device: Mutex::new(device),
+    }
+  }
+}
+
+impl PyUsbDevice {
+  // Runs `f` on the device without holding the GIL.
+  fn run<T: Send>(
+    &self,
+    py: Python<'_>,
+    f: impl FnOnce(&mut UsbDevice) -> T + Send,
+  ) -> T {
+    py.allow_threads(|| f(&mut self.device.lock().unwrap()))
+  }
+}
+
+#[pymethods]
+impl PyUsbDevice {
+  #[getter]
+  fn opened(&self, py: Python<'_>) -> bool {
+    self.run(py, |device| device.opened)
+  }
+
+  #[getter]
+  fn configuration(&self, py: Python<'_>) -> Option<PyUsbConfiguration> {
+    let configuration = self.run(py, |device| device.configuration.clone());
+    configuration.map(PyUsbConfiguration)
+  }
+
+  #[getter]
+  fn configurations(&self, py: Python<'_>) -> Vec<PyUsbConfiguration> {
+    let configurations = self.run(py, |device| device.configurations.clone());
+    configurations.into_iter().map(PyUsbConfiguration).collect()
+  }
+
+  #[getter]
+  fn url(&self, py: Python<'_>) -> Option<String> {
+    self.run(py, |device| device.url.clone())
+  }
+
+  fn open(&self, py: Python<'_>) -> PyResult<()> {
+    Ok(self.run(py, |device| device.open())?)
+  }
+
+  fn close(&self, py: Python<'_>) -> PyResult<()> {
+    Ok(self.run(py, |device| device.close())?)
+  }
+
+  fn reset(&self, py: Python<'_>) -> PyResult<()> {
+    Ok(self.run(py, |device| device.reset())?)
+  }
+
+  fn select_configuration(
+    &self,
+    py: Python<'_>,
+    configuration_value: u8,
+  ) -> PyResult<()> {
+    Ok(self.run(py, |device| {
+      device.select_configuration(configuration_value)
+    })?)
+  }
+
+  fn claim_interface(
+    &self,
+    py: Python<'_>,
+    interface_number: u8,
+  ) -> PyResult<()> {
+    Ok(self.run(py, |device| device.claim_interface(interface_number))?)
+  }
+
+  fn release_interface(
+    &self,
+    py: Python<'_>,
+    interface_number: u8,
+  ) -> PyResult<()> {
+    Ok(self.run(py, |device| device.release_interface(interface_number))?)
+  }
+
+  fn select_alternate_interface(
+    &self,
+    py: Python<'_>,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> PyResult<()> {
+    Ok(self.run(py, |device| {
+      device.select_alternate_interface(interface_number, alternate_setting)
+    })?)
+  }
+
+  /// `direction` is "in" or "out".
+  fn clear_halt(
+    &self,
+    py: Python<'_>,
+    direction: &str,
+    endpoint_number: u8,
+  ) -> PyResult<()> {
+    let direction = parse_direction(direction)?;
+    Ok(self.run(py, |device| device.clear_halt(direction, endpoint_number))?)
+  }
+
+  fn control_transfer_in<'py>(
+    &self,
+    py: Python<'py>,
+    setup: &PyUsbControlTransferParameters,
+    length: u16,
+  ) -> PyResult<Bound<'py, PyBytes>> {
+    let setup = setup.parse()?;
+    let data = self.run(py, |device| {
+      device.control_transfer_in(setup, length as usize)
+    })?;
+    Ok(PyBytes::new(py, &data))
+  }
+
+  /// Returns the number of bytes written.
+  #[pyo3(signature = (setup, data = b"".as_slice()))]
+  fn control_transfer_out(
+    &self,
+    py: Python<'_>,
+    setup: &PyUsbControlTransferParameters,
+    data: &[u8],
+  ) -> PyResult<usize> {
+    let setup = setup.parse()?;
+    Ok(self.run(py, |device| device.control_transfer_out(setup, data))?)
+  }
+
+  fn transfer_in<'py>(
+    &self,
+    py: Python<'py>,
+    endpoint_number: u8,
+    length: usize,
+  ) -> PyResult<Bound<'py, PyBytes>> {
+    let data =
+      self.run(py, |device| device.transfer_in(endpoint_number, length))?;
+    Ok(PyBytes::new(py, &data))
+  }
+
+  /// Returns the number of bytes written.
+  fn transfer_out(
+    &self,
+    py: Python<'_>,
+    endpoint_number: u8,
+    data: &[u8],
+  ) -> PyResult<usize> {
+    Ok(self.run(py, |device| device.transfer_out(endpoint_number, data))?)
+  }
+
+  /// Bulk and interrupt transfers that do not complete within `timeout`
+  /// seconds raise `TimeoutError`. None, the default, waits forever.
+  #[pyo3(signature = (timeout))]
+  fn set_transfer_timeout(
+    &self,
+    py: Python<'_>,
+    timeout: Option<f64>,
+  ) -> PyResult<()> {
+    let timeout = timeout
+      .map(Duration::try_from_secs_f64)
+      .transpose()
+      .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    self.run(py, |device| device.set_transfer_timeout(timeout));
+    Ok(())
+  }
+
+  fn __repr__(&self) -> String {
+    format!(
+      "<UsbDevice {} {:04x}:{:04x} {:?}>",
+      self.port_path,
+      self.vendor_id,
+      self.product_id,
+      self.product_name.as_deref().unwrap_or("")
+    )
+  }
+}
+
+/// Provides device enumeration.
+#[pyclass(name = "Context", module = "webusb", frozen)]
+pub struct PyContext(Context);
+
+#[pymethods]
+impl PyContext {
+  #[new]
+  fn new() -> PyResult<Self> {
+    Ok(PyContext(Context::init()?))
+  }
+
+  /// Hubs are only listed if `include_hubs` is true.
+  #[pyo3(signature = (include_hubs = false))]
+  fn devices(
+    &self,
+    py: Python<'_>,
+    include_hubs: bool,
+  ) -> PyResult<Vec<PyUsbDevice>> {
+    let options = EnumerationOptions { include_hubs };
+    let devices = py.allow_threads(|| self.0.devices_with_options(&options))?;
+    Ok(devices.into_iter().map(Into::into).collect())
+  }
+}
+
+#[pymodule]
+fn webusb(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  let py = m.py();
+  m.add_class::<PyContext>()?;
+  m.add_class::<PyUsbDevice>()?;
+  m.add_class::<PyUsbConfiguration>()?;
+  m.add_class::<PyUsbInterface>()?;
+  m.add_class::<PyUsbAlternateInterface>()?;
+  m.add_class::<PyUsbEndpoint>()?;
+  m.add_class::<PyUsbControlTransferParameters>()?;
+  m.add("UsbError", py.get_type::<UsbError>())?;
+  m.add("NotFoundError", py.get_type::<NotFoundError>())?;
+  m.add("InvalidStateError", py.get_type::<InvalidStateError>())?;
+  m.add("InvalidAccessError", py.get_type::<InvalidAccessError>())?;
+  m.add("StallError", py.get_type::<StallError>())?;
+  Ok(())
+}
+
+#[cfg(all(test, any(feature = "usbip", feature = "proxy")))]
+mod tests {
+  use std::sync::mpsc;
+  use std::sync::Arc;
+  use std::time::Instant;
+
+  use super::*;
+  use crate::loopback::Loopback;
+  use crate::loopback::DEVICE_DESCRIPTOR;
+
+  fn with_gil<T>(f: impl FnOnce(Python<'_>) -> T) -> T {
+    pyo3::prepare_freethreaded_python();
+    Python::with_gil(f)
+  }
+
+  fn loopback(py: Python<'_>) -> PyUsbDevice {
+    let device = PyUsbDevice::from(Loopback::device());
+    device.open(py).unwrap();
+    device.select_configuration(py, 1).unwrap();
+    device.claim_interface(py, 0).unwrap();
+    device
+  }
+
+  #[test]
+  fn test_error() {
+    with_gil(|py| {
+      let err = PyErr::from(Error::NotFound);
+      assert!(err.is_instance_of::<NotFoundError>(py));
+      assert!(err.is_instance_of::<UsbError>(py));
+      assert!(PyErr::from(Error::InvalidState)
+        .is_instance_of::<InvalidStateError>(py));
+      assert!(PyErr::from(Error::Stall).is_instance_of::<StallError>(py));
+      assert!(PyErr::from(Error::Io(std::io::ErrorKind::TimedOut))
+        .is_instance_of::<PyTimeoutError>(py));
+      assert!(PyErr::from(Error::Io(std::io::ErrorKind::BrokenPipe))
+        .is_instance_of::<PyOSError>(py));
+      assert!(PyErr::from(Error::Usb(rusb::Error::Access))
+        .is_instance_of::<PyPermissionError>(py));
+      assert!(PyErr::from(Error::Usb(rusb::Error::NoDevice))
+        .is_instance_of::<NotFoundError>(py));
+
+      let err = PyErr::from(Error::Usb(rusb::Error::Busy));
+      assert!(err.is_instance_of::<UsbError>(py));
+      assert_eq!(err.value(py).to_string(), "Usb(Busy)");
+    });
+  }
+
+  #[test]
+  fn test_control_transfer_parameters() {
+    with_gil(|py| {
+      let setup = PyUsbControlTransferParameters::new(
+        "vendor".to_string(),
+        "interface".to_string(),
+        0x01,
+        0x0203,
+        0x0405,
+      )
+      .unwrap();
+      let setup = setup.parse().unwrap();
+      assert!(matches!(setup.request_type, UsbRequestType::Vendor));
+      assert!(matches!(setup.recipient, UsbRecipient::Interface));
+      assert_eq!((setup.request, setup.value, setup.index), (1, 0x203, 0x405));
+
+      for (request_type, recipient) in [("host", "device"), ("class", "hub")] {
+        let err = PyUsbControlTransferParameters::new(
+          request_type.to_string(),
+          recipient.to_string(),
+          0,
+          0,
+          0,
+        )
+        .err()
+        .unwrap();
+        assert!(err.is_instance_of::<PyValueError>(py));
+      }
+    });
+  }
+
+  #[test]
+  fn test_transfers() {
+    with_gil(|py| {
+      let device = PyUsbDevice::from(Loopback::device());
+      let err = device.transfer_in(py, 1, 64).err().unwrap();
+      assert!(err.is_instance_of::<InvalidStateError>(py));
+
+      let device = loopback(py);
+      assert!(device.opened(py));
+      let setup = PyUsbControlTransferParameters::new(
+        "standard".to_string(),
+        "device".to_string(),
+        0x06,
+        0x0100,
+        0,
+      )
+      .unwrap();
+      let descriptor = device.control_transfer_in(py, &setup, 18).unwrap();
+      assert_eq!(descriptor.as_bytes(), DEVICE_DESCRIPTOR);
+
+      assert_eq!(device.transfer_out(py, 2, b"hello").unwrap(), 5);
+      let data = device.transfer_in(py, 1, 64).unwrap();
+      assert_eq!(data.as_bytes(), b"hello");
+
+      let err = device.transfer_in(py, 3, 64).err().unwrap();
+      assert!(err.is_instance_of::<StallError>(py));
+    });
+  }
+
+  #[test]
+  fn test_allow_threads() {
+    let device = Arc::new(with_gil(|py| {
+      let device = loopback(py);
+      device.set_transfer_timeout(py, Some(0.5)).unwrap();
+      device
+    }));
+
+    // Nothing to read, the transfer waits for the timeout.
+    let (started, starting) = mpsc::channel();
+    let reader = std::thread::spawn({
+      let device = device.clone();
+      move || {
+        with_gil(|py| {
+          started.send(Instant::now()).unwrap();
+          let err = device.transfer_in(py, 1, 64).err().unwrap();
+          err.is_instance_of::<PyTimeoutError>(py)
+        })
+      }
+    });
+
+    // The GIL is not held while waiting.
+    let start = starting.recv().unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    with_gil(|_| assert!(start.elapsed() < Duration::from_millis(400)));
+    assert!(reader.join().unwrap());
+  }
+}
@@ -0,0 +1,782 @@
+//! Linux usbfs backend. Talks to `/dev/bus/usb` directly and enumerates
+//! devices through sysfs, without libusb.
+//!
+//! Used by `Context` when the crate is built with the `usbfs` feature and
+//! without the `libusb` feature:
+//!
+//! ```toml
+//! webusb = { version = "0.5", default-features = false, features = ["usbfs"] }
+//! ```
+//!
+//! NOTE: Kernel drivers detached by `UsbDevice::claim_interface` are not
+//! re-attached when the interface is released.
+//!
+//! NOTE: Devices are listed from the descriptors cached in sysfs without
+//! being opened. Configuration and interface names, and the WebUSB landing
+//! page, are not read.
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::backend::DeviceBackend;
+use crate::constants::HUB_CLASS;
+use crate::enumeration::parse_device;
+use crate::enumeration::DeviceStrings;
+use crate::enumeration::Topology;
+use crate::ioctl::error_from_errno;
+use crate::ioctl::ioc;
+use crate::ioctl::ioctl;
+use crate::ioctl::last_errno;
+use crate::ioctl::IOC_NONE;
+use crate::ioctl::IOC_READ;
+use crate::ioctl::IOC_WRITE;
+use crate::ioctl::UINT_SIZE;
+use crate::EnumerationOptions;
+use crate::Error;
+use crate::Result;
+use crate::UsbDevice;
+use crate::UsbEndpointType;
+use crate::UsbParentHub;
+use crate::UsbSpeed;
+
+const SYSFS_DEVICES: &str = "/sys/bus/usb/devices";
+const DEV_BUS_USB: &str = "/dev/bus/usb";
+
+// linux/usbdevice_fs.h
+#[repr(C)]
+struct UsbdevfsUrb {
+  r#type: u8,
+  endpoint: u8,
+  status: libc::c_int,
+  flags: libc::c_uint,
+  buffer: *mut libc::c_void,
+  buffer_length: libc::c_int,
+  actual_length: libc::c_int,
+  start_frame: libc::c_int,
+  number_of_packets: libc::c_int,
+  error_count: libc::c_int,
+  signr: libc::c_uint,
+  usercontext: *mut libc::c_void,
+}
+
+#[repr(C)]
+struct UsbdevfsIsoPacketDesc {
+  length: libc::c_uint,
+  actual_length: libc::c_uint,
+  status: libc::c_uint,
+}
+
+#[repr(C)]
+struct UsbdevfsSetInterface {
+  interface: libc::c_uint,
+  altsetting: libc::c_uint,
+}
+
+#[repr(C)]
+struct UsbdevfsDisconnectClaim {
+  interface: libc::c_uint,
+  flags: libc::c_uint,
+  driver: [libc::c_char; 256],
+}
+
+const USBDEVFS_URB_TYPE_ISO: u8 = 0;
+const USBDEVFS_URB_TYPE_INTERRUPT: u8 = 1;
+const USBDEVFS_URB_TYPE_CONTROL: u8 = 2;
+const USBDEVFS_URB_TYPE_BULK: u8 = 3;
+
+// Schedule isochronous packets from the next free frame.
+const USBDEVFS_URB_ISO_ASAP: libc::c_uint = 0x02;
+
+// Detach any driver but usbfs itself.
+const USBDEVFS_DISCONNECT_CLAIM_EXCEPT_DRIVER: libc::c_uint = 0x02;
+
+const PTR_SIZE: usize = std::mem::size_of::<*mut libc::c_void>();
+
+const USBDEVFS_SETINTERFACE: u32 =
+  ioc(IOC_READ, 4, std::mem::size_of::<UsbdevfsSetInterface>());
+const USBDEVFS_SETCONFIGURATION: u32 = ioc(IOC_READ, 5, UINT_SIZE);
+const USBDEVFS_SUBMITURB: u32 =
+  ioc(IOC_READ, 10, std::mem::size_of::<UsbdevfsUrb>());
+const USBDEVFS_DISCARDURB: u32 = ioc(IOC_NONE, 11, 0);
+const USBDEVFS_REAPURB: u32 = ioc(IOC_WRITE, 12, PTR_SIZE);
+const USBDEVFS_RELEASEINTERFACE: u32 = ioc(IOC_READ, 16, UINT_SIZE);
+const USBDEVFS_RESET: u32 = ioc(IOC_NONE, 20, 0);
+const USBDEVFS_CLEAR_HALT: u32 = ioc(IOC_READ, 21, UINT_SIZE);
+const USBDEVFS_DISCONNECT_CLAIM: u32 =
+  ioc(IOC_READ, 27, std::mem::size_of::<UsbdevfsDisconnectClaim>());
+
+/// Lists the devices found in sysfs. Their descriptors are read from sysfs
+/// as well, device nodes are only opened by `UsbDevice::open`.
+// Context uses libusb instead when both features are enabled.
+#[cfg_attr(feature = "libusb", allow(dead_code))]
+pub(crate) fn devices(options: &EnumerationOptions) -> Result<Vec<UsbDevice>> {
+  Ok(
+    sysfs_devices(Path::new(SYSFS_DEVICES), options)?
+      .into_iter()
+      // Skip devices that are gone, or whose descriptors do not parse.
+      .filter_map(|(dir, topology)| read_sysfs_device(&dir, topology))
+      .collect(),
+  )
+}
+
+// Sysfs directories and topology of the devices in `root`. Interfaces
+// (`1-2:1.0`) share the directory with devices and are skipped.
+fn sysfs_devices(
+  root: &Path,
+  options: &EnumerationOptions,
+) -> Result<Vec<(PathBuf, Topology)>> {
+  let mut devices = vec![];
+  for entry in std::fs::read_dir(root)? {
+    let name = entry?.file_name().to_string_lossy().into_owned();
+    if name.contains(':') {
+      continue;
+    }
+
+    let dir = root.join(&name);
+    let (bus_number, device_address) =
+      match (read_attr(&dir, "busnum"), read_attr(&dir, "devnum")) {
+        (Some(busnum), Some(devnum)) => (busnum, devnum),
+        _ => continue,
+      };
+
+    let device_class = read_hex_attr(&dir, "bDeviceClass").unwrap_or(0);
+    if !options.include_hubs && device_class == HUB_CLASS as u16 {
+      continue;
+    }
+
+    let speed = match read_string_attr(&dir, "speed").as_deref() {
+      Some("1.5") => UsbSpeed::Low,
+      Some("12") => UsbSpeed::Full,
+      Some("480") => UsbSpeed::High,
+      Some("5000") => UsbSpeed::Super,
+      Some("10000") | Some("20000") => UsbSpeed::SuperPlus,
+      _ => UsbSpeed::Unknown,
+    };
+
+    let topology = Topology {
+      bus_number,
+      device_address,
+      port_numbers: port_numbers(&name),
+      speed,
+      parent_hub: parent_name(&name)
+        .and_then(|parent| parent_hub(&root.join(&parent), &parent)),
+    };
+    devices.push((dir, topology));
+  }
+
+  // read_dir does not guarantee any order.
+  devices.sort_by_key(|(_, topology)| {
+    (topology.bus_number, topology.device_address)
+  });
+  Ok(devices)
+}
+
+// The `descriptors` attribute holds the device descriptor followed by
+// every configuration descriptor, as read by the kernel on enumeration.
+fn read_sysfs_device(dir: &Path, topology: Topology) -> Option<UsbDevice> {
+  let descriptors = std::fs::read(dir.join("descriptors")).ok()?;
+  let strings = DeviceStrings {
+    manufacturer_name: read_string_attr(dir, "manufacturer"),
+    product_name: read_string_attr(dir, "product"),
+    serial_number: read_string_attr(dir, "serial"),
+  };
+  let path = Path::new(DEV_BUS_USB)
+    .join(format!("{:03}", topology.bus_number))
+    .join(format!("{:03}", topology.device_address));
+  parse_device(
+    Box::new(Usbfs::new(path)),
+    &descriptors,
+    strings,
+    // Empty while the device is unconfigured.
+    read_attr(dir, "bConfigurationValue"),
+    topology,
+  )
+  .ok()
+}
+
+// Port numbers of a sysfs device name, `1-2.4` is [2, 4]. Root hubs
+// (`usb1`) have none.
+fn port_numbers(name: &str) -> Vec<u8> {
+  match name.split_once('-') {
+    Some((_, ports)) => ports
+      .split('.')
+      .filter_map(|port| port.parse().ok())
+      .collect(),
+    None => vec![],
+  }
+}
+
+// `1-2.4` is plugged into `1-2`, `1-2` into the root hub `usb1`.
+fn parent_name(name: &str) -> Option<String> {
+  let (bus, ports) = name.split_once('-')?;
+  match ports.rsplit_once('.') {
+    Some((parent_ports, _)) => Some(format!("{}-{}", bus, parent_ports)),
+    None => Some(format!("usb{}", bus)),
+  }
+}
+
+fn parent_hub(dir: &Path, name: &str) -> Option<UsbParentHub> {
+  Some(UsbParentHub {
+    bus_number: read_attr(dir, "busnum")?,
+    device_address: read_attr(dir, "devnum")?,
+    port_numbers: port_numbers(name),
+    vendor_id: read_hex_attr(dir, "idVendor")?,
+    product_id: read_hex_attr(dir, "idProduct")?,
+  })
+}
+
+fn read_string_attr(dir: &Path, attr: &str) -> Option<String> {
+  let value = std::fs::read_to_string(dir.join(attr)).ok()?;
+  Some(value.trim().to_string())
+}
+
+fn read_attr(dir: &Path, attr: &str) -> Option<u8> {
+  read_string_attr(dir, attr)?.parse().ok()
+}
+
+fn read_hex_attr(dir: &Path, attr: &str) -> Option<u16> {
+  u16::from_str_radix(&read_string_attr(dir, attr)?, 16).ok()
+}
+
+/// Backend of a device node in `/dev/bus/usb`.
+pub(crate) struct Usbfs {
+  path: PathBuf,
+  file: Option<File>,
+  transfer_timeout: Option<Duration>,
+}
+
+impl Usbfs {
+  pub(crate) fn new(path: PathBuf) -> Self {
+    Self {
+      path,
+      file: None,
+      transfer_timeout: None,
+    }
+  }
+
+  fn fd(&self) -> Result<libc::c_int> {
+    match &self.file {
+      Some(file) => Ok(file.as_raw_fd()),
+      None => Err(Error::InvalidState),
+    }
+  }
+
+  fn ioctl(&self, request: u32, arg: *mut libc::c_void) -> Result<libc::c_int> {
+    ioctl(self.fd()?, request, arg)
+  }
+
+  fn ioctl_uint(&self, request: u32, value: u32) -> Result<()> {
+    let mut value = value as libc::c_uint;
+    self.ioctl(request, &mut value as *mut _ as *mut libc::c_void)?;
+    Ok(())
+  }
+
+  // Submits an URB and waits for it to complete (USBDEVFS_SUBMITURB,
+  // USBDEVFS_REAPURB). URBs still pending after `timeout` are discarded.
+  // Returns the actual length.
+  fn transfer(
+    &self,
+    r#type: u8,
+    endpoint: u8,
+    buffer: &mut [u8],
+    timeout: Option<Duration>,
+  ) -> Result<usize> {
+    let mut urb = UsbdevfsUrb {
+      r#type,
+      endpoint,
+      status: 0,
+      flags: 0,
+      buffer: buffer.as_mut_ptr() as *mut libc::c_void,
+      buffer_length: buffer.len() as libc::c_int,
+      actual_length: 0,
+      start_frame: 0,
+      number_of_packets: 0,
+      error_count: 0,
+      signr: 0,
+      usercontext: std::ptr::null_mut(),
+    };
+    self.run(&mut urb, timeout)?;
+    match urb.status {
+      0 => Ok(urb.actual_length as usize),
+      status => Err(error_from_errno(-status)),
+    }
+  }
+
+  // Isochronous URB of one packet per entry of `packet_lengths`. The
+  // packet descriptors follow the URB in memory, so both are allocated as
+  // one array of URBs.
+  fn isochronous(
+    &self,
+    endpoint: u8,
+    buffer: &mut [u8],
+    packet_lengths: &[u32],
+    timeout: Option<Duration>,
+  ) -> Result<Vec<Result<usize>>> {
+    let urb_size = std::mem::size_of::<UsbdevfsUrb>();
+    let packets_size =
+      packet_lengths.len() * std::mem::size_of::<UsbdevfsIsoPacketDesc>();
+    let mut urbs = (0..1 + packets_size.div_ceil(urb_size))
+      .map(|_| UsbdevfsUrb {
+        r#type: USBDEVFS_URB_TYPE_ISO,
+        endpoint,
+        status: 0,
+        flags: USBDEVFS_URB_ISO_ASAP,
+        buffer: buffer.as_mut_ptr() as *mut libc::c_void,
+        buffer_length: buffer.len() as libc::c_int,
+        actual_length: 0,
+        start_frame: 0,
+        number_of_packets: packet_lengths.len() as libc::c_int,
+        error_count: 0,
+        signr: 0,
+        usercontext: std::ptr::null_mut(),
+      })
+      .collect::<Vec<_>>();
+    let packets =
+      unsafe { urbs.as_mut_ptr().add(1) as *mut UsbdevfsIsoPacketDesc };
+    for (index, length) in packet_lengths.iter().enumerate() {
+      unsafe {
+        packets.add(index).write(UsbdevfsIsoPacketDesc {
+          length: *length,
+          actual_length: 0,
+          status: 0,
+        })
+      };
+    }
+
+    self.run(&mut urbs[0], timeout)?;
+    if urbs[0].status != 0 {
+      return Err(error_from_errno(-urbs[0].status));
+    }
+    Ok(
+      (0..packet_lengths.len())
+        .map(|index| {
+          let packet = unsafe { packets.add(index).read() };
+          match packet.status as libc::c_int {
+            0 => Ok(packet.actual_length as usize),
+            status => Err(error_from_errno(-status)),
+          }
+        })
+        .collect(),
+    )
+  }
+
+  // Submits `urb` and waits up to `timeout` for it, discarding it if it
+  // does not complete in time.
+  fn run(
+    &self,
+    urb: &mut UsbdevfsUrb,
+    timeout: Option<Duration>,
+  ) -> Result<()> {
+    let urb_ptr: *mut UsbdevfsUrb = urb;
+
+    self.ioctl(USBDEVFS_SUBMITURB, urb_ptr as *mut libc::c_void)?;
+
+    // From here on the kernel owns the URB and its buffer until the URB
+    // has been reaped, so every path below reaps it.
+    let completed = match timeout {
+      Some(timeout) => self.wait_writable(timeout),
+      None => Ok(true),
+    };
+    if !matches!(completed, Ok(true)) {
+      // Fails if the URB completed in the meantime, it is reaped below
+      // either way.
+      let _ = self.ioctl(USBDEVFS_DISCARDURB, urb_ptr as *mut libc::c_void);
+    }
+    self.reap(urb_ptr)?;
+
+    match completed {
+      Ok(true) => Ok(()),
+      Ok(false) => Err(Error::Io(std::io::ErrorKind::TimedOut)),
+      Err(err) => Err(err),
+    }
+  }
+
+  // Blocks until the kernel gives `urb` back (USBDEVFS_REAPURB). The kernel
+  // only writes to the URB and its buffer while reaping, so URBs left over
+  // by an earlier failed reap are skipped rather than touched.
+  fn reap(&self, urb: *mut UsbdevfsUrb) -> Result<()> {
+    loop {
+      let mut reaped: *mut UsbdevfsUrb = std::ptr::null_mut();
+      let reaped_ptr = &mut reaped as *mut _ as *mut libc::c_void;
+      self.ioctl(USBDEVFS_REAPURB, reaped_ptr)?;
+      if reaped == urb {
+        return Ok(());
+      }
+    }
+  }
+
+  // usbfs signals completed URBs as writable.
+  fn wait_writable(&self, timeout: Duration) -> Result<bool> {
+    let mut pollfd = libc::pollfd {
+      fd: self.fd()?,
+      events: libc::POLLOUT,
+      revents: 0,
+    };
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128);
+    loop {
+      let ret = unsafe { libc::poll(&mut pollfd, 1, timeout as libc::c_int) };
+      if ret >= 0 {
+        return Ok(ret > 0);
+      }
+      match last_errno() {
+        libc::EINTR => continue,
+        errno => return Err(error_from_errno(errno)),
+      }
+    }
+  }
+
+  fn control(
+    &self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &mut [u8],
+  ) -> Result<usize> {
+    // The setup packet is sent in front of the data.
+    let mut buffer = Vec::with_capacity(8 + data.len());
+    buffer.extend_from_slice(&[request_type, request]);
+    buffer.extend_from_slice(&value.to_le_bytes());
+    buffer.extend_from_slice(&index.to_le_bytes());
+    buffer.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    buffer.extend_from_slice(data);
+
+    let length =
+      self.transfer(USBDEVFS_URB_TYPE_CONTROL, 0, &mut buffer, None)?;
+    if request_type & 0x80 != 0 {
+      data[..length].copy_from_slice(&buffer[8..8 + length]);
+    }
+    Ok(length)
+  }
+}
+
+impl DeviceBackend for Usbfs {
+  fn open(&mut self) -> Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+    self.file = Some(file);
+    Ok(())
+  }
+
+  fn close(&mut self) -> Result<()> {
+    // Closing the file releases claimed interfaces.
+    self.file = None;
+    Ok(())
+  }
+
+  fn set_configuration(&mut self, configuration_value: u8) -> Result<()> {
+    self.ioctl_uint(USBDEVFS_SETCONFIGURATION, configuration_value as u32)
+  }
+
+  fn claim_interface(&mut self, interface_number: u8) -> Result<()> {
+    let mut claim = UsbdevfsDisconnectClaim {
+      interface: interface_number as libc::c_uint,
+      flags: USBDEVFS_DISCONNECT_CLAIM_EXCEPT_DRIVER,
+      driver: [0; 256],
+    };
+    for (i, b) in b"usbfs".iter().enumerate() {
+      claim.driver[i] = *b as libc::c_char;
+    }
+    self.ioctl(
+      USBDEVFS_DISCONNECT_CLAIM,
+      &mut claim as *mut _ as *mut libc::c_void,
+    )?;
+    Ok(())
+  }
+
+  fn release_interface(&mut self, interface_number: u8) -> Result<()> {
+    self.ioctl_uint(USBDEVFS_RELEASEINTERFACE, interface_number as u32)
+  }
+
+  fn set_alternate_setting(
+    &mut self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Result<()> {
+    let mut setting = UsbdevfsSetInterface {
+      interface: interface_number as libc::c_uint,
+      altsetting: alternate_setting as libc::c_uint,
+    };
+    self.ioctl(
+      USBDEVFS_SETINTERFACE,
+      &mut setting as *mut _ as *mut libc::c_void,
+    )?;
+    Ok(())
+  }
+
+  fn control_in(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    self.control(request_type, request, value, index, buffer)
+  }
+
+  fn control_out(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+  ) -> Result<usize> {
+    self.control(request_type, request, value, index, &mut data.to_vec())
+  }
+
+  fn clear_halt(&mut self, endpoint: u8) -> Result<()> {
+    self.ioctl_uint(USBDEVFS_CLEAR_HALT, endpoint as u32)
+  }
+
+  fn transfer_in(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    let r#type = match r#type {
+      UsbEndpointType::Bulk => USBDEVFS_URB_TYPE_BULK,
+      UsbEndpointType::Interrupt => USBDEVFS_URB_TYPE_INTERRUPT,
+      _ => return Err(Error::InvalidAccess),
+    };
+    self.transfer(r#type, endpoint, buffer, self.transfer_timeout)
+  }
+
+  fn transfer_out(
+    &mut self,
+    r#type: UsbEndpointType,
+    endpoint: u8,
+    data: &[u8],
+  ) -> Result<usize> {
+    let r#type = match r#type {
+      UsbEndpointType::Bulk => USBDEVFS_URB_TYPE_BULK,
+      UsbEndpointType::Interrupt => USBDEVFS_URB_TYPE_INTERRUPT,
+      _ => return Err(Error::InvalidAccess),
+    };
+    // The kernel only reads from the buffer of OUT transfers.
+    let mut data = data.to_vec();
+    self.transfer(r#type, endpoint, &mut data, self.transfer_timeout)
+  }
+
+  fn reset(&mut self) -> Result<()> {
+    self.ioctl(USBDEVFS_RESET, std::ptr::null_mut())?;
+    Ok(())
+  }
+
+  fn set_transfer_timeout(&mut self, timeout: Option<Duration>) {
+    self.transfer_timeout = timeout;
+  }
+
+  fn isochronous_transfer(
+    &mut self,
+    endpoint: u8,
+    buffer: &mut [u8],
+    packet_lengths: &[u32],
+  ) -> Result<Vec<Result<usize>>> {
+    self.isochronous(endpoint, buffer, packet_lengths, self.transfer_timeout)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use crate::enumeration::Topology;
+  use crate::usbfs::devices;
+  use crate::usbfs::parent_name;
+  use crate::usbfs::port_numbers;
+  use crate::usbfs::read_sysfs_device;
+  use crate::usbfs::sysfs_devices;
+  use crate::usbfs::USBDEVFS_CLEAR_HALT;
+  use crate::usbfs::USBDEVFS_DISCONNECT_CLAIM;
+  use crate::usbfs::USBDEVFS_REAPURB;
+  use crate::usbfs::USBDEVFS_SUBMITURB;
+  use crate::Direction;
+  use crate::EnumerationOptions;
+  use crate::UsbEndpointType;
+  use crate::UsbSpeed;
+
+  #[test]
+  #[cfg(target_pointer_width = "64")]
+  fn test_ioctl_numbers() {
+    // Values from linux/usbdevice_fs.h on x86_64.
+    assert_eq!(USBDEVFS_SUBMITURB, 0x8038550A);
+    assert_eq!(USBDEVFS_REAPURB, 0x4008550C);
+    assert_eq!(USBDEVFS_CLEAR_HALT, 0x80045515);
+    assert_eq!(USBDEVFS_DISCONNECT_CLAIM, 0x8108551B);
+  }
+
+  #[test]
+  fn test_sysfs_names() {
+    assert_eq!(port_numbers("1-2.4"), vec![2, 4]);
+    assert_eq!(port_numbers("usb1"), Vec::<u8>::new());
+    assert_eq!(parent_name("1-2.4"), Some("1-2".to_string()));
+    assert_eq!(parent_name("1-2"), Some("usb1".to_string()));
+    assert_eq!(parent_name("usb1"), None);
+  }
+
+  fn write_device(root: &Path, name: &str, attrs: &[(&str, &str)]) {
+    let dir = root.join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (attr, value) in attrs {
+      std::fs::write(dir.join(attr), format!("{}\n", value)).unwrap();
+    }
+  }
+
+  #[test]
+  fn test_sysfs_devices() {
+    let root =
+      std::env::temp_dir().join(format!("webusb-sysfs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+
+    let hub = [
+      ("busnum", "1"),
+      ("devnum", "1"),
+      ("bDeviceClass", "09"),
+      ("idVendor", "1d6b"),
+      ("idProduct", "0002"),
+      ("speed", "480"),
+    ];
+    write_device(&root, "usb1", &hub);
+    write_device(
+      &root,
+      "1-2",
+      &[
+        ("busnum", "1"),
+        ("devnum", "7"),
+        ("bDeviceClass", "ef"),
+        ("idVendor", "2341"),
+        ("idProduct", "8036"),
+        ("speed", "12"),
+      ],
+    );
+    // Interface of 1-2.
+    write_device(&root, "1-2:1.0", &[("bInterfaceClass", "02")]);
+
+    let devices = sysfs_devices(&root, &EnumerationOptions::default()).unwrap();
+    assert_eq!(devices.len(), 1);
+
+    let (dir, topology) = &devices[0];
+    assert_eq!(dir, &root.join("1-2"));
+    assert_eq!(topology.port_numbers, vec![2]);
+    assert_eq!(topology.speed, UsbSpeed::Full);
+    let parent_hub = topology.parent_hub.as_ref().unwrap();
+    assert_eq!(parent_hub.vendor_id, 0x1d6b);
+    assert!(parent_hub.port_numbers.is_empty());
+
+    let options = EnumerationOptions { include_hubs: true };
+    assert_eq!(sysfs_devices(&root, &options).unwrap().len(), 2);
+
+    // No `descriptors` attribute.
+    let (dir, topology) = devices.into_iter().next().unwrap();
+    assert!(read_sysfs_device(&dir, topology).is_none());
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn test_read_sysfs_device() {
+    let root = std::env::temp_dir()
+      .join(format!("webusb-sysfs-device-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    write_device(
+      &root,
+      "1-2",
+      &[
+        ("bConfigurationValue", "1"),
+        ("manufacturer", "Arduino LLC"),
+        ("product", "Arduino Leonardo"),
+      ],
+    );
+
+    #[rustfmt::skip]
+    let descriptors: &[u8] = &[
+      // Device
+      18, 1, 0x00, 0x02, 0xef, 0x02, 0x01, 64, 0x41, 0x23, 0x36, 0x80,
+      0x00, 0x01, 1, 2, 0, 1,
+      // Configuration
+      9, 2, 25, 0, 1, 1, 0, 0x80, 250,
+      // Interface
+      9, 4, 0, 0, 1, 0xff, 0, 0, 0,
+      // Endpoint
+      7, 5, 0x81, 2, 64, 0, 0,
+    ];
+    std::fs::write(root.join("1-2").join("descriptors"), descriptors).unwrap();
+
+    let topology = Topology {
+      bus_number: 1,
+      device_address: 7,
+      port_numbers: vec![2],
+      speed: UsbSpeed::Full,
+      parent_hub: None,
+    };
+    let device = read_sysfs_device(&root.join("1-2"), topology).unwrap();
+    assert_eq!(device.vendor_id, 0x2341);
+    assert_eq!(device.product_id, 0x8036);
+    assert_eq!(device.product_name.as_deref(), Some("Arduino Leonardo"));
+    assert_eq!(device.serial_number, None);
+    assert_eq!(device.configurations.len(), 1);
+    let configuration = device.configuration.as_ref().unwrap();
+    assert_eq!(configuration.configuration_value, 1);
+    assert_eq!(configuration.max_power, 500);
+    let endpoints = &configuration.interfaces[0].alternate.endpoints;
+    assert_eq!(endpoints[0].endpoint_number, 1);
+    assert!(!device.opened);
+
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  // Needs the loopback configuration of gadget zero on a dummy host
+  // controller, and write access to its device node:
+  //
+  //   modprobe dummy_hcd
+  //   modprobe g_zero
+  #[test]
+  #[ignore]
+  fn test_gadget_zero() {
+    let mut device = devices(&EnumerationOptions::default())
+      .unwrap()
+      .into_iter()
+      .find(|d| d.vendor_id == 0x0525 && d.product_id == 0xa4a0)
+      .expect("gadget zero not found");
+
+    device.open().unwrap();
+    // Loopback
+    device.select_configuration(2).unwrap();
+    device.claim_interface(0).unwrap();
+
+    let configuration = device.configuration.clone().unwrap();
+    let endpoint = |direction| {
+      configuration
+        .interface(0)
+        .unwrap()
+        .endpoints()
+        .iter()
+        .find(|e| {
+          e.direction() == direction
+            && e.endpoint_type() == UsbEndpointType::Bulk
+        })
+        .unwrap()
+        .endpoint_number()
+    };
+    let (ep_in, ep_out) = (endpoint(Direction::In), endpoint(Direction::Out));
+
+    let data: Vec<u8> = (0..64).collect();
+    assert_eq!(device.transfer_out(ep_out, &data).unwrap(), 64);
+    assert_eq!(device.transfer_in(ep_in, 64).unwrap(), data);
+
+    device.set_transfer_timeout(Some(std::time::Duration::from_millis(100)));
+    assert_eq!(
+      device.transfer_in(ep_in, 64).unwrap_err(),
+      crate::Error::Io(std::io::ErrorKind::TimedOut)
+    );
+
+    device.release_interface(0).unwrap();
+    device.close().unwrap();
+  }
+}
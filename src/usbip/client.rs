@@ -0,0 +1,852 @@
+//! USB/IP client. Imports devices exported by a USB/IP server (e.g. Linux
+//! `usbipd`) and drives them as ordinary `UsbDevice`s.
+//!
+//! ```no_run
+//! # fn main() -> webusb::Result<()> {
+//! use webusb::usbip::client::UsbIpHost;
+//! use webusb::Context;
+//!
+//! let mut ctx = Context::init()?;
+//! ctx.add_usbip_host(UsbIpHost::new("lab-machine:3240")?);
+//!
+//! // Local devices followed by the devices exported by lab-machine.
+//! let devices = ctx.devices()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The server hands a device to one client at a time. Devices listed by
+//! `UsbIpHost::devices` are only imported while they are open, devices
+//! returned by `UsbIpHost::import` for as long as they exist.
+
+use core::convert::TryFrom;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use crate::backend::DeviceBackend;
+use crate::constants::CLEAR_FEATURE_REQUEST;
+use crate::constants::ENDPOINT_HALT_FEATURE;
+use crate::constants::HUB_CLASS;
+use crate::constants::PORT_RESET_FEATURE;
+use crate::constants::SET_CONFIGURATION_REQUEST;
+use crate::constants::SET_FEATURE_REQUEST;
+use crate::constants::SET_INTERFACE_REQUEST;
+use crate::enumeration::read_device;
+use crate::enumeration::Topology;
+use crate::usbip::parse_busid;
+use crate::usbip::speed_from_usbip;
+use crate::usbip::BasicHeader;
+use crate::usbip::ExportedDevice;
+use crate::usbip::OpHeader;
+use crate::usbip::SubmitCommand;
+use crate::usbip::SubmitReply;
+use crate::usbip::UnlinkCommand;
+use crate::usbip::UnlinkReply;
+use crate::usbip::OP_REP_DEVLIST;
+use crate::usbip::OP_REP_IMPORT;
+use crate::usbip::OP_REQ_DEVLIST;
+use crate::usbip::OP_REQ_IMPORT;
+use crate::usbip::USBIP_CMD_SUBMIT;
+use crate::usbip::USBIP_CMD_UNLINK;
+use crate::usbip::USBIP_DIR_IN;
+use crate::usbip::USBIP_DIR_OUT;
+use crate::usbip::USBIP_RET_SUBMIT;
+use crate::usbip::USBIP_RET_UNLINK;
+use crate::EnumerationOptions;
+use crate::Error;
+use crate::Result;
+use crate::UsbDevice;
+use crate::UsbEndpointType;
+
+// Linux errno values reported in the status of RET_SUBMIT.
+const ENOENT: i32 = 2;
+const ENODEV: i32 = 19;
+const EPIPE: i32 = 32;
+const ECONNRESET: i32 = 104;
+const ESHUTDOWN: i32 = 108;
+
+// The server releases a device some time after the connection of the
+// previous import closed. `open` keeps trying for this long.
+const IMPORT_RETRY: Duration = Duration::from_secs(1);
+
+/// A USB/IP server.
+#[derive(Clone, Debug)]
+pub struct UsbIpHost {
+  addr: SocketAddr,
+  timeout: Option<Duration>,
+}
+
+impl UsbIpHost {
+  /// `addr` is the address of the server, usually on port
+  /// `usbip::USBIP_PORT`.
+  pub fn new(addr: impl ToSocketAddrs) -> Result<Self> {
+    let addr = addr
+      .to_socket_addrs()?
+      .next()
+      .ok_or(Error::Io(std::io::ErrorKind::AddrNotAvailable))?;
+    Ok(UsbIpHost {
+      addr,
+      timeout: None,
+    })
+  }
+
+  /// Bulk and interrupt transfers that do not complete within `timeout` are
+  /// cancelled with USBIP_CMD_UNLINK and fail with
+  /// `Error::Io(ErrorKind::TimedOut)`. By default, transfers never time out.
+  ///
+  /// Same as `UsbDevice::set_transfer_timeout` on every imported device.
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Lists the devices exported by the server (OP_REQ_DEVLIST).
+  pub fn exported_devices(&self) -> Result<Vec<ExportedDevice>> {
+    let mut stream = TcpStream::connect(self.addr)?;
+    OpHeader::new(OP_REQ_DEVLIST, 0).write_to(&mut stream)?;
+
+    let header = OpHeader::read_from(&mut stream)?;
+    if header.code != OP_REP_DEVLIST || header.status != 0 {
+      return Err(Error::Io(std::io::ErrorKind::InvalidData));
+    }
+
+    let mut num_devices = [0; 4];
+    std::io::Read::read_exact(&mut stream, &mut num_devices)?;
+    let mut devices = vec![];
+    for _ in 0..u32::from_be_bytes(num_devices) {
+      devices.push(ExportedDevice::read_from(&mut stream, true)?);
+    }
+
+    Ok(devices)
+  }
+
+  /// Lists the exported devices as `UsbDevice`s. Devices imported by
+  /// someone else are skipped.
+  ///
+  /// Every device is imported just long enough to read its descriptors.
+  /// `UsbDevice::open` imports it again and `UsbDevice::close` releases
+  /// it, so listed devices stay available to other clients until opened.
+  pub fn devices(
+    &self,
+    options: &EnumerationOptions,
+  ) -> Result<Vec<UsbDevice>> {
+    let mut devices = vec![];
+    for exported in self.exported_devices()? {
+      if !options.include_hubs && exported.device_class == HUB_CLASS {
+        continue;
+      }
+
+      let backend = UsbIp {
+        host: self.clone(),
+        busid: exported.busid.clone(),
+        stream: None,
+        devid: exported.devid(),
+        seqnum: 0,
+        timeout: self.timeout,
+        lazy: true,
+      };
+      match read_device(Box::new(backend), topology(&exported)) {
+        Ok(device) => devices.push(device),
+        // Imported by someone else, or gone since it was listed.
+        Err(Error::NotFound) => {}
+        Err(err) => return Err(err),
+      }
+    }
+    Ok(devices)
+  }
+
+  /// Imports the device with the given bus ID (OP_REQ_IMPORT).
+  ///
+  /// The server hands the device to a single client at a time. It stays
+  /// imported until the returned `UsbDevice` is dropped. Fails with
+  /// `Error::NotFound` if the device does not exist or is imported by
+  /// someone else.
+  pub fn import(&self, busid: &str) -> Result<UsbDevice> {
+    let (stream, exported) = self.request_import(busid)?;
+    let backend = UsbIp {
+      host: self.clone(),
+      busid: busid.to_string(),
+      stream: Some(stream),
+      devid: exported.devid(),
+      seqnum: 0,
+      timeout: self.timeout,
+      lazy: false,
+    };
+    read_device(Box::new(backend), topology(&exported))
+  }
+
+  // OP_REQ_IMPORT. Returns the connection of the import, which then
+  // carries the URBs.
+  fn request_import(&self, busid: &str) -> Result<(TcpStream, ExportedDevice)> {
+    let mut stream = TcpStream::connect(self.addr)?;
+    stream.set_nodelay(true)?;
+
+    let mut request = vec![];
+    OpHeader::new(OP_REQ_IMPORT, 0).write_to(&mut request)?;
+    let mut busid_field = [0; 32];
+    let n = busid.len().min(31);
+    busid_field[..n].copy_from_slice(&busid.as_bytes()[..n]);
+    request.extend_from_slice(&busid_field);
+    std::io::Write::write_all(&mut stream, &request)?;
+
+    let header = OpHeader::read_from(&mut stream)?;
+    if header.code != OP_REP_IMPORT {
+      return Err(Error::Io(std::io::ErrorKind::InvalidData));
+    }
+    if header.status != 0 {
+      return Err(Error::NotFound);
+    }
+    let exported = ExportedDevice::read_from(&mut stream, false)?;
+    Ok((stream, exported))
+  }
+}
+
+fn topology(exported: &ExportedDevice) -> Topology {
+  let (bus_number, port_numbers) =
+    parse_busid(&exported.busid).unwrap_or((exported.busnum as u8, vec![]));
+  Topology {
+    bus_number,
+    device_address: exported.devnum as u8,
+    port_numbers,
+    speed: speed_from_usbip(exported.speed),
+    // The hub on the server is not visible to the client.
+    parent_hub: None,
+  }
+}
+
+/// Backend of an imported device. Every operation is a synchronous
+/// USBIP_CMD_SUBMIT on the connection of the import.
+struct UsbIp {
+  host: UsbIpHost,
+  busid: String,
+  // None while the device is not imported.
+  stream: Option<TcpStream>,
+  devid: u32,
+  seqnum: u32,
+  timeout: Option<Duration>,
+  // Imported from `open` to `close` rather than for the whole lifetime of
+  // the backend.
+  lazy: bool,
+}
+
+impl UsbIp {
+  fn stream(&mut self) -> Result<&mut TcpStream> {
+    self.stream.as_mut().ok_or(Error::InvalidState)
+  }
+
+  fn submit(
+    &mut self,
+    endpoint: u8,
+    setup: [u8; 8],
+    data: &[u8],
+    in_length: usize,
+  ) -> Result<SubmitReply> {
+    let direction_in = endpoint & 0x80 != 0;
+    let command = SubmitCommand {
+      header: self.next_header(USBIP_CMD_SUBMIT, endpoint),
+      transfer_flags: 0,
+      transfer_buffer_length: match direction_in {
+        true => in_length as u32,
+        false => data.len() as u32,
+      },
+      start_frame: 0,
+      number_of_packets: 0xFFFFFFFF,
+      interval: 0,
+      setup,
+      data: match direction_in {
+        true => vec![],
+        false => data.to_vec(),
+      },
+    };
+    let seqnum = command.header.seqnum;
+    command.write_to(self.stream()?)?;
+
+    // Control transfers are never cancelled.
+    let timeout = match endpoint & 0x0F {
+      0 => None,
+      _ => self.timeout,
+    };
+    let reply = match self.wait_for_reply(timeout)? {
+      true => {
+        let stream = self.stream()?;
+        let header = BasicHeader::read_from(stream)?;
+        if header.command != USBIP_RET_SUBMIT || header.seqnum != seqnum {
+          return Err(Error::Io(std::io::ErrorKind::InvalidData));
+        }
+        SubmitReply::read_body(header, stream, direction_in)?
+      }
+      false => match self.unlink(endpoint, seqnum, direction_in)? {
+        Some(reply) => reply,
+        None => return Err(Error::Io(std::io::ErrorKind::TimedOut)),
+      },
+    };
+
+    match reply.status {
+      0 => Ok(reply),
+      status => Err(error_from_status(status)),
+    }
+  }
+
+  // Waits up to `timeout` for the next reply without consuming it.
+  // Returns false on timeout.
+  fn wait_for_reply(&mut self, timeout: Option<Duration>) -> Result<bool> {
+    let timeout = match timeout {
+      Some(timeout) => timeout,
+      None => return Ok(true),
+    };
+
+    let stream = self.stream()?;
+    stream.set_read_timeout(Some(timeout))?;
+    let result = stream.peek(&mut [0; 1]);
+    stream.set_read_timeout(None)?;
+
+    match result {
+      Ok(0) => Err(Error::Io(std::io::ErrorKind::UnexpectedEof)),
+      Ok(_) => Ok(true),
+      Err(err)
+        if err.kind() == std::io::ErrorKind::WouldBlock
+          || err.kind() == std::io::ErrorKind::TimedOut =>
+      {
+        Ok(false)
+      }
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  // Cancels the CMD_SUBMIT with `unlink_seqnum`. The server answers with
+  // RET_UNLINK, but the RET_SUBMIT may still arrive first if the transfer
+  // completed in the meantime. It is returned then, None otherwise.
+  fn unlink(
+    &mut self,
+    endpoint: u8,
+    unlink_seqnum: u32,
+    direction_in: bool,
+  ) -> Result<Option<SubmitReply>> {
+    let command = UnlinkCommand {
+      header: self.next_header(USBIP_CMD_UNLINK, endpoint),
+      unlink_seqnum,
+    };
+    let seqnum = command.header.seqnum;
+    let stream = self.stream()?;
+    command.write_to(stream)?;
+
+    let mut completed = None;
+    loop {
+      let header = BasicHeader::read_from(stream)?;
+      match header.command {
+        USBIP_RET_SUBMIT if header.seqnum == unlink_seqnum => {
+          completed =
+            Some(SubmitReply::read_body(header, stream, direction_in)?);
+        }
+        USBIP_RET_UNLINK if header.seqnum == seqnum => {
+          UnlinkReply::read_body(header, stream)?;
+          return Ok(completed);
+        }
+        _ => return Err(Error::Io(std::io::ErrorKind::InvalidData)),
+      }
+    }
+  }
+
+  fn next_header(&mut self, command: u32, endpoint: u8) -> BasicHeader {
+    self.seqnum = self.seqnum.wrapping_add(1);
+    BasicHeader {
+      command,
+      seqnum: self.seqnum,
+      devid: self.devid,
+      direction: match endpoint & 0x80 {
+        0 => USBIP_DIR_OUT,
+        _ => USBIP_DIR_IN,
+      },
+      ep: (endpoint & 0x0F) as u32,
+    }
+  }
+
+  fn control(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+    in_length: usize,
+  ) -> Result<SubmitReply> {
+    let length = match request_type & 0x80 {
+      0 => data.len(),
+      _ => in_length,
+    };
+    // wLength of the setup packet.
+    let length = u16::try_from(length).map_err(|_| Error::InvalidAccess)?;
+    let mut setup = [0; 8];
+    setup[0] = request_type;
+    setup[1] = request;
+    setup[2..4].copy_from_slice(&value.to_le_bytes());
+    setup[4..6].copy_from_slice(&index.to_le_bytes());
+    setup[6..8].copy_from_slice(&length.to_le_bytes());
+
+    // The direction of endpoint 0 is taken from bmRequestType.
+    self.submit(request_type & 0x80, setup, data, in_length)
+  }
+}
+
+fn error_from_status(status: i32) -> Error {
+  match -status {
+    EPIPE => Error::Stall,
+    ENODEV | ESHUTDOWN => Error::NotFound,
+    ENOENT | ECONNRESET => Error::Io(std::io::ErrorKind::Interrupted),
+    _ => Error::Io(std::io::ErrorKind::Other),
+  }
+}
+
+impl DeviceBackend for UsbIp {
+  // Devices returned by `UsbIpHost::import` are imported already, and
+  // stay imported until they are dropped.
+  fn open(&mut self) -> Result<()> {
+    if self.stream.is_some() {
+      return Ok(());
+    }
+
+    let start = std::time::Instant::now();
+    let stream = loop {
+      match self.host.request_import(&self.busid) {
+        Ok((stream, exported)) => {
+          self.devid = exported.devid();
+          break stream;
+        }
+        Err(Error::NotFound) if start.elapsed() < IMPORT_RETRY => {
+          std::thread::sleep(Duration::from_millis(10));
+        }
+        Err(err) => return Err(err),
+      }
+    };
+    self.stream = Some(stream);
+    self.seqnum = 0;
+    Ok(())
+  }
+
+  // Closing the connection makes the server release the device.
+  fn close(&mut self) -> Result<()> {
+    if self.lazy {
+      self.stream = None;
+    }
+    Ok(())
+  }
+
+  fn set_configuration(&mut self, configuration_value: u8) -> Result<()> {
+    // Intercepted by the server, which calls usb_set_configuration.
+    self.control(
+      0x00,
+      SET_CONFIGURATION_REQUEST,
+      configuration_value as u16,
+      0,
+      &[],
+      0,
+    )?;
+    Ok(())
+  }
+
+  // Interfaces are claimed by the server for the whole device.
+  fn claim_interface(&mut self, _: u8) -> Result<()> {
+    Ok(())
+  }
+
+  fn release_interface(&mut self, _: u8) -> Result<()> {
+    Ok(())
+  }
+
+  fn set_alternate_setting(
+    &mut self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Result<()> {
+    self.control(
+      0x01,
+      SET_INTERFACE_REQUEST,
+      alternate_setting as u16,
+      interface_number as u16,
+      &[],
+      0,
+    )?;
+    Ok(())
+  }
+
+  fn control_in(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    let reply =
+      self.control(request_type, request, value, index, &[], buffer.len())?;
+    let length = reply.data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&reply.data[..length]);
+    Ok(length)
+  }
+
+  fn control_out(
+    &mut self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+  ) -> Result<usize> {
+    let reply = self.control(request_type, request, value, index, data, 0)?;
+    Ok(reply.actual_length as usize)
+  }
+
+  fn clear_halt(&mut self, endpoint: u8) -> Result<()> {
+    self.control(
+      0x02,
+      CLEAR_FEATURE_REQUEST,
+      ENDPOINT_HALT_FEATURE,
+      endpoint as u16,
+      &[],
+      0,
+    )?;
+    Ok(())
+  }
+
+  fn transfer_in(
+    &mut self,
+    _: UsbEndpointType,
+    endpoint: u8,
+    buffer: &mut [u8],
+  ) -> Result<usize> {
+    let reply = self.submit(endpoint, [0; 8], &[], buffer.len())?;
+    let length = reply.data.len().min(buffer.len());
+    buffer[..length].copy_from_slice(&reply.data[..length]);
+    Ok(length)
+  }
+
+  fn transfer_out(
+    &mut self,
+    _: UsbEndpointType,
+    endpoint: u8,
+    data: &[u8],
+  ) -> Result<usize> {
+    let reply = self.submit(endpoint, [0; 8], data, 0)?;
+    Ok(reply.actual_length as usize)
+  }
+
+  fn reset(&mut self) -> Result<()> {
+    // SET_FEATURE(PORT_RESET) to the port of the device, intercepted by the
+    // server which resets the device instead.
+    self.control(0x23, SET_FEATURE_REQUEST, PORT_RESET_FEATURE, 0, &[], 0)?;
+    Ok(())
+  }
+
+  fn set_transfer_timeout(&mut self, timeout: Option<Duration>) {
+    self.timeout = timeout;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::VecDeque;
+  use std::net::TcpListener;
+  use std::net::TcpStream;
+  use std::time::Duration;
+
+  use crate::loopback::control;
+  use crate::loopback::DEVICE_DESCRIPTOR;
+  use crate::usbip::client::UsbIpHost;
+  use crate::usbip::BasicHeader;
+  use crate::usbip::ExportedDevice;
+  use crate::usbip::ExportedInterface;
+  use crate::usbip::OpHeader;
+  use crate::usbip::SubmitCommand;
+  use crate::usbip::SubmitReply;
+  use crate::usbip::UnlinkCommand;
+  use crate::usbip::UnlinkReply;
+  use crate::usbip::OP_REP_DEVLIST;
+  use crate::usbip::OP_REP_IMPORT;
+  use crate::usbip::OP_REQ_DEVLIST;
+  use crate::usbip::USBIP_CMD_SUBMIT;
+  use crate::usbip::USBIP_DIR_IN;
+  use crate::usbip::USBIP_RET_SUBMIT;
+  use crate::usbip::USBIP_RET_UNLINK;
+  use crate::EnumerationOptions;
+  use crate::Error;
+  use crate::UsbControlTransferParameters;
+  use crate::UsbRecipient;
+  use crate::UsbRequestType;
+  use crate::UsbSpeed;
+
+  fn exported_device() -> ExportedDevice {
+    ExportedDevice {
+      path: "/sys/devices/platform/vhci/usb1/1-1.3".to_string(),
+      busid: "1-1.3".to_string(),
+      busnum: 1,
+      devnum: 5,
+      speed: 3,
+      vendor_id: 0x1234,
+      product_id: 0x5678,
+      bcd_device: 0x0100,
+      device_class: 0,
+      device_subclass: 0,
+      device_protocol: 0,
+      configuration_value: 1,
+      num_configurations: 1,
+      num_interfaces: 1,
+      interfaces: vec![ExportedInterface {
+        interface_class: 0xFF,
+        interface_subclass: 0,
+        interface_protocol: 0,
+      }],
+    }
+  }
+
+  // Serves a single imported loopback device: data written to endpoint 2
+  // can be read back from endpoint 1. Reads from endpoint 1 without data
+  // stay pending until they are unlinked, reads of data starting with
+  // "late" are answered after 200 ms.
+  fn serve_device(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut buffered: VecDeque<u8> = VecDeque::new();
+    let mut pending: Option<SubmitCommand> = None;
+
+    loop {
+      let header = BasicHeader::read_from(&mut stream)?;
+      let mut reply = SubmitReply {
+        header: BasicHeader {
+          command: USBIP_RET_SUBMIT,
+          seqnum: header.seqnum,
+          devid: 0,
+          direction: 0,
+          ep: 0,
+        },
+        status: 0,
+        actual_length: 0,
+        start_frame: 0,
+        number_of_packets: 0xFFFFFFFF,
+        error_count: 0,
+        data: vec![],
+      };
+
+      if header.command != USBIP_CMD_SUBMIT {
+        let command = UnlinkCommand::read_body(header, &mut stream)?;
+        let unlinked = pending
+          .take()
+          .filter(|p| p.header.seqnum == command.unlink_seqnum);
+        UnlinkReply {
+          header: BasicHeader {
+            command: USBIP_RET_UNLINK,
+            seqnum: command.header.seqnum,
+            devid: 0,
+            direction: 0,
+            ep: 0,
+          },
+          status: match unlinked {
+            Some(_) => -104,
+            None => 0,
+          },
+        }
+        .write_to(&mut stream)?;
+        continue;
+      }
+
+      let command = SubmitCommand::read_body(header, &mut stream)?;
+      match (command.header.ep, command.header.direction) {
+        (0, _) => {
+          let (status, data) = control(&command.setup);
+          reply.status = status;
+          reply.data = data;
+        }
+        (1, USBIP_DIR_IN) if buffered.is_empty() => {
+          pending = Some(command);
+          continue;
+        }
+        (1, USBIP_DIR_IN) => {
+          if buffered.iter().take(4).eq(b"late") {
+            std::thread::sleep(Duration::from_millis(200));
+          }
+          let length =
+            buffered.len().min(command.transfer_buffer_length as usize);
+          reply.data = buffered.drain(..length).collect();
+        }
+        (2, _) => {
+          buffered.extend(&command.data);
+          reply.actual_length = command.data.len() as u32;
+        }
+        _ => reply.status = -32,
+      }
+
+      if command.header.direction == USBIP_DIR_IN {
+        reply.data.truncate(command.transfer_buffer_length as usize);
+        reply.actual_length = reply.data.len() as u32;
+      }
+      reply.write_to(&mut stream)?;
+    }
+  }
+
+  // Minimal in-process USB/IP server exporting one loopback device.
+  fn stand_in_server() -> UsbIpHost {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = UsbIpHost::new(listener.local_addr().unwrap()).unwrap();
+
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        std::thread::spawn(move || -> std::io::Result<()> {
+          let request = OpHeader::read_from(&mut stream)?;
+          if request.code == OP_REQ_DEVLIST {
+            OpHeader::new(OP_REP_DEVLIST, 0).write_to(&mut stream)?;
+            std::io::Write::write_all(&mut stream, &1u32.to_be_bytes())?;
+            return exported_device().write_to(&mut stream, true);
+          }
+
+          let mut busid = [0; 32];
+          std::io::Read::read_exact(&mut stream, &mut busid)?;
+          if !busid.starts_with(b"1-1.3\0") {
+            return OpHeader::new(OP_REP_IMPORT, 1).write_to(&mut stream);
+          }
+          OpHeader::new(OP_REP_IMPORT, 0).write_to(&mut stream)?;
+          exported_device().write_to(&mut stream, false)?;
+          serve_device(stream)
+        });
+      }
+    });
+
+    host
+  }
+
+  #[test]
+  fn test_exported_devices() {
+    let host = stand_in_server();
+    let devices = host.exported_devices().unwrap();
+    assert_eq!(devices, vec![exported_device()]);
+  }
+
+  #[test]
+  fn test_import() {
+    let host = stand_in_server();
+    let device = host.import("1-1.3").unwrap();
+
+    assert_eq!(device.vendor_id, 0x1234);
+    assert_eq!(device.product_id, 0x5678);
+    assert_eq!(device.usb_version_major, 2);
+    assert_eq!(device.manufacturer_name, Some("Stand-in".to_string()));
+    assert_eq!(device.product_name, Some("Loopback".to_string()));
+    assert_eq!(device.serial_number, None);
+    assert_eq!(device.port_path(), "1-1.3");
+    assert_eq!(device.speed, UsbSpeed::High);
+    assert_eq!(device.configurations.len(), 1);
+
+    let configuration = device.configuration.as_ref().unwrap();
+    assert_eq!(configuration.configuration_value(), 1);
+    assert_eq!(configuration.max_power(), 100);
+    assert_eq!(configuration.interfaces()[0].endpoints().len(), 3);
+
+    assert_eq!(host.import("2-1").err(), Some(Error::NotFound));
+  }
+
+  #[test]
+  fn test_transfers() {
+    let host = stand_in_server();
+    let mut device = host.import("1-1.3").unwrap();
+
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+
+    assert_eq!(device.transfer_out(2, b"hello").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"hello");
+
+    assert_eq!(device.transfer_in(3, 64).err(), Some(Error::Stall));
+
+    let descriptor = device
+      .control_transfer_in(
+        UsbControlTransferParameters {
+          request_type: UsbRequestType::Standard,
+          recipient: UsbRecipient::Device,
+          request: 0x06,
+          value: 0x0100,
+          index: 0,
+        },
+        8,
+      )
+      .unwrap();
+    assert_eq!(descriptor, &DEVICE_DESCRIPTOR[..8]);
+
+    // wLength is 16 bits wide.
+    let parameters = UsbControlTransferParameters {
+      request_type: UsbRequestType::Vendor,
+      recipient: UsbRecipient::Device,
+      request: 0x01,
+      value: 0,
+      index: 0,
+    };
+    assert_eq!(
+      device
+        .control_transfer_in(parameters.clone(), 0x10000)
+        .err(),
+      Some(Error::InvalidAccess)
+    );
+    assert_eq!(
+      device.control_transfer_out(parameters, &[0; 0x10000]).err(),
+      Some(Error::InvalidAccess)
+    );
+
+    device.close().unwrap();
+  }
+
+  #[test]
+  fn test_unlink_on_timeout() {
+    let host = stand_in_server().with_timeout(Duration::from_millis(100));
+    let mut device = host.import("1-1.3").unwrap();
+
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+
+    // Nothing to read, the transfer is unlinked.
+    assert_eq!(
+      device.transfer_in(1, 64).err(),
+      Some(Error::Io(std::io::ErrorKind::TimedOut))
+    );
+
+    // The connection is still usable.
+    assert_eq!(device.transfer_out(2, b"again").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"again");
+  }
+
+  #[test]
+  fn test_reply_before_unlink() {
+    let host = stand_in_server().with_timeout(Duration::from_millis(100));
+    let mut device = host.import("1-1.3").unwrap();
+
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+
+    // The transfer completes after it timed out, but before the server
+    // got the unlink. Its data is not lost.
+    assert_eq!(device.transfer_out(2, b"late").unwrap(), 4);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"late");
+
+    assert_eq!(device.transfer_out(2, b"again").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"again");
+  }
+
+  #[test]
+  fn test_devices() {
+    let host = stand_in_server();
+    let options = EnumerationOptions::default();
+
+    // Listing devices does not import them for good.
+    let devices = host.devices(&options).unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].product_name, Some("Loopback".to_string()));
+    let mut device = host.devices(&options).unwrap().remove(0);
+
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+    assert_eq!(device.transfer_out(2, b"hello").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"hello");
+    device.close().unwrap();
+  }
+}
@@ -0,0 +1,622 @@
+//! The USB/IP protocol.
+//!
+//! https://www.kernel.org/doc/html/latest/usb/usbip_protocol.html
+//!
+//! All fields are big-endian, except for the setup packet which is sent
+//! as-is.
+
+use std::io::Read;
+use std::io::Write;
+
+use crate::UsbSpeed;
+
+pub mod client;
+pub mod server;
+
+/// Default port of `usbipd`.
+pub const USBIP_PORT: u16 = 3240;
+
+pub const USBIP_VERSION: u16 = 0x0111;
+
+pub const OP_REQ_DEVLIST: u16 = 0x8005;
+pub const OP_REP_DEVLIST: u16 = 0x0005;
+pub const OP_REQ_IMPORT: u16 = 0x8003;
+pub const OP_REP_IMPORT: u16 = 0x0003;
+
+pub const USBIP_CMD_SUBMIT: u32 = 0x0001;
+pub const USBIP_CMD_UNLINK: u32 = 0x0002;
+pub const USBIP_RET_SUBMIT: u32 = 0x0003;
+pub const USBIP_RET_UNLINK: u32 = 0x0004;
+
+pub const USBIP_DIR_OUT: u32 = 0;
+pub const USBIP_DIR_IN: u32 = 1;
+
+/// Largest transfer_buffer_length accepted from the peer. Longer URBs are
+/// a protocol error rather than an allocation of whatever size was asked.
+pub const MAX_TRANSFER_BUFFER_LENGTH: u32 = 16 * 1024 * 1024;
+
+// Lengths of the fixed size parts of the messages.
+const PATH_LENGTH: usize = 256;
+const BUSID_LENGTH: usize = 32;
+const URB_PADDING_LENGTH: usize = 24;
+const ISO_PACKET_DESCRIPTOR_LENGTH: usize = 16;
+
+/// Header of the OP_REQ_* and OP_REP_* messages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpHeader {
+  pub version: u16,
+  pub code: u16,
+  /// 0 for OK, 1 for error.
+  pub status: u32,
+}
+
+impl OpHeader {
+  pub fn new(code: u16, status: u32) -> Self {
+    OpHeader {
+      version: USBIP_VERSION,
+      code,
+      status,
+    }
+  }
+
+  pub fn read_from(r: &mut impl Read) -> std::io::Result<Self> {
+    Ok(OpHeader {
+      version: read_u16(r)?,
+      code: read_u16(r)?,
+      status: read_u32(r)?,
+    })
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    w.write_all(&self.version.to_be_bytes())?;
+    w.write_all(&self.code.to_be_bytes())?;
+    w.write_all(&self.status.to_be_bytes())
+  }
+}
+
+/// Interface of an exported device, as listed by OP_REP_DEVLIST.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedInterface {
+  pub interface_class: u8,
+  pub interface_subclass: u8,
+  pub interface_protocol: u8,
+}
+
+/// Device record of OP_REP_DEVLIST and OP_REP_IMPORT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedDevice {
+  /// sysfs path of the device on the server.
+  pub path: String,
+  /// Bus ID of the device on the server, e.g. `1-2.4`.
+  pub busid: String,
+  pub busnum: u32,
+  pub devnum: u32,
+  /// See `speed_from_usbip`.
+  pub speed: u32,
+  pub vendor_id: u16,
+  pub product_id: u16,
+  pub bcd_device: u16,
+  pub device_class: u8,
+  pub device_subclass: u8,
+  pub device_protocol: u8,
+  pub configuration_value: u8,
+  pub num_configurations: u8,
+  pub num_interfaces: u8,
+  /// Only sent by OP_REP_DEVLIST.
+  pub interfaces: Vec<ExportedInterface>,
+}
+
+impl ExportedDevice {
+  /// Reads the device record. Interfaces are read only if
+  /// `with_interfaces` is set, as OP_REP_IMPORT does not include them.
+  pub fn read_from(
+    r: &mut impl Read,
+    with_interfaces: bool,
+  ) -> std::io::Result<Self> {
+    let mut device = ExportedDevice {
+      path: read_string(r, PATH_LENGTH)?,
+      busid: read_string(r, BUSID_LENGTH)?,
+      busnum: read_u32(r)?,
+      devnum: read_u32(r)?,
+      speed: read_u32(r)?,
+      vendor_id: read_u16(r)?,
+      product_id: read_u16(r)?,
+      bcd_device: read_u16(r)?,
+      device_class: read_u8(r)?,
+      device_subclass: read_u8(r)?,
+      device_protocol: read_u8(r)?,
+      configuration_value: read_u8(r)?,
+      num_configurations: read_u8(r)?,
+      num_interfaces: read_u8(r)?,
+      interfaces: vec![],
+    };
+
+    if with_interfaces {
+      for _ in 0..device.num_interfaces {
+        let mut bytes = [0; 4];
+        r.read_exact(&mut bytes)?;
+        device.interfaces.push(ExportedInterface {
+          interface_class: bytes[0],
+          interface_subclass: bytes[1],
+          interface_protocol: bytes[2],
+        });
+      }
+    }
+
+    Ok(device)
+  }
+
+  pub fn write_to(
+    &self,
+    w: &mut impl Write,
+    with_interfaces: bool,
+  ) -> std::io::Result<()> {
+    write_string(w, &self.path, PATH_LENGTH)?;
+    write_string(w, &self.busid, BUSID_LENGTH)?;
+    w.write_all(&self.busnum.to_be_bytes())?;
+    w.write_all(&self.devnum.to_be_bytes())?;
+    w.write_all(&self.speed.to_be_bytes())?;
+    w.write_all(&self.vendor_id.to_be_bytes())?;
+    w.write_all(&self.product_id.to_be_bytes())?;
+    w.write_all(&self.bcd_device.to_be_bytes())?;
+    w.write_all(&[
+      self.device_class,
+      self.device_subclass,
+      self.device_protocol,
+      self.configuration_value,
+      self.num_configurations,
+      self.num_interfaces,
+    ])?;
+
+    if with_interfaces {
+      for interface in &self.interfaces {
+        w.write_all(&[
+          interface.interface_class,
+          interface.interface_subclass,
+          interface.interface_protocol,
+          0,
+        ])?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// devid field of the URB messages.
+  pub fn devid(&self) -> u32 {
+    self.busnum << 16 | self.devnum
+  }
+}
+
+/// usbip_header_basic, common to all URB messages.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicHeader {
+  pub command: u32,
+  pub seqnum: u32,
+  pub devid: u32,
+  /// `USBIP_DIR_OUT` or `USBIP_DIR_IN`.
+  pub direction: u32,
+  /// Endpoint number, without the direction bit.
+  pub ep: u32,
+}
+
+impl BasicHeader {
+  pub fn read_from(r: &mut impl Read) -> std::io::Result<Self> {
+    Ok(BasicHeader {
+      command: read_u32(r)?,
+      seqnum: read_u32(r)?,
+      devid: read_u32(r)?,
+      direction: read_u32(r)?,
+      ep: read_u32(r)?,
+    })
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    w.write_all(&self.command.to_be_bytes())?;
+    w.write_all(&self.seqnum.to_be_bytes())?;
+    w.write_all(&self.devid.to_be_bytes())?;
+    w.write_all(&self.direction.to_be_bytes())?;
+    w.write_all(&self.ep.to_be_bytes())
+  }
+}
+
+/// USBIP_CMD_SUBMIT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubmitCommand {
+  pub header: BasicHeader,
+  pub transfer_flags: u32,
+  pub transfer_buffer_length: u32,
+  pub start_frame: u32,
+  /// 0xFFFFFFFF for non-isochronous transfers.
+  pub number_of_packets: u32,
+  pub interval: u32,
+  /// Setup packet of control transfers, zeroes otherwise.
+  pub setup: [u8; 8],
+  /// Data of OUT transfers.
+  pub data: Vec<u8>,
+}
+
+impl SubmitCommand {
+  /// Reads the rest of the message after `header`. Fails with
+  /// `ErrorKind::InvalidData` if transfer_buffer_length exceeds
+  /// `MAX_TRANSFER_BUFFER_LENGTH`.
+  ///
+  /// NOTE: Isochronous packet descriptors are read but dropped.
+  pub fn read_body(
+    header: BasicHeader,
+    r: &mut impl Read,
+  ) -> std::io::Result<Self> {
+    let mut command = SubmitCommand {
+      header,
+      transfer_flags: read_u32(r)?,
+      transfer_buffer_length: read_u32(r)?,
+      start_frame: read_u32(r)?,
+      number_of_packets: read_u32(r)?,
+      interval: read_u32(r)?,
+      setup: [0; 8],
+      data: vec![],
+    };
+    r.read_exact(&mut command.setup)?;
+
+    if command.transfer_buffer_length > MAX_TRANSFER_BUFFER_LENGTH {
+      return Err(std::io::ErrorKind::InvalidData.into());
+    }
+    if command.header.direction == USBIP_DIR_OUT {
+      command.data = vec![0; command.transfer_buffer_length as usize];
+      r.read_exact(&mut command.data)?;
+    }
+    skip_iso_packet_descriptors(r, command.number_of_packets)?;
+
+    Ok(command)
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(48 + self.data.len());
+    self.header.write_to(&mut bytes)?;
+    bytes.extend_from_slice(&self.transfer_flags.to_be_bytes());
+    bytes.extend_from_slice(&self.transfer_buffer_length.to_be_bytes());
+    bytes.extend_from_slice(&self.start_frame.to_be_bytes());
+    bytes.extend_from_slice(&self.number_of_packets.to_be_bytes());
+    bytes.extend_from_slice(&self.interval.to_be_bytes());
+    bytes.extend_from_slice(&self.setup);
+    bytes.extend_from_slice(&self.data);
+    w.write_all(&bytes)
+  }
+}
+
+/// USBIP_RET_SUBMIT.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubmitReply {
+  pub header: BasicHeader,
+  /// 0 for success, a negative Linux errno otherwise.
+  pub status: i32,
+  pub actual_length: u32,
+  pub start_frame: u32,
+  pub number_of_packets: u32,
+  pub error_count: u32,
+  /// Data of IN transfers.
+  pub data: Vec<u8>,
+}
+
+impl SubmitReply {
+  /// Reads the rest of the message after `header`.
+  ///
+  /// The direction of RET_SUBMIT is always 0, so whether data follows
+  /// (`direction_in`) has to be looked up from the matching CMD_SUBMIT.
+  pub fn read_body(
+    header: BasicHeader,
+    r: &mut impl Read,
+    direction_in: bool,
+  ) -> std::io::Result<Self> {
+    let mut reply = SubmitReply {
+      header,
+      status: read_u32(r)? as i32,
+      actual_length: read_u32(r)?,
+      start_frame: read_u32(r)?,
+      number_of_packets: read_u32(r)?,
+      error_count: read_u32(r)?,
+      data: vec![],
+    };
+    let mut padding = [0; 8];
+    r.read_exact(&mut padding)?;
+
+    if reply.actual_length > MAX_TRANSFER_BUFFER_LENGTH {
+      return Err(std::io::ErrorKind::InvalidData.into());
+    }
+    if direction_in {
+      reply.data = vec![0; reply.actual_length as usize];
+      r.read_exact(&mut reply.data)?;
+    }
+    skip_iso_packet_descriptors(r, reply.number_of_packets)?;
+
+    Ok(reply)
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(48 + self.data.len());
+    self.header.write_to(&mut bytes)?;
+    bytes.extend_from_slice(&self.status.to_be_bytes());
+    bytes.extend_from_slice(&self.actual_length.to_be_bytes());
+    bytes.extend_from_slice(&self.start_frame.to_be_bytes());
+    bytes.extend_from_slice(&self.number_of_packets.to_be_bytes());
+    bytes.extend_from_slice(&self.error_count.to_be_bytes());
+    bytes.extend_from_slice(&[0; 8]);
+    bytes.extend_from_slice(&self.data);
+    w.write_all(&bytes)
+  }
+}
+
+/// USBIP_CMD_UNLINK.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnlinkCommand {
+  pub header: BasicHeader,
+  /// seqnum of the CMD_SUBMIT to cancel.
+  pub unlink_seqnum: u32,
+}
+
+impl UnlinkCommand {
+  pub fn read_body(
+    header: BasicHeader,
+    r: &mut impl Read,
+  ) -> std::io::Result<Self> {
+    let unlink_seqnum = read_u32(r)?;
+    let mut padding = [0; URB_PADDING_LENGTH];
+    r.read_exact(&mut padding)?;
+    Ok(UnlinkCommand {
+      header,
+      unlink_seqnum,
+    })
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(48);
+    self.header.write_to(&mut bytes)?;
+    bytes.extend_from_slice(&self.unlink_seqnum.to_be_bytes());
+    bytes.extend_from_slice(&[0; URB_PADDING_LENGTH]);
+    w.write_all(&bytes)
+  }
+}
+
+/// USBIP_RET_UNLINK.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnlinkReply {
+  pub header: BasicHeader,
+  /// -ECONNRESET if the URB was cancelled, 0 if it had already completed.
+  pub status: i32,
+}
+
+impl UnlinkReply {
+  pub fn read_body(
+    header: BasicHeader,
+    r: &mut impl Read,
+  ) -> std::io::Result<Self> {
+    let status = read_u32(r)? as i32;
+    let mut padding = [0; URB_PADDING_LENGTH];
+    r.read_exact(&mut padding)?;
+    Ok(UnlinkReply { header, status })
+  }
+
+  pub fn write_to(&self, w: &mut impl Write) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(48);
+    self.header.write_to(&mut bytes)?;
+    bytes.extend_from_slice(&self.status.to_be_bytes());
+    bytes.extend_from_slice(&[0; URB_PADDING_LENGTH]);
+    w.write_all(&bytes)
+  }
+}
+
+/// Speed field of `ExportedDevice` (`enum usb_device_speed`).
+pub fn speed_from_usbip(speed: u32) -> UsbSpeed {
+  match speed {
+    1 => UsbSpeed::Low,
+    2 => UsbSpeed::Full,
+    3 => UsbSpeed::High,
+    // 4 is wireless USB, which is full speed at most.
+    4 => UsbSpeed::Full,
+    5 => UsbSpeed::Super,
+    6 => UsbSpeed::SuperPlus,
+    _ => UsbSpeed::Unknown,
+  }
+}
+
+pub fn speed_to_usbip(speed: UsbSpeed) -> u32 {
+  match speed {
+    UsbSpeed::Unknown => 0,
+    UsbSpeed::Low => 1,
+    UsbSpeed::Full => 2,
+    UsbSpeed::High => 3,
+    UsbSpeed::Super => 5,
+    UsbSpeed::SuperPlus => 6,
+  }
+}
+
+/// Splits a bus ID such as `1-2.4` into the bus number and port numbers.
+pub fn parse_busid(busid: &str) -> Option<(u8, Vec<u8>)> {
+  let mut parts = busid.splitn(2, '-');
+  let bus_number = parts.next()?.parse().ok()?;
+  let port_numbers = parts
+    .next()?
+    .split('.')
+    .map(|port| port.parse().ok())
+    .collect::<Option<Vec<u8>>>()?;
+  Some((bus_number, port_numbers))
+}
+
+fn skip_iso_packet_descriptors(
+  r: &mut impl Read,
+  number_of_packets: u32,
+) -> std::io::Result<()> {
+  if number_of_packets == 0 || number_of_packets == 0xFFFFFFFF {
+    return Ok(());
+  }
+  let length = number_of_packets as u64 * ISO_PACKET_DESCRIPTOR_LENGTH as u64;
+  std::io::copy(&mut r.take(length), &mut std::io::sink())?;
+  Ok(())
+}
+
+fn read_u8(r: &mut impl Read) -> std::io::Result<u8> {
+  let mut bytes = [0; 1];
+  r.read_exact(&mut bytes)?;
+  Ok(bytes[0])
+}
+
+fn read_u16(r: &mut impl Read) -> std::io::Result<u16> {
+  let mut bytes = [0; 2];
+  r.read_exact(&mut bytes)?;
+  Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+  let mut bytes = [0; 4];
+  r.read_exact(&mut bytes)?;
+  Ok(u32::from_be_bytes(bytes))
+}
+
+// NUL padded string of a fixed size field.
+fn read_string(r: &mut impl Read, length: usize) -> std::io::Result<String> {
+  let mut bytes = vec![0; length];
+  r.read_exact(&mut bytes)?;
+  let end = bytes.iter().position(|b| *b == 0).unwrap_or(length);
+  Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn write_string(
+  w: &mut impl Write,
+  string: &str,
+  length: usize,
+) -> std::io::Result<()> {
+  let mut bytes = vec![0; length];
+  // Keep the last byte as the NUL terminator.
+  let n = string.len().min(length - 1);
+  bytes[..n].copy_from_slice(&string.as_bytes()[..n]);
+  w.write_all(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::usbip::parse_busid;
+  use crate::usbip::BasicHeader;
+  use crate::usbip::ExportedDevice;
+  use crate::usbip::ExportedInterface;
+  use crate::usbip::SubmitCommand;
+  use crate::usbip::SubmitReply;
+  use crate::usbip::MAX_TRANSFER_BUFFER_LENGTH;
+  use crate::usbip::USBIP_CMD_SUBMIT;
+  use crate::usbip::USBIP_DIR_IN;
+  use crate::usbip::USBIP_DIR_OUT;
+  use crate::usbip::USBIP_RET_SUBMIT;
+
+  #[test]
+  fn test_exported_device() {
+    let device = ExportedDevice {
+      path: "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2".to_string(),
+      busid: "1-2".to_string(),
+      busnum: 1,
+      devnum: 3,
+      speed: 2,
+      vendor_id: 0x2341,
+      product_id: 0x8036,
+      bcd_device: 0x0100,
+      device_class: 0xEF,
+      device_subclass: 0x02,
+      device_protocol: 0x01,
+      configuration_value: 1,
+      num_configurations: 1,
+      num_interfaces: 1,
+      interfaces: vec![ExportedInterface {
+        interface_class: 0xFF,
+        interface_subclass: 0,
+        interface_protocol: 0,
+      }],
+    };
+
+    let mut bytes = vec![];
+    device.write_to(&mut bytes, true).unwrap();
+    assert_eq!(bytes.len(), 312 + 4);
+    assert_eq!(&bytes[288..292], &[0, 0, 0, 1]);
+    assert_eq!(&bytes[300..302], &[0x23, 0x41]);
+
+    let parsed = ExportedDevice::read_from(&mut &bytes[..], true).unwrap();
+    assert_eq!(parsed, device);
+    assert_eq!(parsed.devid(), 0x0001_0003);
+  }
+
+  #[test]
+  fn test_submit() {
+    let command = SubmitCommand {
+      header: BasicHeader {
+        command: USBIP_CMD_SUBMIT,
+        seqnum: 7,
+        devid: 0x0001_0003,
+        direction: USBIP_DIR_OUT,
+        ep: 2,
+      },
+      transfer_flags: 0,
+      transfer_buffer_length: 3,
+      start_frame: 0,
+      number_of_packets: 0xFFFFFFFF,
+      interval: 0,
+      setup: [0; 8],
+      data: vec![1, 2, 3],
+    };
+
+    let mut bytes = vec![];
+    command.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 48 + 3);
+
+    let r = &mut &bytes[..];
+    let header = BasicHeader::read_from(r).unwrap();
+    assert_eq!(SubmitCommand::read_body(header, r).unwrap(), command);
+
+    let reply = SubmitReply {
+      header: BasicHeader {
+        command: USBIP_RET_SUBMIT,
+        seqnum: 8,
+        devid: 0,
+        direction: USBIP_DIR_IN,
+        ep: 0,
+      },
+      status: -32,
+      actual_length: 2,
+      start_frame: 0,
+      number_of_packets: 0xFFFFFFFF,
+      error_count: 0,
+      data: vec![4, 5],
+    };
+
+    let mut bytes = vec![];
+    reply.write_to(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 48 + 2);
+
+    let r = &mut &bytes[..];
+    let header = BasicHeader::read_from(r).unwrap();
+    assert_eq!(SubmitReply::read_body(header, r, true).unwrap(), reply);
+
+    // The buffer is not allocated for oversized URBs.
+    let oversized = SubmitCommand {
+      header: BasicHeader {
+        direction: USBIP_DIR_IN,
+        ..command.header.clone()
+      },
+      transfer_buffer_length: MAX_TRANSFER_BUFFER_LENGTH + 1,
+      data: vec![],
+      ..command
+    };
+    let mut bytes = vec![];
+    oversized.write_to(&mut bytes).unwrap();
+    let r = &mut &bytes[..];
+    let header = BasicHeader::read_from(r).unwrap();
+    assert_eq!(
+      SubmitCommand::read_body(header, r).unwrap_err().kind(),
+      std::io::ErrorKind::InvalidData
+    );
+  }
+
+  #[test]
+  fn test_parse_busid() {
+    assert_eq!(parse_busid("1-2"), Some((1, vec![2])));
+    assert_eq!(parse_busid("3-1.4.2"), Some((3, vec![1, 4, 2])));
+    assert_eq!(parse_busid("usb1"), None);
+    assert_eq!(parse_busid("1-x"), None);
+  }
+}
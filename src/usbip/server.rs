@@ -0,0 +1,751 @@
+//! USB/IP server. Exports `UsbDevice`s so that remote hosts can attach them
+//! with Linux `usbip attach` or `usbip::client::UsbIpHost`.
+//!
+//! ```no_run
+//! # fn main() -> webusb::Result<()> {
+//! use std::net::TcpListener;
+//! use webusb::usbip::server::UsbIpServer;
+//! use webusb::usbip::USBIP_PORT;
+//! use webusb::Context;
+//!
+//! let ctx = Context::init()?;
+//! let server = UsbIpServer::new(ctx.devices()?);
+//! server.serve(TcpListener::bind(("0.0.0.0", USBIP_PORT))?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every URB is carried out with the `UsbDevice` methods, one thread per
+//! endpoint. Bulk and interrupt transfers do not hold the device while they
+//! are pending, so endpoints do not wait on each other, and
+//! USBIP_CMD_UNLINK cancels them.
+
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use crate::backend::Transfer;
+use crate::constants::CLEAR_FEATURE_REQUEST;
+use crate::constants::ENDPOINT_HALT_FEATURE;
+use crate::constants::PORT_RESET_FEATURE;
+use crate::constants::SET_CONFIGURATION_REQUEST;
+use crate::constants::SET_FEATURE_REQUEST;
+use crate::constants::SET_INTERFACE_REQUEST;
+use crate::usbip::speed_to_usbip;
+use crate::usbip::BasicHeader;
+use crate::usbip::ExportedDevice;
+use crate::usbip::ExportedInterface;
+use crate::usbip::OpHeader;
+use crate::usbip::SubmitCommand;
+use crate::usbip::SubmitReply;
+use crate::usbip::UnlinkCommand;
+use crate::usbip::UnlinkReply;
+use crate::usbip::OP_REP_DEVLIST;
+use crate::usbip::OP_REP_IMPORT;
+use crate::usbip::OP_REQ_DEVLIST;
+use crate::usbip::OP_REQ_IMPORT;
+use crate::usbip::USBIP_CMD_SUBMIT;
+use crate::usbip::USBIP_CMD_UNLINK;
+use crate::usbip::USBIP_DIR_IN;
+use crate::usbip::USBIP_RET_SUBMIT;
+use crate::usbip::USBIP_RET_UNLINK;
+use crate::usbip::USBIP_VERSION;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbRecipient;
+use crate::UsbRequestType;
+
+// Linux errno values reported in the status of RET_SUBMIT and RET_UNLINK.
+const EPIPE: i32 = 32;
+#[cfg(feature = "libusb")]
+const ENODEV: i32 = 19;
+const EINVAL: i32 = 22;
+const EPROTO: i32 = 71;
+#[cfg(feature = "libusb")]
+const EOVERFLOW: i32 = 75;
+const ECONNRESET: i32 = 104;
+
+/// Exports devices over USB/IP.
+#[derive(Clone)]
+pub struct UsbIpServer {
+  exports: Arc<Vec<Mutex<Export>>>,
+}
+
+struct Export {
+  info: ExportedDevice,
+  // None while the device is imported by a client.
+  device: Option<UsbDevice>,
+}
+
+impl UsbIpServer {
+  /// Devices are exported under their `UsbDevice::port_path` as bus ID.
+  pub fn new(devices: Vec<UsbDevice>) -> Self {
+    let exports = devices
+      .into_iter()
+      .map(|device| {
+        Mutex::new(Export {
+          info: exported_device(&device),
+          device: Some(device),
+        })
+      })
+      .collect();
+    UsbIpServer {
+      exports: Arc::new(exports),
+    }
+  }
+
+  /// Device records as listed by OP_REP_DEVLIST.
+  pub fn exported_devices(&self) -> Vec<ExportedDevice> {
+    self
+      .exports
+      .iter()
+      .map(|export| export.lock().unwrap().info.clone())
+      .collect()
+  }
+
+  /// Accepts connections on `listener` until it fails. Every connection is
+  /// served on its own thread.
+  pub fn serve(&self, listener: TcpListener) -> Result<()> {
+    for stream in listener.incoming() {
+      let stream = stream?;
+      let server = self.clone();
+      std::thread::spawn(move || {
+        // Errors only affect this connection.
+        let _ = server.handle_connection(stream);
+      });
+    }
+    Ok(())
+  }
+
+  fn handle_connection(&self, mut stream: TcpStream) -> Result<()> {
+    stream.set_nodelay(true)?;
+
+    let request = OpHeader::read_from(&mut stream)?;
+    if request.version != USBIP_VERSION {
+      return Err(Error::Io(std::io::ErrorKind::InvalidData));
+    }
+
+    match request.code {
+      OP_REQ_DEVLIST => {
+        let devices = self.exported_devices();
+        let mut reply = vec![];
+        OpHeader::new(OP_REP_DEVLIST, 0).write_to(&mut reply)?;
+        reply.extend_from_slice(&(devices.len() as u32).to_be_bytes());
+        for device in devices {
+          device.write_to(&mut reply, true)?;
+        }
+        std::io::Write::write_all(&mut stream, &reply)?;
+        Ok(())
+      }
+      OP_REQ_IMPORT => {
+        let mut busid = [0; 32];
+        std::io::Read::read_exact(&mut stream, &mut busid)?;
+        let end = busid.iter().position(|b| *b == 0).unwrap_or(32);
+        let busid = String::from_utf8_lossy(&busid[..end]).into_owned();
+
+        let (export, device) = match self.attach(&busid) {
+          Some(attached) => attached,
+          None => {
+            OpHeader::new(OP_REP_IMPORT, 1).write_to(&mut stream)?;
+            return Ok(());
+          }
+        };
+
+        let mut reply = vec![];
+        OpHeader::new(OP_REP_IMPORT, 0).write_to(&mut reply)?;
+        exported_device(&device).write_to(&mut reply, false)?;
+        std::io::Write::write_all(&mut stream, &reply)?;
+
+        let mut device = Session::run(device, stream);
+
+        // Make the device available for the next import.
+        let _ = detach(&mut device);
+        let mut export = export.lock().unwrap();
+        export.info = exported_device(&device);
+        export.device = Some(device);
+        Ok(())
+      }
+      _ => Err(Error::Io(std::io::ErrorKind::InvalidData)),
+    }
+  }
+
+  // Takes the device with `busid` out of the export list and opens it.
+  fn attach(&self, busid: &str) -> Option<(&Mutex<Export>, UsbDevice)> {
+    let export = self
+      .exports
+      .iter()
+      .find(|export| export.lock().unwrap().info.busid == busid)?;
+    let mut device = export.lock().unwrap().device.take()?;
+
+    match device.open().and_then(|_| claim_interfaces(&mut device)) {
+      Ok(()) => Some((export, device)),
+      Err(_) => {
+        let _ = detach(&mut device);
+        export.lock().unwrap().device = Some(device);
+        None
+      }
+    }
+  }
+}
+
+fn detach(device: &mut UsbDevice) -> Result<()> {
+  release_interfaces(device)?;
+  device.close()
+}
+
+// The remote host drives the whole device, so every interface of the
+// active configuration is claimed on its behalf.
+fn claim_interfaces(device: &mut UsbDevice) -> Result<()> {
+  let interface_numbers = match &device.configuration {
+    Some(configuration) => configuration
+      .interfaces()
+      .iter()
+      .map(|itf| itf.interface_number())
+      .collect(),
+    None => vec![],
+  };
+  for interface_number in interface_numbers {
+    device.claim_interface(interface_number)?;
+  }
+  Ok(())
+}
+
+fn release_interfaces(device: &mut UsbDevice) -> Result<()> {
+  let interface_numbers = match &device.configuration {
+    Some(configuration) => configuration
+      .interfaces()
+      .iter()
+      .map(|itf| itf.interface_number())
+      .collect(),
+    None => vec![],
+  };
+  for interface_number in interface_numbers {
+    device.release_interface(interface_number)?;
+  }
+  Ok(())
+}
+
+// CMD_SUBMITs without a reply by seqnum, along with their transfer once it
+// started. Removed by whoever answers first: the endpoint thread with
+// RET_SUBMIT or an UNLINK with RET_UNLINK.
+type InFlight = HashMap<u32, Option<Arc<dyn Transfer>>>;
+
+/// An imported device and the connection of the import.
+struct Session {
+  device: Arc<Mutex<UsbDevice>>,
+  writer: Arc<Mutex<TcpStream>>,
+  in_flight: Arc<Mutex<InFlight>>,
+}
+
+impl Session {
+  // Serves URBs until the connection is closed and returns the device.
+  fn run(device: UsbDevice, mut stream: TcpStream) -> UsbDevice {
+    let writer = match stream.try_clone() {
+      Ok(writer) => writer,
+      Err(_) => return device,
+    };
+    let session = Session {
+      device: Arc::new(Mutex::new(device)),
+      writer: Arc::new(Mutex::new(writer)),
+      in_flight: Arc::new(Mutex::new(HashMap::new())),
+    };
+
+    let mut endpoints: HashMap<
+      u32,
+      (mpsc::Sender<SubmitCommand>, JoinHandle<()>),
+    > = HashMap::new();
+    while let Ok(header) = BasicHeader::read_from(&mut stream) {
+      let result = match header.command {
+        USBIP_CMD_SUBMIT => {
+          SubmitCommand::read_body(header, &mut stream).map(|command| {
+            // Both directions of endpoint 0 share a thread to keep control
+            // transfers in order.
+            let key = match command.header.ep {
+              0 => 0,
+              ep => command.header.direction << 4 | ep,
+            };
+            let (sender, _) = endpoints
+              .entry(key)
+              .or_insert_with(|| session.spawn_endpoint());
+            session
+              .in_flight
+              .lock()
+              .unwrap()
+              .insert(command.header.seqnum, None);
+            let _ = sender.send(command);
+          })
+        }
+        USBIP_CMD_UNLINK => UnlinkCommand::read_body(header, &mut stream)
+          .and_then(|command| session.unlink(command)),
+        _ => break,
+      };
+      if result.is_err() {
+        break;
+      }
+    }
+
+    // Cancel everything that is still in flight and wait for the endpoint
+    // threads to notice.
+    for (_, transfer) in session.in_flight.lock().unwrap().drain() {
+      if let Some(transfer) = transfer {
+        transfer.cancel();
+      }
+    }
+    for (_, (sender, thread)) in endpoints.drain() {
+      drop(sender);
+      let _ = thread.join();
+    }
+
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    match Arc::try_unwrap(session.device) {
+      Ok(device) => device.into_inner().unwrap(),
+      Err(_) => unreachable!("endpoint threads have been joined"),
+    }
+  }
+
+  fn spawn_endpoint(&self) -> (mpsc::Sender<SubmitCommand>, JoinHandle<()>) {
+    let (sender, receiver) = mpsc::channel::<SubmitCommand>();
+    let device = self.device.clone();
+    let writer = self.writer.clone();
+    let in_flight = self.in_flight.clone();
+
+    let thread = std::thread::spawn(move || {
+      for command in receiver {
+        let seqnum = command.header.seqnum;
+        // Unlinked while queued.
+        if !in_flight.lock().unwrap().contains_key(&seqnum) {
+          continue;
+        }
+
+        let result = match command.header.ep {
+          0 => control(&mut device.lock().unwrap(), &command),
+          _ => {
+            // The device is released before waiting for the transfer.
+            let started = start(&mut device.lock().unwrap(), &command);
+            started.and_then(|transfer| finish(&in_flight, &command, transfer))
+          }
+        };
+
+        // Unlinked while transferring.
+        let mut in_flight = in_flight.lock().unwrap();
+        if in_flight.remove(&seqnum).is_none() {
+          continue;
+        }
+        let (status, actual_length, data) = match result {
+          Ok((actual_length, data)) => (0, actual_length, data),
+          Err(err) => (status_from_error(&err), 0, vec![]),
+        };
+        let reply = SubmitReply {
+          header: BasicHeader {
+            command: USBIP_RET_SUBMIT,
+            seqnum,
+            devid: 0,
+            direction: 0,
+            ep: 0,
+          },
+          status,
+          actual_length,
+          start_frame: 0,
+          number_of_packets: command.number_of_packets,
+          error_count: 0,
+          data,
+        };
+        if reply.write_to(&mut *writer.lock().unwrap()).is_err() {
+          break;
+        }
+      }
+    });
+
+    (sender, thread)
+  }
+
+  fn unlink(&self, command: UnlinkCommand) -> std::io::Result<()> {
+    let mut in_flight = self.in_flight.lock().unwrap();
+    // -ECONNRESET if the URB was cancelled before it was answered. 0 if its
+    // RET_SUBMIT has already been sent.
+    let status = match in_flight.remove(&command.unlink_seqnum) {
+      Some(transfer) => {
+        if let Some(transfer) = transfer {
+          transfer.cancel();
+        }
+        -ECONNRESET
+      }
+      None => 0,
+    };
+    UnlinkReply {
+      header: BasicHeader {
+        command: USBIP_RET_UNLINK,
+        seqnum: command.header.seqnum,
+        devid: 0,
+        direction: 0,
+        ep: 0,
+      },
+      status,
+    }
+    .write_to(&mut *self.writer.lock().unwrap())
+  }
+}
+
+// Starts the bulk or interrupt transfer of a CMD_SUBMIT.
+fn start(
+  device: &mut UsbDevice,
+  command: &SubmitCommand,
+) -> Result<Arc<dyn Transfer>> {
+  let endpoint_number = command.header.ep as u8;
+  match command.header.direction {
+    USBIP_DIR_IN => {
+      let buffer = vec![0; command.transfer_buffer_length as usize];
+      device.submit_transfer(Direction::In, endpoint_number, buffer)
+    }
+    _ => {
+      let data = command.data.clone();
+      device.submit_transfer(Direction::Out, endpoint_number, data)
+    }
+  }
+}
+
+// Waits for the transfer started for `command`, which an UNLINK may cancel
+// meanwhile. Returns actual_length and the IN data.
+fn finish(
+  in_flight: &Mutex<InFlight>,
+  command: &SubmitCommand,
+  transfer: Arc<dyn Transfer>,
+) -> Result<(u32, Vec<u8>)> {
+  match in_flight.lock().unwrap().get_mut(&command.header.seqnum) {
+    Some(started) => *started = Some(transfer.clone()),
+    // Unlinked while starting.
+    None => transfer.cancel(),
+  }
+  transfer.wait(None)?;
+  let data = transfer.take()?;
+  match command.header.direction {
+    USBIP_DIR_IN => Ok((data.len() as u32, data)),
+    _ => Ok((data.len() as u32, vec![])),
+  }
+}
+
+// Carries out a CMD_SUBMIT on endpoint 0. Returns actual_length and the IN
+// data.
+fn control(
+  device: &mut UsbDevice,
+  command: &SubmitCommand,
+) -> Result<(u32, Vec<u8>)> {
+  let setup = &command.setup;
+  let request_type = setup[0];
+  let request = setup[1];
+  let value = u16::from_le_bytes([setup[2], setup[3]]);
+  let index = u16::from_le_bytes([setup[4], setup[5]]);
+  let length = u16::from_le_bytes([setup[6], setup[7]]);
+
+  // Requests that change the state of the device go through the
+  // corresponding `UsbDevice` methods, like the Linux stub driver does.
+  match (request_type, request) {
+    (0x00, SET_CONFIGURATION_REQUEST) => {
+      release_interfaces(device)?;
+      device.select_configuration(value as u8)?;
+      claim_interfaces(device)?;
+      return Ok((0, vec![]));
+    }
+    (0x01, SET_INTERFACE_REQUEST) => {
+      device.select_alternate_interface(index as u8, value as u8)?;
+      return Ok((0, vec![]));
+    }
+    (0x02, CLEAR_FEATURE_REQUEST) if value == ENDPOINT_HALT_FEATURE => {
+      let direction = match index & 0x80 {
+        0 => Direction::Out,
+        _ => Direction::In,
+      };
+      device.clear_halt(direction, index as u8 & 0x0F)?;
+      return Ok((0, vec![]));
+    }
+    (0x23, SET_FEATURE_REQUEST) if value == PORT_RESET_FEATURE => {
+      device.reset()?;
+      return Ok((0, vec![]));
+    }
+    _ => {}
+  }
+
+  let setup = UsbControlTransferParameters {
+    request_type: match (request_type >> 5) & 0x3 {
+      0 => UsbRequestType::Standard,
+      1 => UsbRequestType::Class,
+      2 => UsbRequestType::Vendor,
+      _ => return Err(Error::InvalidAccess),
+    },
+    recipient: match request_type & 0x1F {
+      0 => UsbRecipient::Device,
+      1 => UsbRecipient::Interface,
+      2 => UsbRecipient::Endpoint,
+      3 => UsbRecipient::Other,
+      _ => return Err(Error::InvalidAccess),
+    },
+    request,
+    value,
+    index,
+  };
+
+  match request_type & 0x80 {
+    0 => {
+      let length = device.control_transfer_out(setup, &command.data)?;
+      Ok((length as u32, vec![]))
+    }
+    _ => {
+      let data = device.control_transfer_in(setup, length as usize)?;
+      Ok((data.len() as u32, data))
+    }
+  }
+}
+
+fn status_from_error(err: &Error) -> i32 {
+  match err {
+    Error::Stall => -EPIPE,
+    #[cfg(feature = "libusb")]
+    Error::Usb(rusb::Error::NoDevice) => -ENODEV,
+    #[cfg(feature = "libusb")]
+    Error::Usb(rusb::Error::Overflow) => -EOVERFLOW,
+    Error::NotFound | Error::InvalidState | Error::InvalidAccess => -EINVAL,
+    _ => -EPROTO,
+  }
+}
+
+fn exported_device(device: &UsbDevice) -> ExportedDevice {
+  let interfaces = match &device.configuration {
+    Some(configuration) => configuration
+      .interfaces()
+      .iter()
+      .map(|itf| ExportedInterface {
+        interface_class: itf.alternate().interface_class,
+        interface_subclass: itf.alternate().interface_subclass,
+        interface_protocol: itf.alternate().interface_protocol,
+      })
+      .collect(),
+    None => vec![],
+  };
+
+  ExportedDevice {
+    path: format!("/sys/bus/usb/devices/{}", device.port_path()),
+    busid: device.port_path(),
+    busnum: device.bus_number as u32,
+    devnum: device.device_address as u32,
+    speed: speed_to_usbip(device.speed),
+    vendor_id: device.vendor_id,
+    product_id: device.product_id,
+    bcd_device: ((device.device_version_major / 10) as u16) << 12
+      | ((device.device_version_major % 10) as u16) << 8
+      | (device.device_version_minor as u16) << 4
+      | device.device_version_subminor as u16,
+    device_class: device.device_class,
+    device_subclass: device.device_subclass,
+    device_protocol: device.device_protocol,
+    configuration_value: device
+      .configuration
+      .as_ref()
+      .map(|configuration| configuration.configuration_value())
+      .unwrap_or(0),
+    num_configurations: device.configurations.len() as u8,
+    num_interfaces: interfaces.len() as u8,
+    interfaces,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::net::SocketAddr;
+  use std::net::TcpListener;
+  use std::net::TcpStream;
+  use std::time::Duration;
+
+  use crate::loopback::Loopback;
+  use crate::loopback::DEVICE_DESCRIPTOR;
+  use crate::usbip::client::UsbIpHost;
+  use crate::usbip::server::UsbIpServer;
+  use crate::usbip::BasicHeader;
+  use crate::usbip::ExportedDevice;
+  use crate::usbip::OpHeader;
+  use crate::usbip::SubmitCommand;
+  use crate::usbip::SubmitReply;
+  use crate::usbip::OP_REQ_IMPORT;
+  use crate::usbip::USBIP_CMD_SUBMIT;
+  use crate::usbip::USBIP_DIR_IN;
+  use crate::usbip::USBIP_DIR_OUT;
+  use crate::EnumerationOptions;
+  use crate::Error;
+
+  fn serve_loopback() -> UsbIpHost {
+    UsbIpHost::new(serve_loopback_at()).unwrap()
+  }
+
+  fn serve_loopback_at() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = UsbIpServer::new(vec![Loopback::device()]);
+    std::thread::spawn(move || server.serve(listener));
+    addr
+  }
+
+  fn send_submit(
+    stream: &mut TcpStream,
+    seqnum: u32,
+    (direction, ep): (u32, u32),
+    setup: [u8; 8],
+    data: &[u8],
+    length: u32,
+  ) {
+    SubmitCommand {
+      header: BasicHeader {
+        command: USBIP_CMD_SUBMIT,
+        seqnum,
+        devid: 0,
+        direction,
+        ep,
+      },
+      transfer_flags: 0,
+      transfer_buffer_length: length,
+      start_frame: 0,
+      number_of_packets: 0xFFFFFFFF,
+      interval: 0,
+      setup,
+      data: data.to_vec(),
+    }
+    .write_to(stream)
+    .unwrap();
+  }
+
+  fn read_reply(stream: &mut TcpStream, direction_in: bool) -> SubmitReply {
+    let header = BasicHeader::read_from(stream).unwrap();
+    SubmitReply::read_body(header, stream, direction_in).unwrap()
+  }
+
+  #[test]
+  fn test_exported_devices() {
+    let host = serve_loopback();
+    let devices = host.exported_devices().unwrap();
+
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].busid, "1-1.3");
+    assert_eq!(devices[0].vendor_id, 0x1234);
+    assert_eq!(devices[0].bcd_device, 0x0100);
+    assert_eq!(devices[0].configuration_value, 1);
+    assert_eq!(devices[0].interfaces[0].interface_class, 0xFF);
+  }
+
+  #[test]
+  fn test_import() {
+    let host = serve_loopback();
+    let mut device = host.import("1-1.3").unwrap();
+    assert_eq!(device.product_name, Some("Loopback".to_string()));
+
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+
+    assert_eq!(device.transfer_out(2, b"hello").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"hello");
+    assert_eq!(device.transfer_in(3, 64).err(), Some(Error::Stall));
+
+    // Only one client at a time.
+    assert_eq!(host.import("1-1.3").err(), Some(Error::NotFound));
+    assert_eq!(host.import("2-1").err(), Some(Error::NotFound));
+
+    // Exported again once the client is gone.
+    drop(device);
+    let mut reimported = None;
+    for _ in 0..50 {
+      if let Ok(device) = host.import("1-1.3") {
+        reimported = Some(device);
+        break;
+      }
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(reimported.is_some());
+  }
+
+  #[test]
+  fn test_unlink() {
+    let host = serve_loopback().with_timeout(Duration::from_millis(200));
+    let mut device = host.import("1-1.3").unwrap();
+
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+
+    // Nothing to read. The client unlinks the transfer, and the server
+    // stops polling the endpoint.
+    assert_eq!(
+      device.transfer_in(1, 64).err(),
+      Some(Error::Io(std::io::ErrorKind::TimedOut))
+    );
+
+    assert_eq!(device.transfer_out(2, b"again").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"again");
+  }
+
+  #[test]
+  fn test_devices() {
+    let host = serve_loopback();
+    let options = EnumerationOptions::default();
+
+    let mut device = host.devices(&options).unwrap().remove(0);
+    assert_eq!(device.product_name, Some("Loopback".to_string()));
+    // Still available, the device is only imported once opened.
+    assert_eq!(host.devices(&options).unwrap().len(), 1);
+
+    device.open().unwrap();
+    device.select_configuration(1).unwrap();
+    device.claim_interface(0).unwrap();
+    assert_eq!(device.transfer_out(2, b"hello").unwrap(), 5);
+    assert_eq!(device.transfer_in(1, 64).unwrap(), b"hello");
+    assert!(host.devices(&options).unwrap().is_empty());
+
+    device.close().unwrap();
+    device.open().unwrap();
+    device.close().unwrap();
+  }
+
+  #[test]
+  fn test_pending_transfer() {
+    let mut stream = TcpStream::connect(serve_loopback_at()).unwrap();
+    OpHeader::new(OP_REQ_IMPORT, 0)
+      .write_to(&mut stream)
+      .unwrap();
+    let mut busid = [0; 32];
+    busid[..5].copy_from_slice(b"1-1.3");
+    std::io::Write::write_all(&mut stream, &busid).unwrap();
+    assert_eq!(OpHeader::read_from(&mut stream).unwrap().status, 0);
+    ExportedDevice::read_from(&mut stream, false).unwrap();
+
+    // A read from endpoint 1 without data stays pending, without a
+    // timeout...
+    send_submit(&mut stream, 1, (USBIP_DIR_IN, 1), [0; 8], &[], 64);
+
+    // ...while control transfers and other endpoints go ahead.
+    let setup = [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00];
+    send_submit(&mut stream, 2, (USBIP_DIR_IN, 0), setup, &[], 18);
+    let reply = read_reply(&mut stream, true);
+    assert_eq!(reply.header.seqnum, 2);
+    assert_eq!(reply.data, DEVICE_DESCRIPTOR);
+
+    // The pending read completes with the whole transfer, its reply may
+    // come before or after the one of the write.
+    send_submit(&mut stream, 3, (USBIP_DIR_OUT, 2), [0; 8], b"hello", 5);
+    let mut replies = vec![];
+    for _ in 0..2 {
+      let header = BasicHeader::read_from(&mut stream).unwrap();
+      let direction_in = header.seqnum == 1;
+      replies.push(
+        SubmitReply::read_body(header, &mut stream, direction_in).unwrap(),
+      );
+    }
+    replies.sort_by_key(|reply| reply.header.seqnum);
+    assert_eq!(replies[0].data, b"hello");
+    assert_eq!((replies[1].header.seqnum, replies[1].actual_length), (3, 5));
+  }
+}
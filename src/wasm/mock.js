@@ -0,0 +1,199 @@
+// Stand-in for `navigator.usb` used by the wasm tests under Node.
+//
+// Provides one device, 1234:5678, with the loopback interface of
+// src/loopback.rs: data written to endpoint 2 can be read back from
+// endpoint 1, endpoint 3 always stalls. Vendor request 0x01 echoes wValue
+// and wIndex, vendor request 0x02 accepts any data. Everything else on
+// endpoint 0 stalls.
+
+function error(name) {
+  return new DOMException(name, name);
+}
+
+function bytes(data) {
+  // Views of the wasm memory are only valid until the next call into wasm.
+  if (ArrayBuffer.isView(data)) {
+    return Array.from(
+      new Uint8Array(data.buffer, data.byteOffset, data.byteLength),
+    );
+  }
+  return Array.from(new Uint8Array(data));
+}
+
+function inResult(data) {
+  return { status: "ok", data: new DataView(new Uint8Array(data).buffer) };
+}
+
+function loopbackDevice() {
+  const endpoint = (endpointNumber, direction) => ({
+    endpointNumber,
+    direction,
+    type: "bulk",
+    packetSize: 64,
+  });
+  const alternate = {
+    alternateSetting: 0,
+    interfaceClass: 0xff,
+    interfaceSubclass: 0,
+    interfaceProtocol: 0,
+    interfaceName: null,
+    endpoints: [endpoint(1, "in"), endpoint(2, "out"), endpoint(3, "in")],
+  };
+  const iface = {
+    interfaceNumber: 0,
+    alternate,
+    alternates: [alternate],
+    claimed: false,
+  };
+  const configuration = {
+    configurationValue: 1,
+    configurationName: "Loopback",
+    interfaces: [iface],
+  };
+  let buffered = [];
+
+  const device = {
+    usbVersionMajor: 2,
+    usbVersionMinor: 0,
+    usbVersionSubminor: 0,
+    deviceClass: 0,
+    deviceSubclass: 0,
+    deviceProtocol: 0,
+    vendorId: 0x1234,
+    productId: 0x5678,
+    deviceVersionMajor: 1,
+    deviceVersionMinor: 0,
+    deviceVersionSubminor: 0,
+    manufacturerName: "Stand-in",
+    productName: "Loopback",
+    serialNumber: null,
+    configurations: [configuration],
+    configuration: null,
+    opened: false,
+
+    checkEndpoint(endpointNumber, direction) {
+      if (!this.opened) throw error("InvalidStateError");
+      if (this.configuration === null) throw error("NotFoundError");
+      const found = alternate.endpoints.some((e) =>
+        e.endpointNumber === endpointNumber && e.direction === direction
+      );
+      if (!found) throw error("NotFoundError");
+      if (!iface.claimed) throw error("InvalidStateError");
+    },
+    checkInterface(interfaceNumber) {
+      if (!this.opened) throw error("InvalidStateError");
+      if (this.configuration === null || interfaceNumber !== 0) {
+        throw error("NotFoundError");
+      }
+    },
+
+    async open() {
+      this.opened = true;
+    },
+    async close() {
+      this.opened = false;
+      iface.claimed = false;
+    },
+    async selectConfiguration(configurationValue) {
+      if (configurationValue !== 1) throw error("NotFoundError");
+      if (!this.opened) throw error("InvalidStateError");
+      this.configuration = configuration;
+    },
+    async claimInterface(interfaceNumber) {
+      this.checkInterface(interfaceNumber);
+      iface.claimed = true;
+    },
+    async releaseInterface(interfaceNumber) {
+      this.checkInterface(interfaceNumber);
+      iface.claimed = false;
+    },
+    async selectAlternateInterface(interfaceNumber, alternateSetting) {
+      this.checkInterface(interfaceNumber);
+      if (alternateSetting !== 0) throw error("NotFoundError");
+      if (!iface.claimed) throw error("InvalidStateError");
+    },
+    async controlTransferIn(setup, length) {
+      if (!this.opened) throw error("InvalidStateError");
+      if (
+        setup.requestType !== "vendor" || setup.recipient !== "device" ||
+        setup.request !== 0x01
+      ) {
+        return { status: "stall" };
+      }
+      const data = [
+        setup.value & 0xff,
+        setup.value >> 8,
+        setup.index & 0xff,
+        setup.index >> 8,
+      ];
+      return inResult(data.slice(0, length));
+    },
+    async controlTransferOut(setup, data) {
+      if (!this.opened) throw error("InvalidStateError");
+      if (setup.requestType !== "vendor" || setup.request !== 0x02) {
+        return { status: "stall" };
+      }
+      return { status: "ok", bytesWritten: bytes(data ?? []).length };
+    },
+    async clearHalt(direction, endpointNumber) {
+      this.checkEndpoint(endpointNumber, direction);
+    },
+    async transferIn(endpointNumber, length) {
+      this.checkEndpoint(endpointNumber, "in");
+      if (endpointNumber === 3) return { status: "stall" };
+      return inResult(buffered.splice(0, length));
+    },
+    async transferOut(endpointNumber, data) {
+      data = bytes(data);
+      this.checkEndpoint(endpointNumber, "out");
+      buffered.push(...data);
+      return { status: "ok", bytesWritten: data.length };
+    },
+    async reset() {
+      if (!this.opened) throw error("InvalidStateError");
+      buffered = [];
+    },
+  };
+  return device;
+}
+
+function matches(device, filter) {
+  return (filter.vendorId === undefined ||
+    filter.vendorId === device.vendorId) &&
+    (filter.productId === undefined ||
+      filter.productId === device.productId) &&
+    (filter.classCode === undefined ||
+      filter.classCode === device.deviceClass);
+}
+
+// Replaces `navigator.usb` with a new stand-in. The device is only listed
+// by `getDevices` once it has been picked through `requestDevice`.
+export function installMock() {
+  const device = loopbackDevice();
+  let granted = false;
+  const usb = {
+    async getDevices() {
+      return granted ? [device] : [];
+    },
+    async requestDevice(options) {
+      // There is no user to pick a device, the first match is taken.
+      if (!options.filters.some((filter) => matches(device, filter))) {
+        throw error("NotFoundError");
+      }
+      granted = true;
+      return device;
+    },
+  };
+
+  // Node only provides `navigator` from version 21 on.
+  if (globalThis.navigator === undefined) {
+    Object.defineProperty(globalThis, "navigator", {
+      value: {},
+      configurable: true,
+    });
+  }
+  Object.defineProperty(globalThis.navigator, "usb", {
+    value: usb,
+    configurable: true,
+  });
+}
@@ -0,0 +1,598 @@
+//! Backend of the browser's WebUSB implementation, `navigator.usb`.
+//!
+//! Browsers only expose WebUSB through promises, so on `wasm32` the methods
+//! of `Context` and `UsbDevice` that talk to the device are `async`.
+//! Otherwise the API is the same as on native targets:
+//!
+//! ```no_run
+//! # async fn blink() -> webusb::Result<()> {
+//! use webusb::wasm::UsbDeviceFilter;
+//! use webusb::Context;
+//!
+//! let ctx = Context::init()?;
+//! let filter = UsbDeviceFilter {
+//!   vendor_id: Some(0x2341),
+//!   ..Default::default()
+//! };
+//! // Must be called from a user gesture, e.g. a click handler.
+//! let mut device = ctx.request_device(&[filter]).await?;
+//! device.open().await?;
+//! device.select_configuration(1).await?;
+//! device.claim_interface(2).await?;
+//! device.transfer_out(4, b"H").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Browsers do not expose the bus topology or any descriptor fields beyond
+//! those of the WebUSB specification. `bus_number`, `device_address`,
+//! `port_numbers`, `speed`, `parent_hub`, `url` and the descriptor fields
+//! of `UsbConfiguration` and `UsbEndpoint` that are not part of the
+//! specification are left at their defaults.
+//!
+//! The WebUSB bindings of `web-sys` are unstable, build with
+//! `RUSTFLAGS=--cfg=web_sys_unstable_apis` (set in `.cargo/config`).
+
+use core::convert::TryFrom;
+
+use js_sys::Reflect;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::JsValue;
+
+use crate::Context;
+use crate::Direction;
+use crate::Error;
+use crate::Result;
+use crate::UsbAlternateInterface;
+use crate::UsbConfiguration;
+use crate::UsbControlTransferParameters;
+use crate::UsbDevice;
+use crate::UsbEndpoint;
+use crate::UsbEndpointType;
+use crate::UsbInterface;
+use crate::UsbRecipient;
+use crate::UsbRequestType;
+use crate::UsbSpeed;
+use crate::UsbSyncType;
+use crate::UsbUsageType;
+
+pub use crate::UsbDeviceFilter;
+
+impl UsbDeviceFilter {
+  fn to_web_sys(&self) -> web_sys::UsbDeviceFilter {
+    let filter = web_sys::UsbDeviceFilter::new();
+    if let Some(vendor_id) = self.vendor_id {
+      filter.set_vendor_id(vendor_id);
+    }
+    if let Some(product_id) = self.product_id {
+      filter.set_product_id(product_id);
+    }
+    if let Some(class_code) = self.class_code {
+      filter.set_class_code(class_code);
+    }
+    if let Some(subclass_code) = self.subclass_code {
+      filter.set_subclass_code(subclass_code);
+    }
+    if let Some(protocol_code) = self.protocol_code {
+      filter.set_protocol_code(protocol_code);
+    }
+    if let Some(serial_number) = &self.serial_number {
+      filter.set_serial_number(serial_number);
+    }
+    filter
+  }
+}
+
+// `navigator.usb` of the window or worker.
+fn usb() -> Result<web_sys::Usb> {
+  let navigator = Reflect::get(&js_sys::global(), &"navigator".into())
+    .map_err(error_from_js)?;
+  if navigator.is_undefined() {
+    return Err(Error::NotFound);
+  }
+  let usb = Reflect::get(&navigator, &"usb".into()).map_err(error_from_js)?;
+  if usb.is_undefined() {
+    // WebUSB is not supported by this browser.
+    return Err(Error::NotFound);
+  }
+  Ok(usb.unchecked_into())
+}
+
+// Maps the DOMException a WebUSB promise rejects with.
+fn error_from_js(err: JsValue) -> Error {
+  let name = Reflect::get(&err, &"name".into())
+    .ok()
+    .and_then(|name| name.as_string());
+  match name.as_deref() {
+    Some("NotFoundError") => Error::NotFound,
+    Some("InvalidStateError") => Error::InvalidState,
+    Some("InvalidAccessError") => Error::InvalidAccess,
+    Some("SecurityError") | Some("NotAllowedError") => {
+      Error::Io(std::io::ErrorKind::PermissionDenied)
+    }
+    // Transfer cancelled by `close` or `release_interface`.
+    Some("AbortError") => Error::Io(std::io::ErrorKind::Interrupted),
+    Some("TypeError") | Some("RangeError") => {
+      Error::Io(std::io::ErrorKind::InvalidInput)
+    }
+    _ => Error::Io(std::io::ErrorKind::Other),
+  }
+}
+
+fn status_result(status: web_sys::UsbTransferStatus) -> Result<()> {
+  match status {
+    web_sys::UsbTransferStatus::Ok => Ok(()),
+    web_sys::UsbTransferStatus::Stall => Err(Error::Stall),
+    // Babble, the device sent more data than requested.
+    _ => Err(Error::Io(std::io::ErrorKind::InvalidData)),
+  }
+}
+
+fn in_result(result: web_sys::UsbInTransferResult) -> Result<Vec<u8>> {
+  status_result(result.status())?;
+  let data = match result.data() {
+    Some(view) => (0..view.byte_length()).map(|i| view.get_uint8(i)).collect(),
+    None => vec![],
+  };
+  Ok(data)
+}
+
+fn out_result(result: web_sys::UsbOutTransferResult) -> Result<usize> {
+  status_result(result.status())?;
+  Ok(result.bytes_written() as usize)
+}
+
+impl Context {
+  /// Lists the devices the user granted this origin access to.
+  /// https://wicg.github.io/webusb/#dom-usb-getdevices
+  pub async fn devices(&self) -> Result<Vec<UsbDevice>> {
+    let devices = usb()?.get_devices().await.map_err(error_from_js)?;
+    Ok(devices.iter().map(UsbDevice::from_web_sys).collect())
+  }
+
+  /// Asks the user to grant access to a device matching any of `filters`.
+  /// Fails with `Error::NotFound` if the user cancels the prompt.
+  /// https://wicg.github.io/webusb/#dom-usb-requestdevice
+  pub async fn request_device(
+    &self,
+    filters: &[UsbDeviceFilter],
+  ) -> Result<UsbDevice> {
+    let filters = filters
+      .iter()
+      .map(UsbDeviceFilter::to_web_sys)
+      .collect::<Vec<_>>();
+    let options = web_sys::UsbDeviceRequestOptions::new(&filters);
+    let device = usb()?
+      .request_device(&options)
+      .await
+      .map_err(error_from_js)?;
+    Ok(UsbDevice::from_web_sys(device))
+  }
+}
+
+impl UsbConfiguration {
+  fn from_web_sys(configuration: web_sys::UsbConfiguration) -> Self {
+    UsbConfiguration {
+      configuration_name: configuration.configuration_name(),
+      configuration_value: configuration.configuration_value(),
+      interfaces: configuration
+        .interfaces()
+        .iter()
+        .map(UsbInterface::from_web_sys)
+        .collect(),
+      self_powered: false,
+      remote_wakeup: false,
+      max_power: 0,
+      raw_descriptor: None,
+    }
+  }
+}
+
+impl UsbInterface {
+  fn from_web_sys(interface: web_sys::UsbInterface) -> Self {
+    UsbInterface {
+      interface_number: interface.interface_number(),
+      alternate: UsbAlternateInterface::from_web_sys(interface.alternate()),
+      alternates: interface
+        .alternates()
+        .iter()
+        .map(UsbAlternateInterface::from_web_sys)
+        .collect(),
+      claimed: interface.claimed(),
+    }
+  }
+}
+
+impl UsbAlternateInterface {
+  fn from_web_sys(alternate: web_sys::UsbAlternateInterface) -> Self {
+    UsbAlternateInterface {
+      alternate_setting: alternate.alternate_setting(),
+      interface_class: alternate.interface_class(),
+      interface_subclass: alternate.interface_subclass(),
+      interface_protocol: alternate.interface_protocol(),
+      interface_name: alternate.interface_name(),
+      endpoints: alternate
+        .endpoints()
+        .iter()
+        .map(UsbEndpoint::from_web_sys)
+        .collect(),
+    }
+  }
+}
+
+impl UsbEndpoint {
+  fn from_web_sys(endpoint: web_sys::UsbEndpoint) -> Self {
+    UsbEndpoint {
+      endpoint_number: endpoint.endpoint_number(),
+      direction: match endpoint.direction() {
+        web_sys::UsbDirection::In => Direction::In,
+        _ => Direction::Out,
+      },
+      r#type: match endpoint.type_() {
+        web_sys::UsbEndpointType::Bulk => UsbEndpointType::Bulk,
+        web_sys::UsbEndpointType::Interrupt => UsbEndpointType::Interrupt,
+        _ => UsbEndpointType::Isochronous,
+      },
+      packet_size: endpoint.packet_size() as u16,
+      transactions_per_microframe: 1,
+      interval: 0,
+      sync_type: UsbSyncType::None,
+      usage_type: UsbUsageType::Data,
+      superspeed_companion: None,
+    }
+  }
+}
+
+impl UsbControlTransferParameters {
+  fn to_web_sys(&self) -> web_sys::UsbControlTransferParameters {
+    let request_type = match self.request_type {
+      UsbRequestType::Standard => web_sys::UsbRequestType::Standard,
+      UsbRequestType::Class => web_sys::UsbRequestType::Class,
+      UsbRequestType::Vendor => web_sys::UsbRequestType::Vendor,
+    };
+    let recipient = match self.recipient {
+      UsbRecipient::Device => web_sys::UsbRecipient::Device,
+      UsbRecipient::Interface => web_sys::UsbRecipient::Interface,
+      UsbRecipient::Endpoint => web_sys::UsbRecipient::Endpoint,
+      UsbRecipient::Other => web_sys::UsbRecipient::Other,
+    };
+    web_sys::UsbControlTransferParameters::new(
+      self.index,
+      recipient,
+      self.request,
+      request_type,
+      self.value,
+    )
+  }
+}
+
+// The browser validates every request against its own copy of the device
+// state, so the methods below only forward the request and then pick up
+// the resulting state with `update`.
+impl UsbDevice {
+  fn from_web_sys(device: web_sys::UsbDevice) -> Self {
+    let mut usb_device = UsbDevice {
+      configurations: device
+        .configurations()
+        .iter()
+        .map(UsbConfiguration::from_web_sys)
+        .collect(),
+      configuration: None,
+      device_class: device.device_class(),
+      device_subclass: device.device_subclass(),
+      device_protocol: device.device_protocol(),
+      device_version_major: device.device_version_major(),
+      device_version_minor: device.device_version_minor(),
+      device_version_subminor: device.device_version_subminor(),
+      manufacturer_name: device.manufacturer_name(),
+      product_id: device.product_id(),
+      product_name: device.product_name(),
+      serial_number: device.serial_number(),
+      usb_version_major: device.usb_version_major(),
+      usb_version_minor: device.usb_version_minor(),
+      usb_version_subminor: device.usb_version_subminor(),
+      vendor_id: device.vendor_id(),
+      opened: false,
+      bus_number: 0,
+      device_address: 0,
+      port_numbers: vec![],
+      speed: UsbSpeed::Unknown,
+      parent_hub: None,
+      url: None,
+      device: Some(device),
+    };
+    usb_device.update();
+    usb_device
+  }
+
+  fn web_sys(&self) -> Result<&web_sys::UsbDevice> {
+    self.device.as_ref().ok_or(Error::NotFound)
+  }
+
+  fn update(&mut self) {
+    if let Some(device) = &self.device {
+      self.opened = device.opened();
+      self.configuration =
+        device.configuration().map(UsbConfiguration::from_web_sys);
+    }
+  }
+
+  pub async fn open(&mut self) -> Result<()> {
+    let result = self.web_sys()?.open().await;
+    self.update();
+    result.map_err(error_from_js)?;
+    Ok(())
+  }
+
+  pub async fn close(&mut self) -> Result<()> {
+    let result = self.web_sys()?.close().await;
+    self.update();
+    result.map_err(error_from_js)?;
+    Ok(())
+  }
+
+  /// `configuration_value` is the bConfigurationValue of the device configuration.
+  pub async fn select_configuration(
+    &mut self,
+    configuration_value: u8,
+  ) -> Result<()> {
+    let result = self
+      .web_sys()?
+      .select_configuration(configuration_value)
+      .await;
+    self.update();
+    result.map_err(error_from_js)?;
+    Ok(())
+  }
+
+  pub async fn claim_interface(&mut self, interface_number: u8) -> Result<()> {
+    let result = self.web_sys()?.claim_interface(interface_number).await;
+    self.update();
+    result.map_err(error_from_js)?;
+    Ok(())
+  }
+
+  pub async fn release_interface(
+    &mut self,
+    interface_number: u8,
+  ) -> Result<()> {
+    let result = self.web_sys()?.release_interface(interface_number).await;
+    self.update();
+    result.map_err(error_from_js)?;
+    Ok(())
+  }
+
+  pub async fn select_alternate_interface(
+    &mut self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Result<()> {
+    let result = self
+      .web_sys()?
+      .select_alternate_interface(interface_number, alternate_setting)
+      .await;
+    self.update();
+    result.map_err(error_from_js)?;
+    Ok(())
+  }
+
+  pub async fn control_transfer_in(
+    &mut self,
+    setup: UsbControlTransferParameters,
+    length: usize,
+  ) -> Result<Vec<u8>> {
+    // wLength is 16 bits.
+    let length = u16::try_from(length)
+      .map_err(|_| Error::Io(std::io::ErrorKind::InvalidInput))?;
+    let result = self
+      .web_sys()?
+      .control_transfer_in(&setup.to_web_sys(), length)
+      .await
+      .map_err(error_from_js)?;
+    in_result(result)
+  }
+
+  pub async fn control_transfer_out(
+    &mut self,
+    setup: UsbControlTransferParameters,
+    data: &[u8],
+  ) -> Result<usize> {
+    let result = self
+      .web_sys()?
+      .control_transfer_out_with_u8_slice(&setup.to_web_sys(), data)
+      .map_err(error_from_js)?
+      .await
+      .map_err(error_from_js)?;
+    out_result(result)
+  }
+
+  pub async fn clear_halt(
+    &mut self,
+    direction: Direction,
+    endpoint_number: u8,
+  ) -> Result<()> {
+    let direction = match direction {
+      Direction::In => web_sys::UsbDirection::In,
+      Direction::Out => web_sys::UsbDirection::Out,
+    };
+    self
+      .web_sys()?
+      .clear_halt(direction, endpoint_number)
+      .await
+      .map_err(error_from_js)?;
+    Ok(())
+  }
+
+  pub async fn transfer_in(
+    &mut self,
+    endpoint_number: u8,
+    length: usize,
+  ) -> Result<Vec<u8>> {
+    let length = u32::try_from(length)
+      .map_err(|_| Error::Io(std::io::ErrorKind::InvalidInput))?;
+    let result = self
+      .web_sys()?
+      .transfer_in(endpoint_number, length)
+      .await
+      .map_err(error_from_js)?;
+    in_result(result)
+  }
+
+  pub async fn transfer_out(
+    &mut self,
+    endpoint_number: u8,
+    data: &[u8],
+  ) -> Result<usize> {
+    let result = self
+      .web_sys()?
+      .transfer_out_with_u8_slice(endpoint_number, data)
+      .map_err(error_from_js)?
+      .await
+      .map_err(error_from_js)?;
+    out_result(result)
+  }
+
+  pub async fn reset(&mut self) -> Result<()> {
+    let result = self.web_sys()?.reset().await;
+    self.update();
+    result.map_err(error_from_js)?;
+    Ok(())
+  }
+}
+
+// Run under Node against the stand-in of `mock.js` with:
+//
+//   cargo install wasm-bindgen-cli
+//   cargo test --target wasm32-unknown-unknown --no-default-features --lib
+#[cfg(test)]
+mod tests {
+  use wasm_bindgen::prelude::wasm_bindgen;
+  use wasm_bindgen_test::wasm_bindgen_test;
+
+  use crate::wasm::UsbDeviceFilter;
+  use crate::Context;
+  use crate::Error;
+  use crate::UsbControlTransferParameters;
+  use crate::UsbDevice;
+  use crate::UsbRecipient;
+  use crate::UsbRequestType;
+
+  #[wasm_bindgen(module = "/src/wasm/mock.js")]
+  extern "C" {
+    #[wasm_bindgen(js_name = installMock)]
+    fn install_mock();
+  }
+
+  fn loopback_filter() -> UsbDeviceFilter {
+    UsbDeviceFilter {
+      vendor_id: Some(0x1234),
+      ..Default::default()
+    }
+  }
+
+  async fn loopback_device() -> UsbDevice {
+    install_mock();
+    let ctx = Context::init().unwrap();
+    ctx.request_device(&[loopback_filter()]).await.unwrap()
+  }
+
+  #[wasm_bindgen_test]
+  async fn test_request_device() {
+    install_mock();
+    let ctx = Context::init().unwrap();
+    assert!(ctx.devices().await.unwrap().is_empty());
+
+    let filter = UsbDeviceFilter {
+      vendor_id: Some(0xFFFF),
+      ..Default::default()
+    };
+    assert_eq!(
+      ctx.request_device(&[filter]).await.err(),
+      Some(Error::NotFound)
+    );
+
+    let device = ctx.request_device(&[loopback_filter()]).await.unwrap();
+    assert_eq!(device.vendor_id, 0x1234);
+    assert_eq!(device.product_id, 0x5678);
+    assert_eq!(device.product_name.as_deref(), Some("Loopback"));
+    assert!(!device.opened);
+    assert!(device.configuration.is_none());
+
+    let configuration = &device.configurations[0];
+    assert_eq!(configuration.configuration_value(), 1);
+    assert_eq!(configuration.configuration_name(), Some("Loopback"));
+    let interface = configuration.interface(0).unwrap();
+    assert_eq!(interface.alternate().interface_class, 0xFF);
+    assert_eq!(interface.endpoints().len(), 3);
+    assert_eq!(configuration.endpoint(0x81).unwrap().packet_size(), 64);
+
+    assert_eq!(ctx.devices().await.unwrap().len(), 1);
+  }
+
+  #[wasm_bindgen_test]
+  async fn test_transfers() {
+    let mut device = loopback_device().await;
+    device.open().await.unwrap();
+    assert!(device.opened);
+    device.select_configuration(1).await.unwrap();
+    device.claim_interface(0).await.unwrap();
+    assert!(device.configuration.as_ref().unwrap().interfaces()[0].claimed());
+
+    let data: Vec<u8> = (0..64).collect();
+    assert_eq!(device.transfer_out(2, &data).await.unwrap(), 64);
+    assert_eq!(device.transfer_in(1, 64).await.unwrap(), data);
+    assert_eq!(device.transfer_in(3, 64).await.err(), Some(Error::Stall));
+    device.clear_halt(crate::Direction::In, 3).await.unwrap();
+
+    let setup = UsbControlTransferParameters {
+      request_type: UsbRequestType::Vendor,
+      recipient: UsbRecipient::Device,
+      request: 0x01,
+      value: 0x1234,
+      index: 0x0005,
+    };
+    assert_eq!(
+      device.control_transfer_in(setup, 4).await.unwrap(),
+      vec![0x34, 0x12, 0x05, 0x00]
+    );
+    let setup = UsbControlTransferParameters {
+      request_type: UsbRequestType::Vendor,
+      recipient: UsbRecipient::Device,
+      request: 0x02,
+      value: 0,
+      index: 0,
+    };
+    assert_eq!(
+      device.control_transfer_out(setup, &[1, 2]).await.unwrap(),
+      2
+    );
+
+    device.release_interface(0).await.unwrap();
+    device.close().await.unwrap();
+    assert!(!device.opened);
+  }
+
+  #[wasm_bindgen_test]
+  async fn test_invalid_state() {
+    let mut device = loopback_device().await;
+    assert_eq!(
+      device.transfer_in(1, 64).await.err(),
+      Some(Error::InvalidState)
+    );
+
+    device.open().await.unwrap();
+    assert_eq!(
+      device.select_configuration(2).await.err(),
+      Some(Error::NotFound)
+    );
+    device.select_configuration(1).await.unwrap();
+    assert_eq!(
+      device.transfer_out(2, &[0]).await.err(),
+      Some(Error::InvalidState)
+    );
+    assert_eq!(
+      device.transfer_out(5, &[0]).await.err(),
+      Some(Error::NotFound)
+    );
+  }
+}